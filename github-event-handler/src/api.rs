@@ -1,12 +1,26 @@
-use octocrab::models::{CheckRunId, Repository};
+use crate::payload::ReleaseAsset;
+use crate::rate_limit::{current_policy, RateLimit, RateLimitKind, RateLimitPolicy};
+use bytes::Bytes;
+use futures_util::stream::BoxStream;
+use futures_util::{StreamExt, TryStreamExt};
+use http_body_util::combinators::BoxBody;
+use hyper::header::{
+    HeaderMap, HeaderValue, ACCEPT, CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_NONE_MATCH,
+    LAST_MODIFIED,
+};
+use hyper::StatusCode;
+use octocrab::models::{CheckRunId, CommentId, Repository, RepositoryId, StatusState};
 use octocrab::params::checks::{
     CheckRunConclusion, CheckRunOutput, CheckRunOutputAnnotation, CheckRunOutputAnnotationLevel,
     CheckRunStatus,
 };
 use octocrab::Octocrab;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use snafu::{Backtrace, ResultExt, Snafu};
 use std::fmt::Debug;
 use std::future::Future;
+use std::time::{Duration, SystemTime};
 use tracing::instrument;
 
 pub trait GitHubApi: Send {
@@ -15,6 +29,254 @@ pub trait GitHubApi: Send {
         repository: &Repository,
         sha: &str,
     ) -> impl Future<Output = Result<impl Debug, impl std::error::Error + Send + Sync + 'static>> + Send;
+
+    fn create_check_run(
+        &self,
+        repository: &Repository,
+        name: &str,
+        head_sha: &str,
+    ) -> impl Future<Output = Result<CheckRunId, impl std::error::Error + Send + Sync + 'static>> + Send;
+
+    /// Updates the check run, `output` replaces its title and summary and adds its annotations
+    /// to those already sent.
+    fn update_check_run(
+        &self,
+        repository: &Repository,
+        id: CheckRunId,
+        status: Option<CheckRunStatus>,
+        conclusion: Option<CheckRunConclusion>,
+        output: CheckRunOutput,
+    ) -> impl Future<Output = Result<(), impl std::error::Error + Send + Sync + 'static>> + Send;
+
+    /// Creates a completed check run concluded as failed, with `summary` as its output.
+    fn create_failed_check_run(
+        &self,
+        repository: &Repository,
+        name: &str,
+        head_sha: &str,
+        title: &str,
+        summary: &str,
+    ) -> impl Future<Output = Result<CheckRunId, impl std::error::Error + Send + Sync + 'static>> + Send;
+
+    /// Sets the status of the commit `sha` for the given `context` through the status API.
+    fn set_commit_status(
+        &self,
+        repository: &Repository,
+        sha: &str,
+        state: StatusState,
+        context: &str,
+        description: Option<&str>,
+        target_url: Option<&str>,
+    ) -> impl Future<Output = Result<(), impl std::error::Error + Send + Sync + 'static>> + Send;
+
+    /// Reports the state of the deployment, `environment` moves it to another environment.
+    fn create_deployment_status(
+        &self,
+        repository: &Repository,
+        deployment_id: u64,
+        state: DeploymentState,
+        environment: Option<&str>,
+    ) -> impl Future<Output = Result<(), impl std::error::Error + Send + Sync + 'static>> + Send;
+
+    /// Download urls of the artifacts of the workflow run (first page only).
+    fn workflow_run_artifacts(
+        &self,
+        repository: &Repository,
+        run_id: u64,
+    ) -> impl Future<Output = Result<Vec<String>, impl std::error::Error + Send + Sync + 'static>> + Send;
+
+    /// `GET`s the `route` unless it still matches the `etag` of an earlier response, unchanged
+    /// responses don't count against the rate limit.
+    fn get_conditional(
+        &self,
+        route: &str,
+        etag: Option<&str>,
+    ) -> impl Future<Output = Result<Conditional, impl std::error::Error + Send + Sync + 'static>> + Send;
+
+    /// Updates the first comment of the issue containing `marker`, or else creates one.
+    fn upsert_issue_comment(
+        &self,
+        repository: &Repository,
+        issue_number: u64,
+        marker: &str,
+        body: &str,
+    ) -> impl Future<Output = Result<UpsertedComment, impl std::error::Error + Send + Sync + 'static>>
+           + Send;
+
+    /// Adds the labels to the issue or pull request, returns all its labels afterwards.
+    fn add_issue_labels(
+        &self,
+        repository: &Repository,
+        issue_number: u64,
+        labels: &[&str],
+    ) -> impl Future<Output = Result<Vec<String>, impl std::error::Error + Send + Sync + 'static>> + Send;
+
+    /// Removes the label from the issue or pull request, returns the labels left.
+    fn remove_issue_label(
+        &self,
+        repository: &Repository,
+        issue_number: u64,
+        label: &str,
+    ) -> impl Future<Output = Result<Vec<String>, impl std::error::Error + Send + Sync + 'static>> + Send;
+
+    /// Requests reviews of the pull request from the users and teams, by login and team slug.
+    fn request_pull_request_reviewers(
+        &self,
+        repository: &Repository,
+        number: u64,
+        users: &[&str],
+        teams: &[&str],
+    ) -> impl Future<Output = Result<(), impl std::error::Error + Send + Sync + 'static>> + Send;
+
+    /// Merges the pull request, pull requests GitHub refuses to merge aren't an error.
+    fn merge_pull_request(
+        &self,
+        repository: &Repository,
+        number: u64,
+        method: MergeMethod,
+    ) -> impl Future<Output = Result<Merge, impl std::error::Error + Send + Sync + 'static>> + Send;
+
+    /// Closes the pull request without merging it.
+    fn close_pull_request(
+        &self,
+        repository: &Repository,
+        number: u64,
+    ) -> impl Future<Output = Result<(), impl std::error::Error + Send + Sync + 'static>> + Send;
+
+    /// Dismisses a code scanning alert or resolves a secret scanning alert for the reason.
+    fn close_scanning_alert(
+        &self,
+        repository: &Repository,
+        kind: ScanningAlertKind,
+        number: u64,
+        reason: &str,
+        comment: Option<&str>,
+    ) -> impl Future<Output = Result<(), impl std::error::Error + Send + Sync + 'static>> + Send;
+
+    /// Triggers the `workflow_dispatch` event of the workflow, by id or file name, on `git_ref`.
+    fn dispatch_workflow(
+        &self,
+        repository: &Repository,
+        workflow: &str,
+        git_ref: &str,
+        inputs: &serde_json::Value,
+    ) -> impl Future<Output = Result<Dispatch, impl std::error::Error + Send + Sync + 'static>> + Send;
+
+    /// Triggers a `repository_dispatch` event of the type, handing `payload` to the workflows.
+    fn dispatch_repository_event(
+        &self,
+        repository: &Repository,
+        event_type: &str,
+        payload: &serde_json::Value,
+    ) -> impl Future<Output = Result<Dispatch, impl std::error::Error + Send + Sync + 'static>> + Send;
+
+    /// Uploads `body` as an asset of the release through the uploads endpoint.
+    fn upload_release_asset(
+        &self,
+        repository: &Repository,
+        release_id: u64,
+        name: &str,
+        body: Bytes,
+        content_type: &str,
+    ) -> impl Future<Output = Result<ReleaseAsset, impl std::error::Error + Send + Sync + 'static>> + Send;
+
+    /// The repository with all its details, looked up by its id.
+    fn repository(
+        &self,
+        id: RepositoryId,
+    ) -> impl Future<Output = Result<Repository, impl std::error::Error + Send + Sync + 'static>> + Send;
+
+    /// The unified diff of the pull request.
+    fn pull_request_diff(
+        &self,
+        repository: &Repository,
+        number: u64,
+    ) -> impl Future<Output = Result<String, impl std::error::Error + Send + Sync + 'static>> + Send;
+
+    /// Streams the raw content of the file at `path`, of the default branch unless `git_ref`
+    /// is given, as it arrives.
+    fn stream_content(
+        &self,
+        owner: &str,
+        repo: &str,
+        path: &str,
+        git_ref: Option<&str>,
+    ) -> impl Future<Output = Result<ContentStream, impl std::error::Error + Send + Sync + 'static>> + Send;
+}
+
+/// Chunks of a file's content, see [`GitHubApi::stream_content`].
+pub type ContentStream =
+    BoxStream<'static, Result<Bytes, Box<dyn std::error::Error + Send + Sync>>>;
+
+/// How [`GitHubApi::merge_pull_request`] merges, see the merge methods allowed by the repository.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeMethod {
+    Merge,
+    Squash,
+    Rebase,
+}
+
+/// Result of [`GitHubApi::merge_pull_request`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Merge {
+    Merged {
+        sha: String,
+    },
+    /// `405` or `409`, e.g. for conflicts, failing required checks or a changed head.
+    NotMergeable {
+        message: String,
+    },
+}
+
+/// Result of [`GitHubApi::dispatch_workflow`] and [`GitHubApi::dispatch_repository_event`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Dispatch {
+    Dispatched,
+    /// `403`, the installation lacks the permission to dispatch.
+    Forbidden {
+        message: String,
+    },
+}
+
+/// Which alerts [`GitHubApi::close_scanning_alert`] closes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanningAlertKind {
+    CodeScanning,
+    SecretScanning,
+}
+
+/// Which comment [`GitHubApi::upsert_issue_comment`] wrote to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpsertedComment {
+    Created(CommentId),
+    Updated(CommentId),
+}
+
+/// Response of a conditional request, see [`GitHubApi::get_conditional`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Conditional {
+    /// `304 Not Modified`, the earlier response is still current.
+    NotModified,
+    Modified {
+        body: serde_json::Value,
+        /// Sent along the next request for the same route.
+        etag: Option<String>,
+        last_modified: Option<String>,
+    },
+}
+
+/// State of a deployment status, see the deployment statuses API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeploymentState {
+    Error,
+    Failure,
+    Inactive,
+    InProgress,
+    Queued,
+    Pending,
+    Success,
 }
 
 impl GitHubApi for Octocrab {
@@ -28,45 +290,780 @@ impl GitHubApi for Octocrab {
         let Some(owner) = repository.clone().owner else {
             return MissingOwnerSnafu.fail();
         };
-        self.checks(owner.login.to_owned(), repository.name.to_owned())
-            .create_check_run("my-check", sha)
-            .details_url("https://54aa-91-118-110-130.ngrok-free.app/1234")
-            .external_id("1234")
-            .status(CheckRunStatus::Completed)
-            .conclusion(CheckRunConclusion::Success)
-            .output(CheckRunOutput {
-                title: "my title".to_string(),
-                summary: indoc::indoc! {"
+        let output = CheckRunOutput {
+            title: "my title".to_string(),
+            summary: indoc::indoc! {"
                     this **worked** right?
                 "}
-                .to_string(),
-                text: Some(
-                    indoc::indoc! {"
+            .to_string(),
+            text: Some(
+                indoc::indoc! {"
                     # github yada
 
                     > [!CAUTION]
                     > Advises about risks or negative outcomes of certain actions.
                 "}
-                    .to_string(),
-                ),
-                annotations: vec![CheckRunOutputAnnotation {
-                    path: "Cargo.toml".to_string(),
-                    start_line: 5,
-                    end_line: 5,
-                    start_column: Some(8),
-                    end_column: Some(12),
-                    annotation_level: CheckRunOutputAnnotationLevel::Warning,
-                    message: "Is this **markdown**? insert meme here".to_string(),
-                    title: Some("invalid rule".into()),
-                    raw_details: Some("`yada` yada?".into()),
-                }],
-                images: vec![],
-            })
-            .send()
+                .to_string(),
+            ),
+            annotations: vec![CheckRunOutputAnnotation {
+                path: "Cargo.toml".to_string(),
+                start_line: 5,
+                end_line: 5,
+                start_column: Some(8),
+                end_column: Some(12),
+                annotation_level: CheckRunOutputAnnotationLevel::Warning,
+                message: "Is this **markdown**? insert meme here".to_string(),
+                title: Some("invalid rule".into()),
+                raw_details: Some("`yada` yada?".into()),
+            }],
+            images: vec![],
+        };
+        let body = serde_json::json!({
+            "name": "my-check",
+            "head_sha": sha,
+            "details_url": "https://54aa-91-118-110-130.ngrok-free.app/1234",
+            "external_id": "1234",
+            "status": CheckRunStatus::Completed,
+            "conclusion": CheckRunConclusion::Success,
+            "output": output,
+        });
+        post_check_run(self, &owner.login, &repository.name, &body).await
+    }
+
+    #[allow(refining_impl_trait)]
+    #[instrument(skip(self, repository), fields(repo = %repository.name), ret)]
+    async fn create_check_run(
+        &self,
+        repository: &Repository,
+        name: &str,
+        head_sha: &str,
+    ) -> Result<CheckRunId, GitHubActionError> {
+        let Some(owner) = repository.clone().owner else {
+            return MissingOwnerSnafu.fail();
+        };
+        let body = serde_json::json!({ "name": name, "head_sha": head_sha });
+        post_check_run(self, &owner.login, &repository.name, &body).await
+    }
+
+    #[allow(refining_impl_trait)]
+    #[instrument(skip(self, repository, output), fields(repo = %repository.name))]
+    async fn update_check_run(
+        &self,
+        repository: &Repository,
+        id: CheckRunId,
+        status: Option<CheckRunStatus>,
+        conclusion: Option<CheckRunConclusion>,
+        output: CheckRunOutput,
+    ) -> Result<(), GitHubActionError> {
+        let Some(ref owner) = repository.owner else {
+            return MissingOwnerSnafu.fail();
+        };
+        // octocrab's builder would parse the whole check run from the response
+        let route = format!("/repos/{}/{}/check-runs/{id}", owner.login, repository.name);
+        let mut body = serde_json::json!({ "output": output });
+        if let Some(status) = status {
+            body["status"] = serde_json::json!(status);
+        }
+        if let Some(conclusion) = conclusion {
+            body["conclusion"] = serde_json::json!(conclusion);
+        }
+        let response = send_rate_limited(&current_policy(), || {
+            self._patch(route.as_str(), Some(&body))
+        })
+        .await?;
+        succeeded(response).await
+    }
+
+    #[allow(refining_impl_trait)]
+    #[instrument(skip(self, repository, summary), fields(repo = %repository.name), ret)]
+    async fn create_failed_check_run(
+        &self,
+        repository: &Repository,
+        name: &str,
+        head_sha: &str,
+        title: &str,
+        summary: &str,
+    ) -> Result<CheckRunId, GitHubActionError> {
+        let Some(owner) = repository.clone().owner else {
+            return MissingOwnerSnafu.fail();
+        };
+        let output = CheckRunOutput {
+            title: title.to_owned(),
+            summary: summary.to_owned(),
+            text: None,
+            annotations: vec![],
+            images: vec![],
+        };
+        let body = serde_json::json!({
+            "name": name,
+            "head_sha": head_sha,
+            "status": CheckRunStatus::Completed,
+            "conclusion": CheckRunConclusion::Failure,
+            "output": output,
+        });
+        post_check_run(self, &owner.login, &repository.name, &body).await
+    }
+
+    #[allow(refining_impl_trait)]
+    #[instrument(skip(self, repository), fields(repo = %repository.name), ret)]
+    async fn set_commit_status(
+        &self,
+        repository: &Repository,
+        sha: &str,
+        state: StatusState,
+        context: &str,
+        description: Option<&str>,
+        target_url: Option<&str>,
+    ) -> Result<(), GitHubActionError> {
+        #[derive(Serialize)]
+        struct Body<'a> {
+            state: StatusState,
+            context: &'a str,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            description: Option<&'a str>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            target_url: Option<&'a str>,
+        }
+
+        let Some(ref owner) = repository.owner else {
+            return MissingOwnerSnafu.fail();
+        };
+        let route = format!("/repos/{}/{}/statuses/{sha}", owner.login, repository.name);
+        let body = Body {
+            state,
+            context,
+            description,
+            target_url,
+        };
+        let response = send_rate_limited(&current_policy(), || {
+            self._post(route.as_str(), Some(&body))
+        })
+        .await?;
+        succeeded(response).await
+    }
+
+    #[allow(refining_impl_trait)]
+    #[instrument(skip(self, repository), fields(repo = %repository.name), ret)]
+    async fn create_deployment_status(
+        &self,
+        repository: &Repository,
+        deployment_id: u64,
+        state: DeploymentState,
+        environment: Option<&str>,
+    ) -> Result<(), GitHubActionError> {
+        #[derive(Serialize)]
+        struct Body<'a> {
+            state: DeploymentState,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            environment: Option<&'a str>,
+        }
+
+        let Some(owner) = repository.clone().owner else {
+            return MissingOwnerSnafu.fail();
+        };
+        let route = format!(
+            "/repos/{}/{}/deployments/{deployment_id}/statuses",
+            owner.login, repository.name
+        );
+        let body = Body { state, environment };
+        let response = send_rate_limited(&current_policy(), || {
+            self._post(route.as_str(), Some(&body))
+        })
+        .await?;
+        succeeded(response).await
+    }
+
+    #[allow(refining_impl_trait)]
+    #[instrument(skip(self, repository), fields(repo = %repository.name), ret)]
+    async fn workflow_run_artifacts(
+        &self,
+        repository: &Repository,
+        run_id: u64,
+    ) -> Result<Vec<String>, GitHubActionError> {
+        let Some(owner) = repository.clone().owner else {
+            return MissingOwnerSnafu.fail();
+        };
+        #[derive(serde::Deserialize)]
+        struct Artifacts {
+            artifacts: Vec<Artifact>,
+        }
+        #[derive(serde::Deserialize)]
+        struct Artifact {
+            archive_download_url: String,
+        }
+
+        let route = format!(
+            "/repos/{}/{}/actions/runs/{run_id}/artifacts",
+            owner.login, repository.name
+        );
+        let response = send_rate_limited(&current_policy(), || self._get(route.as_str())).await?;
+        let page: Artifacts = json_body(self, response).await?;
+        Ok(page
+            .artifacts
+            .into_iter()
+            .map(|artifact| artifact.archive_download_url)
+            .collect())
+    }
+
+    #[allow(refining_impl_trait)]
+    #[instrument(skip(self), ret)]
+    async fn get_conditional(
+        &self,
+        route: &str,
+        etag: Option<&str>,
+    ) -> Result<Conditional, GitHubActionError> {
+        let mut headers = HeaderMap::new();
+        if let Some(etag) = etag {
+            let etag = HeaderValue::try_from(etag).map_err(|_| InvalidEtagSnafu.build())?;
+            headers.insert(IF_NONE_MATCH, etag);
+        }
+        let response = send_rate_limited(&current_policy(), || {
+            self._get_with_headers(route, Some(headers.clone()))
+        })
+        .await?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(Conditional::NotModified);
+        }
+        let response = octocrab::map_github_error(response)
             .await
-            .context(OctocrabSnafu)
-            .map(|s| s.id)
+            .context(OctocrabSnafu)?;
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value: &HeaderValue| value.to_str().ok())
+                .map(str::to_owned)
+        };
+        let (etag, last_modified) = (header(ETAG), header(LAST_MODIFIED));
+        let body = self.body_to_string(response).await.context(OctocrabSnafu)?;
+        Ok(Conditional::Modified {
+            body: serde_json::from_str(&body).context(InvalidBodySnafu)?,
+            etag,
+            last_modified,
+        })
+    }
+
+    #[allow(refining_impl_trait)]
+    #[instrument(skip(self, repository, body), fields(repo = %repository.name))]
+    async fn upsert_issue_comment(
+        &self,
+        repository: &Repository,
+        issue_number: u64,
+        marker: &str,
+        body: &str,
+    ) -> Result<UpsertedComment, GitHubActionError> {
+        let Some(ref owner) = repository.owner else {
+            return MissingOwnerSnafu.fail();
+        };
+        #[derive(serde::Deserialize)]
+        struct Comment {
+            id: CommentId,
+            body: Option<String>,
+        }
+
+        const PER_PAGE: usize = 100;
+        let comments = format!(
+            "/repos/{}/{}/issues/{issue_number}/comments",
+            owner.login, repository.name
+        );
+        let mut marked = None;
+        for page in 1.. {
+            let route = format!("{comments}?per_page={PER_PAGE}&page={page}");
+            let response =
+                send_rate_limited(&current_policy(), || self._get(route.as_str())).await?;
+            let listed: Vec<Comment> = json_body(self, response).await?;
+            let last = listed.len() < PER_PAGE;
+            marked = listed.into_iter().find(|comment| {
+                comment
+                    .body
+                    .as_deref()
+                    .is_some_and(|text| text.contains(marker))
+            });
+            if marked.is_some() || last {
+                break;
+            }
+        }
+        let body = serde_json::json!({ "body": body });
+        match marked {
+            Some(comment) => {
+                let route = format!(
+                    "/repos/{}/{}/issues/comments/{}",
+                    owner.login, repository.name, comment.id
+                );
+                let response = send_rate_limited(&current_policy(), || {
+                    self._patch(route.as_str(), Some(&body))
+                })
+                .await?;
+                succeeded(response).await?;
+                Ok(UpsertedComment::Updated(comment.id))
+            }
+            None => {
+                let response = send_rate_limited(&current_policy(), || {
+                    self._post(comments.as_str(), Some(&body))
+                })
+                .await?;
+                let created: Comment = json_body(self, response).await?;
+                Ok(UpsertedComment::Created(created.id))
+            }
+        }
+    }
+
+    #[allow(refining_impl_trait)]
+    #[instrument(skip(self, repository), fields(repo = %repository.name))]
+    async fn add_issue_labels(
+        &self,
+        repository: &Repository,
+        issue_number: u64,
+        labels: &[&str],
+    ) -> Result<Vec<String>, GitHubActionError> {
+        let Some(ref owner) = repository.owner else {
+            return MissingOwnerSnafu.fail();
+        };
+        let route = format!(
+            "/repos/{}/{}/issues/{issue_number}/labels",
+            owner.login, repository.name
+        );
+        let body = serde_json::json!({ "labels": labels });
+        let response = send_rate_limited(&current_policy(), || {
+            self._post(route.as_str(), Some(&body))
+        })
+        .await?;
+        label_names(self, response).await
     }
+
+    #[allow(refining_impl_trait)]
+    #[instrument(skip(self, repository), fields(repo = %repository.name))]
+    async fn remove_issue_label(
+        &self,
+        repository: &Repository,
+        issue_number: u64,
+        label: &str,
+    ) -> Result<Vec<String>, GitHubActionError> {
+        let Some(ref owner) = repository.owner else {
+            return MissingOwnerSnafu.fail();
+        };
+        let route = format!(
+            "/repos/{}/{}/issues/{issue_number}/labels/{}",
+            owner.login,
+            repository.name,
+            percent_encode(label)
+        );
+        let response = send_rate_limited(&current_policy(), || {
+            self._delete(route.as_str(), None::<&()>)
+        })
+        .await?;
+        label_names(self, response).await
+    }
+
+    #[allow(refining_impl_trait)]
+    #[instrument(skip(self, repository), fields(repo = %repository.name))]
+    async fn request_pull_request_reviewers(
+        &self,
+        repository: &Repository,
+        number: u64,
+        users: &[&str],
+        teams: &[&str],
+    ) -> Result<(), GitHubActionError> {
+        let Some(ref owner) = repository.owner else {
+            return MissingOwnerSnafu.fail();
+        };
+        // octocrab's `request_reviews` expects a review in response, GitHub answers with the
+        // pull request
+        let route = format!(
+            "/repos/{}/{}/pulls/{number}/requested_reviewers",
+            owner.login, repository.name
+        );
+        let body = serde_json::json!({ "reviewers": users, "team_reviewers": teams });
+        let response = send_rate_limited(&current_policy(), || {
+            self._post(route.as_str(), Some(&body))
+        })
+        .await?;
+        succeeded(response).await
+    }
+
+    #[allow(refining_impl_trait)]
+    #[instrument(skip(self, repository), fields(repo = %repository.name))]
+    async fn merge_pull_request(
+        &self,
+        repository: &Repository,
+        number: u64,
+        method: MergeMethod,
+    ) -> Result<Merge, GitHubActionError> {
+        let Some(ref owner) = repository.owner else {
+            return MissingOwnerSnafu.fail();
+        };
+        let route = format!(
+            "/repos/{}/{}/pulls/{number}/merge",
+            owner.login, repository.name
+        );
+        let body = serde_json::json!({ "merge_method": method });
+        let response =
+            send_rate_limited(&current_policy(), || self._put(route.as_str(), Some(&body))).await?;
+        match json_body::<serde_json::Value>(self, response).await {
+            Ok(merged) => Ok(Merge::Merged {
+                sha: merged["sha"].as_str().unwrap_or_default().to_owned(),
+            }),
+            Err(GitHubActionError::Octocrab {
+                source: octocrab::Error::GitHub { source, .. },
+                ..
+            }) if source.status_code == StatusCode::METHOD_NOT_ALLOWED
+                || source.status_code == StatusCode::CONFLICT =>
+            {
+                Ok(Merge::NotMergeable {
+                    message: source.message,
+                })
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    #[allow(refining_impl_trait)]
+    #[instrument(skip(self, repository), fields(repo = %repository.name))]
+    async fn close_pull_request(
+        &self,
+        repository: &Repository,
+        number: u64,
+    ) -> Result<(), GitHubActionError> {
+        let Some(ref owner) = repository.owner else {
+            return MissingOwnerSnafu.fail();
+        };
+        let route = format!("/repos/{}/{}/pulls/{number}", owner.login, repository.name);
+        let body = serde_json::json!({ "state": "closed" });
+        let response = send_rate_limited(&current_policy(), || {
+            self._patch(route.as_str(), Some(&body))
+        })
+        .await?;
+        succeeded(response).await
+    }
+
+    #[allow(refining_impl_trait)]
+    #[instrument(skip(self, repository, comment), fields(repo = %repository.name))]
+    async fn close_scanning_alert(
+        &self,
+        repository: &Repository,
+        kind: ScanningAlertKind,
+        number: u64,
+        reason: &str,
+        comment: Option<&str>,
+    ) -> Result<(), GitHubActionError> {
+        let Some(ref owner) = repository.owner else {
+            return MissingOwnerSnafu.fail();
+        };
+        let (alerts, body) = match kind {
+            ScanningAlertKind::CodeScanning => (
+                "code-scanning",
+                serde_json::json!({
+                    "state": "dismissed",
+                    "dismissed_reason": reason,
+                    "dismissed_comment": comment,
+                }),
+            ),
+            ScanningAlertKind::SecretScanning => (
+                "secret-scanning",
+                serde_json::json!({
+                    "state": "resolved",
+                    "resolution": reason,
+                    "resolution_comment": comment,
+                }),
+            ),
+        };
+        let route = format!(
+            "/repos/{}/{}/{alerts}/alerts/{number}",
+            owner.login, repository.name
+        );
+        let response = send_rate_limited(&current_policy(), || {
+            self._patch(route.as_str(), Some(&body))
+        })
+        .await?;
+        succeeded(response).await
+    }
+
+    #[allow(refining_impl_trait)]
+    #[instrument(skip(self, repository, inputs), fields(repo = %repository.name))]
+    async fn dispatch_workflow(
+        &self,
+        repository: &Repository,
+        workflow: &str,
+        git_ref: &str,
+        inputs: &serde_json::Value,
+    ) -> Result<Dispatch, GitHubActionError> {
+        let Some(ref owner) = repository.owner else {
+            return MissingOwnerSnafu.fail();
+        };
+        let route = format!(
+            "/repos/{}/{}/actions/workflows/{}/dispatches",
+            owner.login,
+            repository.name,
+            percent_encode(workflow)
+        );
+        let body = serde_json::json!({ "ref": git_ref, "inputs": inputs });
+        dispatch(self, &route, &body).await
+    }
+
+    #[allow(refining_impl_trait)]
+    #[instrument(skip(self, repository, payload), fields(repo = %repository.name))]
+    async fn dispatch_repository_event(
+        &self,
+        repository: &Repository,
+        event_type: &str,
+        payload: &serde_json::Value,
+    ) -> Result<Dispatch, GitHubActionError> {
+        let Some(ref owner) = repository.owner else {
+            return MissingOwnerSnafu.fail();
+        };
+        let route = format!("/repos/{}/{}/dispatches", owner.login, repository.name);
+        let body = serde_json::json!({ "event_type": event_type, "client_payload": payload });
+        dispatch(self, &route, &body).await
+    }
+
+    #[allow(refining_impl_trait)]
+    #[instrument(skip(self, repository, body), fields(repo = %repository.name))]
+    async fn upload_release_asset(
+        &self,
+        repository: &Repository,
+        release_id: u64,
+        name: &str,
+        body: Bytes,
+        content_type: &str,
+    ) -> Result<ReleaseAsset, GitHubActionError> {
+        let Some(ref owner) = repository.owner else {
+            return MissingOwnerSnafu.fail();
+        };
+        #[derive(serde::Deserialize)]
+        struct Uploads {
+            upload_url: String,
+        }
+        // the uploads endpoint lives on another host, the release knows which
+        let route = format!(
+            "/repos/{}/{}/releases/{release_id}",
+            owner.login, repository.name
+        );
+        let response = send_rate_limited(&current_policy(), || self._get(route.as_str())).await?;
+        let release: Uploads = json_body(self, response).await?;
+        let upload_url: hyper::Uri = format!(
+            "{}?name={}",
+            release.upload_url.replace("{?name,label}", ""),
+            percent_encode(name)
+        )
+        .parse()
+        .map_err(|_| InvalidUploadSnafu.build())?;
+        let content_type =
+            HeaderValue::try_from(content_type).map_err(|_| InvalidUploadSnafu.build())?;
+        let response = send_rate_limited(&current_policy(), || async {
+            let request = hyper::Request::post(upload_url.clone())
+                .header(CONTENT_TYPE, content_type.clone())
+                .header(CONTENT_LENGTH, body.len())
+                .body(body.clone())
+                .expect("the upload url and headers were validated");
+            self.execute(request).await
+        })
+        .await?;
+        json_body(self, response).await
+    }
+
+    #[allow(refining_impl_trait)]
+    #[instrument(skip(self))]
+    async fn repository(&self, id: RepositoryId) -> Result<Repository, GitHubActionError> {
+        let route = format!("/repositories/{id}");
+        let response = send_rate_limited(&current_policy(), || self._get(route.as_str())).await?;
+        json_body(self, response).await
+    }
+
+    #[allow(refining_impl_trait)]
+    #[instrument(skip(self, repository), fields(repo = %repository.name))]
+    async fn pull_request_diff(
+        &self,
+        repository: &Repository,
+        number: u64,
+    ) -> Result<String, GitHubActionError> {
+        let Some(ref owner) = repository.owner else {
+            return MissingOwnerSnafu.fail();
+        };
+        let route = format!("/repos/{}/{}/pulls/{number}", owner.login, repository.name);
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            ACCEPT,
+            HeaderValue::from_static("application/vnd.github.v3.diff"),
+        );
+        let response = send_rate_limited(&current_policy(), || {
+            self._get_with_headers(&route, Some(headers.clone()))
+        })
+        .await?;
+        let response = octocrab::map_github_error(response)
+            .await
+            .context(OctocrabSnafu)?;
+        self.body_to_string(response).await.context(OctocrabSnafu)
+    }
+
+    #[allow(refining_impl_trait)]
+    #[instrument(skip(self))]
+    async fn stream_content(
+        &self,
+        owner: &str,
+        repo: &str,
+        path: &str,
+        git_ref: Option<&str>,
+    ) -> Result<ContentStream, GitHubActionError> {
+        // the slashes separate the directories, anything else within the path is data
+        let path = path
+            .split('/')
+            .map(percent_encode)
+            .collect::<Vec<_>>()
+            .join("/");
+        let mut route = format!("/repos/{owner}/{repo}/contents/{path}");
+        if let Some(git_ref) = git_ref {
+            route = format!("{route}?ref={}", percent_encode(git_ref));
+        }
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            ACCEPT,
+            HeaderValue::from_static("application/vnd.github.raw+json"),
+        );
+        let response = send_rate_limited(&current_policy(), || {
+            self._get_with_headers(&route, Some(headers.clone()))
+        })
+        .await?;
+        let response = octocrab::map_github_error(response)
+            .await
+            .context(OctocrabSnafu)?;
+        Ok(http_body_util::BodyDataStream::new(response.into_body())
+            .map_err(|err| Box::new(err) as _)
+            .boxed())
+    }
+}
+
+/// Sends the request again once the rate limit it ran into is lifted, if the policy waits that
+/// long. A request still limited after the wait fails.
+///
+/// Octocrab's typed requests drop the headers telling the limits apart, every call of
+/// [`GitHubApi`] sends its requests through here and looks at the raw response instead.
+pub(crate) async fn send_rate_limited<F, Fut, B>(
+    policy: &RateLimitPolicy,
+    send: F,
+) -> Result<hyper::Response<B>, GitHubActionError>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<hyper::Response<B>, octocrab::Error>>,
+{
+    let mut waited = false;
+    loop {
+        let response = send().await.context(OctocrabSnafu)?;
+        let Some(limit) =
+            RateLimit::from_response(response.status(), response.headers(), SystemTime::now())
+        else {
+            return Ok(response);
+        };
+        if waited || !policy.waits_for(&limit) {
+            return RateLimitedSnafu {
+                kind: limit.kind,
+                retry_after: limit.retry_after,
+            }
+            .fail();
+        }
+        tracing::warn!(
+            kind = ?limit.kind,
+            retry_after_secs = limit.retry_after.as_secs(),
+            "rate limited, waiting for the limit to be lifted"
+        );
+        tokio::time::sleep(limit.retry_after).await;
+        waited = true;
+    }
+}
+
+/// A response [`send_rate_limited`] passed on.
+type RawResponse = hyper::Response<BoxBody<Bytes, octocrab::Error>>;
+
+/// Parses the body of a successful response, error statuses fail.
+async fn json_body<T: DeserializeOwned>(
+    octocrab: &Octocrab,
+    response: RawResponse,
+) -> Result<T, GitHubActionError> {
+    let response = octocrab::map_github_error(response)
+        .await
+        .context(OctocrabSnafu)?;
+    let body = octocrab
+        .body_to_string(response)
+        .await
+        .context(OctocrabSnafu)?;
+    serde_json::from_str(&body).context(InvalidBodySnafu)
+}
+
+/// Fails on error statuses, the body is of no interest.
+async fn succeeded(response: RawResponse) -> Result<(), GitHubActionError> {
+    octocrab::map_github_error(response)
+        .await
+        .context(OctocrabSnafu)
+        .map(|_| ())
+}
+
+/// Creates the check run `body` describes.
+async fn post_check_run(
+    octocrab: &Octocrab,
+    owner: &str,
+    repo: &str,
+    body: &serde_json::Value,
+) -> Result<CheckRunId, GitHubActionError> {
+    #[derive(serde::Deserialize)]
+    struct Created {
+        id: CheckRunId,
+    }
+
+    let route = format!("/repos/{owner}/{repo}/check-runs");
+    let response = send_rate_limited(&current_policy(), || {
+        octocrab._post(route.as_str(), Some(body))
+    })
+    .await?;
+    let created: Created = json_body(octocrab, response).await?;
+    Ok(created.id)
+}
+
+/// The names of the labels an issue is left with.
+async fn label_names(
+    octocrab: &Octocrab,
+    response: RawResponse,
+) -> Result<Vec<String>, GitHubActionError> {
+    #[derive(serde::Deserialize)]
+    struct Label {
+        name: String,
+    }
+
+    let labels: Vec<Label> = json_body(octocrab, response).await?;
+    Ok(labels.into_iter().map(|label| label.name).collect())
+}
+
+/// Posts a dispatch, GitHub answers `204` without a body once the event was created.
+async fn dispatch(
+    octocrab: &Octocrab,
+    route: &str,
+    body: &serde_json::Value,
+) -> Result<Dispatch, GitHubActionError> {
+    let response =
+        send_rate_limited(&current_policy(), || octocrab._post(route, Some(body))).await?;
+    match octocrab::map_github_error(response).await {
+        Ok(_) => Ok(Dispatch::Dispatched),
+        Err(octocrab::Error::GitHub { source, .. })
+            if source.status_code == StatusCode::FORBIDDEN =>
+        {
+            Ok(Dispatch::Forbidden {
+                message: source.message,
+            })
+        }
+        Err(err) => Err(err).context(OctocrabSnafu),
+    }
+}
+
+/// Encodes everything but the unreserved characters of RFC 3986 for use in a query or as a
+/// path segment.
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
 }
 
 #[derive(Debug, Snafu)]
@@ -78,4 +1075,150 @@ pub enum GitHubActionError {
         source: octocrab::Error,
         backtrace: Backtrace,
     },
+    #[snafu(display("The ETag is not a valid header value"))]
+    InvalidEtag { backtrace: Backtrace },
+    #[snafu(display("The content type or name of the upload is invalid"))]
+    InvalidUpload { backtrace: Backtrace },
+    #[snafu(display("Rate limited by the {kind:?} limit for another {retry_after:?}"))]
+    RateLimited {
+        kind: RateLimitKind,
+        retry_after: Duration,
+        backtrace: Backtrace,
+    },
+    #[snafu(display("The response is not valid JSON: {source}"))]
+    InvalidBody {
+        source: serde_json::Error,
+        backtrace: Backtrace,
+    },
+}
+
+#[cfg(test)]
+mod test {
+    use super::{GitHubActionError, GitHubApi, Merge, MergeMethod};
+    use crate::client::octocrab_client;
+    use crate::rate_limit::RateLimitKind;
+    use axum::{extract::State, http::Uri, response::IntoResponse, Json, Router};
+    use futures_util::TryStreamExt;
+    use hyper::StatusCode;
+    use octocrab::models::Repository;
+    use octocrab::Octocrab;
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    /// A client of the API `mock` serves.
+    async fn client_of(mock: Router) -> Octocrab {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await });
+        octocrab_client(
+            format!("http://{addr}").parse().unwrap(),
+            "wild-git-yonder",
+            &[],
+            None,
+            None,
+        )
+        .unwrap()
+    }
+
+    fn repository() -> Repository {
+        let url = "https://api.github.local/users/acme";
+        serde_json::from_value(json!({
+            "id": 1,
+            "name": "anvil",
+            "url": "https://api.github.local/repos/acme/anvil",
+            "owner": {
+                "login": "acme", "id": 1, "node_id": "dGVzdA==", "gravatar_id": "",
+                "avatar_url": url, "url": url, "html_url": url, "followers_url": url,
+                "following_url": url, "gists_url": url, "starred_url": url,
+                "subscriptions_url": url, "organizations_url": url, "repos_url": url,
+                "events_url": url, "received_events_url": url,
+                "type": "Organization", "site_admin": false
+            }
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_calls_fail_with_the_limit() {
+        let reset = SystemTime::now() + Duration::from_secs(3600);
+        let reset = reset
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            .to_string();
+        let limited = move || async move {
+            let headers = [
+                ("x-ratelimit-remaining", "0".to_owned()),
+                ("x-ratelimit-reset", reset),
+            ];
+            let message = json!({ "message": "API rate limit exceeded" });
+            (StatusCode::FORBIDDEN, headers, Json(message)).into_response()
+        };
+        let client = client_of(Router::new().fallback(limited)).await;
+
+        let labelled = client.add_issue_labels(&repository(), 7, &["bug"]).await;
+        assert!(matches!(
+            labelled,
+            Err(GitHubActionError::RateLimited {
+                kind: RateLimitKind::Primary,
+                ..
+            })
+        ));
+        let merged = client
+            .merge_pull_request(&repository(), 7, MergeMethod::Squash)
+            .await;
+        assert!(matches!(merged, Err(GitHubActionError::RateLimited { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_conflicting_merge_is_not_mergeable() {
+        let conflict = || async {
+            let message = json!({ "message": "Head branch was modified" });
+            (StatusCode::CONFLICT, Json(message))
+        };
+        let client = client_of(Router::new().fallback(conflict)).await;
+
+        let merged = client
+            .merge_pull_request(&repository(), 7, MergeMethod::Squash)
+            .await
+            .unwrap();
+        assert_eq!(
+            merged,
+            Merge::NotMergeable {
+                message: "Head branch was modified".into()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_content_path_and_ref_are_percent_encoded() {
+        async fn record(State(requested): State<Arc<Mutex<Vec<Uri>>>>, uri: Uri) -> &'static str {
+            requested.lock().unwrap().push(uri);
+            "content"
+        }
+
+        let requested = Arc::new(Mutex::new(Vec::new()));
+        let mock = Router::new().fallback(record).with_state(requested.clone());
+        let client = client_of(mock).await;
+
+        let content = client
+            .stream_content(
+                "acme",
+                "anvil",
+                "docs/road runner#1?.md",
+                Some("feature/a&b"),
+            )
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        assert_eq!(content.concat(), b"content");
+        assert_eq!(
+            requested.lock().unwrap()[0],
+            "/repos/acme/anvil/contents/docs/road%20runner%231%3F.md?ref=feature%2Fa%26b"
+        );
+    }
 }
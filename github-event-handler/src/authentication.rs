@@ -1,65 +1,1640 @@
 use crate::api::GitHubApi;
-use hyper::http::Uri;
-use jsonwebtoken::EncodingKey;
+use crate::cache::{Flights, LruMap};
+use crate::client::octocrab_client;
+use futures_util::{stream, StreamExt};
+use hyper::http::header::{HeaderValue, InvalidHeaderValue, AUTHORIZATION};
+use hyper::http::{Method, Request, StatusCode, Uri};
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
 use octocrab::{
-    models::{AppId, InstallationId},
-    Octocrab,
+    models::{
+        webhook_events::EventInstallation, AppId, Installation, InstallationId,
+        InstallationRepositories, InstallationToken, Repository,
+    },
+    FromResponse, Octocrab,
 };
-use snafu::{ResultExt, Snafu};
+use serde::{Deserialize, Serialize};
+use snafu::{GenerateImplicitData, ResultExt, Snafu};
+use std::collections::HashMap;
 use std::fmt::Debug;
 use std::future::Future;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+// follows tokio's clock, which tests can pause and advance
+use tokio::time::Instant;
 
-#[derive(Clone)]
+/// Installation tokens are valid for an hour, refresh cached clients a bit earlier.
+const INSTALLATION_CLIENT_TTL: Duration = Duration::from_secs(55 * 60);
+
+/// Entries each cache of an [`AuthenticatedClient`] holds unless configured otherwise.
+pub const DEFAULT_CACHE_CAPACITY: usize = 10_000;
+
+/// Repositories resolved at once by [`AuthenticatedClient::installations_for_repos`].
+const INSTALLATION_LOOKUP_CONCURRENCY: usize = 8;
+
+/// The installation count is for dashboards, it may lag behind by this much.
+const INSTALLATION_COUNT_TTL: Duration = Duration::from_secs(60);
+
+/// Suspensions are taken for granted this long, a mint is tried again afterwards in case the
+/// unsuspend event got lost.
+pub const SUSPENSION_TTL: Duration = Duration::from_secs(10 * 60);
+
+pub const TOKEN_CACHE_HITS: &str = "github_token_cache_hits_total";
+pub const TOKEN_CACHE_MISSES: &str = "github_token_cache_misses_total";
+
+/// A lookup of an installation token, see [`AuthenticatedClient::with_token_cache_observer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenCacheLookup {
+    /// A cached token was reused.
+    Hit,
+    /// A token was minted.
+    Miss,
+}
+
+impl TokenCacheLookup {
+    /// The counter the lookup is counted by.
+    pub fn metric(&self) -> &'static str {
+        match self {
+            TokenCacheLookup::Hit => TOKEN_CACHE_HITS,
+            TokenCacheLookup::Miss => TOKEN_CACHE_MISSES,
+        }
+    }
+}
+
+type TokenCacheObserver = Arc<dyn Fn(TokenCacheLookup) + Send + Sync>;
+
+/// The App's client, caching installation clients, repository mappings and installation
+/// details in bounded caches.
+///
+/// Full caches evict the least recently used entry, expired entries are replaced once they are
+/// looked up again. Clients handed out keep their token when evicted, the next call for the
+/// installation mints another.
 pub struct AuthenticatedClient<C: InstallationAuthenticator> {
     pub client: C,
+    accounts: Arc<Mutex<LruMap<InstallationId, String>>>,
+    installations: Arc<Mutex<LruMap<InstallationId, CachedInstallation<C::Api>>>>,
+    /// Held whilst a token of the installation is minted, concurrent callers wait for it.
+    minting: Flights<InstallationId>,
+    repositories: Arc<Mutex<LruMap<String, CachedInstallation<C::Api>>>>,
+    accessible: Arc<Mutex<LruMap<InstallationId, AccessibleRepositories>>>,
+    app: Arc<RwLock<Option<AppMetadata>>>,
+    installation_count: Arc<Mutex<Option<(u64, Instant)>>>,
+    max_token_age: Duration,
+    /// Installations known to be suspended, by their events or a failed token mint, and when.
+    suspended: Arc<Mutex<HashMap<InstallationId, Instant>>>,
+    observe_token_cache: TokenCacheObserver,
+}
+
+struct CachedInstallation<A> {
+    installation: InstallationId,
+    client: A,
+    created: Instant,
+    max_age: Duration,
+}
+
+impl<A> CachedInstallation<A> {
+    fn is_live(&self) -> bool {
+        self.created.elapsed() < self.max_age
+    }
+}
+
+struct AccessibleRepositories {
+    repositories: Vec<Repository>,
+    fetched: Instant,
+}
+
+impl AccessibleRepositories {
+    fn is_live(&self) -> bool {
+        self.fetched.elapsed() < INSTALLATION_CLIENT_TTL
+    }
+}
+
+impl<C: InstallationAuthenticator> Clone for AuthenticatedClient<C> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            accounts: self.accounts.clone(),
+            installations: self.installations.clone(),
+            minting: self.minting.clone(),
+            repositories: self.repositories.clone(),
+            accessible: self.accessible.clone(),
+            app: self.app.clone(),
+            installation_count: self.installation_count.clone(),
+            max_token_age: self.max_token_age,
+            suspended: self.suspended.clone(),
+            observe_token_cache: self.observe_token_cache.clone(),
+        }
+    }
+}
+
+impl<C: InstallationAuthenticator> AuthenticatedClient<C> {
+    pub fn new(client: C) -> Self {
+        Self::with_cache_capacity(client, DEFAULT_CACHE_CAPACITY)
+    }
+
+    /// Each cache holds at most `capacity` entries.
+    pub fn with_cache_capacity(client: C, capacity: usize) -> Self {
+        Self {
+            client,
+            accounts: Arc::new(Mutex::new(LruMap::new(capacity))),
+            installations: Arc::new(Mutex::new(LruMap::new(capacity))),
+            minting: Default::default(),
+            repositories: Arc::new(Mutex::new(LruMap::new(capacity))),
+            accessible: Arc::new(Mutex::new(LruMap::new(capacity))),
+            app: Default::default(),
+            installation_count: Default::default(),
+            max_token_age: INSTALLATION_CLIENT_TTL,
+            suspended: Default::default(),
+            observe_token_cache: Arc::new(|lookup| metrics::counter!(lookup.metric()).increment(1)),
+        }
+    }
+
+    /// Mints installation tokens again once they are this old, even though they are valid for
+    /// longer. Ages beyond the validity of a token are ignored.
+    pub fn with_max_token_age(self, max_token_age: Duration) -> Self {
+        Self {
+            max_token_age: max_token_age.min(INSTALLATION_CLIENT_TTL),
+            ..self
+        }
+    }
+
+    /// Tells `observer` about every token lookup of [`Self::installation`] instead of counting
+    /// them through the `metrics` facade.
+    pub fn with_token_cache_observer(
+        self,
+        observer: impl Fn(TokenCacheLookup) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            observe_token_cache: Arc::new(observer),
+            ..self
+        }
+    }
+
+    /// Returns the App's own metadata, it's looked up via the API once and cached afterwards.
+    pub async fn app_metadata(&self) -> Result<AppMetadata, C::Error> {
+        if let Some(app) = self.app.read().unwrap().as_ref() {
+            return Ok(app.clone());
+        }
+        let app = self.client.app_metadata().await?;
+        *self.app.write().unwrap() = Some(app.clone());
+        Ok(app)
+    }
+
+    /// Number of installations of the App, cached for a minute.
+    pub async fn installation_count(&self) -> Result<u64, C::Error> {
+        let cached = *self.installation_count.lock().unwrap();
+        if let Some((count, fetched)) = cached {
+            if fetched.elapsed() < INSTALLATION_COUNT_TTL {
+                return Ok(count);
+            }
+        }
+        let count = self.client.installation_count().await?;
+        *self.installation_count.lock().unwrap() = Some((count, Instant::now()));
+        Ok(count)
+    }
+
+    /// Returns a client for the installation, its token is cached until it would expire.
+    ///
+    /// Only one token is minted per installation at a time, concurrent callers get the client
+    /// of the token minted meanwhile.
+    ///
+    /// Reused tokens count as hits of `github_token_cache_hits_total`, minted ones as misses of
+    /// `github_token_cache_misses_total`, unless they are observed otherwise.
+    pub async fn installation(&self, id: InstallationId) -> Result<C::Api, C::Error> {
+        if let Some(client) = self.cached_installation(id) {
+            (self.observe_token_cache)(TokenCacheLookup::Hit);
+            return Ok(client);
+        }
+        let _minting = self.minting.board(id).await;
+        if let Some(client) = self.cached_installation(id) {
+            (self.observe_token_cache)(TokenCacheLookup::Hit);
+            return Ok(client);
+        }
+        (self.observe_token_cache)(TokenCacheLookup::Miss);
+        tracing::debug!(installation = id.0, "minting an installation token");
+        let client = self.client.for_installation(id).await.inspect_err(|err| {
+            if C::is_suspended(err) {
+                self.set_suspended(id, true);
+            }
+        })?;
+        self.set_suspended(id, false);
+        self.installations.lock().unwrap().insert(
+            id,
+            CachedInstallation {
+                installation: id,
+                client: client.clone(),
+                created: Instant::now(),
+                max_age: self.max_token_age,
+            },
+        );
+        Ok(client)
+    }
+
+    fn cached_installation(&self, id: InstallationId) -> Option<C::Api> {
+        self.installations
+            .lock()
+            .unwrap()
+            .get(&id)
+            .filter(|cached| cached.is_live())
+            .map(|cached| cached.client.clone())
+    }
+
+    /// Installations which currently have a live cached token.
+    pub fn cached_installations(&self) -> Vec<InstallationId> {
+        let mut installations = self
+            .installations
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|cached| cached.is_live())
+            .map(|cached| cached.installation)
+            .collect::<Vec<_>>();
+        installations.sort_by_key(|id| id.0);
+        installations
+    }
+
+    /// Drops the cached token of the installation, e.g. after a suspected leak, so the next
+    /// call mints a new one.
+    pub fn revoke(&self, installation: InstallationId) {
+        tracing::info!(
+            installation = installation.0,
+            "revoking cached installation token"
+        );
+        self.installations.lock().unwrap().remove(&installation);
+        self.invalidate_repositories(installation);
+    }
+
+    /// Drops everything cached for the installation, e.g. after its permissions changed or it
+    /// was suspended.
+    pub fn forget_installation(&self, installation: InstallationId) {
+        self.revoke(installation);
+        self.accounts.lock().unwrap().remove(&installation);
+    }
+
+    /// Whether the installation was suspended when last heard of, less than [`SUSPENSION_TTL`]
+    /// ago. No token can be minted for it until it is unsuspended.
+    pub fn is_suspended(&self, installation: InstallationId) -> bool {
+        let mut known = self.suspended.lock().unwrap();
+        match known.get(&installation) {
+            Some(since) if since.elapsed() < SUSPENSION_TTL => true,
+            Some(_) => {
+                known.remove(&installation);
+                false
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn set_suspended(&self, installation: InstallationId, suspended: bool) {
+        let mut known = self.suspended.lock().unwrap();
+        match suspended {
+            true => known.insert(installation, Instant::now()),
+            false => known.remove(&installation),
+        };
+    }
+
+    /// Returns a client for the installation which has access to `owner/repo`.
+    ///
+    /// Clients are cached per repository until their installation token would expire, so
+    /// repeated calls don't cause any additional requests to GitHub.
+    pub async fn for_repository(&self, owner: &str, repo: &str) -> Result<C::Api, C::Error> {
+        self.repository_client(owner, repo)
+            .await
+            .map(|(_, client)| client)
+    }
+
+    /// Resolves the installations of many `(owner, repo)` pairs concurrently, keyed by
+    /// `owner/repo`, and caches their clients like [`for_repository`](Self::for_repository).
+    ///
+    /// Repositories the App isn't installed on map to `Ok(None)`, a failed lookup only fails
+    /// the entry of its repository.
+    pub async fn installations_for_repos(
+        &self,
+        repositories: &[(&str, &str)],
+    ) -> HashMap<String, Result<Option<InstallationId>, C::Error>> {
+        stream::iter(repositories)
+            .map(|(owner, repo)| async move {
+                let installation = match self.repository_client(owner, repo).await {
+                    Ok((installation, _)) => Ok(Some(installation)),
+                    Err(err) if C::is_not_installed(&err) => Ok(None),
+                    Err(err) => Err(err),
+                };
+                (format!("{owner}/{repo}"), installation)
+            })
+            .buffer_unordered(INSTALLATION_LOOKUP_CONCURRENCY)
+            .collect()
+            .await
+    }
+
+    async fn repository_client(
+        &self,
+        owner: &str,
+        repo: &str,
+    ) -> Result<(InstallationId, C::Api), C::Error> {
+        let key = format!("{owner}/{repo}");
+        if let Some(cached) = self.repositories.lock().unwrap().get(&key) {
+            if cached.is_live() {
+                return Ok((cached.installation, cached.client.clone()));
+            }
+        }
+        let installation = self.client.repository_installation(owner, repo).await?;
+        let client = self.installation(installation).await?;
+        self.repositories.lock().unwrap().insert(
+            key,
+            CachedInstallation {
+                installation,
+                client: client.clone(),
+                created: Instant::now(),
+                max_age: self.max_token_age,
+            },
+        );
+        Ok((installation, client))
+    }
+
+    /// Lists all repositories the installation can access, the list is cached as long as the
+    /// installation token it was fetched with.
+    pub async fn installation_repositories(
+        &self,
+        installation: InstallationId,
+    ) -> Result<Vec<Repository>, C::Error> {
+        if let Some(cached) = self.accessible.lock().unwrap().get(&installation) {
+            if cached.is_live() {
+                return Ok(cached.repositories.clone());
+            }
+        }
+        let client = self.installation(installation).await?;
+        let repositories = self.client.installation_repositories(&client).await?;
+        self.accessible.lock().unwrap().insert(
+            installation,
+            AccessibleRepositories {
+                repositories: repositories.clone(),
+                fetched: Instant::now(),
+            },
+        );
+        Ok(repositories)
+    }
+
+    /// Returns a client using a token which is restricted to the given repositories (names
+    /// without owner) and permissions, e.g. `("contents", "read")`.
+    ///
+    /// Scoped clients are not cached, every call mints a new token.
+    pub async fn scoped_token(
+        &self,
+        installation: InstallationId,
+        repositories: &[&str],
+        permissions: &[(&str, &str)],
+    ) -> Result<C::Api, C::Error> {
+        let scope = TokenScope {
+            repositories: repositories.iter().map(|repo| repo.to_string()).collect(),
+            permissions: permissions
+                .iter()
+                .map(|(permission, access)| (permission.to_string(), access.to_string()))
+                .collect(),
+        };
+        self.client.scoped_installation(installation, &scope).await
+    }
+
+    /// Drops all cached repository clients of the installation, e.g. because its repository
+    /// selection has changed.
+    pub fn invalidate_repositories(&self, installation: InstallationId) {
+        self.repositories
+            .lock()
+            .unwrap()
+            .retain(|_, cached| cached.installation != installation);
+        self.accessible.lock().unwrap().remove(&installation);
+    }
+
+    /// Resolves the login of the account the installation belongs to.
+    ///
+    /// Full installation objects already carry the account, for minimal ones it's looked up
+    /// via the API once and cached per installation afterwards.
+    pub async fn account_login(
+        &self,
+        installation: &EventInstallation,
+    ) -> Result<String, C::Error> {
+        let id = match installation {
+            EventInstallation::Full(installation) => {
+                let login = installation.account.login.clone();
+                self.accounts
+                    .lock()
+                    .unwrap()
+                    .insert(installation.id, login.clone());
+                return Ok(login);
+            }
+            EventInstallation::Minimal(installation) => installation.id,
+        };
+        if let Some(login) = self.accounts.lock().unwrap().get(&id) {
+            return Ok(login.clone());
+        }
+        let login = self.client.installation_account(id).await?;
+        self.accounts.lock().unwrap().insert(id, login.clone());
+        Ok(login)
+    }
+}
+
+/// The parts of `GET /app` needed to recognise the App, e.g. in events it caused itself.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct AppMetadata {
+    pub slug: String,
+    pub name: String,
+}
+
+impl AppMetadata {
+    /// Login of the bot user acting on behalf of the App.
+    pub fn bot_login(&self) -> String {
+        format!("{}[bot]", self.slug)
+    }
+}
+
+/// GitHub only accepts RS256 signed JWTs to authenticate as an App.
+pub fn app_jwt_header() -> Header {
+    Header {
+        typ: Some("JWT".to_string()),
+        alg: Algorithm::RS256,
+        ..Default::default()
+    }
+}
+
+/// Builds the JWT authenticating as the App, valid for nine minutes. The clock and the `jti`
+/// claim can be fixed so tests can assert the exact claims.
+#[derive(Debug, Clone)]
+pub struct AppJwtBuilder {
+    app_id: AppId,
+    clock: fn() -> SystemTime,
+    jti: Option<String>,
+}
+
+/// The claims of an App JWT, see [`AppJwtBuilder`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AppJwtClaims {
+    pub iat: u64,
+    pub exp: u64,
+    pub iss: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+}
+
+impl AppJwtBuilder {
+    pub fn new(app_id: AppId) -> Self {
+        Self {
+            app_id,
+            clock: SystemTime::now,
+            jti: None,
+        }
+    }
+
+    /// Reads the current time from `clock` instead of the system clock.
+    pub fn clock(self, clock: fn() -> SystemTime) -> Self {
+        Self { clock, ..self }
+    }
+
+    /// Adds a `jti` claim, GitHub doesn't require one.
+    pub fn jti(self, jti: impl Into<String>) -> Self {
+        Self {
+            jti: Some(jti.into()),
+            ..self
+        }
+    }
+
+    pub fn claims(&self) -> AppJwtClaims {
+        let now = (self.clock)()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        AppJwtClaims {
+            // GitHub recommends backdating to allow for clock drift
+            iat: now.saturating_sub(60),
+            exp: now + 9 * 60,
+            iss: self.app_id.0,
+            jti: self.jti.clone(),
+        }
+    }
+
+    pub fn build(&self, app_key: &EncodingKey) -> Result<String, jsonwebtoken::errors::Error> {
+        jsonwebtoken::encode(&app_jwt_header(), &self.claims(), app_key)
+    }
+}
+
+/// Restrictions of an installation token, empty fields inherit everything of the installation.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct TokenScope {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub repositories: Vec<String>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub permissions: HashMap<String, String>,
 }
 
 pub trait GitHubAppAuthenticator {
     type Next: InstallationAuthenticator + Send + Sync;
     type Error: std::error::Error + Sync + Send;
 
+    /// `user_agent` identifies the App on every API call, GitHub rejects calls without one.
+    /// `api_timeout` bounds connecting to the API and each read of a call, if any.
     fn authenticate_app(
         base_uri: Uri,
         app_id: AppId,
         app_key: EncodingKey,
+        user_agent: &str,
+        api_timeout: Option<Duration>,
     ) -> Result<Self::Next, Self::Error>;
 }
 
 pub trait InstallationAuthenticator: Clone + Send + Sync {
+    type Api: GitHubApi + Clone + Sync + 'static;
     type Error: std::error::Error + Send + Sync + Debug + 'static;
+    /// Whether minting a token failed because the installation is suspended.
+    fn is_suspended(_err: &Self::Error) -> bool {
+        false
+    }
     fn for_installation(
         &self,
         id: InstallationId,
-    ) -> impl Future<Output = Result<impl GitHubApi, Self::Error>> + Send;
+    ) -> impl Future<Output = Result<Self::Api, Self::Error>> + Send;
+    /// A client authenticated as the App itself, for events sent without an installation.
+    /// Its authentication may expire within minutes, it is meant for handling one event.
+    fn for_app(&self) -> impl Future<Output = Result<Self::Api, Self::Error>> + Send;
+    fn installation_account(
+        &self,
+        id: InstallationId,
+    ) -> impl Future<Output = Result<String, Self::Error>> + Send;
+    fn repository_installation(
+        &self,
+        owner: &str,
+        repo: &str,
+    ) -> impl Future<Output = Result<InstallationId, Self::Error>> + Send;
+    /// Whether a failed [`repository_installation`](Self::repository_installation) means the
+    /// App isn't installed on the repository.
+    fn is_not_installed(_error: &Self::Error) -> bool {
+        false
+    }
+    fn scoped_installation(
+        &self,
+        id: InstallationId,
+        scope: &TokenScope,
+    ) -> impl Future<Output = Result<Self::Api, Self::Error>> + Send;
+    fn app_metadata(&self) -> impl Future<Output = Result<AppMetadata, Self::Error>> + Send;
+    /// The `installations_count` of the App's record.
+    fn installation_count(&self) -> impl Future<Output = Result<u64, Self::Error>> + Send;
+    /// Pages through `GET /installation/repositories` with the client of the installation.
+    fn installation_repositories(
+        &self,
+        installation: &Self::Api,
+    ) -> impl Future<Output = Result<Vec<Repository>, Self::Error>> + Send;
 }
 
 #[derive(Debug, Snafu)]
 pub enum OctocrabAuthenticationError {
     #[snafu(display("Error whilst creating the authentication: {source}"))]
     Octocrab { source: octocrab::Error },
+    #[snafu(display("The user agent is not a valid header value: {source}"))]
+    UserAgent { source: InvalidHeaderValue },
+}
+
+/// Octocrab client authenticated as the GitHub App.
+#[derive(Clone)]
+pub struct OctocrabApp {
+    /// Sends the calls of the App, each authenticated by a JWT built by `jwt`.
+    client: Octocrab,
+    base_uri: Uri,
+    jwt: AppJwtBuilder,
+    app_key: EncodingKey,
+    user_agent: String,
+    api_timeout: Option<Duration>,
+}
+
+impl Debug for OctocrabApp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OctocrabApp")
+            .field("base_uri", &self.base_uri)
+            .field("jwt", &self.jwt)
+            .finish_non_exhaustive()
+    }
+}
+
+impl OctocrabApp {
+    /// Sends a request authenticated as the App by a freshly built JWT.
+    async fn as_app<B: Serialize + ?Sized, R: FromResponse>(
+        &self,
+        method: Method,
+        route: &str,
+        body: Option<&B>,
+    ) -> Result<R, octocrab::Error> {
+        let jwt = self.jwt.build(&self.app_key).map_err(jwt_error)?;
+        let mut authorization =
+            HeaderValue::try_from(format!("Bearer {jwt}")).expect("JWTs are valid header values");
+        authorization.set_sensitive(true);
+        let request = Request::builder()
+            .method(method)
+            .uri(route)
+            .header(AUTHORIZATION, authorization);
+        let request = self.client.build_request(request, body)?;
+        let response = self.client.execute(request).await?;
+        R::from_response(octocrab::map_github_error(response).await?).await
+    }
+
+    /// A client authenticated by `token`, sent with the App's user agent.
+    fn client(&self, token: &str) -> Result<Octocrab, InvalidHeaderValue> {
+        octocrab_client(
+            self.base_uri.clone(),
+            &self.user_agent,
+            &[],
+            Some(token),
+            self.api_timeout,
+        )
+    }
+}
+
+fn invalid_header_error(source: InvalidHeaderValue) -> octocrab::Error {
+    octocrab::Error::Other {
+        source: Box::new(source),
+        backtrace: GenerateImplicitData::generate(),
+    }
+}
+
+fn jwt_error(source: jsonwebtoken::errors::Error) -> octocrab::Error {
+    octocrab::Error::JWT {
+        source,
+        backtrace: GenerateImplicitData::generate(),
+    }
 }
 
 impl GitHubAppAuthenticator for Octocrab {
-    type Next = Octocrab;
+    type Next = OctocrabApp;
     type Error = OctocrabAuthenticationError;
 
     fn authenticate_app(
         base_uri: Uri,
         app_id: AppId,
         app_key: EncodingKey,
+        user_agent: &str,
+        api_timeout: Option<Duration>,
     ) -> Result<Self::Next, Self::Error> {
-        Octocrab::builder()
-            .base_uri(base_uri)
-            .context(OctocrabSnafu)?
-            .app(app_id, app_key)
-            .build()
-            .context(OctocrabSnafu)
+        let client = octocrab_client(base_uri.clone(), user_agent, &[], None, api_timeout)
+            .context(UserAgentSnafu)?;
+        Ok(OctocrabApp {
+            client,
+            base_uri,
+            jwt: AppJwtBuilder::new(app_id),
+            app_key,
+            user_agent: user_agent.to_string(),
+            api_timeout,
+        })
     }
 }
 
-impl InstallationAuthenticator for Octocrab {
+impl InstallationAuthenticator for OctocrabApp {
+    type Api = Octocrab;
     type Error = octocrab::Error;
-    async fn for_installation(&self, id: InstallationId) -> Result<impl GitHubApi, Self::Error> {
-        self.installation_and_token(id).await.map(|r| r.0)
+
+    fn is_suspended(err: &Self::Error) -> bool {
+        // GitHub answers "This installation has been suspended"
+        matches!(err, octocrab::Error::GitHub { source, .. }
+            if source.status_code == StatusCode::FORBIDDEN && source.message.contains("suspended"))
+    }
+    async fn for_installation(&self, id: InstallationId) -> Result<Self::Api, Self::Error> {
+        self.scoped_installation(id, &TokenScope::default()).await
+    }
+
+    async fn for_app(&self) -> Result<Self::Api, Self::Error> {
+        let jwt = self.jwt.build(&self.app_key).map_err(jwt_error)?;
+        self.client(&jwt).map_err(invalid_header_error)
+    }
+
+    async fn installation_account(&self, id: InstallationId) -> Result<String, Self::Error> {
+        self.as_app(
+            Method::GET,
+            &format!("/app/installations/{id}"),
+            None::<&()>,
+        )
+        .await
+        .map(|installation: Installation| installation.account.login)
+    }
+
+    async fn repository_installation(
+        &self,
+        owner: &str,
+        repo: &str,
+    ) -> Result<InstallationId, Self::Error> {
+        self.as_app(
+            Method::GET,
+            &format!("/repos/{owner}/{repo}/installation"),
+            None::<&()>,
+        )
+        .await
+        .map(|installation: Installation| installation.id)
+    }
+
+    fn is_not_installed(error: &Self::Error) -> bool {
+        matches!(error, octocrab::Error::GitHub { source, .. } if source.status_code == StatusCode::NOT_FOUND)
+    }
+
+    async fn scoped_installation(
+        &self,
+        id: InstallationId,
+        scope: &TokenScope,
+    ) -> Result<Self::Api, Self::Error> {
+        let token: InstallationToken = self
+            .as_app(
+                Method::POST,
+                &format!("/app/installations/{id}/access_tokens"),
+                Some(scope),
+            )
+            .await?;
+        self.client(&token.token).map_err(invalid_header_error)
+    }
+
+    async fn app_metadata(&self) -> Result<AppMetadata, Self::Error> {
+        self.as_app(Method::GET, "/app", None::<&()>).await
+    }
+
+    async fn installation_count(&self) -> Result<u64, Self::Error> {
+        #[derive(Deserialize)]
+        struct App {
+            installations_count: u64,
+        }
+
+        let app: App = self.as_app(Method::GET, "/app", None::<&()>).await?;
+        Ok(app.installations_count)
+    }
+
+    async fn installation_repositories(
+        &self,
+        installation: &Self::Api,
+    ) -> Result<Vec<Repository>, Self::Error> {
+        #[derive(Serialize)]
+        struct Params {
+            per_page: u8,
+            page: u32,
+        }
+
+        let mut repositories = Vec::new();
+        for page in 1.. {
+            let listed: InstallationRepositories = installation
+                .get(
+                    "/installation/repositories",
+                    Some(&Params {
+                        per_page: 100,
+                        page,
+                    }),
+                )
+                .await?;
+            let empty = listed.repositories.is_empty();
+            repositories.extend(listed.repositories);
+            if empty || repositories.len() as i64 >= listed.total_count {
+                break;
+            }
+        }
+        Ok(repositories)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        AppMetadata, AuthenticatedClient, GitHubAppAuthenticator, InstallationAuthenticator,
+        OctocrabApp, TokenScope, SUSPENSION_TTL,
+    };
+
+    use crate::context::EventContext;
+    use crate::handle::{handle_event, HandleOptions};
+    use crate::handler::Handlers;
+    use crate::mock::NoOpApi;
+
+    use metrics_exporter_prometheus::PrometheusBuilder;
+    use octocrab::models::webhook_events::WebhookEvent;
+    use octocrab::models::{InstallationId, Repository};
+    use serde_json::json;
+    use std::convert::Infallible;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[derive(Clone, Default)]
+    struct CountingClient {
+        lookups: Arc<AtomicUsize>,
+        repository_lookups: Arc<AtomicUsize>,
+        mints: Arc<AtomicUsize>,
+        listings: Arc<AtomicUsize>,
+    }
+
+    impl InstallationAuthenticator for CountingClient {
+        type Api = NoOpApi;
+        type Error = Infallible;
+
+        async fn for_installation(&self, _id: InstallationId) -> Result<Self::Api, Self::Error> {
+            self.mints.fetch_add(1, Ordering::SeqCst);
+            // lets concurrent callers catch up whilst the token is minted
+            tokio::task::yield_now().await;
+            Ok(NoOpApi)
+        }
+
+        async fn for_app(&self) -> Result<Self::Api, Self::Error> {
+            Ok(NoOpApi)
+        }
+
+        async fn installation_account(&self, _id: InstallationId) -> Result<String, Self::Error> {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            Ok("acme".to_string())
+        }
+
+        async fn repository_installation(
+            &self,
+            _owner: &str,
+            _repo: &str,
+        ) -> Result<InstallationId, Self::Error> {
+            self.repository_lookups.fetch_add(1, Ordering::SeqCst);
+            Ok(InstallationId(1))
+        }
+
+        async fn scoped_installation(
+            &self,
+            _id: InstallationId,
+            _scope: &TokenScope,
+        ) -> Result<Self::Api, Self::Error> {
+            Ok(NoOpApi)
+        }
+
+        async fn app_metadata(&self) -> Result<AppMetadata, Self::Error> {
+            Ok(AppMetadata {
+                slug: "wild-git-yonder".into(),
+                name: "Wild Git Yonder".into(),
+            })
+        }
+
+        async fn installation_count(&self) -> Result<u64, Self::Error> {
+            Ok(1)
+        }
+
+        async fn installation_repositories(
+            &self,
+            _installation: &Self::Api,
+        ) -> Result<Vec<Repository>, Self::Error> {
+            self.listings.fetch_add(1, Ordering::SeqCst);
+            Ok(vec![
+                serde_json::from_value(repository_json(1, "anvil")).unwrap()
+            ])
+        }
+    }
+
+    /// The App authenticated against a mock of the API at `base_uri`.
+    fn app_at(base_uri: hyper::Uri) -> OctocrabApp {
+        let key = include_bytes!("../testdata/app_key.pem");
+        octocrab::Octocrab::authenticate_app(
+            base_uri,
+            octocrab::models::AppId(1),
+            jsonwebtoken::EncodingKey::from_rsa_pem(key).unwrap(),
+            "wild-git-yonder-test",
+            None,
+        )
+        .unwrap()
+    }
+
+    fn repository_json(id: u64, name: &str) -> serde_json::Value {
+        json!({
+            "id": id,
+            "name": name,
+            "url": format!("https://api.github.local/repos/acme/{name}")
+        })
+    }
+
+    #[tokio::test]
+    async fn test_account_login_is_cached_per_installation() {
+        let client = CountingClient::default();
+        let lookups = client.lookups.clone();
+        let client = AuthenticatedClient::new(client);
+        let body = json!({
+            "installation": { "id": 1, "node_id": "dGVzdA==" },
+            "zen": "Keep it logically awesome."
+        });
+        let event = WebhookEvent::try_from_header_and_body("ping", &body.to_string()).unwrap();
+        let installation = event.installation.as_ref().unwrap();
+
+        let login = client.account_login(installation).await.unwrap();
+        assert_eq!(client.account_login(installation).await.unwrap(), "acme");
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+
+        let ctx = EventContext::new(event, Some(login), NoOpApi);
+        assert_eq!(ctx.account_login(), Some("acme"));
+    }
+
+    #[tokio::test]
+    async fn test_revoked_installation_token_is_minted_again() {
+        let client = CountingClient::default();
+        let mints = client.mints.clone();
+        let client = AuthenticatedClient::new(client);
+
+        client.installation(InstallationId(1)).await.unwrap();
+        client.installation(InstallationId(1)).await.unwrap();
+        client.installation(InstallationId(2)).await.unwrap();
+        assert_eq!(mints.load(Ordering::SeqCst), 2);
+        assert_eq!(
+            client.cached_installations(),
+            [InstallationId(1), InstallationId(2)]
+        );
+
+        client.revoke(InstallationId(1));
+        assert_eq!(client.cached_installations(), [InstallationId(2)]);
+        client.installation(InstallationId(1)).await.unwrap();
+        assert_eq!(mints.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_installation_token_is_minted_once_for_concurrent_callers() {
+        let client = CountingClient::default();
+        let mints = client.mints.clone();
+        let client = AuthenticatedClient::new(client);
+        client.installation(InstallationId(1)).await.unwrap();
+        // leaves the installation without a live token, like an expired one
+        client.revoke(InstallationId(1));
+
+        let calls = (0..16).map(|_| client.installation(InstallationId(1)));
+        let clients = futures_util::future::join_all(calls).await;
+
+        assert!(clients.iter().all(Result::is_ok));
+        assert_eq!(mints.load(Ordering::SeqCst), 2);
+        assert_eq!(client.cached_installations(), [InstallationId(1)]);
+        // landed mints don't keep the installation around
+        assert_eq!(client.minting.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_token_cache_hits_and_misses_are_counted() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+        let _guard = metrics::set_default_local_recorder(&recorder);
+        let client = AuthenticatedClient::new(CountingClient::default());
+        let counted = |name: &str| {
+            handle
+                .render()
+                .lines()
+                .find_map(|line| line.strip_prefix(name)?.trim().parse::<u64>().ok())
+        };
+
+        client.installation(InstallationId(1)).await.unwrap();
+        assert_eq!(counted("github_token_cache_misses_total"), Some(1));
+        assert_eq!(counted("github_token_cache_hits_total"), None);
+
+        client.installation(InstallationId(1)).await.unwrap();
+        assert_eq!(counted("github_token_cache_hits_total"), Some(1));
+
+        // an expired token is minted again, just like a revoked one
+        client.revoke(InstallationId(1));
+        client.installation(InstallationId(1)).await.unwrap();
+        assert_eq!(counted("github_token_cache_misses_total"), Some(2));
+        assert_eq!(counted("github_token_cache_hits_total"), Some(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_token_older_than_the_max_age_is_minted_again() {
+        let client = CountingClient::default();
+        let mints = client.mints.clone();
+        let client =
+            AuthenticatedClient::new(client).with_max_token_age(Duration::from_secs(30 * 60));
+
+        client.installation(InstallationId(1)).await.unwrap();
+        tokio::time::advance(Duration::from_secs(29 * 60)).await;
+        client.installation(InstallationId(1)).await.unwrap();
+        assert_eq!(mints.load(Ordering::SeqCst), 1);
+
+        // still valid for almost half an hour, but older than allowed
+        tokio::time::advance(Duration::from_secs(2 * 60)).await;
+        assert!(client.cached_installations().is_empty());
+        client.installation(InstallationId(1)).await.unwrap();
+        assert_eq!(mints.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_least_recently_used_installation_is_evicted() {
+        let client = CountingClient::default();
+        let mints = client.mints.clone();
+        let client = AuthenticatedClient::with_cache_capacity(client, 2);
+
+        client.installation(InstallationId(1)).await.unwrap();
+        client.installation(InstallationId(2)).await.unwrap();
+        client.installation(InstallationId(1)).await.unwrap();
+        client.installation(InstallationId(3)).await.unwrap();
+
+        assert_eq!(
+            client.cached_installations(),
+            [InstallationId(1), InstallationId(3)]
+        );
+        assert_eq!(mints.load(Ordering::SeqCst), 3);
+        client.installation(InstallationId(2)).await.unwrap();
+        assert_eq!(mints.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn test_suspended_installation_token_is_dropped() {
+        let client = CountingClient::default();
+        let mints = client.mints.clone();
+        let client = AuthenticatedClient::new(client);
+        client.installation(InstallationId(1)).await.unwrap();
+        client.for_repository("acme", "anvil").await.unwrap();
+
+        let body = json!({
+            "action": "suspend",
+            "installation": { "id": 1, "node_id": "dGVzdA==" },
+            "requester": null
+        });
+        let event =
+            WebhookEvent::try_from_header_and_body("installation", &body.to_string()).unwrap();
+        handle_event(
+            client.clone(),
+            &Handlers::default(),
+            &HandleOptions::default(),
+            event,
+            Default::default(),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+
+        assert!(client.cached_installations().is_empty());
+        assert_eq!(mints.load(Ordering::SeqCst), 1);
+        client.for_repository("acme", "anvil").await.unwrap();
+        assert_eq!(mints.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_installation_event_with_a_disallowed_action_still_invalidates_the_cache() {
+        use crate::handle::{Delivery, OutcomeStatus};
+        use std::collections::{HashMap, HashSet};
+
+        let client = AuthenticatedClient::new(CountingClient::default());
+        client.installation(InstallationId(1)).await.unwrap();
+        let options = HandleOptions {
+            allowed_actions: Some(HashMap::from([(
+                "installation".to_string(),
+                HashSet::from(["created".to_string()]),
+            )])),
+            ..Default::default()
+        };
+
+        let body = json!({
+            "action": "new_permissions_accepted",
+            "installation": { "id": 1, "node_id": "dGVzdA==" },
+            "requester": null
+        })
+        .to_string();
+        let event = WebhookEvent::try_from_header_and_body("installation", &body).unwrap();
+        let delivery = Delivery {
+            body: Some(body.into()),
+            ..Default::default()
+        };
+        let handled = handle_event(
+            client.clone(),
+            &Handlers::default(),
+            &options,
+            event,
+            delivery,
+            Default::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(handled.status, OutcomeStatus::Skipped);
+        assert!(client.cached_installations().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_suspended_installation_is_acknowledged_when_configured() {
+        use crate::handle::{HandleEventError, OutcomeStatus};
+        use axum::{http::StatusCode, routing::post, Json, Router};
+        use jsonwebtoken::EncodingKey;
+        use octocrab::models::AppId;
+
+        let mints = Arc::new(AtomicUsize::new(0));
+        let counted = mints.clone();
+        let mock = Router::new().route(
+            "/app/installations/1/access_tokens",
+            post(move || async move {
+                counted.fetch_add(1, Ordering::SeqCst);
+                let message = json!({ "message": "This installation has been suspended" });
+                (StatusCode::FORBIDDEN, Json(message))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await });
+        let app = octocrab::Octocrab::authenticate_app(
+            format!("http://{addr}").parse().unwrap(),
+            AppId(1),
+            EncodingKey::from_rsa_pem(include_bytes!("../testdata/app_key.pem")).unwrap(),
+            "wild-git-yonder",
+            None,
+        )
+        .unwrap();
+        let client = AuthenticatedClient::new(app);
+        let event = || {
+            let body = json!({
+                "installation": { "id": 1, "node_id": "dGVzdA==" },
+                "zen": "Keep it logically awesome."
+            });
+            WebhookEvent::try_from_header_and_body("ping", &body.to_string()).unwrap()
+        };
+        let handle = |options: HandleOptions| {
+            let client = client.clone();
+            async move {
+                handle_event(
+                    client,
+                    &Handlers::default(),
+                    &options,
+                    event(),
+                    Default::default(),
+                    Default::default(),
+                )
+                .await
+            }
+        };
+
+        let failed = handle(HandleOptions::default()).await;
+        assert!(matches!(
+            failed,
+            Err(HandleEventError::InstallationAuthentication { .. })
+        ));
+        assert!(client.is_suspended(InstallationId(1)));
+
+        let acknowledge = || HandleOptions {
+            acknowledge_suspended_installations: true,
+            ..Default::default()
+        };
+        let outcome = handle(acknowledge()).await.unwrap();
+        assert_eq!(outcome.status, OutcomeStatus::Skipped);
+        // the cached suspension spares minting another token
+        assert_eq!(mints.load(Ordering::SeqCst), 1);
+
+        // until it expired, without an unsuspend event
+        tokio::time::pause();
+        tokio::time::advance(SUSPENSION_TTL).await;
+        tokio::time::resume();
+        assert!(!client.is_suspended(InstallationId(1)));
+        let outcome = handle(acknowledge()).await.unwrap();
+        assert_eq!(outcome.status, OutcomeStatus::Skipped);
+        assert_eq!(mints.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_repository_client_is_cached() {
+        let client = CountingClient::default();
+        let lookups = client.repository_lookups.clone();
+        let client = AuthenticatedClient::new(client);
+
+        client.for_repository("acme", "anvil").await.unwrap();
+        client.for_repository("acme", "anvil").await.unwrap();
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+
+        client.for_repository("acme", "rocket").await.unwrap();
+        assert_eq!(lookups.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_repository_client_is_invalidated_on_removed_repository() {
+        let client = CountingClient::default();
+        let lookups = client.repository_lookups.clone();
+        let client = AuthenticatedClient::new(client);
+        client.for_repository("acme", "anvil").await.unwrap();
+
+        let body = json!({
+            "action": "removed",
+            "installation": { "id": 1, "node_id": "dGVzdA==" },
+            "repository_selection": "selected",
+            "repositories_added": [],
+            "repositories_removed": [{
+                "id": 1,
+                "node_id": "dGVzdA==",
+                "name": "anvil",
+                "full_name": "acme/anvil",
+                "private": false
+            }],
+            "requester": null
+        });
+        let event =
+            WebhookEvent::try_from_header_and_body("installation_repositories", &body.to_string())
+                .unwrap();
+        handle_event(
+            client.clone(),
+            &Handlers::default(),
+            &HandleOptions::default(),
+            event,
+            Default::default(),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+
+        client.for_repository("acme", "anvil").await.unwrap();
+        assert_eq!(lookups.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_scoped_token_requests_repositories_and_permissions() {
+        use axum::{extract::State, routing::post, Json, Router};
+        use jsonwebtoken::EncodingKey;
+        use octocrab::models::AppId;
+        use std::sync::Mutex;
+
+        async fn access_tokens(
+            State(requests): State<Arc<Mutex<Vec<serde_json::Value>>>>,
+            Json(body): Json<serde_json::Value>,
+        ) -> Json<serde_json::Value> {
+            requests.lock().unwrap().push(body);
+            Json(json!({ "token": "ghs_scoped", "permissions": { "contents": "read" } }))
+        }
+
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let mock = Router::new()
+            .route("/app/installations/{id}/access_tokens", post(access_tokens))
+            .with_state(requests.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await });
+
+        // signing requires RSA keys of at least 2048 bits, which are too slow to generate here
+        let pem = include_str!("../testdata/app_key.pem");
+        let app = octocrab::Octocrab::authenticate_app(
+            format!("http://{addr}").parse().unwrap(),
+            AppId(1),
+            EncodingKey::from_rsa_pem(pem.as_bytes()).unwrap(),
+            "wild-git-yonder-test",
+            None,
+        )
+        .unwrap();
+        let client = AuthenticatedClient::new(app);
+
+        client
+            .scoped_token(InstallationId(7), &["anvil"], &[("contents", "read")])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![json!({
+                "repositories": ["anvil"],
+                "permissions": { "contents": "read" }
+            })]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_installations_are_resolved_for_many_repositories() {
+        use axum::{extract::Path, http::StatusCode, response::IntoResponse, routing::get};
+        use axum::{routing::post, Json, Router};
+        use jsonwebtoken::EncodingKey;
+        use octocrab::models::AppId;
+
+        async fn installation(Path((_, repo)): Path<(String, String)>) -> impl IntoResponse {
+            let url = "https://api.github.local/users/acme";
+            let id = match repo.as_str() {
+                "anvil" => 1,
+                "rocket" => 2,
+                _ => {
+                    let body = json!({ "message": "Not Found", "documentation_url": url });
+                    return (StatusCode::NOT_FOUND, Json(body)).into_response();
+                }
+            };
+            Json(json!({
+                "id": id,
+                "account": {
+                    "login": "acme", "id": 1, "node_id": "dGVzdA==", "gravatar_id": "",
+                    "avatar_url": url, "url": url, "html_url": url, "followers_url": url,
+                    "following_url": url, "gists_url": url, "starred_url": url,
+                    "subscriptions_url": url, "organizations_url": url, "repos_url": url,
+                    "events_url": url, "received_events_url": url,
+                    "type": "Organization", "site_admin": false
+                },
+                "permissions": {},
+                "events": []
+            }))
+            .into_response()
+        }
+
+        let mock = Router::new()
+            .route("/repos/{owner}/{repo}/installation", get(installation))
+            .route(
+                "/app/installations/{id}/access_tokens",
+                post(|| async { Json(json!({ "token": "ghs_repo", "permissions": {} })) }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await });
+        let app = octocrab::Octocrab::authenticate_app(
+            format!("http://{addr}").parse().unwrap(),
+            AppId(1),
+            EncodingKey::from_rsa_pem(include_bytes!("../testdata/app_key.pem")).unwrap(),
+            "wild-git-yonder-test",
+            None,
+        )
+        .unwrap();
+        let client = AuthenticatedClient::new(app);
+
+        let installations = client
+            .installations_for_repos(&[("acme", "anvil"), ("acme", "rocket"), ("acme", "coyote")])
+            .await;
+
+        assert_eq!(installations.len(), 3);
+        assert_eq!(
+            installations["acme/anvil"].as_ref().unwrap(),
+            &Some(InstallationId(1))
+        );
+        assert_eq!(
+            installations["acme/rocket"].as_ref().unwrap(),
+            &Some(InstallationId(2))
+        );
+        assert_eq!(installations["acme/coyote"].as_ref().unwrap(), &None);
+        assert_eq!(
+            client.cached_installations(),
+            [InstallationId(1), InstallationId(2)]
+        );
+    }
+
+    #[test]
+    fn test_app_jwt_declares_rs256() {
+        let key =
+            jsonwebtoken::EncodingKey::from_rsa_pem(include_bytes!("../testdata/app_key.pem"))
+                .unwrap();
+        let jwt = super::AppJwtBuilder::new(octocrab::models::AppId(1))
+            .build(&key)
+            .unwrap();
+
+        let header = jsonwebtoken::decode_header(&jwt).unwrap();
+        assert_eq!(header.alg, jsonwebtoken::Algorithm::RS256);
+        assert_eq!(header.typ.as_deref(), Some("JWT"));
+    }
+
+    #[test]
+    fn test_app_jwt_claims_follow_the_clock() {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let key =
+            jsonwebtoken::EncodingKey::from_rsa_pem(include_bytes!("../testdata/app_key.pem"))
+                .unwrap();
+        let jwt = super::AppJwtBuilder::new(octocrab::models::AppId(42))
+            .clock(|| UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+            .jti("fixed")
+            .build(&key)
+            .unwrap();
+
+        let mut validation = jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::RS256);
+        validation.insecure_disable_signature_validation();
+        validation.validate_exp = false;
+        let claims = jsonwebtoken::decode::<super::AppJwtClaims>(
+            &jwt,
+            &jsonwebtoken::DecodingKey::from_secret(&[]),
+            &validation,
+        )
+        .unwrap()
+        .claims;
+        assert_eq!(
+            claims,
+            super::AppJwtClaims {
+                iat: 1_699_999_940,
+                exp: 1_700_000_540,
+                iss: 42,
+                jti: Some("fixed".to_string()),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_configured_user_agent_is_sent() {
+        use axum::http::{header::USER_AGENT, HeaderMap, StatusCode};
+        use axum::{extract::State, response::IntoResponse, Json, Router};
+        use jsonwebtoken::EncodingKey;
+        use octocrab::models::AppId;
+        use std::sync::Mutex;
+
+        async fn record(
+            State(agents): State<Arc<Mutex<Vec<Vec<String>>>>>,
+            headers: HeaderMap,
+        ) -> impl IntoResponse {
+            agents.lock().unwrap().push(
+                headers
+                    .get_all(USER_AGENT)
+                    .iter()
+                    .map(|agent| agent.to_str().unwrap().to_string())
+                    .collect(),
+            );
+            let token = json!({ "token": "ghs_scoped", "permissions": {} });
+            (StatusCode::CREATED, Json(token))
+        }
+
+        let agents = Arc::new(Mutex::new(Vec::new()));
+        let mock = Router::new().fallback(record).with_state(agents.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await });
+
+        let app = octocrab::Octocrab::authenticate_app(
+            format!("http://{addr}").parse().unwrap(),
+            AppId(1),
+            EncodingKey::from_rsa_pem(include_bytes!("../testdata/app_key.pem")).unwrap(),
+            "wild-git-yonder/1.2.3",
+            None,
+        )
+        .unwrap();
+        let scoped = app
+            .scoped_installation(InstallationId(1), &TokenScope::default())
+            .await
+            .unwrap();
+        let _ = scoped._get("/installation/repositories").await.unwrap();
+
+        let agents = agents.lock().unwrap();
+        assert_eq!(agents.len(), 2);
+        for agents in agents.iter() {
+            assert_eq!(agents, &["wild-git-yonder/1.2.3"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_app_metadata_is_fetched_once() {
+        use axum::{routing::get, Json, Router};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let mock = Router::new().route(
+            "/app",
+            get(move || async move {
+                counted.fetch_add(1, Ordering::SeqCst);
+                Json(json!({ "id": 1, "slug": "wild-git-yonder", "name": "Wild Git Yonder" }))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await });
+        let base_uri: hyper::Uri = format!("http://{addr}").parse().unwrap();
+        let client = AuthenticatedClient::new(app_at(base_uri));
+
+        let app = client.app_metadata().await.unwrap();
+        client.clone().app_metadata().await.unwrap();
+
+        assert_eq!(app.slug, "wild-git-yonder");
+        assert_eq!(app.bot_login(), "wild-git-yonder[bot]");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_app_calls_carry_the_app_jwt() {
+        use axum::{http::HeaderMap, routing::get, Json, Router};
+        use std::sync::Mutex;
+
+        let authorizations = Arc::new(Mutex::new(Vec::new()));
+        let recorded = authorizations.clone();
+        let mock = Router::new().route(
+            "/app",
+            get(move |headers: HeaderMap| async move {
+                let authorization = headers["authorization"].to_str().unwrap().to_owned();
+                recorded.lock().unwrap().push(authorization);
+                Json(json!({ "id": 1, "slug": "wild-git-yonder", "name": "Wild Git Yonder" }))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await });
+
+        app_at(format!("http://{addr}").parse().unwrap())
+            .app_metadata()
+            .await
+            .unwrap();
+
+        let authorizations = authorizations.lock().unwrap();
+        let jwt = authorizations[0].strip_prefix("Bearer ").unwrap();
+        let header = jsonwebtoken::decode_header(jwt).unwrap();
+        assert_eq!(header.alg, jsonwebtoken::Algorithm::RS256);
+        let mut validation = jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::RS256);
+        validation.insecure_disable_signature_validation();
+        let claims = jsonwebtoken::decode::<super::AppJwtClaims>(
+            jwt,
+            &jsonwebtoken::DecodingKey::from_secret(&[]),
+            &validation,
+        )
+        .unwrap()
+        .claims;
+        assert_eq!(claims.iss, 1);
+        assert_eq!(claims.exp - claims.iat, 10 * 60);
+    }
+
+    #[tokio::test]
+    async fn test_installation_count_is_cached() {
+        use axum::{routing::get, Json, Router};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let mock = Router::new().route(
+            "/app",
+            get(move || async move {
+                counted.fetch_add(1, Ordering::SeqCst);
+                Json(json!({ "id": 1, "slug": "wild-git-yonder", "installations_count": 42 }))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await });
+        let base_uri: hyper::Uri = format!("http://{addr}").parse().unwrap();
+        let client = AuthenticatedClient::new(app_at(base_uri));
+
+        assert_eq!(client.installation_count().await.unwrap(), 42);
+        assert_eq!(client.clone().installation_count().await.unwrap(), 42);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_installation_repositories_are_paged_through() {
+        use axum::{extract::Query, routing::get, Json, Router};
+        use std::collections::HashMap;
+
+        let mock = Router::new().route(
+            "/installation/repositories",
+            get(|Query(query): Query<HashMap<String, String>>| async move {
+                let repositories = match query.get("page").map(String::as_str) {
+                    Some("1") => vec![repository_json(1, "anvil"), repository_json(2, "rocket")],
+                    Some("2") => vec![repository_json(3, "magnet")],
+                    _ => vec![],
+                };
+                Json(json!({ "total_count": 3, "repositories": repositories }))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await });
+        let base_uri: hyper::Uri = format!("http://{addr}").parse().unwrap();
+        let installation = octocrab::Octocrab::builder()
+            .base_uri(base_uri.clone())
+            .unwrap()
+            .build()
+            .unwrap();
+        let app = app_at(base_uri);
+
+        let repositories = app.installation_repositories(&installation).await.unwrap();
+
+        assert_eq!(
+            repositories
+                .iter()
+                .map(|repository| repository.name.as_str())
+                .collect::<Vec<_>>(),
+            ["anvil", "rocket", "magnet"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_repository_selection_is_only_listed_to_reject_uncovered_repositories() {
+        let client = CountingClient::default();
+        let listings = client.listings.clone();
+        let client = AuthenticatedClient::new(client);
+        let handle = |reject_uncovered_repositories| {
+            let client = client.clone();
+            let body = json!({
+                "zen": "Keep it logically awesome.",
+                "repository": repository_json(1, "anvil"),
+                "installation": { "id": 1, "node_id": "dGVzdA==" }
+            });
+            let event = WebhookEvent::try_from_header_and_body("ping", &body.to_string()).unwrap();
+            async move {
+                let options = HandleOptions {
+                    reject_uncovered_repositories,
+                    ..Default::default()
+                };
+                handle_event(
+                    client,
+                    &Handlers::default(),
+                    &options,
+                    event,
+                    Default::default(),
+                    Default::default(),
+                )
+                .await
+                .unwrap()
+            }
+        };
+
+        handle(false).await;
+        assert_eq!(listings.load(Ordering::SeqCst), 0);
+
+        handle(true).await;
+        assert_eq!(listings.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_installation_repositories_are_cached_with_the_token() {
+        let client = CountingClient::default();
+        let (listings, mints) = (client.listings.clone(), client.mints.clone());
+        let client = AuthenticatedClient::new(client);
+
+        let repositories = client
+            .installation_repositories(InstallationId(1))
+            .await
+            .unwrap();
+        client
+            .installation_repositories(InstallationId(1))
+            .await
+            .unwrap();
+        assert_eq!(repositories[0].name, "anvil");
+        assert_eq!(listings.load(Ordering::SeqCst), 1);
+        assert_eq!(mints.load(Ordering::SeqCst), 1);
+
+        client.invalidate_repositories(InstallationId(1));
+        client
+            .installation_repositories(InstallationId(1))
+            .await
+            .unwrap();
+        assert_eq!(listings.load(Ordering::SeqCst), 2);
     }
 }
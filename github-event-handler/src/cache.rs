@@ -0,0 +1,114 @@
+use lru::LruCache;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OwnedMutexGuard;
+
+/// A map of at most `capacity` entries, inserting into a full map evicts the least recently
+/// used entry.
+pub(crate) struct LruMap<K, V> {
+    entries: LruCache<K, V>,
+}
+
+impl<K: Eq + Hash + Clone, V> LruMap<K, V> {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            entries: LruCache::new(NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN)),
+        }
+    }
+
+    /// Returns the entry and marks it as the most recently used one.
+    pub(crate) fn get(&mut self, key: &K) -> Option<&V> {
+        self.entries.get(key)
+    }
+
+    /// Inserts the entry as the most recently used one. Expired entries aren't used anymore,
+    /// they are the first to be evicted unless they are replaced before.
+    pub(crate) fn insert(&mut self, key: K, value: V) {
+        self.entries.put(key, value);
+    }
+
+    pub(crate) fn remove(&mut self, key: &K) -> Option<V> {
+        self.entries.pop(key)
+    }
+
+    pub(crate) fn retain(&mut self, mut keep: impl FnMut(&K, &V) -> bool) {
+        let dropped = self
+            .entries
+            .iter()
+            .filter(|(key, value)| !keep(key, value))
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        for key in dropped {
+            self.entries.pop(&key);
+        }
+    }
+
+    pub(crate) fn values(&self) -> impl Iterator<Item = &V> {
+        self.entries.iter().map(|(_, value)| value)
+    }
+}
+
+/// Runs one flight per key at a time, e.g. minting a token, concurrent callers wait for it.
+/// A key is forgotten once its last flight landed.
+pub(crate) struct Flights<K> {
+    flights: Arc<Mutex<HashMap<K, Arc<tokio::sync::Mutex<()>>>>>,
+}
+
+impl<K> Clone for Flights<K> {
+    fn clone(&self) -> Self {
+        Self {
+            flights: self.flights.clone(),
+        }
+    }
+}
+
+impl<K> Default for Flights<K> {
+    fn default() -> Self {
+        Self {
+            flights: Default::default(),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone> Flights<K> {
+    /// Waits for the flights of `key` before, the returned guard holds off the following ones.
+    pub(crate) async fn board(&self, key: K) -> Flight<K> {
+        let flight = self
+            .flights
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .clone();
+        Flight {
+            _boarded: flight.clone().lock_owned().await,
+            flight,
+            key,
+            flights: self.flights.clone(),
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.flights.lock().unwrap().len()
+    }
+}
+
+pub(crate) struct Flight<K: Eq + Hash> {
+    _boarded: OwnedMutexGuard<()>,
+    flight: Arc<tokio::sync::Mutex<()>>,
+    key: K,
+    flights: Arc<Mutex<HashMap<K, Arc<tokio::sync::Mutex<()>>>>>,
+}
+
+impl<K: Eq + Hash> Drop for Flight<K> {
+    fn drop(&mut self) {
+        let mut flights = self.flights.lock().unwrap();
+        // held by the map, this flight and its guard only, nobody else waits for the key
+        if Arc::strong_count(&self.flight) == 3 {
+            flights.remove(&self.key);
+        }
+    }
+}
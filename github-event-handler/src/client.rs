@@ -0,0 +1,123 @@
+use hyper::http::header::{HeaderName, HeaderValue, InvalidHeaderValue, USER_AGENT};
+use hyper::Uri;
+use hyper_timeout::TimeoutConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use octocrab::service::middleware::{
+    auth_header::AuthHeaderLayer, base_uri::BaseUriLayer, extra_headers::ExtraHeadersLayer,
+};
+use octocrab::{AuthState, Octocrab, OctocrabBuilder};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Builds an Octocrab client for `base_uri` which identifies itself as `user_agent` only,
+/// octocrab's default builder sends its own `User-Agent` next to any configured one.
+///
+/// `headers` are added to every request, `token` authenticates the requests as a bearer.
+/// `timeout` bounds connecting as well as each read and write of a call, if any.
+pub(crate) fn octocrab_client(
+    base_uri: Uri,
+    user_agent: &str,
+    headers: &[(HeaderName, &str)],
+    token: Option<&str>,
+    timeout: Option<Duration>,
+) -> Result<Octocrab, InvalidHeaderValue> {
+    let mut extra_headers = vec![(USER_AGENT, HeaderValue::try_from(user_agent)?)];
+    for (name, value) in headers {
+        extra_headers.push((name.clone(), HeaderValue::try_from(*value)?));
+    }
+    let authorization = token
+        .map(|token| {
+            let mut authorization = HeaderValue::try_from(format!("Bearer {token}"))?;
+            authorization.set_sensitive(true);
+            Ok(authorization)
+        })
+        .transpose()?;
+    let connector = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_webpki_roots()
+        // the API of a GitHub Enterprise Server or a mock of it might be served without TLS
+        .https_or_http()
+        .enable_http1()
+        .build();
+    let mut connector = TimeoutConnector::new(connector);
+    connector.set_connect_timeout(timeout);
+    connector.set_read_timeout(timeout);
+    connector.set_write_timeout(timeout);
+    let client = Client::builder(TokioExecutor::new()).build(connector);
+    let client = OctocrabBuilder::new_empty()
+        .with_service(client)
+        .with_layer(&ExtraHeadersLayer::new(Arc::new(extra_headers)))
+        .with_layer(&BaseUriLayer::new(base_uri.clone()))
+        .with_layer(&AuthHeaderLayer::new(
+            authorization,
+            base_uri.clone(),
+            base_uri,
+        ))
+        .with_auth(AuthState::None)
+        .build();
+    // building the custom stack can't fail
+    Ok(client.unwrap_or_else(|never| match never {}))
+}
+
+#[cfg(test)]
+mod test {
+    use super::octocrab_client;
+    use axum::http::{header::USER_AGENT, HeaderMap, StatusCode};
+    use axum::{extract::State, Router};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_client_sends_a_single_user_agent() {
+        async fn record(
+            State(requests): State<Arc<Mutex<Vec<HeaderMap>>>>,
+            headers: HeaderMap,
+        ) -> StatusCode {
+            requests.lock().unwrap().push(headers);
+            StatusCode::NO_CONTENT
+        }
+
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let mock = Router::new().fallback(record).with_state(requests.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await });
+
+        let client = octocrab_client(
+            format!("http://{addr}").parse().unwrap(),
+            "wild-git-yonder/1.2.3",
+            &[],
+            Some("ghs_token"),
+            None,
+        )
+        .unwrap();
+        client._get("/rate_limit").await.unwrap();
+
+        let requests = requests.lock().unwrap();
+        let agents = requests[0].get_all(USER_AGENT).iter().collect::<Vec<_>>();
+        assert_eq!(agents, ["wild-git-yonder/1.2.3"]);
+        assert_eq!(requests[0]["authorization"], "Bearer ghs_token");
+    }
+
+    #[tokio::test]
+    async fn test_hung_call_times_out() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        // accepts the connection but never answers
+        tokio::spawn(async move {
+            let (_stream, _) = listener.accept().await.unwrap();
+            std::future::pending::<()>().await
+        });
+        let client = octocrab_client(
+            format!("http://{addr}").parse().unwrap(),
+            "wild-git-yonder",
+            &[],
+            None,
+            Some(Duration::from_millis(50)),
+        )
+        .unwrap();
+
+        let call = tokio::time::timeout(Duration::from_secs(5), client._get("/rate_limit"));
+        assert!(call.await.expect("the call did not time out").is_err());
+    }
+}
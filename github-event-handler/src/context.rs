@@ -0,0 +1,2151 @@
+use crate::api::{
+    DeploymentState, Dispatch, GitHubApi, Merge, MergeMethod, ScanningAlertKind, UpsertedComment,
+};
+use crate::authentication::AppMetadata;
+use crate::payload::{
+    BranchProtectionRuleEvent, CheckRun, CheckSuite, CodeScanningAlertEvent, Deployment,
+    DeploymentStatus, Package, Release, ReleaseAsset, RepositoryRulesetEvent,
+    SecretScanningAlertEvent, WorkflowJob, WorkflowRun, REPOSITORY_RULESET,
+};
+use crate::rate_limit::RateLimitPolicy;
+use bytes::Bytes;
+use futures_util::stream::BoxStream;
+use futures_util::StreamExt;
+use hyper::http::{Extensions, HeaderMap};
+use octocrab::models::issues::Comment as IssueComment;
+use octocrab::models::pulls::{Comment, PullRequest, Review, ReviewState};
+use octocrab::models::webhook_events::payload::{
+    InstallationWebhookEventAction, RefType, ReleaseWebhookEventAction, StarWebhookEventPayload,
+    StatusWebhookEventPayload, WatchWebhookEventPayload,
+};
+use octocrab::models::webhook_events::{
+    EventInstallation, WebhookEvent, WebhookEventPayload, WebhookEventType,
+};
+use octocrab::models::{Author, CheckRunId, InstallationId, Repository, StatusState};
+use octocrab::params::checks::{
+    CheckRunConclusion, CheckRunOutput, CheckRunOutputAnnotation, CheckRunStatus,
+};
+use snafu::{ResultExt, Snafu};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OnceCell;
+use tokio_util::sync::CancellationToken;
+
+/// Everything a handler needs to know about the event it is processing.
+pub struct EventContext<A> {
+    event: WebhookEvent,
+    account_login: Option<String>,
+    api: A,
+    cancellation: CancellationToken,
+    target_type: Option<TargetType>,
+    target_id: Option<u64>,
+    delivery_id: Option<String>,
+    raw_body: Option<Bytes>,
+    headers: HeaderMap,
+    enterprise_version: Option<semver::Version>,
+    settings: Arc<HandlerSettings>,
+    covers_repository: Option<bool>,
+    full_repository: OnceCell<Repository>,
+    extensions: Mutex<Extensions>,
+}
+
+/// Read-only settings derived from the configuration, shared by all events.
+#[derive(Debug, Clone, Default)]
+pub struct HandlerSettings {
+    /// Base url of the GitHub API the App talks to.
+    pub base_url: Option<String>,
+    /// The App itself, `None` if it couldn't be looked up at startup.
+    pub app: Option<AppMetadata>,
+    /// Named lists of allowed values, e.g. `deployers` with the logins allowed to deploy.
+    pub allowlists: HashMap<String, Vec<String>>,
+    /// Streamed file contents fail once they exceed this size, `None` doesn't limit them.
+    pub max_content_bytes: Option<usize>,
+    /// How long API requests of the handlers wait for rate limits to be lifted.
+    pub rate_limit: RateLimitPolicy,
+}
+
+impl HandlerSettings {
+    /// Whether `value` is part of the named allowlist, `false` for unknown lists.
+    pub fn is_allowed(&self, list: &str, value: &str) -> bool {
+        self.allowlists
+            .get(list)
+            .is_some_and(|allowed| allowed.iter().any(|allowed| allowed == value))
+    }
+}
+
+/// What the hook is installed on, sent as `X-GitHub-Hook-Installation-Target-Type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetType {
+    /// The hook of the GitHub App itself.
+    Integration,
+    Repository,
+    Organization,
+}
+
+impl std::str::FromStr for TargetType {
+    type Err = UnknownTargetType;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "integration" => Ok(Self::Integration),
+            "repository" => Ok(Self::Repository),
+            "organization" => Ok(Self::Organization),
+            _ => UnknownTargetTypeSnafu { value }.fail(),
+        }
+    }
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(display("Unknown hook installation target type {value:?}"))]
+pub struct UnknownTargetType {
+    value: String,
+}
+
+impl<A> EventContext<A> {
+    pub fn new(event: WebhookEvent, account_login: Option<String>, api: A) -> Self {
+        Self {
+            event,
+            account_login,
+            api,
+            cancellation: CancellationToken::new(),
+            target_type: None,
+            target_id: None,
+            delivery_id: None,
+            raw_body: None,
+            headers: HeaderMap::new(),
+            enterprise_version: None,
+            settings: Default::default(),
+            covers_repository: None,
+            full_repository: OnceCell::new(),
+            extensions: Default::default(),
+        }
+    }
+
+    pub fn with_cancellation(self, cancellation: CancellationToken) -> Self {
+        Self {
+            cancellation,
+            ..self
+        }
+    }
+
+    pub fn with_target_type(self, target_type: Option<TargetType>) -> Self {
+        Self {
+            target_type,
+            ..self
+        }
+    }
+
+    pub fn with_target_id(self, target_id: Option<u64>) -> Self {
+        Self { target_id, ..self }
+    }
+
+    /// The `X-GitHub-Delivery` id and the payload exactly as it was signed, if known.
+    pub fn with_delivery(self, delivery_id: Option<String>, raw_body: Option<Bytes>) -> Self {
+        Self {
+            delivery_id,
+            raw_body,
+            ..self
+        }
+    }
+
+    /// Every header of the delivery.
+    pub fn with_headers(self, headers: HeaderMap) -> Self {
+        Self { headers, ..self }
+    }
+
+    pub fn with_enterprise_version(self, enterprise_version: Option<semver::Version>) -> Self {
+        Self {
+            enterprise_version,
+            ..self
+        }
+    }
+
+    pub fn with_settings(self, settings: Arc<HandlerSettings>) -> Self {
+        Self { settings, ..self }
+    }
+
+    /// Whether the repository of the event is one the installation can access, `None` if
+    /// that's unknown.
+    pub fn with_repository_coverage(self, covers_repository: Option<bool>) -> Self {
+        Self {
+            covers_repository,
+            ..self
+        }
+    }
+
+    pub fn event(&self) -> &WebhookEvent {
+        &self.event
+    }
+
+    /// Unique id of the delivery, stays the same on redeliveries.
+    pub fn delivery_id(&self) -> Option<&str> {
+        self.delivery_id.as_deref()
+    }
+
+    /// The payload as it was received, e.g. for fields octocrab doesn't expose.
+    pub fn raw_body(&self) -> Option<&Bytes> {
+        self.raw_body.as_ref()
+    }
+
+    /// Every header the delivery was received with, empty for events recovered from the
+    /// write-ahead log.
+    ///
+    /// The delivery was verified before any handler runs, its signature headers mustn't be
+    /// checked again or trusted to authorize anything, e.g. they can't tell who sent an event.
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// The `X-GitHub-Enterprise-Version` of deliveries from GitHub Enterprise Server.
+    pub fn enterprise_version(&self) -> Option<&semver::Version> {
+        self.enterprise_version.as_ref()
+    }
+
+    pub fn settings(&self) -> &HandlerSettings {
+        &self.settings
+    }
+
+    /// Whether the repository of the event is part of the installation's repository selection.
+    ///
+    /// Misrouted deliveries, e.g. of another App sharing the endpoint, name repositories the
+    /// installation can't touch. `true` if the event has no repository or the selection is
+    /// unknown, it is only looked up with [`HandleOptions::reject_uncovered_repositories`].
+    ///
+    /// [`HandleOptions::reject_uncovered_repositories`]: crate::handle::HandleOptions::reject_uncovered_repositories
+    pub fn installation_covers_repo(&self) -> bool {
+        self.covers_repository.unwrap_or(true)
+    }
+
+    pub fn app_slug(&self) -> Option<&str> {
+        self.settings.app.as_ref().map(|app| app.slug.as_str())
+    }
+
+    /// Client authenticated as the installation the event was sent for.
+    pub fn api(&self) -> &A {
+        &self.api
+    }
+
+    /// Cancelled once the delivery is abandoned, e.g. because GitHub closed the connection.
+    pub fn cancellation(&self) -> &CancellationToken {
+        &self.cancellation
+    }
+
+    /// What the hook is installed on, `None` if the delivery didn't say.
+    pub fn target_type(&self) -> Option<TargetType> {
+        self.target_type
+    }
+
+    /// Id of what the hook is installed on, `None` if the delivery didn't say.
+    pub fn target_id(&self) -> Option<u64> {
+        self.target_id
+    }
+
+    /// Shares a value with the handlers running after this one, handlers run in
+    /// registration order. Returns the value previously stored for the type.
+    pub fn insert<T: Clone + Send + Sync + 'static>(&self, value: T) -> Option<T> {
+        self.extensions.lock().unwrap().insert(value)
+    }
+
+    /// A value inserted by a previous handler of this event.
+    pub fn get<T: Clone + Send + Sync + 'static>(&self) -> Option<T> {
+        self.extensions.lock().unwrap().get::<T>().cloned()
+    }
+
+    pub fn installation_id(&self) -> Option<InstallationId> {
+        installation_id(&self.event)
+    }
+
+    /// Login of the user or organization the App installation belongs to.
+    pub fn account_login(&self) -> Option<&str> {
+        self.account_login.as_deref()
+    }
+
+    /// Login of the organization the event happened in, the only owner known for
+    /// organization-level events like `organization`, `team` or `membership`.
+    pub fn organization_login(&self) -> Option<&str> {
+        self.event
+            .organization
+            .as_ref()
+            .map(|organization| organization.login.as_str())
+    }
+
+    pub fn check_suite(&self) -> Option<CheckSuite> {
+        let WebhookEventPayload::CheckSuite(ref payload) = self.event.specific else {
+            return None;
+        };
+        serde_json::from_value(payload.check_suite.clone()).ok()
+    }
+
+    pub fn check_run(&self) -> Option<CheckRun> {
+        let WebhookEventPayload::CheckRun(ref payload) = self.event.specific else {
+            return None;
+        };
+        serde_json::from_value(payload.check_run.clone()).ok()
+    }
+
+    pub fn workflow_run(&self) -> Option<WorkflowRun> {
+        let WebhookEventPayload::WorkflowRun(ref payload) = self.event.specific else {
+            return None;
+        };
+        serde_json::from_value(payload.workflow_run.clone()).ok()
+    }
+
+    pub fn workflow_job(&self) -> Option<WorkflowJob> {
+        let WebhookEventPayload::WorkflowJob(ref payload) = self.event.specific else {
+            return None;
+        };
+        serde_json::from_value(payload.workflow_job.clone()).ok()
+    }
+
+    /// The deployment of `deployment` and `deployment_status` events.
+    pub fn deployment(&self) -> Option<Deployment> {
+        let deployment = match self.event.specific {
+            WebhookEventPayload::Deployment(ref payload) => &payload.deployment,
+            WebhookEventPayload::DeploymentStatus(ref payload) => &payload.deployment,
+            _ => return None,
+        };
+        serde_json::from_value(deployment.clone()).ok()
+    }
+
+    pub fn deployment_status(&self) -> Option<DeploymentStatus> {
+        let WebhookEventPayload::DeploymentStatus(ref payload) = self.event.specific else {
+            return None;
+        };
+        serde_json::from_value(payload.deployment_status.clone()).ok()
+    }
+
+    /// The release of `release` events.
+    pub fn release(&self) -> Option<Release> {
+        let WebhookEventPayload::Release(ref payload) = self.event.specific else {
+            return None;
+        };
+        serde_json::from_value(payload.release.clone()).ok()
+    }
+
+    /// What happened to the release of `release` events.
+    pub fn release_action(&self) -> Option<&ReleaseWebhookEventAction> {
+        let WebhookEventPayload::Release(ref payload) = self.event.specific else {
+            return None;
+        };
+        Some(&payload.action)
+    }
+
+    /// The package of `package` and `registry_package` events.
+    pub fn package(&self) -> Option<Package> {
+        let package = match self.event.specific {
+            WebhookEventPayload::Package(ref payload) => &payload.package,
+            WebhookEventPayload::RegistryPackage(ref payload) => &payload.registry_package,
+            _ => return None,
+        };
+        serde_json::from_value(package.clone()).ok()
+    }
+
+    /// The rule of `branch_protection_rule` events, with the previous values of edited settings.
+    pub fn branch_protection_rule(&self) -> Option<BranchProtectionRuleEvent> {
+        match self.event.specific {
+            WebhookEventPayload::BranchProtectionRule(ref payload) => {
+                serde_json::from_value(serde_json::to_value(payload).ok()?).ok()
+            }
+            // payloads octocrab couldn't parse, see [`crate::payload::parse_event`]
+            WebhookEventPayload::Unknown(ref payload)
+                if self.event.kind == WebhookEventType::BranchProtectionRule =>
+            {
+                serde_json::from_value(serde_json::Value::clone(payload)).ok()
+            }
+            _ => None,
+        }
+    }
+
+    /// The alert of `code_scanning_alert` events, with the rule it violates.
+    pub fn code_scanning_alert(&self) -> Option<CodeScanningAlertEvent> {
+        match self.event.specific {
+            WebhookEventPayload::CodeScanningAlert(ref payload) => {
+                serde_json::from_value(serde_json::to_value(payload).ok()?).ok()
+            }
+            WebhookEventPayload::Unknown(ref payload)
+                if self.event.kind == WebhookEventType::CodeScanningAlert =>
+            {
+                serde_json::from_value(serde_json::Value::clone(payload)).ok()
+            }
+            _ => None,
+        }
+    }
+
+    /// The alert of `secret_scanning_alert` events, with the type of the leaked secret.
+    pub fn secret_scanning_alert(&self) -> Option<SecretScanningAlertEvent> {
+        match self.event.specific {
+            WebhookEventPayload::SecretScanningAlert(ref payload) => {
+                serde_json::from_value(serde_json::to_value(payload).ok()?).ok()
+            }
+            WebhookEventPayload::Unknown(ref payload)
+                if self.event.kind == WebhookEventType::SecretScanningAlert =>
+            {
+                serde_json::from_value(serde_json::Value::clone(payload)).ok()
+            }
+            _ => None,
+        }
+    }
+
+    /// The ruleset of `repository_ruleset` events, with the previous values of edited settings.
+    pub fn repository_ruleset(&self) -> Option<RepositoryRulesetEvent> {
+        let WebhookEventPayload::Unknown(ref payload) = self.event.specific else {
+            return None;
+        };
+        if self.event.kind != WebhookEventType::Unknown(REPOSITORY_RULESET.into()) {
+            return None;
+        }
+        serde_json::from_value(serde_json::Value::clone(payload)).ok()
+    }
+
+    /// The user who triggered the event, e.g. who starred, watched or forked the repository.
+    pub fn sender(&self) -> Option<&Author> {
+        self.event.sender.as_ref()
+    }
+
+    /// Whether the repository of `star` events was starred or unstarred, and when.
+    pub fn star(&self) -> Option<&StarWebhookEventPayload> {
+        let WebhookEventPayload::Star(ref payload) = self.event.specific else {
+            return None;
+        };
+        Some(payload)
+    }
+
+    /// `watch` events are only ever sent for a repository being starred, despite their name.
+    pub fn watch(&self) -> Option<&WatchWebhookEventPayload> {
+        let WebhookEventPayload::Watch(ref payload) = self.event.specific else {
+            return None;
+        };
+        Some(payload)
+    }
+
+    /// The new fork of `fork` events, the event's repository is the forked one.
+    pub fn forkee(&self) -> Option<&Repository> {
+        let WebhookEventPayload::Fork(ref payload) = self.event.specific else {
+            return None;
+        };
+        Some(&payload.forkee)
+    }
+
+    /// The legacy commit status of `status` events, check runs and suites are separate events.
+    pub fn commit_status(&self) -> Option<&StatusWebhookEventPayload> {
+        let WebhookEventPayload::Status(ref payload) = self.event.specific else {
+            return None;
+        };
+        Some(payload)
+    }
+
+    /// Commit the event is about: the pushed head, the head of the pull request or the head
+    /// the commit status, check suite, check run, workflow or deployment ran against.
+    pub fn head_sha(&self) -> Option<String> {
+        match self.event.specific {
+            WebhookEventPayload::Push(ref payload) => Some(payload.after.clone()),
+            WebhookEventPayload::Status(ref payload) => Some(payload.sha.clone()),
+            WebhookEventPayload::Deployment(_) | WebhookEventPayload::DeploymentStatus(_) => {
+                self.deployment().map(|deployment| deployment.sha)
+            }
+            WebhookEventPayload::CheckSuite(_) => self.check_suite().map(|suite| suite.head_sha),
+            WebhookEventPayload::CheckRun(_) => self.check_run().map(|run| run.head_sha),
+            WebhookEventPayload::WorkflowRun(_) => self.workflow_run().map(|run| run.head_sha),
+            WebhookEventPayload::WorkflowJob(_) => self.workflow_job().map(|job| job.head_sha),
+            _ => pull_request(&self.event).map(|pr| pr.head.sha.clone()),
+        }
+    }
+
+    /// What happened to the installation for `installation` lifecycle events.
+    pub fn installation_action(&self) -> Option<&InstallationWebhookEventAction> {
+        let WebhookEventPayload::Installation(ref payload) = self.event.specific else {
+            return None;
+        };
+        Some(&payload.action)
+    }
+
+    /// Name of the branch or tag of `create` and `delete` events.
+    pub fn ref_name(&self) -> Option<&str> {
+        match self.event.specific {
+            WebhookEventPayload::Create(ref payload) => Some(&payload.r#ref),
+            WebhookEventPayload::Delete(ref payload) => Some(&payload.r#ref),
+            _ => None,
+        }
+    }
+
+    /// Whether `create` and `delete` events are about a branch or a tag.
+    pub fn ref_type(&self) -> Option<&RefType> {
+        match self.event.specific {
+            WebhookEventPayload::Create(ref payload) => Some(&payload.ref_type),
+            WebhookEventPayload::Delete(ref payload) => Some(&payload.ref_type),
+            _ => None,
+        }
+    }
+
+    /// The pull request of `pull_request`, `pull_request_review` and
+    /// `pull_request_review_comment` events.
+    pub fn pull_request(&self) -> Option<&PullRequest> {
+        pull_request(&self.event)
+    }
+
+    pub fn pull_request_number(&self) -> Option<u64> {
+        self.pull_request().map(|pr| pr.number)
+    }
+
+    /// Whether the event's pull request is a draft, `None` for events without one.
+    pub fn is_draft_pull_request(&self) -> Option<bool> {
+        is_draft_pull_request(&self.event)
+    }
+
+    pub fn review(&self) -> Option<&Review> {
+        let WebhookEventPayload::PullRequestReview(ref payload) = self.event.specific else {
+            return None;
+        };
+        Some(&payload.review)
+    }
+
+    /// State of the review, e.g. `approved`, `changes_requested` or `commented`.
+    pub fn review_state(&self) -> Option<ReviewState> {
+        self.review().and_then(|review| review.state)
+    }
+
+    pub fn review_comment(&self) -> Option<&Comment> {
+        let WebhookEventPayload::PullRequestReviewComment(ref payload) = self.event.specific else {
+            return None;
+        };
+        Some(&payload.comment)
+    }
+
+    /// The comment of `issue_comment` events, pull requests share them with issues.
+    pub fn issue_comment(&self) -> Option<&IssueComment> {
+        let WebhookEventPayload::IssueComment(ref payload) = self.event.specific else {
+            return None;
+        };
+        Some(&payload.comment)
+    }
+
+    /// The first line of the issue comment starting with `prefix`, e.g. `/deploy staging`
+    /// for the prefix `/`.
+    pub fn parse_command(&self, prefix: &str) -> Option<Command> {
+        self.issue_comment()?
+            .body
+            .as_deref()?
+            .lines()
+            .find_map(|line| Command::parse(line.trim(), prefix))
+    }
+}
+
+impl<A: GitHubApi> EventContext<A> {
+    /// Creates a check run on the event's repository using the installation client, annotate
+    /// and complete it through the returned handle.
+    pub async fn create_check_run(
+        &self,
+        name: &str,
+        head_sha: &str,
+    ) -> Result<CheckRunHandle<'_, A>, ContextError> {
+        let Some(ref repository) = self.event.repository else {
+            return MissingRepositorySnafu.fail();
+        };
+        let id = self
+            .api
+            .create_check_run(repository, name, head_sha)
+            .await
+            .map_err(|err| Box::new(err) as _)
+            .context(ApiSnafu)?;
+        Ok(CheckRunHandle {
+            api: &self.api,
+            repository,
+            id,
+            name: name.to_owned(),
+            annotations: Vec::new(),
+        })
+    }
+
+    /// Sets a commit status on the event's repository, for `sha` or else the event's
+    /// [`head_sha`](Self::head_sha).
+    pub async fn set_commit_status(
+        &self,
+        sha: Option<&str>,
+        state: StatusState,
+        context: &str,
+        description: Option<&str>,
+        target_url: Option<&str>,
+    ) -> Result<(), ContextError> {
+        let Some(ref repository) = self.event.repository else {
+            return MissingRepositorySnafu.fail();
+        };
+        let Some(sha) = sha.map(str::to_owned).or_else(|| self.head_sha()) else {
+            return MissingShaSnafu.fail();
+        };
+        self.api
+            .set_commit_status(repository, &sha, state, context, description, target_url)
+            .await
+            .map_err(|err| Box::new(err) as _)
+            .context(ApiSnafu)
+    }
+
+    /// Reports the state of a deployment on the event's repository using the installation
+    /// client.
+    pub async fn create_deployment_status(
+        &self,
+        deployment_id: u64,
+        state: DeploymentState,
+        environment: Option<&str>,
+    ) -> Result<(), ContextError> {
+        let Some(ref repository) = self.event.repository else {
+            return MissingRepositorySnafu.fail();
+        };
+        self.api
+            .create_deployment_status(repository, deployment_id, state, environment)
+            .await
+            .map_err(|err| Box::new(err) as _)
+            .context(ApiSnafu)
+    }
+
+    /// Posts `body` as the comment of the issue or pull request marked with `marker`, updating
+    /// the marked comment instead of adding another one once it exists. The marker is appended
+    /// to bodies which don't contain it yet, e.g. `<!-- wild-git-yonder:lint -->`.
+    pub async fn upsert_comment(
+        &self,
+        issue_number: u64,
+        marker: &str,
+        body: &str,
+    ) -> Result<UpsertedComment, ContextError> {
+        let Some(ref repository) = self.event.repository else {
+            return MissingRepositorySnafu.fail();
+        };
+        let body = match body.contains(marker) {
+            true => body.to_owned(),
+            false => format!("{body}\n\n{marker}"),
+        };
+        self.api
+            .upsert_issue_comment(repository, issue_number, marker, &body)
+            .await
+            .map_err(|err| Box::new(err) as _)
+            .context(ApiSnafu)
+    }
+
+    /// Adds the labels to an issue or pull request of the event's repository, returns all its
+    /// labels afterwards.
+    pub async fn add_labels(
+        &self,
+        issue_number: u64,
+        labels: &[&str],
+    ) -> Result<Vec<String>, ContextError> {
+        let Some(ref repository) = self.event.repository else {
+            return MissingRepositorySnafu.fail();
+        };
+        self.api
+            .add_issue_labels(repository, issue_number, labels)
+            .await
+            .map_err(|err| Box::new(err) as _)
+            .context(ApiSnafu)
+    }
+
+    /// Requests reviews of a pull request of the event's repository from the users and teams,
+    /// by login and team slug. Fails for events which aren't about a pull request, comments on
+    /// pull requests included.
+    pub async fn request_reviewers(
+        &self,
+        pr_number: u64,
+        users: &[&str],
+        teams: &[&str],
+    ) -> Result<(), ContextError> {
+        let repository = self.pull_request_repository()?;
+        self.api
+            .request_pull_request_reviewers(repository, pr_number, users, teams)
+            .await
+            .map_err(|err| Box::new(err) as _)
+            .context(ApiSnafu)
+    }
+
+    /// Merges a pull request of the event's repository, failing with
+    /// [`ContextError::NotMergeable`] if GitHub refuses to, e.g. because of conflicts. Fails for
+    /// events which aren't about a pull request, comments on pull requests included.
+    pub async fn merge_pull_request(
+        &self,
+        pr_number: u64,
+        method: MergeMethod,
+    ) -> Result<String, ContextError> {
+        let repository = self.pull_request_repository()?;
+        match self
+            .api
+            .merge_pull_request(repository, pr_number, method)
+            .await
+            .map_err(|err| Box::new(err) as _)
+            .context(ApiSnafu)?
+        {
+            Merge::Merged { sha } => Ok(sha),
+            Merge::NotMergeable { message } => NotMergeableSnafu { message }.fail(),
+        }
+    }
+
+    /// Closes a pull request of the event's repository without merging it. Fails for events
+    /// which aren't about a pull request, comments on pull requests included.
+    pub async fn close_pull_request(&self, pr_number: u64) -> Result<(), ContextError> {
+        let repository = self.pull_request_repository()?;
+        self.api
+            .close_pull_request(repository, pr_number)
+            .await
+            .map_err(|err| Box::new(err) as _)
+            .context(ApiSnafu)
+    }
+
+    /// Triggers the `workflow_dispatch` event of a workflow of the event's repository, by id or
+    /// file name, on `git_ref`. Fails with [`ContextError::MissingPermission`] unless the App
+    /// may write to Actions.
+    pub async fn trigger_workflow_dispatch(
+        &self,
+        workflow: &str,
+        git_ref: &str,
+        inputs: serde_json::Value,
+    ) -> Result<(), ContextError> {
+        let Some(ref repository) = self.event.repository else {
+            return MissingRepositorySnafu.fail();
+        };
+        let dispatch = self
+            .api
+            .dispatch_workflow(repository, workflow, git_ref, &inputs)
+            .await
+            .map_err(|err| Box::new(err) as _)
+            .context(ApiSnafu)?;
+        dispatched(dispatch, "actions: write")
+    }
+
+    /// Triggers a `repository_dispatch` event of the type in the event's repository, handing
+    /// `payload` to the workflows as `client_payload`. Fails with
+    /// [`ContextError::MissingPermission`] unless the App may write to the repository contents.
+    pub async fn trigger_repository_dispatch(
+        &self,
+        event_type: &str,
+        payload: serde_json::Value,
+    ) -> Result<(), ContextError> {
+        let Some(ref repository) = self.event.repository else {
+            return MissingRepositorySnafu.fail();
+        };
+        let dispatch = self
+            .api
+            .dispatch_repository_event(repository, event_type, &payload)
+            .await
+            .map_err(|err| Box::new(err) as _)
+            .context(ApiSnafu)?;
+        dispatched(dispatch, "contents: write")
+    }
+
+    /// The repository of events about a pull request.
+    fn pull_request_repository(&self) -> Result<&Repository, ContextError> {
+        let Some(ref repository) = self.event.repository else {
+            return MissingRepositorySnafu.fail();
+        };
+        let about_pull_request = match self.event.specific {
+            WebhookEventPayload::IssueComment(ref payload) => payload.issue.pull_request.is_some(),
+            _ => self.pull_request().is_some(),
+        };
+        if !about_pull_request {
+            return MissingPullRequestSnafu.fail();
+        }
+        Ok(repository)
+    }
+
+    /// Closes the alert of a `code_scanning_alert` or `secret_scanning_alert` event, `reason`
+    /// being a dismissed reason like `false positive` for code scanning alerts and a
+    /// resolution like `revoked` for secret scanning alerts.
+    pub async fn close_alert(
+        &self,
+        reason: &str,
+        comment: Option<&str>,
+    ) -> Result<(), ContextError> {
+        let Some(ref repository) = self.event.repository else {
+            return MissingRepositorySnafu.fail();
+        };
+        let (kind, number) = match (self.code_scanning_alert(), self.secret_scanning_alert()) {
+            (Some(event), _) => (ScanningAlertKind::CodeScanning, event.alert.number),
+            (None, Some(event)) => (ScanningAlertKind::SecretScanning, event.alert.number),
+            (None, None) => return MissingAlertSnafu.fail(),
+        };
+        self.api
+            .close_scanning_alert(repository, kind, number, reason, comment)
+            .await
+            .map_err(|err| Box::new(err) as _)
+            .context(ApiSnafu)
+    }
+
+    /// Removes the label from an issue or pull request of the event's repository, returns the
+    /// labels left.
+    pub async fn remove_label(
+        &self,
+        issue_number: u64,
+        label: &str,
+    ) -> Result<Vec<String>, ContextError> {
+        let Some(ref repository) = self.event.repository else {
+            return MissingRepositorySnafu.fail();
+        };
+        self.api
+            .remove_issue_label(repository, issue_number, label)
+            .await
+            .map_err(|err| Box::new(err) as _)
+            .context(ApiSnafu)
+    }
+
+    /// Attaches `body` to the release of the event's repository as an asset called `name`.
+    pub async fn upload_release_asset(
+        &self,
+        release_id: u64,
+        name: &str,
+        body: Bytes,
+        content_type: &str,
+    ) -> Result<ReleaseAsset, ContextError> {
+        let Some(ref repository) = self.event.repository else {
+            return MissingRepositorySnafu.fail();
+        };
+        self.api
+            .upload_release_asset(repository, release_id, name, body, content_type)
+            .await
+            .map_err(|err| Box::new(err) as _)
+            .context(ApiSnafu)
+    }
+
+    /// The event's repository with all its details, e.g. its default branch and visibility.
+    ///
+    /// Payloads carrying only parts of the repository have it fetched with the installation
+    /// client, once per delivery.
+    pub async fn repository_full(&self) -> Result<&Repository, ContextError> {
+        let Some(ref repository) = self.event.repository else {
+            return MissingRepositorySnafu.fail();
+        };
+        if repository.default_branch.is_some() && repository.visibility.is_some() {
+            return Ok(repository);
+        }
+        self.full_repository
+            .get_or_try_init(|| async {
+                self.api
+                    .repository(repository.id)
+                    .await
+                    .map_err(|err| Box::new(err) as _)
+                    .context(ApiSnafu)
+            })
+            .await
+    }
+
+    /// Whether a `push` event updated the repository's default branch, `None` for other events
+    /// and if the default branch can't be determined.
+    ///
+    /// Payloads lacking the default branch have the repository fetched, once per delivery.
+    pub async fn is_push_to_default_branch(&self) -> Option<bool> {
+        let WebhookEventPayload::Push(ref payload) = self.event.specific else {
+            return None;
+        };
+        let default_branch = match self.event.repository {
+            Some(Repository {
+                default_branch: Some(ref default_branch),
+                ..
+            }) => default_branch,
+            _ => match self.repository_full().await {
+                Ok(repository) => repository.default_branch.as_ref()?,
+                Err(err) => {
+                    tracing::warn!(%err, "unable to determine the default branch");
+                    return None;
+                }
+            },
+        };
+        let branch = payload.r#ref.strip_prefix("refs/heads/")?;
+        Some(branch == default_branch)
+    }
+
+    /// Fetches the unified diff of the event's pull request using the installation client.
+    pub async fn pull_request_diff(&self) -> Result<String, ContextError> {
+        let Some(ref repository) = self.event.repository else {
+            return MissingRepositorySnafu.fail();
+        };
+        let Some(number) = self.pull_request_number() else {
+            return MissingPullRequestSnafu.fail();
+        };
+        self.api
+            .pull_request_diff(repository, number)
+            .await
+            .map_err(|err| Box::new(err) as _)
+            .context(ApiSnafu)
+    }
+
+    /// Streams the raw content of a file using the installation client, chunk by chunk as it
+    /// arrives instead of buffering the whole file.
+    ///
+    /// The stream fails once more than [`HandlerSettings::max_content_bytes`] were read.
+    pub async fn stream_content(
+        &self,
+        owner: &str,
+        repo: &str,
+        path: &str,
+        git_ref: Option<&str>,
+    ) -> Result<BoxStream<'static, Result<Bytes, ContextError>>, ContextError> {
+        let content = self
+            .api
+            .stream_content(owner, repo, path, git_ref)
+            .await
+            .map_err(|err| Box::new(err) as _)
+            .context(ApiSnafu)?;
+        let limit = self.settings.max_content_bytes;
+        let mut read = 0;
+        Ok(content
+            .map(move |chunk| {
+                let chunk = chunk.context(ApiSnafu)?;
+                read += chunk.len();
+                match limit {
+                    Some(limit) if read > limit => ContentTooLargeSnafu { limit }.fail(),
+                    _ => Ok(chunk),
+                }
+            })
+            .boxed())
+    }
+
+    /// Logs url and artifact download urls of the event's workflow run.
+    pub async fn workflow_run_downloads(&self) -> Result<WorkflowRunDownloads, ContextError> {
+        let Some(ref repository) = self.event.repository else {
+            return MissingRepositorySnafu.fail();
+        };
+        let Some(run) = self.workflow_run() else {
+            return MissingWorkflowRunSnafu.fail();
+        };
+        let artifacts = self
+            .api
+            .workflow_run_artifacts(repository, run.id)
+            .await
+            .map_err(|err| Box::new(err) as _)
+            .context(ApiSnafu)?;
+        Ok(WorkflowRunDownloads {
+            logs: run.logs_url,
+            artifacts,
+        })
+    }
+}
+
+/// A ChatOps command, arguments are separated by whitespace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Command {
+    pub name: String,
+    pub args: Vec<String>,
+}
+
+impl Command {
+    fn parse(line: &str, prefix: &str) -> Option<Self> {
+        let command = line.strip_prefix(prefix)?;
+        // a prefix followed by a space isn't a command, e.g. `/ not a command`
+        if command.starts_with(char::is_whitespace) {
+            return None;
+        }
+        let mut words = command.split_whitespace();
+        let name = words.next()?;
+        Some(Self {
+            name: name.to_owned(),
+            args: words.map(str::to_owned).collect(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkflowRunDownloads {
+    pub logs: String,
+    pub artifacts: Vec<String>,
+}
+
+/// Id of the event's installation, `None` if missing or zero as minimal installations
+/// aren't guaranteed to carry a valid id.
+pub(crate) fn installation_id(event: &WebhookEvent) -> Option<InstallationId> {
+    let id = match event.installation {
+        Some(EventInstallation::Full(ref installation)) => installation.id,
+        Some(EventInstallation::Minimal(ref installation)) => installation.id,
+        None => return None,
+    };
+    (id.0 != 0).then_some(id)
+}
+
+fn pull_request(event: &WebhookEvent) -> Option<&PullRequest> {
+    match event.specific {
+        WebhookEventPayload::PullRequest(ref payload) => Some(&payload.pull_request),
+        WebhookEventPayload::PullRequestReview(ref payload) => Some(&payload.pull_request),
+        WebhookEventPayload::PullRequestReviewComment(ref payload) => Some(&payload.pull_request),
+        _ => None,
+    }
+}
+
+/// GitHub rejects requests adding more annotations to a check run at once.
+pub const MAX_ANNOTATIONS_PER_REQUEST: usize = 50;
+
+/// A check run created by [`EventContext::create_check_run`]. Annotations are sent in batches
+/// of [`MAX_ANNOTATIONS_PER_REQUEST`] as they fill up, the rest once the run is completed.
+pub struct CheckRunHandle<'a, A> {
+    api: &'a A,
+    repository: &'a Repository,
+    id: CheckRunId,
+    name: String,
+    annotations: Vec<CheckRunOutputAnnotation>,
+}
+
+impl<A: GitHubApi> CheckRunHandle<'_, A> {
+    pub fn id(&self) -> CheckRunId {
+        self.id
+    }
+
+    pub async fn add_annotation(
+        &mut self,
+        annotation: CheckRunOutputAnnotation,
+    ) -> Result<(), ContextError> {
+        self.annotations.push(annotation);
+        if self.annotations.len() == MAX_ANNOTATIONS_PER_REQUEST {
+            self.update(Some(CheckRunStatus::InProgress), None, "In progress")
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Concludes the check run with `summary` as its output, sending the annotations left.
+    pub async fn complete(
+        mut self,
+        conclusion: CheckRunConclusion,
+        summary: &str,
+    ) -> Result<(), ContextError> {
+        self.update(Some(CheckRunStatus::Completed), Some(conclusion), summary)
+            .await
+    }
+
+    async fn update(
+        &mut self,
+        status: Option<CheckRunStatus>,
+        conclusion: Option<CheckRunConclusion>,
+        summary: &str,
+    ) -> Result<(), ContextError> {
+        let output = CheckRunOutput {
+            title: self.name.clone(),
+            summary: summary.to_owned(),
+            text: None,
+            annotations: std::mem::take(&mut self.annotations),
+            images: Vec::new(),
+        };
+        self.api
+            .update_check_run(self.repository, self.id, status, conclusion, output)
+            .await
+            .map_err(|err| Box::new(err) as _)
+            .context(ApiSnafu)
+    }
+}
+
+fn dispatched(dispatch: Dispatch, permission: &'static str) -> Result<(), ContextError> {
+    match dispatch {
+        Dispatch::Dispatched => Ok(()),
+        Dispatch::Forbidden { message } => MissingPermissionSnafu {
+            permission,
+            message,
+        }
+        .fail(),
+    }
+}
+
+pub(crate) fn is_draft_pull_request(event: &WebhookEvent) -> Option<bool> {
+    pull_request(event).map(|pr| pr.draft.unwrap_or(false))
+}
+
+/// The `action` of the event as it's named in its `body`, `None` for events without one.
+pub fn event_action(body: &[u8]) -> Option<String> {
+    // octocrab types the action of every event differently, the body names it as sent
+    #[derive(serde::Deserialize)]
+    struct Payload {
+        action: Option<String>,
+    }
+    serde_json::from_slice::<Payload>(body).ok()?.action
+}
+
+#[derive(Debug, Snafu)]
+pub enum ContextError {
+    #[snafu(display("Missing repository in the event"))]
+    MissingRepository,
+    #[snafu(display("The event is not about a workflow run"))]
+    MissingWorkflowRun,
+    #[snafu(display("Missing commit sha, the event is not about a commit"))]
+    MissingSha,
+    #[snafu(display("The event is not about a pull request"))]
+    MissingPullRequest,
+    #[snafu(display("The event is not about a code or secret scanning alert"))]
+    MissingAlert,
+    #[snafu(display("The pull request can't be merged: {message}"))]
+    NotMergeable { message: String },
+    #[snafu(display("The installation lacks the `{permission}` permission: {message}"))]
+    MissingPermission {
+        permission: &'static str,
+        message: String,
+    },
+    #[snafu(display("GitHub API call failed: {source}"))]
+    Api {
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    #[snafu(display("The content exceeds {limit} bytes"))]
+    ContentTooLarge { limit: usize },
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Command, ContextError, EventContext, HandlerSettings};
+    use crate::api::{Conditional, DeploymentState, GitHubApi, MergeMethod, UpsertedComment};
+    use axum::body::{Body, Bytes};
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::response::IntoResponse;
+    use axum::{
+        extract::{Path, Query},
+        routing::{delete, get, patch, post, put},
+        Json, Router,
+    };
+    use futures_util::StreamExt;
+    use octocrab::models::webhook_events::payload::CommitState;
+    use octocrab::models::webhook_events::{WebhookEvent, WebhookEventPayload};
+    use octocrab::models::{CommentId, StatusState};
+    use octocrab::Octocrab;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::convert::Infallible;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    fn author(login: &str) -> serde_json::Value {
+        let url = format!("https://api.github.local/users/{login}");
+        json!({
+            "login": login, "id": 1, "node_id": "dGVzdA==", "gravatar_id": "",
+            "avatar_url": url, "url": url, "html_url": url, "followers_url": url,
+            "following_url": url, "gists_url": url, "starred_url": url,
+            "subscriptions_url": url, "organizations_url": url, "repos_url": url,
+            "events_url": url, "received_events_url": url,
+            "type": "Organization", "site_admin": false
+        })
+    }
+
+    fn issue_comment_event(body: &str) -> WebhookEvent {
+        let issue = "https://api.github.local/repos/acme/anvil/issues/7";
+        let body = json!({
+            "action": "created",
+            "issue": {
+                "id": 7, "node_id": "dGVzdA==", "url": issue, "repository_url": issue,
+                "labels_url": issue, "comments_url": issue, "events_url": issue,
+                "html_url": issue, "number": 7, "state": "open", "title": "Deploy anvil",
+                "body": null, "user": author("wile"), "labels": [], "assignees": [],
+                "author_association": "MEMBER", "locked": false, "comments": 1,
+                "created_at": "2024-01-01T00:00:00Z", "updated_at": "2024-01-01T00:00:00Z"
+            },
+            "comment": {
+                "id": 1, "node_id": "dGVzdA==", "url": issue, "html_url": issue,
+                "body": body, "author_association": "MEMBER", "user": author("wile"),
+                "created_at": "2024-01-01T00:00:00Z"
+            },
+            "repository": {
+                "id": 1,
+                "name": "anvil",
+                "url": "https://api.github.local/repos/acme/anvil"
+            }
+        });
+        WebhookEvent::try_from_header_and_body("issue_comment", &body.to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_command_is_parsed_from_the_comment() {
+        let ctx = EventContext::new(
+            issue_comment_event("Ready to ship.\r\n/deploy staging --force"),
+            None,
+            (),
+        );
+
+        assert_eq!(
+            ctx.parse_command("/"),
+            Some(Command {
+                name: "deploy".to_string(),
+                args: vec!["staging".to_string(), "--force".to_string()],
+            })
+        );
+        assert_eq!(ctx.parse_command("!"), None);
+    }
+
+    #[test]
+    fn test_comment_without_command_is_ignored() {
+        for body in ["Looks good to me", "/ deploy staging", "/"] {
+            let ctx = EventContext::new(issue_comment_event(body), None, ());
+
+            assert_eq!(ctx.parse_command("/"), None, "{body}");
+        }
+        assert_eq!(
+            EventContext::new(push_event(), None, ()).parse_command("/"),
+            None
+        );
+    }
+
+    fn push_event() -> WebhookEvent {
+        let body = json!({
+            "ref": "refs/heads/main",
+            "before": "0000000000000000000000000000000000000000",
+            "after": "d6fde92930d4715a2b49857d24b940956b26d2d3",
+            "base_ref": null,
+            "commits": [],
+            "compare": "https://github.local/acme/anvil/compare/main",
+            "created": true,
+            "deleted": false,
+            "forced": false,
+            "head_commit": null,
+            "pusher": { "name": "wile", "email": "wile@acme.local" },
+            "repository": {
+                "id": 1,
+                "name": "anvil",
+                "url": "https://api.github.local/repos/acme/anvil",
+                "owner": author("acme")
+            }
+        });
+        WebhookEvent::try_from_header_and_body("push", &body.to_string()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_minimal_repository_is_fetched_once() {
+        let fetches = Arc::new(Mutex::new(0));
+        let counted = fetches.clone();
+        let mock = Router::new().route(
+            "/repositories/{id}",
+            get(move |Path(id): Path<u64>| async move {
+                *counted.lock().unwrap() += 1;
+                Json(json!({
+                    "id": id,
+                    "name": "anvil",
+                    "url": "https://api.github.local/repos/acme/anvil",
+                    "default_branch": "main",
+                    "visibility": "private"
+                }))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await });
+        let api = octocrab::Octocrab::builder()
+            .base_uri(format!("http://{addr}"))
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let ctx = EventContext::new(push_event(), None, api.clone());
+        assert_eq!(
+            ctx.repository_full()
+                .await
+                .unwrap()
+                .default_branch
+                .as_deref(),
+            Some("main")
+        );
+        ctx.repository_full().await.unwrap();
+        assert_eq!(*fetches.lock().unwrap(), 1);
+
+        let mut complete = push_event();
+        if let Some(ref mut repository) = complete.repository {
+            repository.default_branch = Some("trunk".into());
+            repository.visibility = Some("public".into());
+        }
+        let ctx = EventContext::new(complete, None, api);
+        assert_eq!(
+            ctx.repository_full()
+                .await
+                .unwrap()
+                .default_branch
+                .as_deref(),
+            Some("trunk")
+        );
+        assert_eq!(*fetches.lock().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_push_to_default_branch() {
+        let push_to = |r#ref: &str| {
+            let mut event = push_event();
+            if let WebhookEventPayload::Push(ref mut payload) = event.specific {
+                payload.r#ref = r#ref.to_string();
+            }
+            if let Some(ref mut repository) = event.repository {
+                repository.default_branch = Some("main".into());
+            }
+            EventContext::new(event, None, octocrab::Octocrab::default())
+        };
+
+        assert_eq!(
+            push_to("refs/heads/main").is_push_to_default_branch().await,
+            Some(true)
+        );
+        assert_eq!(
+            push_to("refs/heads/feature")
+                .is_push_to_default_branch()
+                .await,
+            Some(false)
+        );
+        let ctx = EventContext::new(
+            issue_comment_event("/deploy"),
+            None,
+            octocrab::Octocrab::default(),
+        );
+        assert_eq!(ctx.is_push_to_default_branch().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_commit_status_is_set_for_pushed_head() {
+        type Requests = Arc<Mutex<Vec<(String, String, String, serde_json::Value)>>>;
+        let requests: Requests = Default::default();
+        let recorded = requests.clone();
+        let mock = Router::new().route(
+            "/repos/{owner}/{repo}/statuses/{sha}",
+            post(
+                move |Path((owner, repo, sha)): Path<(String, String, String)>,
+                      Json(body): Json<serde_json::Value>| async move {
+                    recorded.lock().unwrap().push((owner, repo, sha, body));
+                    Json(json!({ "state": "success" }))
+                },
+            ),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await });
+        let api = octocrab::Octocrab::builder()
+            .base_uri(format!("http://{addr}"))
+            .unwrap()
+            .build()
+            .unwrap();
+        let ctx = EventContext::new(push_event(), None, api);
+
+        ctx.set_commit_status(
+            None,
+            StatusState::Success,
+            "wild-git-yonder/lint",
+            Some("All checks passed"),
+            Some("https://ci.acme.local/1"),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![(
+                "acme".to_string(),
+                "anvil".to_string(),
+                "d6fde92930d4715a2b49857d24b940956b26d2d3".to_string(),
+                json!({
+                    "state": "success",
+                    "context": "wild-git-yonder/lint",
+                    "description": "All checks passed",
+                    "target_url": "https://ci.acme.local/1"
+                })
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deployment_status_is_reported() {
+        type Requests = Arc<Mutex<Vec<(String, String, u64, serde_json::Value)>>>;
+        let requests: Requests = Default::default();
+        let recorded = requests.clone();
+        let mock = Router::new().route(
+            "/repos/{owner}/{repo}/deployments/{id}/statuses",
+            post(
+                move |Path((owner, repo, id)): Path<(String, String, u64)>,
+                      Json(body): Json<serde_json::Value>| async move {
+                    recorded.lock().unwrap().push((owner, repo, id, body));
+                    Json(json!({ "id": 1, "state": "success" }))
+                },
+            ),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await });
+        let api = octocrab::Octocrab::builder()
+            .base_uri(format!("http://{addr}"))
+            .unwrap()
+            .build()
+            .unwrap();
+        let mut body = serde_json::to_value(push_event()).unwrap();
+        let body = json!({
+            "action": "created",
+            "deployment": {
+                "id": 42,
+                "sha": "d6fde92930d4715a2b49857d24b940956b26d2d3",
+                "ref": "main",
+                "environment": "production",
+                "description": null
+            },
+            "workflow": null,
+            "workflow_run": null,
+            "repository": body["repository"].take(),
+        });
+        let event =
+            WebhookEvent::try_from_header_and_body("deployment", &body.to_string()).unwrap();
+        let ctx = EventContext::new(event, None, api);
+
+        let deployment = ctx.deployment().unwrap();
+        ctx.create_deployment_status(deployment.id, DeploymentState::Success, None)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            ctx.head_sha().as_deref(),
+            Some("d6fde92930d4715a2b49857d24b940956b26d2d3")
+        );
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![(
+                "acme".to_string(),
+                "anvil".to_string(),
+                42,
+                json!({ "state": "success" })
+            )]
+        );
+    }
+
+    #[test]
+    fn test_commit_status_accessors() {
+        let mut body = serde_json::to_value(push_event()).unwrap();
+        let body = json!({
+            "id": 1,
+            "sha": "d6fde92930d4715a2b49857d24b940956b26d2d3",
+            "name": "acme/anvil",
+            "state": "success",
+            "context": "ci/lint",
+            "description": "All checks passed",
+            "target_url": null,
+            "avatar_url": null,
+            "branches": [],
+            "commit": {},
+            "created_at": "2024-01-01T00:00:00Z",
+            "updated_at": "2024-01-01T00:00:00Z",
+            "repository": body["repository"].take(),
+        });
+        let event = WebhookEvent::try_from_header_and_body("status", &body.to_string()).unwrap();
+        let ctx = EventContext::new(event, None, ());
+
+        let status = ctx.commit_status().unwrap();
+
+        assert_eq!(status.state, CommitState::Success);
+        assert_eq!(status.sha, "d6fde92930d4715a2b49857d24b940956b26d2d3");
+        assert_eq!(status.context, "ci/lint");
+        assert_eq!(
+            ctx.head_sha().as_deref(),
+            Some("d6fde92930d4715a2b49857d24b940956b26d2d3")
+        );
+        assert!(ctx.check_run().is_none() && ctx.check_suite().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_not_modified_response_is_surfaced_distinctly() {
+        const ETAG: &str = r#""644b5b0155e6404a9cc4bd9d8b1ae730""#;
+        let mock = Router::new().route(
+            "/repos/acme/anvil/releases",
+            get(|headers: HeaderMap| async move {
+                if headers
+                    .get("if-none-match")
+                    .is_some_and(|etag| etag == ETAG)
+                {
+                    return StatusCode::NOT_MODIFIED.into_response();
+                }
+                (
+                    [
+                        ("etag", ETAG),
+                        ("last-modified", "Thu, 05 Jul 2012 15:31:30 GMT"),
+                    ],
+                    Json(json!([{ "tag_name": "v1.0.0" }])),
+                )
+                    .into_response()
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await });
+        let api = octocrab::Octocrab::builder()
+            .base_uri(format!("http://{addr}"))
+            .unwrap()
+            .build()
+            .unwrap();
+        let ctx = EventContext::new(push_event(), None, api);
+
+        let modified = ctx
+            .api()
+            .get_conditional("/repos/acme/anvil/releases", None)
+            .await
+            .unwrap();
+        let not_modified = ctx
+            .api()
+            .get_conditional("/repos/acme/anvil/releases", Some(ETAG))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            modified,
+            Conditional::Modified {
+                body: json!([{ "tag_name": "v1.0.0" }]),
+                etag: Some(ETAG.to_string()),
+                last_modified: Some("Thu, 05 Jul 2012 15:31:30 GMT".to_string()),
+            }
+        );
+        assert_eq!(not_modified, Conditional::NotModified);
+    }
+
+    async fn serve_large_file() -> octocrab::Octocrab {
+        let mock = Router::new().route(
+            "/repos/acme/anvil/contents/{*path}",
+            get(
+                |Path(path): Path<String>, Query(query): Query<HashMap<String, String>>| async move {
+                    assert_eq!(path, "diffs/large.patch");
+                    assert_eq!(query["ref"], "main");
+                    // pauses between the chunks so they can't arrive as one
+                    let chunks = futures_util::stream::unfold(0, |sent| async move {
+                        (sent < 16).then_some(())?;
+                        tokio::time::sleep(Duration::from_millis(5)).await;
+                        Some((Ok::<_, Infallible>(vec![b'+'; 64 * 1024]), sent + 1))
+                    });
+                    Body::from_stream(chunks)
+                },
+            ),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await });
+        octocrab::Octocrab::builder()
+            .base_uri(format!("http://{addr}"))
+            .unwrap()
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_content_is_streamed_in_chunks() {
+        let ctx = EventContext::new(push_event(), None, serve_large_file().await);
+
+        let mut content = ctx
+            .stream_content("acme", "anvil", "diffs/large.patch", Some("main"))
+            .await
+            .unwrap();
+        let (mut chunks, mut read) = (0, 0);
+        while let Some(chunk) = content.next().await {
+            chunks += 1;
+            read += chunk.unwrap().len();
+        }
+
+        assert_eq!(read, 16 * 64 * 1024);
+        assert!(chunks > 1, "the content arrived as a single chunk");
+    }
+
+    #[tokio::test]
+    async fn test_streamed_content_is_limited() {
+        let settings = HandlerSettings {
+            max_content_bytes: Some(100 * 1024),
+            ..Default::default()
+        };
+        let ctx = EventContext::new(push_event(), None, serve_large_file().await)
+            .with_settings(Arc::new(settings));
+
+        let content = ctx
+            .stream_content("acme", "anvil", "diffs/large.patch", Some("main"))
+            .await
+            .unwrap();
+        let chunks: Vec<_> = content.collect().await;
+
+        assert!(matches!(
+            chunks.iter().find(|chunk| chunk.is_err()),
+            Some(Err(ContextError::ContentTooLarge { limit: 102400 }))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_labels_are_added_and_removed() {
+        fn labels(names: &[&str]) -> Json<serde_json::Value> {
+            let url = "https://api.github.local/repos/acme/anvil/labels/triage";
+            Json(json!(names
+                .iter()
+                .map(|name| json!({
+                    "id": 1, "node_id": "dGVzdA==", "url": url, "name": name,
+                    "color": "f29513", "default": false
+                }))
+                .collect::<Vec<_>>()))
+        }
+
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let (added, removed) = (calls.clone(), calls.clone());
+        let mock = Router::new()
+            .route(
+                "/repos/acme/anvil/issues/3/labels",
+                post(move |Json(body): Json<serde_json::Value>| async move {
+                    added.lock().unwrap().push(("add".to_string(), body));
+                    labels(&["bug", "triage", "blocked"])
+                }),
+            )
+            .route(
+                "/repos/acme/anvil/issues/3/labels/{name}",
+                delete(move |Path(name): Path<String>| async move {
+                    removed
+                        .lock()
+                        .unwrap()
+                        .push(("remove".to_string(), json!(name)));
+                    labels(&["bug", "blocked"])
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await });
+        let api = octocrab::Octocrab::builder()
+            .base_uri(format!("http://{addr}"))
+            .unwrap()
+            .build()
+            .unwrap();
+        let ctx = EventContext::new(push_event(), None, api.clone());
+
+        let after_adding = ctx.add_labels(3, &["triage", "blocked"]).await.unwrap();
+        let after_removing = ctx.remove_label(3, "needs triage").await.unwrap();
+        let ping = WebhookEvent::try_from_header_and_body("ping", r#"{"hook_id":1}"#).unwrap();
+        let without_repository = EventContext::new(ping, None, api)
+            .add_labels(3, &["triage"])
+            .await;
+
+        assert_eq!(after_adding, ["bug", "triage", "blocked"]);
+        assert_eq!(after_removing, ["bug", "blocked"]);
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                (
+                    "add".to_string(),
+                    json!({ "labels": ["triage", "blocked"] })
+                ),
+                ("remove".to_string(), json!("needs triage")),
+            ]
+        );
+        assert!(matches!(
+            without_repository,
+            Err(ContextError::MissingRepository)
+        ));
+    }
+
+    fn pull_request_event() -> WebhookEvent {
+        let url = "https://api.github.local/repos/acme/anvil/pulls/8";
+        let body = json!({
+            "action": "opened",
+            "number": 8,
+            "pull_request": {
+                "url": url, "id": 8, "number": 8, "locked": false, "maintainer_can_modify": false,
+                "head": { "ref": "feature", "sha": "d6fde92930d4715a2b49857d24b940956b26d2d3" },
+                "base": { "ref": "main", "sha": "9049f1265b7d61be4a8904a9a27120d2064dab3b" }
+            },
+            "repository": {
+                "id": 1,
+                "name": "anvil",
+                "url": "https://api.github.local/repos/acme/anvil",
+                "owner": author("acme")
+            }
+        });
+        WebhookEvent::try_from_header_and_body("pull_request", &body.to_string()).unwrap()
+    }
+
+    /// Serves the merge endpoint of pull request 8, merging unless it is `conflicting`.
+    async fn merge_api(
+        conflicting: bool,
+        requests: Arc<Mutex<Vec<serde_json::Value>>>,
+    ) -> Octocrab {
+        let mock = Router::new().route(
+            "/repos/acme/anvil/pulls/8/merge",
+            put(move |Json(body): Json<serde_json::Value>| async move {
+                requests.lock().unwrap().push(body);
+                match conflicting {
+                    true => (
+                        StatusCode::METHOD_NOT_ALLOWED,
+                        Json(json!({ "message": "Merge conflict" })),
+                    ),
+                    false => (
+                        StatusCode::OK,
+                        Json(json!({
+                            "sha": "6dcb09b5b57875f334f61aebed695e2e4193db5e",
+                            "merged": true,
+                            "message": "Pull Request successfully merged"
+                        })),
+                    ),
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await });
+        octocrab::Octocrab::builder()
+            .base_uri(format!("http://{addr}"))
+            .unwrap()
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_pull_request_is_squash_merged() {
+        let requests: Arc<Mutex<Vec<serde_json::Value>>> = Default::default();
+        let api = merge_api(false, requests.clone()).await;
+
+        let sha = EventContext::new(pull_request_event(), None, api.clone())
+            .merge_pull_request(8, MergeMethod::Squash)
+            .await
+            .unwrap();
+        let not_a_pull_request = EventContext::new(push_event(), None, api)
+            .merge_pull_request(8, MergeMethod::Squash)
+            .await;
+
+        assert_eq!(sha, "6dcb09b5b57875f334f61aebed695e2e4193db5e");
+        assert_eq!(
+            *requests.lock().unwrap(),
+            [json!({ "merge_method": "squash" })]
+        );
+        assert!(matches!(
+            not_a_pull_request,
+            Err(ContextError::MissingPullRequest)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_merge_conflict_is_surfaced_distinctly() {
+        let api = merge_api(true, Default::default()).await;
+
+        let merged = EventContext::new(pull_request_event(), None, api)
+            .merge_pull_request(8, MergeMethod::Merge)
+            .await;
+
+        assert!(
+            matches!(merged, Err(ContextError::NotMergeable { ref message }) if message == "Merge conflict"),
+            "{merged:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_run_annotations_are_sent_in_batches() {
+        use octocrab::params::checks::{
+            CheckRunConclusion, CheckRunOutputAnnotation, CheckRunOutputAnnotationLevel,
+        };
+
+        let updates: Arc<Mutex<Vec<serde_json::Value>>> = Default::default();
+        let recorded = updates.clone();
+        let mock = Router::new()
+            .route(
+                "/repos/acme/anvil/check-runs",
+                post(|| async {
+                    let url = "https://api.github.local/repos/acme/anvil/check-runs/4";
+                    Json(json!({
+                        "id": 4, "node_id": "Q1I=", "details_url": null, "head_sha": "abc",
+                        "url": url, "html_url": null, "conclusion": null,
+                        "started_at": null, "completed_at": null, "name": "lint",
+                        "pull_requests": [],
+                        "output": {
+                            "title": null, "summary": null, "text": null,
+                            "annotations_count": 0, "annotations_url": url
+                        }
+                    }))
+                }),
+            )
+            .route(
+                "/repos/acme/anvil/check-runs/4",
+                patch(move |Json(body): Json<serde_json::Value>| async move {
+                    recorded.lock().unwrap().push(body);
+                    Json(json!({}))
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await });
+        let api = octocrab::Octocrab::builder()
+            .base_uri(format!("http://{addr}"))
+            .unwrap()
+            .build()
+            .unwrap();
+        let ctx = EventContext::new(pull_request_event(), None, api);
+
+        let mut check_run = ctx.create_check_run("lint", "abc").await.unwrap();
+        for line in 1..=120 {
+            check_run
+                .add_annotation(CheckRunOutputAnnotation {
+                    path: "src/lib.rs".to_string(),
+                    start_line: line,
+                    end_line: line,
+                    start_column: None,
+                    end_column: None,
+                    annotation_level: CheckRunOutputAnnotationLevel::Warning,
+                    message: format!("line {line} is too long"),
+                    title: None,
+                    raw_details: None,
+                })
+                .await
+                .unwrap();
+        }
+        assert_eq!(check_run.id(), octocrab::models::CheckRunId(4));
+        check_run
+            .complete(CheckRunConclusion::Neutral, "120 warnings")
+            .await
+            .unwrap();
+
+        let updates = updates.lock().unwrap();
+        let batches = updates
+            .iter()
+            .map(|update| update["output"]["annotations"].as_array().unwrap().len())
+            .collect::<Vec<_>>();
+        assert_eq!(batches, [50, 50, 20]);
+        assert_eq!(updates[0]["status"], "in_progress");
+        assert_eq!(updates[0]["output"]["annotations"][0]["start_line"], 1);
+        assert_eq!(updates[2]["output"]["annotations"][19]["start_line"], 120);
+        assert_eq!(updates[2]["status"], "completed");
+        assert_eq!(updates[2]["conclusion"], "neutral");
+        assert_eq!(updates[2]["output"]["summary"], "120 warnings");
+    }
+
+    /// Records the dispatches, answering `403` for repository dispatches if `forbidden`.
+    async fn dispatch_api(
+        forbidden: bool,
+        requests: Arc<Mutex<Vec<(&'static str, serde_json::Value)>>>,
+    ) -> Octocrab {
+        let workflows = requests.clone();
+        let mock = Router::new()
+            .route(
+                "/repos/acme/anvil/actions/workflows/release.yml/dispatches",
+                post(move |Json(body): Json<serde_json::Value>| async move {
+                    workflows.lock().unwrap().push(("workflow", body));
+                    StatusCode::NO_CONTENT.into_response()
+                }),
+            )
+            .route(
+                "/repos/acme/anvil/dispatches",
+                post(move |Json(body): Json<serde_json::Value>| async move {
+                    requests.lock().unwrap().push(("repository", body));
+                    match forbidden {
+                        true => (
+                            StatusCode::FORBIDDEN,
+                            Json(json!({ "message": "Resource not accessible by integration" })),
+                        )
+                            .into_response(),
+                        false => StatusCode::NO_CONTENT.into_response(),
+                    }
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await });
+        octocrab::Octocrab::builder()
+            .base_uri(format!("http://{addr}"))
+            .unwrap()
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_workflow_and_repository_dispatches_are_sent() {
+        let requests: Arc<Mutex<Vec<(&'static str, serde_json::Value)>>> = Default::default();
+        let ctx = EventContext::new(
+            pull_request_event(),
+            None,
+            dispatch_api(false, requests.clone()).await,
+        );
+
+        ctx.trigger_workflow_dispatch("release.yml", "main", json!({ "version": "1.2.0" }))
+            .await
+            .unwrap();
+        ctx.trigger_repository_dispatch("deploy", json!({ "environment": "staging" }))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            *requests.lock().unwrap(),
+            [
+                (
+                    "workflow",
+                    json!({ "ref": "main", "inputs": { "version": "1.2.0" } })
+                ),
+                (
+                    "repository",
+                    json!({ "event_type": "deploy", "client_payload": { "environment": "staging" } })
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_forbidden_dispatch_names_the_missing_permission() {
+        let ctx = EventContext::new(
+            pull_request_event(),
+            None,
+            dispatch_api(true, Default::default()).await,
+        );
+
+        let dispatched = ctx.trigger_repository_dispatch("deploy", json!({})).await;
+
+        assert!(
+            matches!(
+                dispatched,
+                Err(ContextError::MissingPermission {
+                    permission: "contents: write",
+                    ..
+                })
+            ),
+            "{dispatched:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pull_request_diff_is_fetched() {
+        const DIFF: &str = "diff --git a/anvil.rs b/anvil.rs\n--- a/anvil.rs\n+++ b/anvil.rs\n";
+        let mock = Router::new().route(
+            "/repos/acme/anvil/pulls/8",
+            get(|headers: HeaderMap| async move {
+                assert_eq!(headers["accept"], "application/vnd.github.v3.diff");
+                DIFF
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await });
+        let api = octocrab::Octocrab::builder()
+            .base_uri(format!("http://{addr}"))
+            .unwrap()
+            .build()
+            .unwrap();
+        let event = pull_request_event();
+
+        let diff = EventContext::new(event, None, api.clone())
+            .pull_request_diff()
+            .await
+            .unwrap();
+        let not_a_pull_request = EventContext::new(push_event(), None, api)
+            .pull_request_diff()
+            .await;
+
+        assert_eq!(diff, DIFF);
+        assert!(matches!(
+            not_a_pull_request,
+            Err(ContextError::MissingPullRequest)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_reviewers_are_requested() {
+        let requests: Arc<Mutex<Vec<serde_json::Value>>> = Default::default();
+        let recorded = requests.clone();
+        let mock = Router::new().route(
+            "/repos/acme/anvil/pulls/8/requested_reviewers",
+            post(move |Json(body): Json<serde_json::Value>| async move {
+                recorded.lock().unwrap().push(body);
+                (StatusCode::CREATED, Json(json!({ "number": 8 })))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await });
+        let api = octocrab::Octocrab::builder()
+            .base_uri(format!("http://{addr}"))
+            .unwrap()
+            .build()
+            .unwrap();
+        let event = pull_request_event();
+
+        EventContext::new(event, None, api.clone())
+            .request_reviewers(8, &["wile", "roadrunner"], &["anvil-maintainers"])
+            .await
+            .unwrap();
+        let not_a_pull_request = EventContext::new(push_event(), None, api)
+            .request_reviewers(8, &["wile"], &[])
+            .await;
+
+        assert_eq!(
+            *requests.lock().unwrap(),
+            [json!({
+                "reviewers": ["wile", "roadrunner"],
+                "team_reviewers": ["anvil-maintainers"]
+            })]
+        );
+        assert!(matches!(
+            not_a_pull_request,
+            Err(ContextError::MissingPullRequest)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_secret_scanning_alert_is_resolved() {
+        let requests: Arc<Mutex<Vec<serde_json::Value>>> = Default::default();
+        let recorded = requests.clone();
+        let mock = Router::new().route(
+            "/repos/acme/anvil/secret-scanning/alerts/2",
+            patch(move |Json(body): Json<serde_json::Value>| async move {
+                recorded.lock().unwrap().push(body);
+                Json(json!({ "number": 2, "state": "resolved" }))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await });
+        let api = octocrab::Octocrab::builder()
+            .base_uri(format!("http://{addr}"))
+            .unwrap()
+            .build()
+            .unwrap();
+        let body = json!({
+            "action": "validated",
+            "alert": {
+                "number": 2, "state": "open", "secret_type": "github_personal_access_token",
+                "secret_type_display_name": "GitHub Personal Access Token"
+            },
+            "repository": {
+                "id": 1,
+                "name": "anvil",
+                "url": "https://api.github.local/repos/acme/anvil",
+                "owner": author("acme")
+            }
+        });
+        let event =
+            crate::payload::parse_event("secret_scanning_alert", body.to_string().as_bytes())
+                .unwrap();
+        let ctx = EventContext::new(event, None, api.clone());
+
+        assert_eq!(
+            ctx.secret_scanning_alert()
+                .map(|event| (event.action, event.alert.secret_type)),
+            Some((
+                "validated".to_string(),
+                "github_personal_access_token".to_string()
+            ))
+        );
+        ctx.close_alert("revoked", Some("rotated the token"))
+            .await
+            .unwrap();
+        let not_an_alert = EventContext::new(push_event(), None, api)
+            .close_alert("revoked", None)
+            .await;
+
+        assert_eq!(
+            *requests.lock().unwrap(),
+            [json!({
+                "state": "resolved",
+                "resolution": "revoked",
+                "resolution_comment": "rotated the token"
+            })]
+        );
+        assert!(matches!(not_an_alert, Err(ContextError::MissingAlert)));
+    }
+
+    #[tokio::test]
+    async fn test_marked_comment_is_updated_instead_of_duplicated() {
+        const MARKER: &str = "<!-- wild-git-yonder:lint -->";
+        let comment = |id: u64, body: &str| {
+            json!({
+                "id": id,
+                "node_id": format!("IC_{id}"),
+                "url": format!("https://api.github.local/repos/acme/anvil/issues/comments/{id}"),
+                "html_url": format!("https://github.local/acme/anvil/issues/3#issuecomment-{id}"),
+                "body": body,
+                "author_association": "NONE",
+                "user": author("wild-git-yonder[bot]"),
+                "created_at": "2024-01-01T00:00:00Z"
+            })
+        };
+        let written: Arc<Mutex<Vec<(String, serde_json::Value)>>> = Default::default();
+        let (updated, created) = (written.clone(), written.clone());
+        let listed = Json(json!([
+            comment(41, "Looks good to me"),
+            comment(42, &format!("2 lints failed\n\n{MARKER}")),
+        ]));
+        let mock = Router::new()
+            .route(
+                "/repos/acme/anvil/issues/3/comments",
+                get(move || async move { listed.clone() }).post(
+                    move |Json(body): Json<serde_json::Value>| async move {
+                        created
+                            .lock()
+                            .unwrap()
+                            .push(("create".into(), body.clone()));
+                        (
+                            StatusCode::CREATED,
+                            Json(comment(43, body["body"].as_str().unwrap())),
+                        )
+                    },
+                ),
+            )
+            .route(
+                "/repos/acme/anvil/issues/comments/42",
+                patch(move |Json(body): Json<serde_json::Value>| async move {
+                    updated
+                        .lock()
+                        .unwrap()
+                        .push(("update".into(), body.clone()));
+                    Json(comment(42, body["body"].as_str().unwrap()))
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await });
+        let api = octocrab::Octocrab::builder()
+            .base_uri(format!("http://{addr}"))
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let upserted = EventContext::new(push_event(), None, api)
+            .upsert_comment(3, MARKER, "All lints passed")
+            .await
+            .unwrap();
+
+        assert_eq!(upserted, UpsertedComment::Updated(CommentId(42)));
+        assert_eq!(
+            *written.lock().unwrap(),
+            [(
+                "update".to_string(),
+                json!({ "body": format!("All lints passed\n\n{MARKER}") })
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_release_asset_is_uploaded_with_its_content_type() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let upload_url =
+            format!("http://{addr}/uploads/acme/anvil/releases/7/assets{{?name,label}}");
+        let uploaded: Arc<Mutex<Vec<(String, String, Bytes)>>> = Default::default();
+        let recorded = uploaded.clone();
+        let mock = Router::new()
+            .route(
+                "/repos/acme/anvil/releases/7",
+                get(move || async move { Json(json!({ "id": 7, "upload_url": upload_url })) }),
+            )
+            .route(
+                "/uploads/acme/anvil/releases/7/assets",
+                post(
+                    move |Query(query): Query<HashMap<String, String>>,
+                          headers: HeaderMap,
+                          body: Bytes| async move {
+                        let content_type = headers["content-type"].to_str().unwrap().to_owned();
+                        recorded
+                            .lock()
+                            .unwrap()
+                            .push((query["name"].clone(), content_type.clone(), body.clone()));
+                        (
+                            StatusCode::CREATED,
+                            Json(json!({
+                                "id": 42,
+                                "name": query["name"],
+                                "content_type": content_type,
+                                "size": body.len(),
+                                "browser_download_url": "https://github.local/acme/anvil/releases/download/v1.0.0/anvil.tar.gz"
+                            })),
+                        )
+                    },
+                ),
+            );
+        tokio::spawn(async move { axum::serve(listener, mock).await });
+        let api = octocrab::Octocrab::builder()
+            .base_uri(format!("http://{addr}"))
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let asset = EventContext::new(push_event(), None, api)
+            .upload_release_asset(
+                7,
+                "anvil 1.0.tar.gz",
+                Bytes::from_static(b"\x1f\x8b"),
+                "application/gzip",
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(asset.id, 42);
+        assert_eq!(
+            *uploaded.lock().unwrap(),
+            [(
+                "anvil 1.0.tar.gz".to_string(),
+                "application/gzip".to_string(),
+                Bytes::from_static(b"\x1f\x8b")
+            )]
+        );
+    }
+}
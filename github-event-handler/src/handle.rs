@@ -1,57 +1,598 @@
 use crate::api::GitHubApi;
-use crate::authentication::InstallationAuthenticator;
-use octocrab::models::webhook_events::{
-    EventInstallation, WebhookEvent, WebhookEventPayload, WebhookEventType,
+use crate::authentication::{AuthenticatedClient, InstallationAuthenticator};
+use crate::context::{
+    event_action, installation_id, is_draft_pull_request, EventContext, HandlerSettings, TargetType,
 };
+use crate::handler::{GateDecision, HandlerFailure, Handlers, Retries};
+use crate::oauth::OAuthApp;
+use crate::rate_limit;
+use bytes::Bytes;
+use hyper::{HeaderMap, StatusCode};
+use octocrab::models::webhook_events::payload::{
+    CheckRunWebhookEventAction, CheckSuiteWebhookEventAction, InstallationWebhookEventAction,
+    IssueCommentWebhookEventAction, ReleaseWebhookEventAction,
+};
+use octocrab::models::webhook_events::{WebhookEvent, WebhookEventPayload, WebhookEventType};
+use octocrab::models::{InstallationId, Repository};
+use serde::Serialize;
 use snafu::{Backtrace, ResultExt, Snafu};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+/// Settings which influence which events are handled and how.
+#[derive(Debug, Clone, Default)]
+pub struct HandleOptions {
+    /// Acknowledge `pull_request` events for draft pull requests without handling them.
+    pub skip_draft_pull_requests: bool,
+    /// Actions handled per event type, e.g. `pull_request` to `opened` and `reopened`, events
+    /// with other actions are acknowledged without handling them. Event types without an entry
+    /// are handled whatever their action, as are deliveries without a body to read it from.
+    ///
+    /// Skipped `installation` events still update the cached state of their installation.
+    pub allowed_actions: Option<HashMap<String, HashSet<String>>>,
+    /// Handed to every handler through its [`EventContext`].
+    pub settings: Arc<HandlerSettings>,
+    /// How handlers failing with a retryable error are tried again.
+    pub retries: Retries,
+    /// Fail events for repositories outside the installation's repository selection instead
+    /// of handling them. The selection is only looked up if enabled.
+    pub reject_uncovered_repositories: bool,
+    /// Also reject `package` and `registry_package` events by their repository, packages are
+    /// often published by an account rather than the repository they name.
+    pub reject_uncovered_package_repositories: bool,
+    /// Name of a failed check run created on the event's head commit whenever a handler
+    /// fails, summarizing the first failure. Events without a commit only fail the delivery.
+    pub failure_check_run: Option<String>,
+    /// Forgets the cached user tokens of users revoking the App's authorization.
+    pub oauth: Option<OAuthApp>,
+    /// Repositories whose events are handled, events of others are acknowledged and skipped.
+    pub process_visibility: Visibility,
+    /// Installations whose events are handled, e.g. during a staged rollout. Events of others
+    /// are acknowledged and skipped before a token is minted for them.
+    pub allowed_installations: Option<HashSet<InstallationId>>,
+    /// Acknowledge events of suspended installations without handling them, instead of failing
+    /// them as no token can be minted. Known suspensions skip minting for
+    /// [`SUSPENSION_TTL`](crate::authentication::SUSPENSION_TTL), a mint is tried again after.
+    pub acknowledge_suspended_installations: bool,
+}
+
+/// Which repositories events are handled for, by their visibility. Internal repositories count
+/// as private.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Visibility {
+    Public,
+    Private,
+    #[default]
+    Both,
+}
+
+impl Visibility {
+    fn admits(self, private: bool) -> bool {
+        match self {
+            Visibility::Public => !private,
+            Visibility::Private => private,
+            Visibility::Both => true,
+        }
+    }
+}
+
+fn is_private(repository: &Repository) -> Option<bool> {
+    match repository.visibility.as_deref() {
+        Some(visibility) => Some(visibility != "public"),
+        None => repository.private,
+    }
+}
+
+/// What is known about a delivery besides its event.
+#[derive(Debug, Clone, Default)]
+pub struct Delivery {
+    /// Value of the `X-GitHub-Delivery` header.
+    pub id: Option<String>,
+    pub target_type: Option<TargetType>,
+    /// Value of the `X-GitHub-Hook-Installation-Target-ID` header.
+    pub target_id: Option<u64>,
+    /// The payload as it was signed.
+    pub body: Option<Bytes>,
+    /// Every header of the delivery.
+    pub headers: HeaderMap,
+    /// Version of the GitHub Enterprise Server which sent the delivery, `None` for github.com.
+    pub enterprise_version: Option<semver::Version>,
+}
+
+/// What happened to a delivery which was handled without errors.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeliveryOutcome {
+    pub status: OutcomeStatus,
+    pub installation: Option<InstallationId>,
+    /// Every handler invoked for the event, in the order they ran.
+    pub handlers: Vec<HandlerOutcome>,
+    /// Answer to the event itself, e.g. the zen of a ping.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<String>,
+    #[serde(rename = "duration_ms", serialize_with = "serialize_millis")]
+    pub duration: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutcomeStatus {
+    Handled,
+    /// Ignored before any handler ran, e.g. a draft pull request or an event nobody handles.
+    Skipped,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HandlerOutcome {
+    pub name: String,
+    pub result: HandlerStatus,
+}
+
+/// Only successful handlers end up in an outcome, failures fail the whole delivery.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HandlerStatus {
+    Succeeded,
+}
+
+fn serialize_millis<S: serde::Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(duration.as_millis() as u64)
+}
+
+/// The response and handlers of an event, before it is summarized as its outcome.
+#[derive(Default)]
+struct Handled {
+    response: Option<String>,
+    handlers: Vec<HandlerOutcome>,
+}
+
+impl From<Option<String>> for Handled {
+    fn from(response: Option<String>) -> Self {
+        Self {
+            response,
+            handlers: Vec::new(),
+        }
+    }
+}
 
 pub async fn handle_event<C>(
-    app_client: C,
+    app_client: AuthenticatedClient<C>,
+    handlers: &Handlers<C::Api>,
+    options: &HandleOptions,
     event: WebhookEvent,
-) -> Result<Option<String>, HandleEventError>
+    delivery: Delivery,
+    cancellation: CancellationToken,
+) -> Result<DeliveryOutcome, HandleEventError>
 where
     C: InstallationAuthenticator,
 {
-    let id = match event.installation {
-        Some(EventInstallation::Full(ref installation)) => installation.id,
-        Some(EventInstallation::Minimal(ref installation)) => installation.id,
-        None if event.kind == WebhookEventType::Ping => return Ok(Some("pong".to_string())),
-        None => return MissingInstallationSnafu.fail(),
+    let started = Instant::now();
+    let installation = installation_id(&event);
+    let handled = rate_limit::with_policy(
+        options.settings.rate_limit,
+        handle(app_client, handlers, options, event, delivery, cancellation),
+    )
+    .await?;
+    let status = match (handled.response.is_some(), handled.handlers.is_empty()) {
+        (false, true) => OutcomeStatus::Skipped,
+        _ => OutcomeStatus::Handled,
     };
-    let api_client = app_client
-        .for_installation(id)
-        .await
-        .map_err(|err| Box::new(err) as _)
-        .context(InstallationAuthenticationSnafu)?;
-    match event.specific {
-        WebhookEventPayload::Ping(ping) => Ok(ping.zen),
-        WebhookEventPayload::PullRequest(pr) => {
-            let Some(repository) = event.repository else {
+    Ok(DeliveryOutcome {
+        status,
+        installation,
+        handlers: handled.handlers,
+        response: handled.response,
+        duration: started.elapsed(),
+    })
+}
+
+/// The action of the event, if it isn't among the allowed actions of its event type.
+fn disallowed_action(
+    options: &HandleOptions,
+    event: &WebhookEvent,
+    body: Option<&[u8]>,
+) -> Option<String> {
+    let kind = serde_json::to_value(&event.kind).ok()?;
+    let allowed = options.allowed_actions.as_ref()?.get(kind.as_str()?)?;
+    event_action(body?).filter(|action| !allowed.contains(action))
+}
+
+async fn handle<C>(
+    app_client: AuthenticatedClient<C>,
+    handlers: &Handlers<C::Api>,
+    options: &HandleOptions,
+    event: WebhookEvent,
+    delivery: Delivery,
+    cancellation: CancellationToken,
+) -> Result<Handled, HandleEventError>
+where
+    C: InstallationAuthenticator,
+{
+    if let WebhookEventPayload::GithubAppAuthorization(_) = event.specific {
+        if let Some(user) = options
+            .oauth
+            .as_ref()
+            .and_then(|oauth| oauth.forget_revoked(&event))
+        {
+            tracing::info!(
+                user = user.0,
+                "user revoked the authorization, forgot their token"
+            );
+        }
+        // boxed, the future of `handle` is large enough already
+        return Box::pin(handle_as_app(
+            app_client,
+            handlers,
+            options,
+            event,
+            delivery,
+            cancellation,
+        ))
+        .await;
+    }
+    let id = match (installation_id(&event), &event.installation) {
+        (Some(id), _) => id,
+        (None, None) if event.kind == WebhookEventType::Ping => {
+            return Ok(Some("pong".to_string()).into())
+        }
+        (None, None) => return MissingInstallationSnafu.fail(),
+        (None, Some(_)) => {
+            tracing::warn!(kind = ?event.kind, "skipping event with an invalid installation id");
+            return Ok(Handled::default());
+        }
+    };
+    if options
+        .allowed_installations
+        .as_ref()
+        .is_some_and(|allowed| !allowed.contains(&id))
+    {
+        tracing::debug!(
+            installation = id.0,
+            "skipping event of a disallowed installation"
+        );
+        return Ok(Handled::default());
+    }
+    if options.skip_draft_pull_requests
+        && event.kind == WebhookEventType::PullRequest
+        && is_draft_pull_request(&event) == Some(true)
+    {
+        tracing::debug!("skipping draft pull request");
+        return Ok(Handled::default());
+    }
+    if let WebhookEventPayload::Installation(ref installation) = event.specific {
+        // cached tokens and repository mappings carry the old permissions and state
+        match installation.action {
+            InstallationWebhookEventAction::NewPermissionsAccepted => {
+                app_client.forget_installation(id)
+            }
+            InstallationWebhookEventAction::Unsuspend => {
+                app_client.forget_installation(id);
+                app_client.set_suspended(id, false);
+            }
+            InstallationWebhookEventAction::Suspend => {
+                app_client.forget_installation(id);
+                app_client.set_suspended(id, true);
+                tracing::info!(
+                    installation = id.0,
+                    "installation suspended, skipping handlers as no token can be minted"
+                );
+                return Ok(Handled::default());
+            }
+            _ => {}
+        }
+    }
+    if let Some(action) = disallowed_action(options, &event, delivery.body.as_deref()) {
+        tracing::debug!(action, "skipping event with a disallowed action");
+        return Ok(Handled::default());
+    }
+    if options.acknowledge_suspended_installations && app_client.is_suspended(id) {
+        tracing::info!(
+            installation = id.0,
+            "skipping event of a suspended installation"
+        );
+        return Ok(Handled::default());
+    }
+    let account_login = match event.installation {
+        Some(ref installation) => app_client
+            .account_login(installation)
+            .await
+            .inspect_err(|err| tracing::warn!(%err, "unable to resolve installation account"))
+            .ok(),
+        None => None,
+    };
+    let api_client = match app_client.installation(id).await {
+        Ok(api_client) => api_client,
+        Err(err) if options.acknowledge_suspended_installations && C::is_suspended(&err) => {
+            tracing::info!(
+                installation = id.0,
+                %err,
+                "skipping event of a suspended installation"
+            );
+            return Ok(Handled::default());
+        }
+        Err(err) => {
+            return Err(Box::new(err) as _).context(InstallationAuthenticationSnafu);
+        }
+    };
+    let covers_repository = match (&event.repository, &event.specific) {
+        // listing the selection takes a call per page, only worth it to reject events
+        _ if !options.reject_uncovered_repositories => None,
+        // the selection is about to change, these events name repositories already removed
+        (_, WebhookEventPayload::Installation(_))
+        | (_, WebhookEventPayload::InstallationRepositories(_))
+        | (None, _) => None,
+        (_, WebhookEventPayload::Package(_) | WebhookEventPayload::RegistryPackage(_))
+            if !options.reject_uncovered_package_repositories =>
+        {
+            None
+        }
+        (Some(repository), _) => app_client
+            .installation_repositories(id)
+            .await
+            .inspect_err(|err| tracing::warn!(%err, "unable to list installation repositories"))
+            .ok()
+            .map(|accessible| accessible.iter().any(|other| other.id == repository.id)),
+    };
+    if options.reject_uncovered_repositories && covers_repository == Some(false) {
+        let repository = event.repository.as_ref().map(|repository| {
+            repository
+                .full_name
+                .clone()
+                .unwrap_or_else(|| repository.name.clone())
+        });
+        return UncoveredRepositorySnafu {
+            installation: id,
+            repository: repository.unwrap_or_default(),
+        }
+        .fail();
+    }
+    let ctx = event_context(
+        event,
+        account_login,
+        api_client,
+        delivery,
+        options,
+        cancellation,
+    );
+    if options.process_visibility != Visibility::Both && ctx.event().repository.is_some() {
+        let private = match ctx.event().repository.as_ref().and_then(is_private) {
+            Some(private) => Some(private),
+            // payloads of some events only carry parts of the repository
+            None => ctx
+                .repository_full()
+                .await
+                .inspect_err(|err| tracing::warn!(%err, "unable to fetch the repository"))
+                .ok()
+                .and_then(is_private),
+        };
+        match private {
+            Some(private) if options.process_visibility.admits(private) => {}
+            Some(private) => {
+                tracing::debug!(
+                    private,
+                    "skipping event of a repository of another visibility"
+                );
+                return Ok(Handled::default());
+            }
+            None => {
+                tracing::warn!("skipping event of a repository of unknown visibility");
+                return Ok(Handled::default());
+            }
+        }
+    }
+    if let GateDecision::Reject { status, reason } = handlers.admit(&ctx).await {
+        return RejectedSnafu { status, reason }.fail();
+    }
+    let event = ctx.event();
+    let response = match event.specific {
+        WebhookEventPayload::Ping(ref ping) => ping.zen.clone(),
+        WebhookEventPayload::PullRequest(ref pr) => {
+            let Some(ref repository) = event.repository else {
                 return MissingRepositorySnafu.fail();
             };
             let sha = &pr.pull_request.head.sha;
-            api_client
-                .create_commit_status(&repository, sha)
+            ctx.api()
+                .create_commit_status(repository, sha)
                 .await
                 .map_err(|err| Box::new(err) as _)
-                .context(EventHandlingSnafu { event: event.kind })?;
-            Ok(None)
+                .context(EventHandlingSnafu {
+                    event: event.kind.clone(),
+                })?;
+            None
         }
-        WebhookEventPayload::Push(_) => Ok(None),
-        WebhookEventPayload::CheckRun(_) => Ok(None),
-        WebhookEventPayload::CheckSuite(check) => {
-            let Some(_repository) = event.repository else {
+        WebhookEventPayload::PullRequestReview(_)
+        | WebhookEventPayload::PullRequestReviewComment(_) => {
+            if event.repository.is_none() {
+                return MissingRepositorySnafu.fail();
+            }
+            None
+        }
+        WebhookEventPayload::WorkflowRun(_)
+        | WebhookEventPayload::WorkflowJob(_)
+        | WebhookEventPayload::Deployment(_)
+        | WebhookEventPayload::DeploymentStatus(_)
+        | WebhookEventPayload::Status(_)
+        | WebhookEventPayload::Create(_)
+        | WebhookEventPayload::Delete(_) => {
+            if event.repository.is_none() {
+                return MissingRepositorySnafu.fail();
+            }
+            None
+        }
+        WebhookEventPayload::InstallationRepositories(_) => {
+            app_client.invalidate_repositories(id);
+            None
+        }
+        WebhookEventPayload::IssueComment(ref comment) => {
+            if event.repository.is_none() {
+                return MissingRepositorySnafu.fail();
+            }
+            // commands are only run once, not again whenever their comment changes
+            match comment.action {
+                IssueCommentWebhookEventAction::Created => None,
+                _ => {
+                    tracing::debug!(action = ?comment.action, "ignoring issue comment");
+                    return Ok(Handled::default());
+                }
+            }
+        }
+        WebhookEventPayload::Release(ref release) => {
+            if event.repository.is_none() {
+                return MissingRepositorySnafu.fail();
+            }
+            match release.action {
+                ReleaseWebhookEventAction::Published
+                | ReleaseWebhookEventAction::Edited
+                | ReleaseWebhookEventAction::Deleted => None,
+                _ => {
+                    tracing::debug!(action = ?release.action, "ignoring release");
+                    return Ok(Handled::default());
+                }
+            }
+        }
+        WebhookEventPayload::CheckRun(ref check) => match check.action {
+            CheckRunWebhookEventAction::Rerequested
+            | CheckRunWebhookEventAction::RequestedAction => None,
+            _ => {
+                tracing::debug!(action = ?check.action, "ignoring check run");
+                return Ok(Handled::default());
+            }
+        },
+        WebhookEventPayload::CheckSuite(ref check) => {
+            let Some(ref _repository) = event.repository else {
                 return MissingRepositorySnafu.fail();
             };
-            // TODO: need to parse check.check_suite or check.enterprise as it's currently just a json object
-            tracing::debug!(check_suite = ?check, "handling check suite");
-            Ok(None)
+            match check.action {
+                CheckSuiteWebhookEventAction::Requested
+                | CheckSuiteWebhookEventAction::Rerequested => None,
+                _ => {
+                    tracing::debug!(action = ?check.action, "ignoring check suite");
+                    return Ok(Handled::default());
+                }
+            }
         }
-        _ => {
-            tracing::debug!(kind = ?event.kind, "unhandled event");
-            Ok(None)
+        _ => None,
+    };
+    run_handlers(&ctx, handlers, options, response).await
+}
+
+/// Handles an event sent to the App itself, without an installation to mint a token for.
+async fn handle_as_app<C>(
+    app_client: AuthenticatedClient<C>,
+    handlers: &Handlers<C::Api>,
+    options: &HandleOptions,
+    event: WebhookEvent,
+    delivery: Delivery,
+    cancellation: CancellationToken,
+) -> Result<Handled, HandleEventError>
+where
+    C: InstallationAuthenticator,
+{
+    let api_client = match app_client.client.for_app().await {
+        Ok(api_client) => api_client,
+        Err(err) => return Err(Box::new(err) as _).context(InstallationAuthenticationSnafu),
+    };
+    let ctx = event_context(event, None, api_client, delivery, options, cancellation);
+    if let GateDecision::Reject { status, reason } = handlers.admit(&ctx).await {
+        return RejectedSnafu { status, reason }.fail();
+    }
+    run_handlers(&ctx, handlers, options, None).await
+}
+
+fn event_context<A: GitHubApi>(
+    event: WebhookEvent,
+    account_login: Option<String>,
+    api_client: A,
+    delivery: Delivery,
+    options: &HandleOptions,
+    cancellation: CancellationToken,
+) -> EventContext<A> {
+    EventContext::new(event, account_login, api_client)
+        .with_target_type(delivery.target_type)
+        .with_target_id(delivery.target_id)
+        .with_delivery(delivery.id, delivery.body)
+        .with_headers(delivery.headers)
+        .with_enterprise_version(delivery.enterprise_version)
+        .with_settings(options.settings.clone())
+        .with_cancellation(cancellation)
+}
+
+/// Dispatches the event to the handlers, failures are reported as a check run if configured.
+async fn run_handlers<A: GitHubApi>(
+    ctx: &EventContext<A>,
+    handlers: &Handlers<A>,
+    options: &HandleOptions,
+    response: Option<String>,
+) -> Result<Handled, HandleEventError> {
+    let event = ctx.event();
+    let dispatched = handlers.dispatch(ctx, &options.retries).await;
+    if dispatched.invoked() == 0 {
+        tracing::debug!(kind = ?event.kind, "unhandled event");
+    }
+    let (invoked, failed) = (dispatched.invoked(), dispatched.failed());
+    if let Some(Err(source)) = dispatched
+        .outcomes
+        .into_iter()
+        .find(|outcome| outcome.is_err())
+    {
+        if let Some(ref name) = options.failure_check_run {
+            report_failure(ctx, name, &source).await;
         }
+        return Err(source).context(HandlersFailedSnafu {
+            event: event.kind.clone(),
+            failed,
+            invoked,
+        });
+    }
+    Ok(Handled {
+        response,
+        handlers: dispatched
+            .names
+            .into_iter()
+            .map(|name| HandlerOutcome {
+                name,
+                result: HandlerStatus::Succeeded,
+            })
+            .collect(),
+    })
+}
+
+/// Check run summaries are limited to 65535 characters, errors are cut off far earlier.
+const FAILURE_SUMMARY_CHARS: usize = 1000;
+
+/// Failing the check run is best effort, the delivery fails either way.
+async fn report_failure<A: GitHubApi>(ctx: &EventContext<A>, name: &str, failure: &HandlerFailure) {
+    let (Some(repository), Some(sha)) = (&ctx.event().repository, ctx.head_sha()) else {
+        return;
+    };
+    let summary = failure_summary(failure);
+    if let Err(err) = ctx
+        .api()
+        .create_failed_check_run(repository, name, &sha, "A handler failed", &summary)
+        .await
+    {
+        tracing::warn!(%err, "unable to report the handler failure as a check run");
+    }
+}
+
+/// The failure as a code block, without control characters and fences which would break out
+/// of it.
+fn failure_summary(failure: &HandlerFailure) -> String {
+    let error = failure.to_string().replace("```", "'''");
+    let mut summary: String = error
+        .chars()
+        .filter(|c| *c == '\n' || !c.is_control())
+        .take(FAILURE_SUMMARY_CHARS)
+        .collect();
+    if error.chars().count() > FAILURE_SUMMARY_CHARS {
+        summary.push('…');
     }
+    format!("```\n{summary}\n```")
 }
 
 #[derive(Debug, Snafu)]
@@ -60,15 +601,33 @@ pub enum HandleEventError {
     MissingInstallation,
     #[snafu(display("Unable to authenticate installation"))]
     InstallationAuthentication {
-        source: Box<dyn std::error::Error>,
+        source: Box<dyn std::error::Error + Send + Sync>,
         backtrace: Backtrace,
     },
     #[snafu(display("Missing repository in the event"))]
     MissingRepository,
+    #[snafu(display("Repository {repository} is not accessible to installation {installation}"))]
+    UncoveredRepository {
+        installation: InstallationId,
+        repository: String,
+    },
+    #[snafu(display("The gate rejected the delivery with {status}: {reason}"))]
+    Rejected { status: StatusCode, reason: String },
     #[snafu(display("Failed to handle event: {:?}", event))]
     EventHandling {
         event: WebhookEventType,
-        source: Box<dyn std::error::Error>,
+        source: Box<dyn std::error::Error + Send + Sync>,
+        backtrace: Backtrace,
+    },
+    /// All handlers ran, the first failure is the source.
+    #[snafu(display(
+        "{failed} of {invoked} handlers failed for event {event:?}, first: {source}"
+    ))]
+    HandlersFailed {
+        event: WebhookEventType,
+        failed: usize,
+        invoked: usize,
+        source: HandlerFailure,
         backtrace: Backtrace,
     },
 }
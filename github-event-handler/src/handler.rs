@@ -0,0 +1,604 @@
+use crate::context::EventContext;
+use bytes::Bytes;
+use futures_util::future::BoxFuture;
+use futures_util::FutureExt;
+use hyper::{HeaderMap, StatusCode};
+use octocrab::models::webhook_events::WebhookEventType;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use snafu::Snafu;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub type HandlerResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+/// Errors handlers return to tell transient failures from terminal ones, any other error is
+/// terminal.
+#[derive(Debug, Snafu)]
+pub enum HandlerError {
+    /// The handler is tried again after a backoff, e.g. after a timeout or rate limit.
+    #[snafu(display("Transient failure: {source}"))]
+    Retryable {
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    #[snafu(display("{source}"))]
+    Terminal {
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+}
+
+#[derive(Debug, Snafu)]
+pub enum HandlerFailure {
+    #[snafu(display("Handler returned an error: {source}"))]
+    Failed {
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    #[snafu(display("Handler panicked: {message}"))]
+    Panicked { message: String },
+    #[snafu(display("Handler failed {attempts} times, last: {source}"))]
+    RetriesExhausted {
+        attempts: u32,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+}
+
+/// How often handlers failing with [`HandlerError::Retryable`] are tried, waiting an
+/// exponentially growing and randomly jittered delay in between.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Attempts including the first one, `1` disables retries.
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Full jitter, a random delay up to the exponential backoff of the attempt.
+    fn delay(&self, attempt: u32, jitter: &dyn Jitter) -> Duration {
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+        backoff.mul_f64(jitter.factor().clamp(0.0, 1.0))
+    }
+}
+
+/// Picks the share of the backoff a retry waits, seeded for reproducible schedules.
+pub trait Jitter: std::fmt::Debug + Send + Sync {
+    /// A factor within `0.0..=1.0`.
+    fn factor(&self) -> f64;
+}
+
+/// Jitter of the thread's random number generator.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RandomJitter;
+
+impl Jitter for RandomJitter {
+    fn factor(&self) -> f64 {
+        rand::thread_rng().gen_range(0.0..=1.0)
+    }
+}
+
+/// Jitter of a generator seeded with a fixed value, its sequence is the same on every run.
+#[derive(Debug)]
+pub struct SeededJitter(Mutex<StdRng>);
+
+impl SeededJitter {
+    pub fn new(seed: u64) -> Self {
+        Self(Mutex::new(StdRng::seed_from_u64(seed)))
+    }
+}
+
+impl Jitter for SeededJitter {
+    fn factor(&self) -> f64 {
+        self.0.lock().unwrap().gen_range(0.0..=1.0)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(10),
+        }
+    }
+}
+
+/// A handler which failed with retryable errors until it ran out of attempts.
+#[derive(Debug, Clone)]
+pub struct DeadLetter {
+    pub kind: WebhookEventType,
+    pub delivery_id: Option<String>,
+    pub attempts: u32,
+    pub error: String,
+    /// The payload as it was signed, `None` for events recovered without one.
+    pub body: Option<Bytes>,
+    /// Every header of the delivery, enough to replay it along the body.
+    pub headers: HeaderMap,
+}
+
+/// Receives the handlers which ran out of attempts, e.g. to store them for a manual replay.
+pub trait DeadLetters: std::fmt::Debug + Send + Sync {
+    fn dead_letter(&self, letter: DeadLetter);
+}
+
+/// Outcome of every handler invoked for an event, in the order they ran.
+#[derive(Debug, Default)]
+pub struct Dispatched {
+    pub outcomes: Vec<Result<(), HandlerFailure>>,
+    /// [Names](EventHandler::name) of the handlers, in the order of their outcomes.
+    pub names: Vec<String>,
+}
+
+impl Dispatched {
+    pub fn invoked(&self) -> usize {
+        self.outcomes.len()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.outcomes
+            .iter()
+            .filter(|outcome| outcome.is_err())
+            .count()
+    }
+}
+
+/// User provided logic invoked for the events it has been registered for.
+pub trait EventHandler<A>: Send + Sync {
+    fn handle<'a>(&'a self, ctx: &'a EventContext<A>) -> BoxFuture<'a, HandlerResult>;
+
+    /// Identifies the handler in delivery outcomes, its type name without path and generics
+    /// unless overridden.
+    fn name(&self) -> &str {
+        let name = std::any::type_name::<Self>();
+        let name = name.split('<').next().unwrap_or(name);
+        name.rsplit("::").next().unwrap_or(name)
+    }
+}
+
+/// Whether a delivery may be handled, decided by a [`Gate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GateDecision {
+    Allow,
+    /// The delivery is answered with `status` and `reason`, no handler runs.
+    Reject {
+        status: StatusCode,
+        reason: String,
+    },
+}
+
+/// Decides whether a verified delivery is handled at all, e.g. by feature flags or a
+/// maintenance window of a single repository.
+pub trait Gate<A>: Send + Sync {
+    fn allow<'a>(&'a self, ctx: &'a EventContext<A>) -> BoxFuture<'a, GateDecision>;
+}
+
+/// Registry of event handlers, keyed by the event type they are interested in.
+///
+/// Handlers run in ascending priority, handlers with the same priority in registration order.
+pub struct Handlers<A> {
+    handlers: Vec<(WebhookEventType, i32, Arc<dyn EventHandler<A>>)>,
+    fallback: Option<Arc<dyn EventHandler<A>>>,
+    gate: Option<Arc<dyn Gate<A>>>,
+}
+
+impl<A> Default for Handlers<A> {
+    fn default() -> Self {
+        Self {
+            handlers: Vec::new(),
+            fallback: None,
+            gate: None,
+        }
+    }
+}
+
+impl<A> Handlers<A> {
+    /// Registers the handler with the default priority of `0`.
+    pub fn on(self, kind: WebhookEventType, handler: impl EventHandler<A> + 'static) -> Self {
+        self.on_with_priority(kind, 0, handler)
+    }
+
+    /// Registers the handler to run before all handlers with a higher priority, e.g. a negative
+    /// priority runs a validation handler before the handlers registered with [`Handlers::on`].
+    pub fn on_with_priority(
+        mut self,
+        kind: WebhookEventType,
+        priority: i32,
+        handler: impl EventHandler<A> + 'static,
+    ) -> Self {
+        let index = self
+            .handlers
+            .partition_point(|(_, registered, _)| *registered <= priority);
+        self.handlers
+            .insert(index, (kind, priority, Arc::new(handler)));
+        self
+    }
+
+    /// Registers the handler invoked for events without a handler of their own, e.g. to log or
+    /// forward them. Events which are ignored before dispatching don't reach it.
+    pub fn default_handler(mut self, handler: impl EventHandler<A> + 'static) -> Self {
+        self.fallback = Some(Arc::new(handler));
+        self
+    }
+
+    /// Sets the gate consulted for every delivery before anything is done with it.
+    pub fn gate(mut self, gate: impl Gate<A> + 'static) -> Self {
+        self.gate = Some(Arc::new(gate));
+        self
+    }
+
+    /// Allows every delivery if no gate is set.
+    pub(crate) async fn admit(&self, ctx: &EventContext<A>) -> GateDecision {
+        match self.gate {
+            Some(ref gate) => gate.allow(ctx).await,
+            None => GateDecision::Allow,
+        }
+    }
+
+    /// Runs all handlers registered for the event's type in priority order, a handler which
+    /// fails or panics doesn't keep the remaining ones from running.
+    pub(crate) async fn dispatch(&self, ctx: &EventContext<A>, retries: &Retries) -> Dispatched {
+        let mut dispatched = Dispatched::default();
+        for (_, _, handler) in self
+            .handlers
+            .iter()
+            .filter(|(kind, _, _)| *kind == ctx.event().kind)
+        {
+            dispatched.names.push(handler.name().to_owned());
+            dispatched
+                .outcomes
+                .push(retries.run(handler.as_ref(), ctx).await);
+        }
+        if let (0, Some(fallback)) = (dispatched.invoked(), &self.fallback) {
+            dispatched.names.push(fallback.name().to_owned());
+            dispatched
+                .outcomes
+                .push(retries.run(fallback.as_ref(), ctx).await);
+        }
+        dispatched
+    }
+}
+
+/// Retries transient handler failures and hands those out of attempts to the dead letters.
+#[derive(Debug, Clone)]
+pub struct Retries {
+    pub policy: RetryPolicy,
+    pub dead_letters: Option<Arc<dyn DeadLetters>>,
+    pub jitter: Arc<dyn Jitter>,
+}
+
+impl Default for Retries {
+    fn default() -> Self {
+        Self {
+            policy: RetryPolicy::default(),
+            dead_letters: None,
+            jitter: Arc::new(RandomJitter),
+        }
+    }
+}
+
+impl Retries {
+    async fn run<A>(
+        &self,
+        handler: &dyn EventHandler<A>,
+        ctx: &EventContext<A>,
+    ) -> Result<(), HandlerFailure> {
+        let mut attempt = 1;
+        loop {
+            let source = match run(handler, ctx).await {
+                Err(HandlerFailure::Failed { source }) => source,
+                outcome => return outcome,
+            };
+            let source = match source.downcast::<HandlerError>() {
+                Ok(error) => match *error {
+                    HandlerError::Retryable { source } => source,
+                    HandlerError::Terminal { source } => {
+                        return Err(HandlerFailure::Failed { source })
+                    }
+                },
+                Err(source) => return Err(HandlerFailure::Failed { source }),
+            };
+            if attempt >= self.policy.max_attempts {
+                tracing::error!(
+                    attempts = attempt,
+                    kind = ?ctx.event().kind,
+                    "dead-lettering handler, it ran out of attempts"
+                );
+                if let Some(dead_letters) = &self.dead_letters {
+                    dead_letters.dead_letter(DeadLetter {
+                        kind: ctx.event().kind.clone(),
+                        delivery_id: ctx.delivery_id().map(str::to_owned),
+                        attempts: attempt,
+                        error: source.to_string(),
+                        body: ctx.raw_body().cloned(),
+                        headers: ctx.headers().clone(),
+                    });
+                }
+                return Err(HandlerFailure::RetriesExhausted {
+                    attempts: attempt,
+                    source,
+                });
+            }
+            tracing::warn!(%source, attempt, kind = ?ctx.event().kind, "retrying event handler");
+            tokio::select! {
+                _ = tokio::time::sleep(self.policy.delay(attempt, self.jitter.as_ref())) => {}
+                _ = ctx.cancellation().cancelled() => {
+                    return Err(HandlerFailure::Failed { source });
+                }
+            }
+            attempt += 1;
+        }
+    }
+}
+
+async fn run<A>(
+    handler: &dyn EventHandler<A>,
+    ctx: &EventContext<A>,
+) -> Result<(), HandlerFailure> {
+    let outcome = match AssertUnwindSafe(async { handler.handle(ctx).await })
+        .catch_unwind()
+        .await
+    {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(source)) => Err(HandlerFailure::Failed { source }),
+        Err(panic) => Err(HandlerFailure::Panicked {
+            message: panic
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_default(),
+        }),
+    };
+    if let Err(ref failure) = outcome {
+        tracing::warn!(%failure, kind = ?ctx.event().kind, "event handler failed");
+    }
+    outcome
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        DeadLetter, DeadLetters, EventHandler, HandlerError, HandlerFailure, HandlerResult,
+        Handlers, Jitter, Retries, RetryPolicy, SeededJitter,
+    };
+    use crate::context::EventContext;
+    use futures_util::future::BoxFuture;
+    use octocrab::models::webhook_events::{WebhookEvent, WebhookEventType};
+    use serde_json::json;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    struct Recording(&'static str, Arc<Mutex<Vec<&'static str>>>);
+
+    impl EventHandler<()> for Recording {
+        fn handle<'a>(&'a self, _ctx: &'a EventContext<()>) -> BoxFuture<'a, HandlerResult> {
+            self.1.lock().unwrap().push(self.0);
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handlers_run_in_priority_then_registration_order() {
+        let executed = Arc::new(Mutex::new(Vec::new()));
+        let recording = |name| Recording(name, executed.clone());
+        let handlers = Handlers::default()
+            .on(WebhookEventType::Ping, recording("notify"))
+            .on_with_priority(WebhookEventType::Ping, 10, recording("audit"))
+            .on_with_priority(WebhookEventType::Ping, -10, recording("validate"))
+            .on(WebhookEventType::Ping, recording("label"))
+            .on_with_priority(WebhookEventType::Push, -20, recording("push"));
+        let event = WebhookEvent::try_from_header_and_body("ping", r#"{"hook_id":1}"#).unwrap();
+
+        let dispatched = handlers
+            .dispatch(&EventContext::new(event, None, ()), &Default::default())
+            .await;
+
+        assert_eq!(dispatched.invoked(), 4);
+        assert_eq!(
+            *executed.lock().unwrap(),
+            ["validate", "notify", "label", "audit"]
+        );
+    }
+
+    struct Failing(Arc<Mutex<Vec<&'static str>>>);
+
+    impl EventHandler<()> for Failing {
+        fn handle<'a>(&'a self, _ctx: &'a EventContext<()>) -> BoxFuture<'a, HandlerResult> {
+            self.0.lock().unwrap().push("failing");
+            Box::pin(async { Err("the anvil missed".into()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failing_handler_does_not_stop_the_others() {
+        let executed = Arc::new(Mutex::new(Vec::new()));
+        let handlers = Handlers::default()
+            .on(WebhookEventType::Ping, Failing(executed.clone()))
+            .on(
+                WebhookEventType::Ping,
+                Recording("succeeding", executed.clone()),
+            );
+        let event = WebhookEvent::try_from_header_and_body("ping", r#"{"hook_id":1}"#).unwrap();
+
+        let dispatched = handlers
+            .dispatch(&EventContext::new(event, None, ()), &Default::default())
+            .await;
+
+        assert_eq!(*executed.lock().unwrap(), ["failing", "succeeding"]);
+        assert_eq!((dispatched.invoked(), dispatched.failed()), (2, 1));
+        assert!(matches!(
+            dispatched.outcomes[0],
+            Err(HandlerFailure::Failed { ref source }) if source.to_string() == "the anvil missed"
+        ));
+        assert!(dispatched.outcomes[1].is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_default_handler_runs_only_for_events_without_a_handler() {
+        let executed = Arc::new(Mutex::new(Vec::new()));
+        let handlers = Handlers::default()
+            .on(WebhookEventType::Push, Recording("push", executed.clone()))
+            .default_handler(Recording("default", executed.clone()));
+        let push = json!({
+            "ref": "refs/heads/main",
+            "before": "0000000000000000000000000000000000000000",
+            "after": "d6fde92930d4715a2b49857d24b940956b26d2d3",
+            "base_ref": null,
+            "commits": [],
+            "compare": "https://github.local/acme/anvil/compare/main",
+            "created": true,
+            "deleted": false,
+            "forced": false,
+            "head_commit": null,
+            "pusher": { "name": "wile", "email": "wile@acme.local" }
+        });
+        let release = json!({ "action": "published", "release": { "tag_name": "v1.0.0" } });
+
+        for (kind, body) in [("push", push), ("release", release)] {
+            let event = WebhookEvent::try_from_header_and_body(kind, &body.to_string()).unwrap();
+            let dispatched = handlers
+                .dispatch(&EventContext::new(event, None, ()), &Default::default())
+                .await;
+            assert_eq!(dispatched.invoked(), 1);
+        }
+
+        assert_eq!(*executed.lock().unwrap(), ["push", "default"]);
+    }
+
+    /// Fails with a retryable error until it was invoked `failures` times.
+    struct Flaky {
+        failures: u32,
+        attempts: Arc<AtomicU32>,
+    }
+
+    impl EventHandler<()> for Flaky {
+        fn handle<'a>(&'a self, _ctx: &'a EventContext<()>) -> BoxFuture<'a, HandlerResult> {
+            let attempt = self.attempts.fetch_add(1, Ordering::SeqCst) + 1;
+            let failures = self.failures;
+            Box::pin(async move {
+                if attempt <= failures {
+                    Err(HandlerError::Retryable {
+                        source: "rate limited".into(),
+                    }
+                    .into())
+                } else {
+                    Ok(())
+                }
+            })
+        }
+    }
+
+    #[derive(Debug, Default)]
+    struct DeadLetterList(Mutex<Vec<DeadLetter>>);
+
+    impl DeadLetters for DeadLetterList {
+        fn dead_letter(&self, letter: DeadLetter) {
+            self.0.lock().unwrap().push(letter);
+        }
+    }
+
+    async fn dispatch_flaky(failures: u32) -> (u32, Arc<DeadLetterList>, Option<HandlerFailure>) {
+        let attempts = Arc::new(AtomicU32::new(0));
+        let dead_letters = Arc::new(DeadLetterList::default());
+        let retries = Retries {
+            policy: RetryPolicy {
+                max_attempts: 3,
+                base_delay: Duration::from_millis(1),
+                max_delay: Duration::from_millis(5),
+            },
+            dead_letters: Some(dead_letters.clone()),
+            ..Default::default()
+        };
+        let handlers = Handlers::default().on(
+            WebhookEventType::Ping,
+            Flaky {
+                failures,
+                attempts: attempts.clone(),
+            },
+        );
+        let event = WebhookEvent::try_from_header_and_body("ping", r#"{"hook_id":1}"#).unwrap();
+
+        let mut dispatched = handlers
+            .dispatch(&EventContext::new(event, None, ()), &retries)
+            .await;
+
+        let outcome = dispatched.outcomes.pop().unwrap().err();
+        (attempts.load(Ordering::SeqCst), dead_letters, outcome)
+    }
+
+    #[tokio::test]
+    async fn test_retryable_failures_are_retried_until_the_handler_succeeds() {
+        let (attempts, dead_letters, failure) = dispatch_flaky(2).await;
+
+        assert_eq!(attempts, 3);
+        assert!(failure.is_none());
+        assert!(dead_letters.0.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_handler_is_dead_lettered_after_its_last_attempt() {
+        let (attempts, dead_letters, failure) = dispatch_flaky(u32::MAX).await;
+
+        assert_eq!(attempts, 3);
+        assert!(matches!(
+            failure,
+            Some(HandlerFailure::RetriesExhausted { attempts: 3, .. })
+        ));
+        let dead_letters = dead_letters.0.lock().unwrap();
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(dead_letters[0].kind, WebhookEventType::Ping);
+        assert_eq!(dead_letters[0].error, "rate limited");
+    }
+
+    #[tokio::test]
+    async fn test_terminal_failures_are_not_retried() {
+        let executed = Arc::new(Mutex::new(Vec::new()));
+        let handlers = Handlers::default().on(WebhookEventType::Ping, Failing(executed.clone()));
+        let event = WebhookEvent::try_from_header_and_body("ping", r#"{"hook_id":1}"#).unwrap();
+
+        let dispatched = handlers
+            .dispatch(&EventContext::new(event, None, ()), &Default::default())
+            .await;
+
+        assert_eq!(*executed.lock().unwrap(), ["failing"]);
+        assert_eq!(dispatched.failed(), 1);
+    }
+
+    #[derive(Debug)]
+    struct Half;
+
+    impl Jitter for Half {
+        fn factor(&self) -> f64 {
+            0.5
+        }
+    }
+
+    #[test]
+    fn test_backoff_schedule_follows_the_jitter() {
+        let policy = RetryPolicy {
+            max_attempts: 6,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+        };
+        let schedule = |jitter: &dyn Jitter| {
+            (1..policy.max_attempts)
+                .map(|attempt| policy.delay(attempt, jitter))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            schedule(&Half),
+            [50, 100, 200, 400, 500].map(Duration::from_millis)
+        );
+        let seeded = schedule(&SeededJitter::new(7));
+        assert_eq!(
+            seeded,
+            [3_031_736, 61_417_257, 57_056_863, 434_013_749, 272_497_277].map(Duration::from_nanos)
+        );
+        assert_eq!(seeded, schedule(&SeededJitter::new(7)));
+        assert_ne!(seeded, schedule(&SeededJitter::new(8)));
+    }
+}
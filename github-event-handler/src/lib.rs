@@ -1,3 +1,12 @@
 pub mod api;
 pub mod authentication;
+mod cache;
+mod client;
+pub mod context;
 pub mod handle;
+pub mod handler;
+#[cfg(any(test, feature = "test-support"))]
+pub mod mock;
+pub mod oauth;
+pub mod payload;
+pub mod rate_limit;
@@ -0,0 +1,243 @@
+//! Test doubles for handlers and services built on this crate, enable the `test-support`
+//! feature to use them outside of its own tests.
+
+use crate::api::{
+    Conditional, ContentStream, DeploymentState, Dispatch, GitHubApi, Merge, MergeMethod,
+    ScanningAlertKind, UpsertedComment,
+};
+use crate::payload::ReleaseAsset;
+use bytes::Bytes;
+use octocrab::models::{CheckRunId, CommentId, Repository, RepositoryId, StatusState};
+use serde_json::json;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+
+/// Name, head sha and summary of a failed check run created through the [`NoOpApi`].
+pub type FailedCheckRun = (String, String, String);
+
+tokio::task_local! {
+    /// Records the failed check runs created through the [`NoOpApi`] within its scope.
+    pub static FAILED_CHECK_RUNS: Arc<Mutex<Vec<FailedCheckRun>>>;
+}
+
+/// Succeeds at every call without talking to GitHub.
+#[derive(Clone, Debug)]
+pub struct NoOpApi;
+
+impl GitHubApi for NoOpApi {
+    #[allow(refining_impl_trait)]
+    async fn create_commit_status(&self, _: &Repository, _: &str) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    #[allow(refining_impl_trait)]
+    async fn create_check_run(
+        &self,
+        _: &Repository,
+        _: &str,
+        _: &str,
+    ) -> Result<CheckRunId, Infallible> {
+        Ok(CheckRunId(1))
+    }
+
+    #[allow(refining_impl_trait)]
+    async fn update_check_run(
+        &self,
+        _: &Repository,
+        _: CheckRunId,
+        _: Option<octocrab::params::checks::CheckRunStatus>,
+        _: Option<octocrab::params::checks::CheckRunConclusion>,
+        _: octocrab::params::checks::CheckRunOutput,
+    ) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    #[allow(refining_impl_trait)]
+    async fn create_failed_check_run(
+        &self,
+        _: &Repository,
+        name: &str,
+        head_sha: &str,
+        _: &str,
+        summary: &str,
+    ) -> Result<CheckRunId, Infallible> {
+        // tests outside of a scope don't look at their check runs
+        let _ = FAILED_CHECK_RUNS.try_with(|created| {
+            let created_run = (name.to_owned(), head_sha.to_owned(), summary.to_owned());
+            created.lock().unwrap().push(created_run)
+        });
+        Ok(CheckRunId(2))
+    }
+
+    #[allow(refining_impl_trait)]
+    async fn set_commit_status(
+        &self,
+        _: &Repository,
+        _: &str,
+        _: StatusState,
+        _: &str,
+        _: Option<&str>,
+        _: Option<&str>,
+    ) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    #[allow(refining_impl_trait)]
+    async fn create_deployment_status(
+        &self,
+        _: &Repository,
+        _: u64,
+        _: DeploymentState,
+        _: Option<&str>,
+    ) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    #[allow(refining_impl_trait)]
+    async fn workflow_run_artifacts(
+        &self,
+        _: &Repository,
+        _: u64,
+    ) -> Result<Vec<String>, Infallible> {
+        Ok(vec![])
+    }
+
+    #[allow(refining_impl_trait)]
+    async fn get_conditional(&self, _: &str, _: Option<&str>) -> Result<Conditional, Infallible> {
+        Ok(Conditional::NotModified)
+    }
+
+    #[allow(refining_impl_trait)]
+    async fn upsert_issue_comment(
+        &self,
+        _: &Repository,
+        _: u64,
+        _: &str,
+        _: &str,
+    ) -> Result<UpsertedComment, Infallible> {
+        Ok(UpsertedComment::Created(CommentId(1)))
+    }
+
+    #[allow(refining_impl_trait)]
+    async fn add_issue_labels(
+        &self,
+        _: &Repository,
+        _: u64,
+        labels: &[&str],
+    ) -> Result<Vec<String>, Infallible> {
+        Ok(labels.iter().map(|label| label.to_string()).collect())
+    }
+
+    #[allow(refining_impl_trait)]
+    async fn remove_issue_label(
+        &self,
+        _: &Repository,
+        _: u64,
+        _: &str,
+    ) -> Result<Vec<String>, Infallible> {
+        Ok(Vec::new())
+    }
+
+    #[allow(refining_impl_trait)]
+    async fn request_pull_request_reviewers(
+        &self,
+        _: &Repository,
+        _: u64,
+        _: &[&str],
+        _: &[&str],
+    ) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    #[allow(refining_impl_trait)]
+    async fn merge_pull_request(
+        &self,
+        _: &Repository,
+        _: u64,
+        _: MergeMethod,
+    ) -> Result<Merge, Infallible> {
+        Ok(Merge::Merged { sha: String::new() })
+    }
+
+    #[allow(refining_impl_trait)]
+    async fn close_pull_request(&self, _: &Repository, _: u64) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    #[allow(refining_impl_trait)]
+    async fn close_scanning_alert(
+        &self,
+        _: &Repository,
+        _: ScanningAlertKind,
+        _: u64,
+        _: &str,
+        _: Option<&str>,
+    ) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    #[allow(refining_impl_trait)]
+    async fn dispatch_workflow(
+        &self,
+        _: &Repository,
+        _: &str,
+        _: &str,
+        _: &serde_json::Value,
+    ) -> Result<Dispatch, Infallible> {
+        Ok(Dispatch::Dispatched)
+    }
+
+    #[allow(refining_impl_trait)]
+    async fn dispatch_repository_event(
+        &self,
+        _: &Repository,
+        _: &str,
+        _: &serde_json::Value,
+    ) -> Result<Dispatch, Infallible> {
+        Ok(Dispatch::Dispatched)
+    }
+
+    #[allow(refining_impl_trait)]
+    async fn upload_release_asset(
+        &self,
+        _: &Repository,
+        _: u64,
+        name: &str,
+        body: Bytes,
+        content_type: &str,
+    ) -> Result<ReleaseAsset, Infallible> {
+        Ok(ReleaseAsset {
+            id: 1,
+            name: name.to_owned(),
+            content_type: content_type.to_owned(),
+            size: body.len() as u64,
+            browser_download_url: String::new(),
+        })
+    }
+
+    #[allow(refining_impl_trait)]
+    async fn repository(&self, id: RepositoryId) -> Result<Repository, Infallible> {
+        Ok(serde_json::from_value(json!({
+            "id": id.0,
+            "name": "wild-git-yonder",
+            "url": "https://api.github.local/repos/acme/wild-git-yonder"
+        }))
+        .unwrap())
+    }
+
+    #[allow(refining_impl_trait)]
+    async fn pull_request_diff(&self, _: &Repository, _: u64) -> Result<String, Infallible> {
+        Ok(String::new())
+    }
+
+    #[allow(refining_impl_trait)]
+    async fn stream_content(
+        &self,
+        _: &str,
+        _: &str,
+        _: &str,
+        _: Option<&str>,
+    ) -> Result<ContentStream, Infallible> {
+        Ok(Box::pin(futures_util::stream::empty()))
+    }
+}
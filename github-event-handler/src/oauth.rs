@@ -0,0 +1,437 @@
+use crate::cache::Flights;
+use crate::client::octocrab_client;
+use hyper::http::{
+    header::{InvalidHeaderValue, ACCEPT},
+    Uri,
+};
+use octocrab::models::webhook_events::payload::GithubAppAuthorizationWebhookEventAction;
+use octocrab::models::webhook_events::{WebhookEvent, WebhookEventPayload};
+use octocrab::{models::UserId, Octocrab};
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+/// User tokens are refreshed this long before they expire.
+const USER_TOKEN_EXPIRY_MARGIN: Duration = Duration::from_secs(5 * 60);
+
+/// Mints user-to-server tokens through the OAuth web flow, for acting on behalf of a user
+/// instead of the installation.
+///
+/// Tokens are cached per user and refreshed once they are about to expire, Apps without
+/// expiring user tokens get tokens which never need a refresh.
+#[derive(Clone)]
+pub struct OAuthApp {
+    web: Octocrab,
+    api_uri: Uri,
+    user_agent: String,
+    client_id: String,
+    client_secret: String,
+    tokens: Arc<RwLock<HashMap<UserId, UserToken>>>,
+    /// Held whilst the token of the user is refreshed, concurrent callers wait for it.
+    refreshing: Flights<UserId>,
+}
+
+impl std::fmt::Debug for OAuthApp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OAuthApp")
+            .field("api_uri", &self.api_uri)
+            .field("client_id", &self.client_id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A user access token and what is needed to refresh it.
+#[derive(Clone)]
+pub struct UserToken {
+    pub access_token: String,
+    refresh_token: Option<String>,
+    /// `None` for tokens which don't expire.
+    expires_at: Option<Instant>,
+}
+
+impl std::fmt::Debug for UserToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UserToken")
+            .field("expires_at", &self.expires_at)
+            .finish_non_exhaustive()
+    }
+}
+
+impl UserToken {
+    fn is_fresh(&self) -> bool {
+        self.expires_at
+            .is_none_or(|expires_at| Instant::now() + USER_TOKEN_EXPIRY_MARGIN < expires_at)
+    }
+}
+
+#[derive(Serialize)]
+struct TokenRequest<'a> {
+    client_id: &'a str,
+    client_secret: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    grant_type: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    refresh_token: Option<&'a str>,
+}
+
+/// GitHub reports failed exchanges with `200` and an error in the body.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TokenResponse {
+    Granted {
+        access_token: String,
+        refresh_token: Option<String>,
+        expires_in: Option<u64>,
+    },
+    Rejected {
+        error: String,
+        error_description: Option<String>,
+    },
+}
+
+#[derive(Deserialize)]
+struct AuthenticatedUser {
+    id: UserId,
+}
+
+#[derive(Debug, Snafu)]
+pub enum OAuthError {
+    #[snafu(display("GitHub API call failed: {source}"))]
+    Octocrab {
+        #[snafu(source(from(octocrab::Error, Box::new)))]
+        source: Box<octocrab::Error>,
+    },
+    #[snafu(display("The user agent or the token is not a valid header value: {source}"))]
+    InvalidHeader { source: InvalidHeaderValue },
+    #[snafu(display("GitHub rejected the token request with {error}: {description}"))]
+    Rejected { error: String, description: String },
+    #[snafu(display("No token is known for user {user}, they have to authorize the App first"))]
+    UnknownUser { user: UserId },
+    #[snafu(display("The token of user {user} expired and can't be refreshed"))]
+    Expired { user: UserId },
+}
+
+impl OAuthApp {
+    /// `web_uri` serves the OAuth endpoints, e.g. `https://github.com`, `api_uri` the API.
+    pub fn new(
+        web_uri: Uri,
+        api_uri: Uri,
+        user_agent: &str,
+        client_id: impl Into<String>,
+        client_secret: impl Into<String>,
+    ) -> Result<Self, OAuthError> {
+        let web = octocrab_client(
+            web_uri,
+            user_agent,
+            &[(ACCEPT, "application/json")],
+            None,
+            None,
+        )
+        .context(InvalidHeaderSnafu)?;
+        Ok(Self {
+            web,
+            api_uri,
+            user_agent: user_agent.to_string(),
+            client_id: client_id.into(),
+            client_secret: client_secret.into(),
+            tokens: Default::default(),
+            refreshing: Default::default(),
+        })
+    }
+
+    /// Exchanges the `code` GitHub redirected the user back with for a token and caches it
+    /// for the user it belongs to.
+    pub async fn authorize(&self, code: &str) -> Result<UserId, OAuthError> {
+        let token = self
+            .request_token(TokenRequest {
+                code: Some(code),
+                grant_type: None,
+                refresh_token: None,
+                client_id: &self.client_id,
+                client_secret: &self.client_secret,
+            })
+            .await?;
+        let user: AuthenticatedUser = self
+            .client(&token.access_token)?
+            .get("/user", None::<&()>)
+            .await
+            .context(OctocrabSnafu)?;
+        self.tokens.write().unwrap().insert(user.id, token);
+        Ok(user.id)
+    }
+
+    /// A valid token of the user, refreshed if the cached one is about to expire.
+    ///
+    /// Only one refresh per user runs at a time, GitHub invalidates a refresh token once it
+    /// was used. Concurrent callers get the token refreshed meanwhile.
+    pub async fn token(&self, user: UserId) -> Result<UserToken, OAuthError> {
+        if let Some(fresh) = self.fresh_token(user)? {
+            return Ok(fresh);
+        }
+        let _refreshing = self.refreshing.board(user).await;
+        let Some(cached) = self.tokens.read().unwrap().get(&user).cloned() else {
+            return UnknownUserSnafu { user }.fail();
+        };
+        if cached.is_fresh() {
+            return Ok(cached);
+        }
+        let Some(ref refresh_token) = cached.refresh_token else {
+            return ExpiredSnafu { user }.fail();
+        };
+        let refreshed = self
+            .request_token(TokenRequest {
+                code: None,
+                grant_type: Some("refresh_token"),
+                refresh_token: Some(refresh_token),
+                client_id: &self.client_id,
+                client_secret: &self.client_secret,
+            })
+            .await?;
+        self.tokens.write().unwrap().insert(user, refreshed.clone());
+        Ok(refreshed)
+    }
+
+    fn fresh_token(&self, user: UserId) -> Result<Option<UserToken>, OAuthError> {
+        match self.tokens.read().unwrap().get(&user) {
+            Some(cached) => Ok(Some(cached).filter(|cached| cached.is_fresh()).cloned()),
+            None => UnknownUserSnafu { user }.fail(),
+        }
+    }
+
+    /// Client acting as the user, changes made with it are attributed to them.
+    pub async fn user_client(&self, user: UserId) -> Result<Octocrab, OAuthError> {
+        let token = self.token(user).await?;
+        self.client(&token.access_token)
+    }
+
+    /// Drops the cached token, e.g. once the user revoked the authorization.
+    pub fn forget_user(&self, user: UserId) {
+        self.tokens.write().unwrap().remove(&user);
+    }
+
+    /// Forgets the token of the sender of a `github_app_authorization.revoked` event, their
+    /// token stopped working with the revocation. Returns whose token was dropped.
+    pub fn forget_revoked(&self, event: &WebhookEvent) -> Option<UserId> {
+        let WebhookEventPayload::GithubAppAuthorization(ref authorization) = event.specific else {
+            return None;
+        };
+        if authorization.action != GithubAppAuthorizationWebhookEventAction::Revoked {
+            return None;
+        }
+        let user = event.sender.as_ref()?.id;
+        self.forget_user(user);
+        Some(user)
+    }
+
+    async fn request_token(&self, request: TokenRequest<'_>) -> Result<UserToken, OAuthError> {
+        let response: TokenResponse = self
+            .web
+            .post("/login/oauth/access_token", Some(&request))
+            .await
+            .context(OctocrabSnafu)?;
+        match response {
+            TokenResponse::Granted {
+                access_token,
+                refresh_token,
+                expires_in,
+            } => Ok(UserToken {
+                access_token,
+                refresh_token,
+                expires_at: expires_in.map(|secs| Instant::now() + Duration::from_secs(secs)),
+            }),
+            TokenResponse::Rejected {
+                error,
+                error_description,
+            } => RejectedSnafu {
+                error,
+                description: error_description.unwrap_or_default(),
+            }
+            .fail(),
+        }
+    }
+
+    fn client(&self, access_token: &str) -> Result<Octocrab, OAuthError> {
+        octocrab_client(
+            self.api_uri.clone(),
+            &self.user_agent,
+            &[],
+            Some(access_token),
+            None,
+        )
+        .context(InvalidHeaderSnafu)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{OAuthApp, OAuthError};
+    use axum::http::{header::USER_AGENT, HeaderMap};
+    use axum::{
+        routing::{get, post},
+        Json, Router,
+    };
+    use octocrab::models::webhook_events::WebhookEvent;
+    use octocrab::models::UserId;
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    type Requests = Arc<Mutex<Vec<serde_json::Value>>>;
+
+    /// Grants `ghu_first` for the code, refreshing it grants `ghu_refreshed`.
+    async fn mock_github(first_expires_in: u64) -> (OAuthApp, Requests) {
+        let requests: Requests = Default::default();
+        let recorded = requests.clone();
+        let mock = Router::new()
+            .route(
+                "/login/oauth/access_token",
+                post(
+                    move |headers: HeaderMap, Json(body): Json<serde_json::Value>| async move {
+                        assert_eq!(headers.get_all(USER_AGENT).iter().count(), 1);
+                        recorded.lock().unwrap().push(body.clone());
+                        Json(
+                            match (body["code"].as_str(), body["refresh_token"].as_str()) {
+                                (Some("a1b2c3"), _) => json!({
+                                    "access_token": "ghu_first",
+                                    "expires_in": first_expires_in,
+                                    "refresh_token": "ghr_first",
+                                    "token_type": "bearer",
+                                }),
+                                (_, Some("ghr_first")) => json!({
+                                    "access_token": "ghu_refreshed",
+                                    "expires_in": 28800,
+                                    "refresh_token": "ghr_refreshed",
+                                    "token_type": "bearer",
+                                }),
+                                _ => json!({
+                                    "error": "bad_verification_code",
+                                    "error_description": "The code passed is incorrect or expired.",
+                                }),
+                            },
+                        )
+                    },
+                ),
+            )
+            .route(
+                "/user",
+                get(|headers: HeaderMap| async move {
+                    assert_eq!(headers["authorization"], "Bearer ghu_first");
+                    assert_eq!(headers.get_all(USER_AGENT).iter().count(), 1);
+                    Json(json!({ "id": 583231, "login": "wile" }))
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await });
+        let uri: hyper::Uri = format!("http://{addr}").parse().unwrap();
+        let app =
+            OAuthApp::new(uri.clone(), uri, "wild-git-yonder", "Iv1.acme", "hunter2").unwrap();
+        (app, requests)
+    }
+
+    #[tokio::test]
+    async fn test_code_is_exchanged_and_cached_per_user() {
+        let (app, requests) = mock_github(28800).await;
+
+        let user = app.authorize("a1b2c3").await.unwrap();
+        assert_eq!(user, UserId(583231));
+        assert_eq!(app.token(user).await.unwrap().access_token, "ghu_first");
+        assert_eq!(app.token(user).await.unwrap().access_token, "ghu_first");
+
+        assert_eq!(
+            *requests.lock().unwrap(),
+            [json!({ "client_id": "Iv1.acme", "client_secret": "hunter2", "code": "a1b2c3" })]
+        );
+        assert!(matches!(
+            app.token(UserId(1)).await,
+            Err(OAuthError::UnknownUser { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_expiring_token_is_refreshed_once() {
+        let (app, requests) = mock_github(60).await;
+
+        let user = app.authorize("a1b2c3").await.unwrap();
+        assert_eq!(app.token(user).await.unwrap().access_token, "ghu_refreshed");
+        assert_eq!(app.token(user).await.unwrap().access_token, "ghu_refreshed");
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(
+            requests[1],
+            json!({
+                "client_id": "Iv1.acme",
+                "client_secret": "hunter2",
+                "grant_type": "refresh_token",
+                "refresh_token": "ghr_first",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_callers_share_one_refresh() {
+        let (app, requests) = mock_github(60).await;
+        let user = app.authorize("a1b2c3").await.unwrap();
+
+        let (first, second) = tokio::join!(app.token(user), app.token(user));
+
+        assert_eq!(first.unwrap().access_token, "ghu_refreshed");
+        assert_eq!(second.unwrap().access_token, "ghu_refreshed");
+        assert_eq!(requests.lock().unwrap().len(), 2);
+        // the landed refresh doesn't keep the user around
+        assert_eq!(app.refreshing.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_revocation_forgets_the_cached_token() {
+        let (app, _) = mock_github(28800).await;
+        let user = app.authorize("a1b2c3").await.unwrap();
+        let body = json!({
+            "action": "revoked",
+            "sender": {
+                "login": "wile",
+                "id": 583231,
+                "node_id": "MDQ6VXNlcjU4MzIzMQ==",
+                "avatar_url": "https://avatars.github.local/u/583231",
+                "gravatar_id": "",
+                "url": "https://api.github.local/users/wile",
+                "html_url": "https://github.local/wile",
+                "followers_url": "https://api.github.local/users/wile/followers",
+                "following_url": "https://api.github.local/users/wile/following",
+                "gists_url": "https://api.github.local/users/wile/gists",
+                "starred_url": "https://api.github.local/users/wile/starred",
+                "subscriptions_url": "https://api.github.local/users/wile/subscriptions",
+                "organizations_url": "https://api.github.local/users/wile/orgs",
+                "repos_url": "https://api.github.local/users/wile/repos",
+                "events_url": "https://api.github.local/users/wile/events",
+                "received_events_url": "https://api.github.local/users/wile/received_events",
+                "type": "User",
+                "site_admin": false
+            }
+        });
+        let event =
+            WebhookEvent::try_from_header_and_body("github_app_authorization", &body.to_string())
+                .unwrap();
+
+        assert_eq!(app.forget_revoked(&event), Some(user));
+        assert!(matches!(
+            app.token(user).await,
+            Err(OAuthError::UnknownUser { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rejected_code_is_reported() {
+        let (app, _) = mock_github(28800).await;
+
+        let result = app.authorize("expired").await;
+
+        assert!(
+            matches!(result, Err(OAuthError::Rejected { ref error, .. }) if error == "bad_verification_code")
+        );
+    }
+}
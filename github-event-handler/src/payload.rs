@@ -0,0 +1,240 @@
+//! Typed views on the parts of webhook payloads that octocrab only exposes as raw json.
+
+use octocrab::models::webhook_events::{WebhookEvent, WebhookEventType};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct CheckSuite {
+    pub id: u64,
+    pub head_sha: String,
+    pub head_branch: Option<String>,
+    pub status: Option<String>,
+    pub conclusion: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct CheckRun {
+    pub id: u64,
+    pub name: String,
+    pub head_sha: String,
+    pub status: Option<String>,
+    pub conclusion: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct WorkflowRun {
+    pub id: u64,
+    pub name: Option<String>,
+    pub run_number: u64,
+    pub head_sha: String,
+    pub head_branch: Option<String>,
+    pub status: Option<String>,
+    pub conclusion: Option<String>,
+    pub html_url: String,
+    pub logs_url: String,
+    pub artifacts_url: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct WorkflowJob {
+    pub id: u64,
+    pub run_id: u64,
+    pub name: String,
+    pub head_sha: String,
+    pub status: Option<String>,
+    pub conclusion: Option<String>,
+    pub html_url: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Deployment {
+    pub id: u64,
+    pub sha: String,
+    /// Branch, tag or sha that is deployed.
+    #[serde(rename = "ref")]
+    pub git_ref: String,
+    pub environment: String,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct DeploymentStatus {
+    pub id: u64,
+    pub state: String,
+    pub environment: Option<String>,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Release {
+    pub id: u64,
+    pub tag_name: String,
+    pub name: Option<String>,
+    pub draft: bool,
+    pub prerelease: bool,
+    pub html_url: String,
+    /// URI template of the uploads endpoint, e.g. `https://uploads.github.com/.../assets{?name,label}`.
+    pub upload_url: String,
+    #[serde(default)]
+    pub assets: Vec<ReleaseAsset>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ReleaseAsset {
+    pub id: u64,
+    pub name: String,
+    pub content_type: String,
+    pub size: u64,
+    pub browser_download_url: String,
+}
+
+/// The package of `package` and `registry_package` events, which may belong to an account
+/// instead of a repository.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Package {
+    pub id: u64,
+    pub name: String,
+    /// Registry the package is published to, e.g. `CONTAINER`, `npm` or `maven`.
+    pub ecosystem: String,
+    /// The published version, missing for some updates.
+    pub package_version: Option<PackageVersion>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct PackageVersion {
+    pub id: u64,
+    pub version: String,
+}
+
+/// The `branch_protection_rule` event, whatever octocrab parsed it into.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct BranchProtectionRuleEvent {
+    /// `created`, `edited` or `deleted`.
+    pub action: String,
+    pub rule: BranchProtectionRule,
+    /// Previous values of the edited settings, each as `{"from": ...}`.
+    #[serde(default)]
+    pub changes: Option<HashMap<String, serde_json::Value>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct BranchProtectionRule {
+    pub id: u64,
+    /// Branch name pattern the rule protects, e.g. `main` or `release/*`.
+    pub name: String,
+    pub repository_id: u64,
+    /// octocrab serializes the misspelled `admin_enfored`.
+    #[serde(alias = "admin_enfored")]
+    pub admin_enforced: Option<bool>,
+    /// `off`, `non_admins` or `everyone`.
+    pub pull_request_reviews_enforcement_level: Option<String>,
+    pub required_status_checks_enforcement_level: Option<String>,
+    #[serde(default)]
+    pub required_status_checks: Vec<String>,
+}
+
+/// The `repository_ruleset` event, which octocrab leaves unparsed.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RepositoryRulesetEvent {
+    /// `created`, `edited` or `deleted`.
+    pub action: String,
+    pub repository_ruleset: RepositoryRuleset,
+    /// Previous values of the edited settings, e.g. `{"enforcement": {"from": "evaluate"}}`.
+    #[serde(default)]
+    pub changes: Option<HashMap<String, serde_json::Value>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RepositoryRuleset {
+    pub id: u64,
+    pub name: String,
+    /// `disabled`, `active` or `evaluate`.
+    pub enforcement: String,
+    /// `branch`, `tag` or `push`.
+    pub target: Option<String>,
+    /// `Repository` or `Organization`.
+    pub source_type: Option<String>,
+    pub source: Option<String>,
+}
+
+/// Name of the `repository_ruleset` event, register handlers for it with
+/// `WebhookEventType::Unknown(REPOSITORY_RULESET.into())`.
+pub const REPOSITORY_RULESET: &str = "repository_ruleset";
+
+/// The `code_scanning_alert` event, whatever octocrab parsed it into.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct CodeScanningAlertEvent {
+    /// E.g. `created`, `fixed` or `closed_by_user`.
+    pub action: String,
+    pub alert: CodeScanningAlert,
+    /// Empty when a user closed or reopened the alert.
+    #[serde(rename = "ref")]
+    pub git_ref: Option<String>,
+    pub commit_oid: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct CodeScanningAlert {
+    pub number: u64,
+    /// `open`, `dismissed` or `fixed`.
+    pub state: String,
+    pub rule: CodeScanningRule,
+    pub html_url: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct CodeScanningRule {
+    /// Id of the rule in its tool, e.g. `js/sql-injection`.
+    pub id: Option<String>,
+    pub severity: Option<String>,
+    pub description: Option<String>,
+}
+
+/// The `secret_scanning_alert` event, whatever octocrab parsed it into.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SecretScanningAlertEvent {
+    /// E.g. `created`, `resolved` or `validated`.
+    pub action: String,
+    pub alert: SecretScanningAlert,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SecretScanningAlert {
+    pub number: u64,
+    /// `open` or `resolved`.
+    pub state: Option<String>,
+    /// E.g. `github_personal_access_token`.
+    pub secret_type: String,
+    pub secret_type_display_name: Option<String>,
+    pub resolution: Option<String>,
+    pub html_url: Option<String>,
+}
+
+/// Events octocrab fails to parse as GitHub sends them, kept as raw json by [`parse_event`]:
+/// the `branch_protection_rule` payload expects a misspelled `admin_enfored`, the
+/// `code_scanning_alert` payload requires the repository and sender it never gets to see, and
+/// the `secret_scanning_alert` actions lack newer ones like `validated`.
+const LENIENT_EVENTS: [WebhookEventType; 3] = [
+    WebhookEventType::BranchProtectionRule,
+    WebhookEventType::CodeScanningAlert,
+    WebhookEventType::SecretScanningAlert,
+];
+
+/// Parses the event like [`WebhookEvent::try_from_header_and_body`], but keeps the payloads of
+/// some events octocrab can't parse as raw json instead of failing them.
+pub fn parse_event(kind: &str, body: &[u8]) -> Result<WebhookEvent, serde_json::Error> {
+    match WebhookEvent::try_from_header_and_body(kind, body) {
+        Err(err) if err.is_data() => {
+            let kind = serde_json::from_value::<WebhookEventType>(kind.trim_matches('"').into())?;
+            if !LENIENT_EVENTS.contains(&kind) {
+                return Err(err);
+            }
+            // an unknown kind leaves the payload as json
+            let mut event = WebhookEvent::try_from_header_and_body("unparsed", body)?;
+            event.kind = kind;
+            Ok(event)
+        }
+        parsed => parsed,
+    }
+}
@@ -0,0 +1,211 @@
+use hyper::http::{HeaderMap, StatusCode};
+use std::future::Future;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Which of GitHub's rate limits a response ran into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitKind {
+    /// The hourly quota, exhausted with `X-RateLimit-Remaining: 0` until `X-RateLimit-Reset`.
+    Primary,
+    /// Protection against bursts of requests, lifted after `Retry-After` seconds.
+    Secondary,
+}
+
+/// A `403` or `429` response caused by a rate limit, other `403`s are missing permissions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub kind: RateLimitKind,
+    /// How long until the limit is lifted.
+    pub retry_after: Duration,
+}
+
+impl RateLimit {
+    pub fn from_response(status: StatusCode, headers: &HeaderMap, now: SystemTime) -> Option<Self> {
+        if status != StatusCode::FORBIDDEN && status != StatusCode::TOO_MANY_REQUESTS {
+            return None;
+        }
+        let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+        if let Some(seconds) = header("retry-after").and_then(|value| value.parse().ok()) {
+            return Some(Self {
+                kind: RateLimitKind::Secondary,
+                retry_after: Duration::from_secs(seconds),
+            });
+        }
+        if header("x-ratelimit-remaining") != Some("0") {
+            return None;
+        }
+        let reset = header("x-ratelimit-reset")
+            .and_then(|value| value.parse().ok())
+            .map(|seconds| UNIX_EPOCH + Duration::from_secs(seconds))?;
+        Some(Self {
+            kind: RateLimitKind::Primary,
+            retry_after: reset.duration_since(now).unwrap_or_default(),
+        })
+    }
+}
+
+/// How long a request waits for a rate limit to be lifted before it fails.
+///
+/// The default stays below the 10 seconds after which GitHub gives up on a delivery, handlers
+/// acknowledged before they run may wait longer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitPolicy {
+    /// `None` fails right away with [`RateLimited`](crate::api::GitHubActionError::RateLimited).
+    pub max_wait: Option<Duration>,
+}
+
+impl Default for RateLimitPolicy {
+    fn default() -> Self {
+        Self {
+            max_wait: Some(Duration::from_secs(5)),
+        }
+    }
+}
+
+tokio::task_local! {
+    static POLICY: RateLimitPolicy;
+}
+
+/// Runs `future`, e.g. the handling of an event, with its API requests waiting for rate limits
+/// as `policy` allows. Tasks spawned by it fall back to the default policy.
+pub async fn with_policy<F: Future>(policy: RateLimitPolicy, future: F) -> F::Output {
+    POLICY.scope(policy, future).await
+}
+
+/// The policy of the surrounding [`with_policy`], the default outside of one.
+pub fn current_policy() -> RateLimitPolicy {
+    POLICY.try_with(|policy| *policy).unwrap_or_default()
+}
+
+impl RateLimitPolicy {
+    pub fn waits_for(&self, limit: &RateLimit) -> bool {
+        self.max_wait
+            .is_some_and(|max_wait| limit.retry_after <= max_wait)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{current_policy, with_policy, RateLimit, RateLimitKind, RateLimitPolicy};
+    use crate::api::{send_rate_limited, GitHubActionError};
+    use axum::{http::HeaderMap, response::IntoResponse, routing::get, Router};
+    use hyper::http::StatusCode;
+    use octocrab::Octocrab;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.parse().unwrap(), value.parse().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_limits_are_told_apart_by_their_headers() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let secondary = headers(&[("retry-after", "30"), ("x-ratelimit-remaining", "12")]);
+        let primary = headers(&[
+            ("x-ratelimit-remaining", "0"),
+            ("x-ratelimit-reset", "1700000090"),
+        ]);
+
+        assert_eq!(
+            RateLimit::from_response(StatusCode::FORBIDDEN, &secondary, now),
+            Some(RateLimit {
+                kind: RateLimitKind::Secondary,
+                retry_after: Duration::from_secs(30),
+            })
+        );
+        assert_eq!(
+            RateLimit::from_response(StatusCode::FORBIDDEN, &primary, now),
+            Some(RateLimit {
+                kind: RateLimitKind::Primary,
+                retry_after: Duration::from_secs(90),
+            })
+        );
+        let permission = headers(&[("x-ratelimit-remaining", "4999")]);
+        assert_eq!(
+            RateLimit::from_response(StatusCode::FORBIDDEN, &permission, now),
+            None
+        );
+        assert_eq!(
+            RateLimit::from_response(StatusCode::OK, &primary, now),
+            None
+        );
+    }
+
+    /// Answers the first request with the rate limit `headers`, later ones with `200`.
+    async fn mock_github(limited: HeaderMap) -> (Octocrab, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::default());
+        let counted = requests.clone();
+        let mock = Router::new().route(
+            "/repos/acme/anvil/pulls/7",
+            get(move || async move {
+                match counted.fetch_add(1, Ordering::SeqCst) {
+                    0 => (StatusCode::FORBIDDEN, limited, "rate limited").into_response(),
+                    _ => "diff --git".into_response(),
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await });
+        let client = Octocrab::builder()
+            .base_uri(format!("http://{addr}"))
+            .unwrap()
+            .build()
+            .unwrap();
+        (client, requests)
+    }
+
+    #[tokio::test]
+    async fn test_secondary_limit_is_waited_out() {
+        let (client, requests) = mock_github(headers(&[("retry-after", "1")])).await;
+
+        let response = send_rate_limited(&RateLimitPolicy::default(), || {
+            client._get("/repos/acme/anvil/pulls/7")
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_primary_limit_fails_without_waiting() {
+        let reset =
+            SystemTime::now().duration_since(UNIX_EPOCH).unwrap() + Duration::from_secs(600);
+        let reset = reset.as_secs().to_string();
+        let mut limited = headers(&[("x-ratelimit-remaining", "0")]);
+        limited.insert("x-ratelimit-reset", reset.parse().unwrap());
+        let (client, requests) = mock_github(limited).await;
+
+        let result = send_rate_limited(&RateLimitPolicy { max_wait: None }, || {
+            client._get("/repos/acme/anvil/pulls/7")
+        })
+        .await;
+
+        assert!(matches!(
+            result,
+            Err(GitHubActionError::RateLimited {
+                kind: RateLimitKind::Primary,
+                retry_after,
+                ..
+            }) if retry_after > Duration::from_secs(590)
+        ));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_policy_applies_within_its_scope() {
+        let disabled = RateLimitPolicy { max_wait: None };
+        assert_eq!(
+            with_policy(disabled, async { current_policy() }).await,
+            disabled
+        );
+        assert_eq!(current_policy(), RateLimitPolicy::default());
+    }
+}
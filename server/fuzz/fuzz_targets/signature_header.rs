@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use orion::hazardous::mac::hmac::sha256::SecretKey;
+
+fuzz_target!(|header: &str| {
+    let secret = SecretKey::from_slice(b"It's a Secret to Everybody").unwrap();
+    let _ = server::signature::verify_signature(&secret, br#"{"zen":"Keep it"}"#, header);
+});
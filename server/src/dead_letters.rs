@@ -0,0 +1,81 @@
+use crate::replay::CapturedDelivery;
+use github_event_handler::handler::{DeadLetter, DeadLetters};
+use std::path::PathBuf;
+
+/// Stores the delivery of every dead letter in `dir` as a [`CapturedDelivery`], to hand it to
+/// `replay --file` once the cause has been fixed.
+///
+/// The files are named by the delivery id, a delivery with several dead-lettered handlers is
+/// stored once, replaying it runs all of its handlers again. Dead letters without a body are
+/// only logged.
+#[derive(Debug)]
+pub struct FileDeadLetters {
+    dir: PathBuf,
+    extension: &'static str,
+}
+
+impl FileDeadLetters {
+    /// Creates `dir` unless it exists.
+    pub fn open(dir: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            extension: "json",
+        })
+    }
+
+    /// Stores the deliveries gzip-compressed, as `.json.gz` files.
+    pub fn compressed(self) -> Self {
+        Self {
+            extension: "json.gz",
+            ..self
+        }
+    }
+
+    /// Anything but the characters of a UUID is dropped, the id is taken from a header.
+    fn path(&self, letter: &DeadLetter) -> PathBuf {
+        let name = match letter.delivery_id.as_deref() {
+            Some(id) => id
+                .chars()
+                .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+                .collect(),
+            None => String::new(),
+        };
+        let name = match name.is_empty() {
+            true => format!("{:?}-{}", letter.kind, std::process::id()),
+            false => name,
+        };
+        self.dir.join(format!("{name}.{}", self.extension))
+    }
+}
+
+impl DeadLetters for FileDeadLetters {
+    fn dead_letter(&self, letter: DeadLetter) {
+        let Some(ref body) = letter.body else {
+            tracing::warn!(kind = ?letter.kind, "unable to store a dead letter without its body");
+            return;
+        };
+        let delivery = match CapturedDelivery::from_parts(&letter.headers, body) {
+            Ok(delivery) => delivery,
+            Err(err) => {
+                tracing::error!(%err, kind = ?letter.kind, "unable to capture a dead letter");
+                return;
+            }
+        };
+        let path = self.path(&letter);
+        let span = tracing::Span::current();
+        let write = move || {
+            let _span = span.enter();
+            match delivery.write(&path) {
+                Ok(()) => tracing::info!(?path, "stored the dead letter"),
+                Err(err) => tracing::error!(%err, ?path, "unable to store the dead letter"),
+            }
+        };
+        // dead letters are handed over on the runtime, outside of it there is nothing to block
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => drop(runtime.spawn_blocking(write)),
+            Err(_) => write(),
+        }
+    }
+}
@@ -0,0 +1,413 @@
+use crate::clock::{Clock, SystemClock};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Remembers which deliveries have been processed, so redeliveries by GitHub are only
+/// acknowledged instead of being processed again.
+pub trait DeliveryStore: std::fmt::Debug + Send + Sync {
+    /// Records the delivery, returns `false` if it was already recorded and hasn't expired yet.
+    fn record(&self, delivery: &str) -> Result<bool, DeliveryStoreError>;
+    /// Forgets a recorded delivery which wasn't acknowledged, so its redelivery is processed.
+    fn forget(&self, delivery: &str) -> Result<(), DeliveryStoreError>;
+}
+
+#[derive(Debug, Error)]
+pub enum DeliveryStoreError {
+    #[error("Unable to access the delivery store: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// Keeps the deliveries in memory, they are lost on restart.
+#[derive(Debug)]
+pub struct InMemoryDeliveryStore {
+    ttl: Duration,
+    clock: Arc<dyn Clock>,
+    seen: Mutex<HashMap<String, u64>>,
+}
+
+impl InMemoryDeliveryStore {
+    pub fn new(ttl: Duration) -> Self {
+        Self::with_clock(ttl, Arc::new(SystemClock))
+    }
+
+    /// Deliveries expire once `ttl` has passed on `clock`.
+    pub fn with_clock(ttl: Duration, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            ttl,
+            clock,
+            seen: Default::default(),
+        }
+    }
+
+    fn record_at(&self, delivery: &str, now: u64) -> bool {
+        let mut seen = self.seen.lock().unwrap();
+        let ttl = self.ttl.as_secs();
+        seen.retain(|_, recorded| now.saturating_sub(*recorded) < ttl);
+        if seen.contains_key(delivery) {
+            return false;
+        }
+        seen.insert(delivery.to_owned(), now);
+        true
+    }
+}
+
+impl DeliveryStore for InMemoryDeliveryStore {
+    fn record(&self, delivery: &str) -> Result<bool, DeliveryStoreError> {
+        Ok(self.record_at(delivery, self.clock.unix_secs()))
+    }
+
+    fn forget(&self, delivery: &str) -> Result<(), DeliveryStoreError> {
+        self.seen.lock().unwrap().remove(delivery);
+        Ok(())
+    }
+}
+
+/// Lines of expired or forgotten deliveries after which the file is compacted.
+const DEFAULT_COMPACTION_THRESHOLD: usize = 1024;
+
+/// Persists the deliveries to an append-only file, so deduplication survives restarts.
+///
+/// Deliveries are deduplicated in memory, a background thread appends them to the file.
+/// Forgotten deliveries are appended as `<secs> -<delivery>`, the file is compacted whenever
+/// the store is opened and once it holds more than a threshold of lines of expired or
+/// forgotten deliveries.
+#[derive(Debug)]
+pub struct FileDeliveryStore {
+    path: PathBuf,
+    entries: Arc<InMemoryDeliveryStore>,
+    writes: Option<mpsc::Sender<Line>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+/// A line for the writer thread to append.
+#[derive(Debug)]
+enum Line {
+    Record { recorded: u64, delivery: String },
+    Forget { forgotten: u64, delivery: String },
+}
+
+/// Appends the lines and compacts the file, off the threads recording the deliveries.
+struct Writer {
+    path: PathBuf,
+    entries: Arc<InMemoryDeliveryStore>,
+    file: File,
+    /// Lines in the file, some of them expired or forgotten.
+    lines: usize,
+    compaction_threshold: usize,
+}
+
+impl FileDeliveryStore {
+    pub fn open(path: impl AsRef<Path>, ttl: Duration) -> Result<Self, DeliveryStoreError> {
+        Self::open_with_clock(path, ttl, Arc::new(SystemClock))
+    }
+
+    pub fn open_with_clock(
+        path: impl AsRef<Path>,
+        ttl: Duration,
+        clock: Arc<dyn Clock>,
+    ) -> Result<Self, DeliveryStoreError> {
+        Self::open_with_compaction_threshold(path, ttl, clock, DEFAULT_COMPACTION_THRESHOLD)
+    }
+
+    /// Compacts the file once it holds more than `stale_lines` lines of expired or forgotten
+    /// deliveries.
+    pub fn open_with_compaction_threshold(
+        path: impl AsRef<Path>,
+        ttl: Duration,
+        clock: Arc<dyn Clock>,
+        stale_lines: usize,
+    ) -> Result<Self, DeliveryStoreError> {
+        let path = path.as_ref().to_path_buf();
+        let entries = Arc::new(InMemoryDeliveryStore::with_clock(ttl, clock));
+        let now = entries.clock.unix_secs();
+        match File::open(&path) {
+            Ok(file) => {
+                let mut seen = entries.seen.lock().unwrap();
+                for line in BufReader::new(file).lines() {
+                    let line = line?;
+                    let Some((recorded, delivery)) = line.split_once(' ') else {
+                        continue;
+                    };
+                    let Ok(recorded) = recorded.parse::<u64>() else {
+                        continue;
+                    };
+                    if let Some(forgotten) = delivery.strip_prefix('-') {
+                        seen.remove(forgotten);
+                    } else if now.saturating_sub(recorded) < ttl.as_secs() {
+                        seen.insert(delivery.to_owned(), recorded);
+                    }
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+        let (file, lines) = compact(&path, &entries)?;
+        let mut writer = Writer {
+            path: path.clone(),
+            entries: entries.clone(),
+            file,
+            lines,
+            compaction_threshold: stale_lines,
+        };
+        let (writes, received) = mpsc::channel();
+        let writer = std::thread::Builder::new()
+            .name("delivery-store".into())
+            .spawn(move || {
+                for line in received {
+                    writer.append(line);
+                }
+            })?;
+        Ok(Self {
+            path,
+            entries,
+            writes: Some(writes),
+            writer: Some(writer),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn send(&self, line: Line) {
+        let sent = self.writes.as_ref().map(|writes| writes.send(line));
+        if !matches!(sent, Some(Ok(()))) {
+            tracing::error!(path = ?self.path, "the delivery store writer is gone");
+        }
+    }
+}
+
+/// Writes the unexpired deliveries to a new file and swaps it in, returns the file opened for
+/// appending and the number of lines written.
+fn compact(path: &Path, entries: &InMemoryDeliveryStore) -> io::Result<(File, usize)> {
+    // a crash whilst compacting leaves the previous file in place
+    let mut compacted = path.to_path_buf().into_os_string();
+    compacted.push(".compacting");
+    let mut file = File::create(&compacted)?;
+    let now = entries.clock.unix_secs();
+    let ttl = entries.ttl.as_secs();
+    let mut lines = 0;
+    for (delivery, recorded) in entries.seen.lock().unwrap().iter() {
+        if now.saturating_sub(*recorded) < ttl {
+            writeln!(file, "{recorded} {delivery}")?;
+            lines += 1;
+        }
+    }
+    file.sync_all()?;
+    std::fs::rename(&compacted, path)?;
+    Ok((OpenOptions::new().append(true).open(path)?, lines))
+}
+
+impl Writer {
+    fn append(&mut self, line: Line) {
+        let line = match line {
+            Line::Record { recorded, delivery } => format!("{recorded} {delivery}"),
+            Line::Forget {
+                forgotten,
+                delivery,
+            } => format!("{forgotten} -{delivery}"),
+        };
+        let appended = writeln!(self.file, "{line}").and_then(|()| self.file.flush());
+        if let Err(err) = appended {
+            tracing::warn!(%err, path = ?self.path, "unable to persist the delivery");
+        }
+        self.lines += 1;
+        let live = self.entries.seen.lock().unwrap().len();
+        if self.lines.saturating_sub(live) > self.compaction_threshold {
+            match compact(&self.path, &self.entries) {
+                Ok((file, lines)) => (self.file, self.lines) = (file, lines),
+                Err(err) => {
+                    tracing::warn!(%err, path = ?self.path, "unable to compact the deliveries")
+                }
+            }
+        }
+    }
+}
+
+impl DeliveryStore for FileDeliveryStore {
+    fn record(&self, delivery: &str) -> Result<bool, DeliveryStoreError> {
+        let now = self.entries.clock.unix_secs();
+        if !self.entries.record_at(delivery, now) {
+            return Ok(false);
+        }
+        self.send(Line::Record {
+            recorded: now,
+            delivery: delivery.to_owned(),
+        });
+        Ok(true)
+    }
+
+    fn forget(&self, delivery: &str) -> Result<(), DeliveryStoreError> {
+        self.entries.forget(delivery)?;
+        self.send(Line::Forget {
+            forgotten: self.entries.clock.unix_secs(),
+            delivery: delivery.to_owned(),
+        });
+        Ok(())
+    }
+}
+
+impl Drop for FileDeliveryStore {
+    /// Waits for the pending lines to be written, the file is complete once the store is gone.
+    fn drop(&mut self) {
+        drop(self.writes.take());
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DeliveryStore, FileDeliveryStore, InMemoryDeliveryStore};
+    use crate::clock::{ManualClock, SystemClock};
+    use std::sync::Arc;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    #[test]
+    fn test_duplicate_delivery_is_detected() {
+        let store = InMemoryDeliveryStore::new(DAY);
+        assert!(store
+            .record("72d3162e-cc78-11e3-81ab-4c9367dc0958")
+            .unwrap());
+        assert!(!store
+            .record("72d3162e-cc78-11e3-81ab-4c9367dc0958")
+            .unwrap());
+        assert!(store
+            .record("72d3162e-cc78-11e3-81ab-4c9367dc0959")
+            .unwrap());
+    }
+
+    #[test]
+    fn test_expired_delivery_is_recorded_again() {
+        let store = InMemoryDeliveryStore::new(DAY);
+        assert!(store.record_at("72d3162e-cc78-11e3-81ab-4c9367dc0958", 0));
+        assert!(store.record_at("72d3162e-cc78-11e3-81ab-4c9367dc0958", DAY.as_secs()));
+    }
+
+    #[test]
+    fn test_delivery_expires_exactly_at_the_ttl() {
+        let clock = Arc::new(ManualClock::at(UNIX_EPOCH + DAY));
+        let store = InMemoryDeliveryStore::with_clock(DAY, clock.clone());
+        assert!(store
+            .record("72d3162e-cc78-11e3-81ab-4c9367dc0958")
+            .unwrap());
+
+        clock.advance(DAY - Duration::from_secs(1));
+        assert!(!store
+            .record("72d3162e-cc78-11e3-81ab-4c9367dc0958")
+            .unwrap());
+
+        clock.advance(Duration::from_secs(1));
+        assert!(store
+            .record("72d3162e-cc78-11e3-81ab-4c9367dc0958")
+            .unwrap());
+    }
+
+    #[test]
+    fn test_persisted_delivery_expires_at_the_ttl() {
+        let path = std::env::temp_dir().join(format!("deliveries-ttl-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let clock = Arc::new(ManualClock::at(UNIX_EPOCH + DAY));
+
+        let store = FileDeliveryStore::open_with_clock(&path, DAY, clock.clone()).unwrap();
+        assert!(store
+            .record("72d3162e-cc78-11e3-81ab-4c9367dc0958")
+            .unwrap());
+        drop(store);
+        clock.advance(DAY - Duration::from_secs(1));
+
+        let store = FileDeliveryStore::open_with_clock(&path, DAY, clock.clone()).unwrap();
+        assert!(!store
+            .record("72d3162e-cc78-11e3-81ab-4c9367dc0958")
+            .unwrap());
+        drop(store);
+        clock.advance(Duration::from_secs(1));
+
+        let store = FileDeliveryStore::open_with_clock(&path, DAY, clock).unwrap();
+        assert!(store
+            .record("72d3162e-cc78-11e3-81ab-4c9367dc0958")
+            .unwrap());
+        std::fs::remove_file(store.path()).unwrap();
+    }
+
+    #[test]
+    fn test_delivery_is_deduped_after_restart() {
+        let path = std::env::temp_dir().join(format!("deliveries-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let store = FileDeliveryStore::open(&path, DAY).unwrap();
+        assert!(store
+            .record("72d3162e-cc78-11e3-81ab-4c9367dc0958")
+            .unwrap());
+        drop(store);
+
+        let store = FileDeliveryStore::open(&path, DAY).unwrap();
+        assert!(!store
+            .record("72d3162e-cc78-11e3-81ab-4c9367dc0958")
+            .unwrap());
+        assert!(store
+            .record("72d3162e-cc78-11e3-81ab-4c9367dc0959")
+            .unwrap());
+        std::fs::remove_file(store.path()).unwrap();
+    }
+
+    #[test]
+    fn test_forgotten_deliveries_are_compacted_away() {
+        let path = std::env::temp_dir().join(format!("deliveries-compact-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let clock = Arc::new(SystemClock);
+        let store =
+            FileDeliveryStore::open_with_compaction_threshold(&path, DAY, clock, 2).unwrap();
+        for attempt in 0..10 {
+            let delivery = format!("72d3162e-cc78-11e3-81ab-4c9367dc09{attempt:02}");
+            assert!(store.record(&delivery).unwrap());
+            store.forget(&delivery).unwrap();
+        }
+        assert!(store
+            .record("72d3162e-cc78-11e3-81ab-4c9367dc0958")
+            .unwrap());
+        drop(store);
+
+        let lines = std::fs::read_to_string(&path).unwrap().lines().count();
+        assert!(lines <= 3, "{lines} lines");
+        let store = FileDeliveryStore::open(&path, DAY).unwrap();
+        assert!(!store
+            .record("72d3162e-cc78-11e3-81ab-4c9367dc0958")
+            .unwrap());
+        assert!(store
+            .record("72d3162e-cc78-11e3-81ab-4c9367dc0900")
+            .unwrap());
+        std::fs::remove_file(store.path()).unwrap();
+    }
+
+    #[test]
+    fn test_forgotten_delivery_is_recorded_again_after_restart() {
+        let path = std::env::temp_dir().join(format!("deliveries-forget-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let store = FileDeliveryStore::open(&path, DAY).unwrap();
+        assert!(store
+            .record("72d3162e-cc78-11e3-81ab-4c9367dc0958")
+            .unwrap());
+        store
+            .forget("72d3162e-cc78-11e3-81ab-4c9367dc0958")
+            .unwrap();
+        drop(store);
+
+        let store = FileDeliveryStore::open(&path, DAY).unwrap();
+        assert!(store
+            .record("72d3162e-cc78-11e3-81ab-4c9367dc0958")
+            .unwrap());
+        std::fs::remove_file(store.path()).unwrap();
+    }
+}
@@ -0,0 +1,156 @@
+use crate::clock::{Clock, SystemClock};
+use crate::forwarder::{DeliveryForwarder, DeliverySummary};
+use futures_util::future::BoxFuture;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// When [`NdjsonDeliveryLog`] starts a new file, never by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rotation {
+    /// Rotates before a line would grow the file beyond this size.
+    pub max_bytes: Option<u64>,
+    /// Rotates files which have been written to for this long.
+    pub max_age: Option<Duration>,
+}
+
+/// Appends the summary of every delivery as one JSON line to a file, for analysis without a
+/// metrics system. Like forwarded summaries the lines carry neither payloads nor secrets.
+///
+/// Rotated files are renamed to `<path>.<unix seconds>`, they are never deleted.
+#[derive(Debug, Clone)]
+pub struct NdjsonDeliveryLog {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    path: PathBuf,
+    rotation: Rotation,
+    clock: Arc<dyn Clock>,
+    file: Mutex<LogFile>,
+}
+
+#[derive(Debug)]
+struct LogFile {
+    file: File,
+    bytes: u64,
+    opened_at: u64,
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    recorded_at: u64,
+    #[serde(flatten)]
+    summary: &'a DeliverySummary,
+}
+
+impl NdjsonDeliveryLog {
+    pub fn open(path: impl AsRef<Path>, rotation: Rotation) -> io::Result<Self> {
+        Self::open_with_clock(path, rotation, Arc::new(SystemClock))
+    }
+
+    pub fn open_with_clock(
+        path: impl AsRef<Path>,
+        rotation: Rotation,
+        clock: Arc<dyn Clock>,
+    ) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = LogFile::open(&path, clock.unix_secs())?;
+        Ok(Self {
+            inner: Arc::new(Inner {
+                path,
+                rotation,
+                clock,
+                file: Mutex::new(file),
+            }),
+        })
+    }
+}
+
+impl LogFile {
+    fn open(path: &Path, now: u64) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            bytes: file.metadata()?.len(),
+            file,
+            // appending to an existing file restarts its age
+            opened_at: now,
+        })
+    }
+}
+
+impl Inner {
+    fn is_due(&self, file: &LogFile, line: u64, now: u64) -> bool {
+        if file.bytes == 0 {
+            return false;
+        }
+        let too_large = self
+            .rotation
+            .max_bytes
+            .is_some_and(|max_bytes| file.bytes + line > max_bytes);
+        let too_old = self
+            .rotation
+            .max_age
+            .is_some_and(|max_age| now.saturating_sub(file.opened_at) >= max_age.as_secs());
+        too_large || too_old
+    }
+
+    fn rotate(&self, file: &mut LogFile, now: u64) -> io::Result<()> {
+        let mut rotated = self.path.with_extension(suffixed(&self.path, now));
+        let mut n = 1;
+        while rotated.exists() {
+            rotated = self
+                .path
+                .with_extension(suffixed(&self.path, format!("{now}-{n}")));
+            n += 1;
+        }
+        std::fs::rename(&self.path, rotated)?;
+        *file = LogFile::open(&self.path, now)?;
+        Ok(())
+    }
+
+    fn append(&self, summary: &DeliverySummary) -> io::Result<()> {
+        let now = self.clock.unix_secs();
+        let mut line = serde_json::to_vec(&Line {
+            recorded_at: now,
+            summary,
+        })?;
+        line.push(b'\n');
+        let mut file = self.file.lock().unwrap();
+        if self.is_due(&file, line.len() as u64, now) {
+            self.rotate(&mut file, now)?;
+        }
+        file.file.write_all(&line)?;
+        file.bytes += line.len() as u64;
+        Ok(())
+    }
+}
+
+/// The extension of `path` followed by `suffix`, e.g. `deliveries.ndjson.1700000000`.
+fn suffixed(path: &Path, suffix: impl std::fmt::Display) -> String {
+    match path.extension() {
+        Some(extension) => format!("{}.{suffix}", extension.to_string_lossy()),
+        None => suffix.to_string(),
+    }
+}
+
+impl DeliveryForwarder for NdjsonDeliveryLog {
+    fn forward(&self, summary: DeliverySummary) -> BoxFuture<'static, ()> {
+        let inner = self.inner.clone();
+        Box::pin(async move {
+            // the file is written and rotated on a blocking thread, never on the runtime
+            let path = inner.path.clone();
+            let appended = tokio::task::spawn_blocking(move || inner.append(&summary))
+                .await
+                .map_err(io::Error::other)
+                .and_then(|appended| appended);
+            if let Err(err) = appended {
+                tracing::warn!(%err, ?path, "unable to record delivery");
+            }
+        })
+    }
+}
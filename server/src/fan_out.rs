@@ -0,0 +1,94 @@
+use axum::http::{header, HeaderMap, HeaderValue, Method, Request};
+use axum_core::body::Body;
+use bytes::Bytes;
+use hyper::Uri;
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::client::legacy::{connect::HttpConnector, Client};
+use hyper_util::rt::TokioExecutor;
+use orion::hazardous::mac::hmac::sha256::{HmacSha256, SecretKey};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Receivers answering slower than GitHub would wait for are given up on.
+const FAN_OUT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Mirrors verified deliveries to further http or https webhook endpoints, fire-and-forget.
+///
+/// The original body and `X-GitHub-*` headers are posted as they were received. With a secret
+/// the delivery is re-signed with it, otherwise the original signatures are passed on and the
+/// receivers have to share the webhook secret.
+#[derive(Debug, Clone)]
+pub struct FanOut {
+    urls: Arc<[Uri]>,
+    secret: Option<Arc<SecretKey>>,
+    client: Client<HttpsConnector<HttpConnector>, Body>,
+}
+
+impl FanOut {
+    pub fn new(urls: Vec<Uri>, secret: Option<Arc<SecretKey>>) -> Self {
+        Self {
+            urls: urls.into(),
+            secret,
+            client: Client::builder(TokioExecutor::new()).build(
+                HttpsConnectorBuilder::new()
+                    .with_webpki_roots()
+                    .https_or_http()
+                    .enable_http1()
+                    .build(),
+            ),
+        }
+    }
+
+    /// Spawns one request per receiver, failures are only logged.
+    pub fn mirror(&self, headers: &HeaderMap, body: Bytes) {
+        let headers = self.mirrored_headers(headers, &body);
+        for url in self.urls.iter() {
+            let url = url.clone();
+            let client = self.client.clone();
+            let mut request = Request::builder().method(Method::POST).uri(url.clone());
+            if let Some(request_headers) = request.headers_mut() {
+                request_headers.extend(headers.clone());
+            }
+            let request = request.body(Body::from(body.clone()));
+            tokio::spawn(async move {
+                let result = match request {
+                    Ok(request) => tokio::time::timeout(FAN_OUT_TIMEOUT, client.request(request))
+                        .await
+                        .map_err(|err| err.to_string())
+                        .and_then(|response| response.map_err(|err| err.to_string())),
+                    Err(err) => Err(err.to_string()),
+                };
+                match result {
+                    Ok(response) if response.status().is_success() => {}
+                    Ok(response) => {
+                        tracing::warn!(%url, status = %response.status(), "fan-out receiver rejected the delivery")
+                    }
+                    Err(err) => tracing::warn!(%url, %err, "unable to fan out the delivery"),
+                }
+            });
+        }
+    }
+
+    fn mirrored_headers(&self, headers: &HeaderMap, body: &[u8]) -> HeaderMap {
+        let mut mirrored: HeaderMap = headers
+            .iter()
+            .filter(|(name, _)| {
+                name.as_str().starts_with("x-github-")
+                    || *name == header::CONTENT_TYPE
+                    || *name == header::USER_AGENT
+                    || (self.secret.is_none() && name.as_str().starts_with("x-hub-signature"))
+            })
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        if let Some(ref secret) = self.secret {
+            let signature = HmacSha256::hmac(secret, body)
+                .ok()
+                .map(|tag| format!("sha256={}", hex::encode(tag.unprotected_as_bytes())))
+                .and_then(|signature| HeaderValue::try_from(signature).ok());
+            if let Some(signature) = signature {
+                mirrored.insert("x-hub-signature-256", signature);
+            }
+        }
+        mirrored
+    }
+}
@@ -0,0 +1,131 @@
+use axum::http::{header, HeaderMap, Method, Request, StatusCode};
+use axum_core::body::Body;
+use futures_util::future::BoxFuture;
+use hyper::Uri;
+use hyper_util::client::legacy::{connect::HttpConnector, Client};
+use hyper_util::rt::TokioExecutor;
+use octocrab::models::webhook_events::{EventInstallation, WebhookEvent};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+/// Mirrors a summary of every processed delivery to an external sink, e.g. a debugging service.
+///
+/// Forwarding happens after the response has been determined and must never delay it.
+pub trait DeliveryForwarder: std::fmt::Debug + Send + Sync {
+    fn forward(&self, summary: DeliverySummary) -> BoxFuture<'static, ()>;
+}
+
+/// Redacted description of a delivery, neither the payload nor any secret is included.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeliverySummary {
+    pub delivery: Option<String>,
+    pub event: String,
+    pub installation: Option<u64>,
+    pub repository: Option<String>,
+    pub client_ip: Option<IpAddr>,
+    pub status: u16,
+    /// Configured delivery headers, keyed by their lowercase name.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+}
+
+/// Headers carrying signatures or credentials, they are never copied into a summary.
+pub const NEVER_CAPTURED_HEADERS: &[&str] = &[
+    "x-hub-signature",
+    "x-hub-signature-256",
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+];
+
+impl DeliverySummary {
+    pub fn new(headers: &HeaderMap, event: &WebhookEvent) -> Self {
+        Self {
+            delivery: headers
+                .get("x-github-delivery")
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_owned),
+            event: serde_json::to_value(&event.kind)
+                .ok()
+                .and_then(|kind| kind.as_str().map(str::to_owned))
+                .unwrap_or_default(),
+            installation: match event.installation {
+                Some(EventInstallation::Full(ref installation)) => Some(installation.id.0),
+                Some(EventInstallation::Minimal(ref installation)) => Some(installation.id.0),
+                None => None,
+            },
+            repository: event.repository.as_ref().map(|repository| {
+                repository
+                    .full_name
+                    .clone()
+                    .unwrap_or(repository.name.clone())
+            }),
+            client_ip: None,
+            status: 0,
+            headers: BTreeMap::new(),
+        }
+    }
+
+    /// Copies the headers with the given (case-insensitive) names, except for the
+    /// [`NEVER_CAPTURED_HEADERS`].
+    pub fn with_headers(mut self, headers: &HeaderMap, names: &[String]) -> Self {
+        for name in names.iter().map(|name| name.to_ascii_lowercase()) {
+            if NEVER_CAPTURED_HEADERS.contains(&name.as_str()) {
+                continue;
+            }
+            if let Some(value) = headers.get(&name).and_then(|value| value.to_str().ok()) {
+                self.headers.insert(name, value.to_owned());
+            }
+        }
+        self
+    }
+
+    pub fn with_status(self, status: StatusCode) -> Self {
+        Self {
+            status: status.as_u16(),
+            ..self
+        }
+    }
+}
+
+/// Posts the summaries as JSON to the configured (plain http) url.
+#[derive(Debug, Clone)]
+pub struct HttpForwarder {
+    url: Uri,
+    client: Client<HttpConnector, Body>,
+}
+
+impl HttpForwarder {
+    pub fn new(url: Uri) -> Self {
+        Self {
+            url,
+            client: Client::builder(TokioExecutor::new()).build_http(),
+        }
+    }
+}
+
+impl DeliveryForwarder for HttpForwarder {
+    fn forward(&self, summary: DeliverySummary) -> BoxFuture<'static, ()> {
+        let url = self.url.clone();
+        let client = self.client.clone();
+        Box::pin(async move {
+            let request = Request::builder()
+                .method(Method::POST)
+                .uri(url)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(serde_json::to_vec(&summary).unwrap_or_default()));
+            let result = match request {
+                Ok(request) => client.request(request).await.map_err(|err| err.to_string()),
+                Err(err) => Err(err.to_string()),
+            };
+            match result {
+                Ok(response) if response.status().is_success() => {}
+                Ok(response) => {
+                    tracing::warn!(status = %response.status(), "delivery forwarder rejected summary")
+                }
+                Err(err) => tracing::warn!(%err, "unable to forward delivery summary"),
+            }
+        })
+    }
+}
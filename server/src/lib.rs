@@ -1,27 +1,63 @@
+pub mod clock;
 pub mod config;
+pub mod dead_letters;
+pub mod deliveries;
+pub mod delivery_log;
+pub mod fan_out;
+pub mod forwarder;
+pub mod pre_auth;
+pub mod probe;
+pub mod publisher;
+pub mod queue;
+pub mod redaction;
+pub mod replay;
 pub mod routes;
+pub mod secrets;
+pub mod shutdown;
+pub mod signature;
+pub mod tls;
+pub mod verification;
+pub mod wal;
 
 use crate::config::{InternalEndpointConfiguration, WebhookEndpointConfiguration};
-use axum::{middleware::from_fn, Router};
+use axum::{middleware::from_fn_with_state, Router};
 use config::GitHubAppConfiguration;
 use github_event_handler::authentication::GitHubAppAuthenticator;
+use probe::GitHubProbe;
+pub use routes::event_handler::AppHandlers;
+pub use routes::maintenance::Maintenance;
 pub use routes::metrics::track_metrics;
+use shutdown::{InFlightHandlers, Shutdown};
+use std::future::{Future, IntoFuture};
+use std::net::SocketAddr;
+use std::time::Duration;
 use tokio::net::TcpListener;
 use tracing::instrument;
 
-#[instrument(skip(app_config))]
-pub async fn public_app<C: GitHubAppAuthenticator>(
+#[instrument(skip(app_config, handlers, shutdown))]
+pub async fn public_app<C: GitHubAppAuthenticator + 'static>(
     app_config: GitHubAppConfiguration,
     endpoint_config: WebhookEndpointConfiguration,
+    handlers: AppHandlers<C>,
+    shutdown: Shutdown,
 ) -> Result<(), Box<dyn std::error::Error>>
 where
     C::Error: 'static,
     C::Next: 'static,
 {
+    // fails before binding the listener on unusable certificates
+    let tls_config = endpoint_config
+        .tls
+        .as_ref()
+        .map(tls::TlsConfiguration::load)
+        .transpose()?;
     let routes = Router::new()
         .merge(routes::ui::router())
-        .merge(routes::event_handler::router::<C>(app_config, &endpoint_config.path).await?)
-        .route_layer(from_fn(track_metrics));
+        .merge(routes::event_handler::router::<C>(app_config, &endpoint_config, handlers).await?)
+        .route_layer(from_fn_with_state(
+            endpoint_config.metrics.clone(),
+            track_metrics,
+        ));
 
     let listener = {
         let addr = endpoint_config.addr;
@@ -29,19 +65,283 @@ where
         TcpListener::bind(addr).await?
     };
 
-    Ok(axum::serve(listener, routes).await?)
+    let served = match tls_config {
+        Some(tls_config) => {
+            drain(
+                tls::serve_tls(listener, routes, tls_config, shutdown.clone().triggered()),
+                shutdown.clone(),
+                endpoint_config.in_flight,
+                endpoint_config.shutdown_drain_timeout,
+            )
+            .await
+        }
+        None => {
+            serve_draining(
+                listener,
+                routes,
+                shutdown.clone(),
+                endpoint_config.in_flight,
+                endpoint_config.shutdown_drain_timeout,
+            )
+            .await
+        }
+    };
+    shutdown.mark_drained();
+    Ok(served?)
+}
+
+/// Serves until the shutdown is triggered and the in-flight requests are drained, handlers still
+/// running after `drain_timeout` are cancelled and abandoned.
+async fn serve_draining(
+    listener: TcpListener,
+    routes: Router,
+    shutdown: Shutdown,
+    in_flight: InFlightHandlers,
+    drain_timeout: Duration,
+) -> std::io::Result<()> {
+    let served = axum::serve(
+        listener,
+        routes.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown.clone().triggered())
+    .into_future();
+    drain(served, shutdown, in_flight, drain_timeout).await
+}
+
+/// Waits for `served` and the in-flight handlers, queued events included, to finish draining
+/// after the shutdown has been triggered.
+pub(crate) async fn drain(
+    served: impl Future<Output = std::io::Result<()>>,
+    shutdown: Shutdown,
+    in_flight: InFlightHandlers,
+    drain_timeout: Duration,
+) -> std::io::Result<()> {
+    let timed_out = async {
+        shutdown.triggered().await;
+        tokio::time::sleep(drain_timeout).await;
+    };
+    let settled = async {
+        let served = served.await;
+        while in_flight.count() > 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        served
+    };
+    tokio::select! {
+        served = settled => {
+            tracing::info!("finished draining in-flight requests");
+            served
+        }
+        _ = timed_out => {
+            let cancelled = in_flight.cancel_all();
+            tracing::warn!(cancelled, "drain timeout elapsed, cancelled remaining handlers");
+            // cancelled handlers wind down and queued events are dead-lettered, neither may
+            // be cut off by the process exiting
+            let settled = tokio::time::timeout(CANCELLATION_GRACE_PERIOD, async {
+                while in_flight.count() > 0 {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+            .await;
+            if settled.is_err() {
+                let remaining = in_flight.count();
+                tracing::error!(remaining, "handlers ignored their cancellation, exiting anyway");
+            }
+            Ok(())
+        }
+    }
 }
 
-#[instrument]
+/// How long cancelled handlers get to finish once the drain timeout elapsed.
+const CANCELLATION_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+#[instrument(skip(maintenance, shutdown))]
 pub async fn internal_app(
-    endpoint_config: InternalEndpointConfiguration,
+    mut endpoint_config: InternalEndpointConfiguration,
+    maintenance: Maintenance,
+    shutdown: Shutdown,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let routes = routes::metrics::router();
+    let probe = match endpoint_config.github_probe.take() {
+        Some(probe_config) => {
+            let probe = GitHubProbe::new(probe_config.interval, probe_config.max_backoff);
+            let check = probe::reachability_check(
+                probe_config.uri,
+                probe_config.timeout,
+                probe_config.max_body_bytes,
+            )?;
+            tokio::spawn(probe.clone().run(check, shutdown.clone()));
+            Some(probe)
+        }
+        None => None,
+    };
     let listener = {
         let addr = endpoint_config.addr;
         tracing::debug!("listening");
         TcpListener::bind(addr).await?
     };
+    let routes = internal_router(endpoint_config, probe, maintenance, shutdown.clone());
+
+    // keeps serving health and metrics until the public endpoint has been drained
+    Ok(axum::serve(listener, routes)
+        .with_graceful_shutdown(shutdown.drained())
+        .await?)
+}
+
+/// The routes of the internal endpoint, those disabled by the configuration answer `404`.
+fn internal_router(
+    endpoint_config: InternalEndpointConfiguration,
+    probe: Option<GitHubProbe>,
+    maintenance: Maintenance,
+    shutdown: Shutdown,
+) -> Router {
+    let mut routes = Router::new();
+    if endpoint_config.enable_maintenance {
+        routes = routes.merge(routes::maintenance::router(
+            maintenance,
+            endpoint_config.maintenance_auth,
+        ));
+    }
+    if endpoint_config.enable_metrics {
+        routes = routes.merge(routes::metrics::router());
+    }
+    if endpoint_config.enable_health {
+        routes = routes.merge(routes::health::router(
+            shutdown,
+            probe,
+            endpoint_config.auth_health,
+        ));
+    }
+    if let Some(debug_config) = endpoint_config.debug_config {
+        routes = routes.merge(routes::debug_config::router(debug_config));
+    }
+    routes
+}
+
+#[cfg(test)]
+mod test {
+    use super::{internal_router, serve_draining, CANCELLATION_GRACE_PERIOD};
+    use crate::config::InternalEndpointConfiguration;
+    use crate::routes::health::AuthHealth;
+    use crate::shutdown::{InFlightHandlers, Shutdown};
+    use crate::Maintenance;
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use axum::{routing::get, Router};
+    use std::time::Duration;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::{TcpListener, TcpStream};
+    use tower::ServiceExt;
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_stuck_handler_is_cancelled_after_drain_timeout() {
+        let in_flight = InFlightHandlers::default();
+        let tracked = in_flight.clone();
+        let routes = Router::new().route(
+            "/stuck",
+            get(move || {
+                let handler = tracked.track();
+                async move {
+                    // ignores its cancellation token
+                    let _handler = handler;
+                    std::future::pending::<&'static str>().await
+                }
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = Shutdown::default();
+        let server = tokio::spawn(serve_draining(
+            listener,
+            routes,
+            shutdown.clone(),
+            in_flight.clone(),
+            Duration::from_millis(100),
+        ));
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"GET /stuck HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        while in_flight.count() == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        shutdown.trigger();
+
+        // the handler ignores its cancellation for the whole grace period
+        tokio::time::timeout(CANCELLATION_GRACE_PERIOD * 2, server)
+            .await
+            .expect("the server did not exit after the drain timeout")
+            .unwrap()
+            .unwrap();
+        assert!(logs_contain("cancelled=1"));
+        assert!(logs_contain("handlers ignored their cancellation"));
+    }
+
+    /// Status of `method uri` on the internal routes with the given endpoints enabled.
+    async fn internal_status(
+        enable_metrics: bool,
+        enable_health: bool,
+        enable_maintenance: bool,
+        method: &str,
+        uri: &str,
+    ) -> StatusCode {
+        let config = InternalEndpointConfiguration {
+            addr: ([127, 0, 0, 1], 0).into(),
+            auth_health: AuthHealth::default(),
+            github_probe: None,
+            debug_config: None,
+            enable_metrics,
+            enable_health,
+            enable_maintenance,
+            maintenance_auth: None,
+        };
+        let routes = internal_router(config, None, Maintenance::default(), Shutdown::default());
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap();
+        routes.oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_disabled_internal_endpoints_are_absent() {
+        for uri in ["/metrics", "/healthz", "/readyz", "/debug/config"] {
+            let status = internal_status(false, false, false, "GET", uri).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{uri}");
+        }
+        assert_eq!(
+            internal_status(false, false, false, "DELETE", "/maintenance").await,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn test_internal_endpoints_are_toggled_independently() {
+        // metrics only
+        let metrics = internal_status(true, false, false, "GET", "/metrics").await;
+        assert_ne!(metrics, StatusCode::NOT_FOUND);
+        for uri in ["/healthz", "/readyz"] {
+            let status = internal_status(true, false, false, "GET", uri).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{uri}");
+        }
+        let maintenance = internal_status(true, false, false, "PUT", "/maintenance").await;
+        assert_eq!(maintenance, StatusCode::NOT_FOUND);
+
+        // health only
+        let metrics = internal_status(false, true, false, "GET", "/metrics").await;
+        assert_eq!(metrics, StatusCode::NOT_FOUND);
+        for uri in ["/healthz", "/readyz"] {
+            let status = internal_status(false, true, false, "GET", uri).await;
+            assert_ne!(status, StatusCode::NOT_FOUND, "{uri}");
+        }
 
-    Ok(axum::serve(listener, routes).await?)
+        // maintenance only, without a token every toggle is rejected
+        let maintenance = internal_status(false, false, true, "PUT", "/maintenance").await;
+        assert_eq!(maintenance, StatusCode::UNAUTHORIZED);
+        let metrics = internal_status(false, false, true, "GET", "/metrics").await;
+        assert_eq!(metrics, StatusCode::NOT_FOUND);
+    }
 }
@@ -4,21 +4,55 @@ use octocrab::{models::AppId, Octocrab};
 use orion::hazardous::mac::hmac::sha256::SecretKey;
 use rand_chacha::ChaCha20Rng;
 use rsa::RsaPrivateKey;
-use server::config::{load_github_app_config, GitHubAppConfiguration};
+use server::config::{
+    load_github_app_config, load_signature_header_name, load_webhook_secret, GitHubAppConfiguration,
+};
+use server::replay::{replay, CapturedDelivery};
+use server::shutdown::{listen_for_signals, Shutdown};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     setup_tracing()?;
     setup_crypto()?;
+    let mut args = std::env::args().skip(1);
+    if args.next().as_deref() == Some("replay") {
+        return replay_command(args).await;
+    }
     let (app_config, public_ep, internal_ep) = load_github_app_config()?; //.unwrap_or(create_dummy_config());
 
+    let shutdown = Shutdown::default();
+    tokio::spawn(listen_for_signals(shutdown.clone()));
+
+    let maintenance = public_ep.maintenance.clone();
     tokio::try_join!(
-        server::public_app::<Octocrab>(app_config, public_ep),
-        server::internal_app(internal_ep)
+        server::public_app::<Octocrab>(app_config, public_ep, Default::default(), shutdown.clone()),
+        server::internal_app(internal_ep, maintenance, shutdown)
     )?;
     Ok(())
 }
 
+/// `replay --file delivery.json --url http://localhost:3000/event_handler`
+async fn replay_command(
+    mut args: impl Iterator<Item = String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (mut file, mut url) = (None, None);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--file" => file = args.next(),
+            "--url" => url = args.next(),
+            _ => return Err(format!("unknown argument {arg}").into()),
+        }
+    }
+    let (Some(file), Some(url)) = (file, url) else {
+        return Err("usage: replay --file <delivery.json> --url <endpoint>".into());
+    };
+    let delivery = CapturedDelivery::read(file)?;
+    let (url, secret) = (Uri::try_from(url)?, load_webhook_secret()?);
+    let status = replay(&delivery, url, &secret, &load_signature_header_name()?).await?;
+    println!("{status}");
+    Ok(())
+}
+
 fn setup_tracing() -> Result<(), Box<dyn std::error::Error>> {
     use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
@@ -56,5 +90,8 @@ fn create_dummy_config() -> GitHubAppConfiguration {
             EncodingKey::from_rsa_pem(cert_pem_str.as_bytes()).unwrap()
         },
         uri: Uri::from_static("https://github.local"),
+        user_agent: server::config::DEFAULT_USER_AGENT.to_string(),
+        api_timeout: None,
+        subscriptions: Vec::new(),
     }
 }
@@ -0,0 +1,60 @@
+use axum::http::{HeaderMap, HeaderName};
+use thiserror::Error;
+
+/// Authenticates a delivery by its headers before its signature is verified and before its
+/// body is read, e.g. by a secret a trusted proxy in front of the endpoint adds.
+///
+/// Rejected deliveries are answered with `401`.
+pub trait PreAuth: std::fmt::Debug + Send + Sync {
+    fn authenticate(&self, headers: &HeaderMap) -> Result<(), PreAuthError>;
+
+    /// The headers carrying the credentials, removed before the delivery reaches the handlers.
+    fn credential_headers(&self) -> Vec<HeaderName> {
+        Vec::new()
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum PreAuthError {
+    #[error("Missing the {0} header")]
+    MissingHeader(HeaderName),
+    #[error("The {0} header does not match")]
+    Mismatch(HeaderName),
+}
+
+/// Requires the header to carry the shared secret, compared in constant time.
+pub struct ProxySecretHeader {
+    header: HeaderName,
+    secret: Vec<u8>,
+}
+
+impl ProxySecretHeader {
+    pub fn new(header: HeaderName, secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            header,
+            secret: secret.into(),
+        }
+    }
+}
+
+impl std::fmt::Debug for ProxySecretHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProxySecretHeader")
+            .field("header", &self.header)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PreAuth for ProxySecretHeader {
+    fn authenticate(&self, headers: &HeaderMap) -> Result<(), PreAuthError> {
+        let Some(value) = headers.get(&self.header) else {
+            return Err(PreAuthError::MissingHeader(self.header.clone()));
+        };
+        orion::util::secure_cmp(value.as_bytes(), &self.secret)
+            .map_err(|_| PreAuthError::Mismatch(self.header.clone()))
+    }
+
+    fn credential_headers(&self) -> Vec<HeaderName> {
+        vec![self.header.clone()]
+    }
+}
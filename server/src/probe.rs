@@ -0,0 +1,177 @@
+use crate::shutdown::Shutdown;
+use futures_util::future::BoxFuture;
+use http_body_util::{BodyExt, LengthLimitError, Limited};
+use hyper::Uri;
+use octocrab::Octocrab;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How often the reachability of GitHub is probed for `/readyz`.
+#[derive(Debug, Clone)]
+pub struct GitHubProbeConfiguration {
+    pub uri: Uri,
+    /// Delay between probes while GitHub is reachable.
+    pub interval: Duration,
+    /// Upper bound of the delay after consecutive failures.
+    pub max_backoff: Duration,
+    /// A probe without a complete response within this long fails.
+    pub timeout: Duration,
+    /// The response body is read up to this size only.
+    pub max_body_bytes: usize,
+}
+
+impl GitHubProbeConfiguration {
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+    pub const DEFAULT_MAX_BODY_BYTES: usize = 1024 * 1024;
+}
+
+/// Result of the last reachability probe, failed probes are retried with an exponentially
+/// growing delay until they succeed again.
+///
+/// GitHub counts as reachable until the first probe completed.
+#[derive(Debug, Clone)]
+pub struct GitHubProbe {
+    interval: Duration,
+    max_backoff: Duration,
+    reachable: Arc<AtomicBool>,
+    failures: Arc<AtomicU32>,
+}
+
+impl GitHubProbe {
+    pub fn new(interval: Duration, max_backoff: Duration) -> Self {
+        Self {
+            interval,
+            max_backoff,
+            reachable: Arc::new(AtomicBool::new(true)),
+            failures: Default::default(),
+        }
+    }
+
+    pub fn is_reachable(&self) -> bool {
+        self.reachable.load(Ordering::SeqCst)
+    }
+
+    pub fn record(&self, reachable: bool) {
+        self.reachable.store(reachable, Ordering::SeqCst);
+        if reachable {
+            self.failures.store(0, Ordering::SeqCst);
+        } else {
+            self.failures.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// The interval doubled for every consecutive failure, capped at the maximum backoff.
+    pub fn next_delay(&self) -> Duration {
+        let failures = self.failures.load(Ordering::SeqCst).min(31);
+        self.interval
+            .saturating_mul(1 << failures)
+            .min(self.max_backoff.max(self.interval))
+    }
+
+    /// Probes with `check` until the shutdown is triggered.
+    pub async fn run<F, Fut>(self, check: F, shutdown: Shutdown)
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = bool>,
+    {
+        let triggered = shutdown.triggered();
+        tokio::pin!(triggered);
+        loop {
+            let reachable = check().await;
+            if reachable != self.is_reachable() {
+                if reachable {
+                    tracing::info!("GitHub is reachable again");
+                } else {
+                    tracing::warn!("GitHub is unreachable, reporting not ready");
+                }
+            }
+            self.record(reachable);
+            tokio::select! {
+                _ = &mut triggered => return,
+                _ = tokio::time::sleep(self.next_delay()) => {}
+            }
+        }
+    }
+}
+
+/// Any response counts as reachable, only failing to get one within the `timeout` doesn't.
+///
+/// The body has to arrive within the `timeout` as well, reading stops after `max_body_bytes`.
+pub fn reachability_check(
+    uri: Uri,
+    timeout: Duration,
+    max_body_bytes: usize,
+) -> Result<impl Fn() -> BoxFuture<'static, bool>, Box<octocrab::Error>> {
+    let client = Octocrab::builder().base_uri(uri)?.build()?;
+    Ok(move || {
+        let client = client.clone();
+        Box::pin(async move {
+            let probe = async {
+                let Ok(response) = client._get("/meta").await else {
+                    return false;
+                };
+                let mut body = Limited::new(response.into_body(), max_body_bytes);
+                while let Some(frame) = body.frame().await {
+                    match frame {
+                        Ok(_) => {}
+                        Err(err) => return err.is::<LengthLimitError>(),
+                    }
+                }
+                true
+            };
+            let reachable = tokio::time::timeout(timeout, probe).await;
+            if reachable.is_err() {
+                tracing::debug!(timeout_ms = timeout.as_millis() as u64, "probe timed out");
+            }
+            reachable.unwrap_or(false)
+        }) as BoxFuture<_>
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{reachability_check, GitHubProbe};
+    use crate::shutdown::Shutdown;
+    use std::time::Duration;
+
+    #[test]
+    fn test_delay_grows_with_failures_up_to_the_maximum() {
+        let probe = GitHubProbe::new(Duration::from_secs(10), Duration::from_secs(60));
+        assert_eq!(probe.next_delay(), Duration::from_secs(10));
+
+        let delays: Vec<_> = (0..4)
+            .map(|_| {
+                probe.record(false);
+                probe.next_delay()
+            })
+            .collect();
+        assert_eq!(delays, [20, 40, 60, 60].map(Duration::from_secs));
+        assert!(!probe.is_reachable());
+
+        probe.record(true);
+        assert_eq!(probe.next_delay(), Duration::from_secs(10));
+        assert!(probe.is_reachable());
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_github_is_recorded() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let uri = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let probe = GitHubProbe::new(Duration::from_millis(5), Duration::from_millis(20));
+        let shutdown = Shutdown::default();
+        let running = tokio::spawn(probe.clone().run(
+            reachability_check(uri.parse().unwrap(), Duration::from_secs(2), 1024).unwrap(),
+            shutdown.clone(),
+        ));
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!probe.is_reachable());
+        assert_eq!(probe.next_delay(), Duration::from_millis(20));
+
+        shutdown.trigger();
+        running.await.unwrap();
+    }
+}
@@ -0,0 +1,260 @@
+use bytes::Bytes;
+use futures_util::future::BoxFuture;
+use octocrab::models::webhook_events::{EventInstallation, WebhookEvent};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Publishes verified deliveries to an external broker, e.g. for consumers running elsewhere.
+///
+/// The key identifies the repository of the event so brokers partitioning by it keep the
+/// events of a repository in order.
+pub trait EventPublisher: std::fmt::Debug + Send + Sync {
+    fn publish(
+        &self,
+        topic: &str,
+        key: &str,
+        payload: Bytes,
+    ) -> BoxFuture<'_, Result<(), PublishError>>;
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PublishError {
+    #[error("Unable to reach the broker: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("The broker rejected the event: {0}")]
+    Rejected(String),
+    #[error("The broker didn't answer within {0:?}")]
+    Timeout(std::time::Duration),
+}
+
+/// Which events are published where.
+#[derive(Debug, Clone)]
+pub struct EventPublishing {
+    pub publisher: Arc<dyn EventPublisher>,
+    /// Topic per `X-GitHub-Event` name, events without one aren't published.
+    pub topics: HashMap<String, String>,
+    /// Published events skip the in-process handlers and are acknowledged with `202`.
+    pub exclusive: bool,
+}
+
+/// The repository's full name, the installation id for events without a repository.
+pub fn partition_key(event: &WebhookEvent) -> String {
+    if let Some(ref repository) = event.repository {
+        return repository
+            .full_name
+            .clone()
+            .unwrap_or(repository.name.clone());
+    }
+    match event.installation {
+        Some(EventInstallation::Full(ref installation)) => installation.id.to_string(),
+        Some(EventInstallation::Minimal(ref installation)) => installation.id.to_string(),
+        None => String::new(),
+    }
+}
+
+#[cfg(feature = "nats")]
+pub use nats::NatsPublisher;
+
+#[cfg(feature = "nats")]
+mod nats {
+    use super::{EventPublisher, PublishError};
+    use bytes::Bytes;
+    use futures_util::future::BoxFuture;
+    use std::future::Future;
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpStream;
+
+    /// Publishes to the subject `<topic>.<owner>.<repository>` of a NATS server, a JetStream
+    /// stream partitions by the repository through a subject mapping of the last two tokens.
+    ///
+    /// Every event is confirmed by a `PING` before its connection is used for the next one.
+    /// The idle connection is opened on first use and again after it failed, concurrent events
+    /// open their own instead of waiting for it. Connecting and publishing each give up after
+    /// the timeout, as the webhook request waits for them.
+    #[derive(Debug)]
+    pub struct NatsPublisher {
+        address: String,
+        timeout: Duration,
+        idle: Mutex<Option<BufReader<TcpStream>>>,
+    }
+
+    impl NatsPublisher {
+        /// `address` is the `host:port` of the server, it requires neither TLS nor credentials.
+        pub fn new(address: impl Into<String>) -> Self {
+            Self {
+                address: address.into(),
+                timeout: Duration::from_secs(2),
+                idle: Mutex::new(None),
+            }
+        }
+
+        pub fn with_timeout(self, timeout: Duration) -> Self {
+            Self { timeout, ..self }
+        }
+
+        async fn timed<T>(
+            &self,
+            future: impl Future<Output = Result<T, PublishError>>,
+        ) -> Result<T, PublishError> {
+            tokio::time::timeout(self.timeout, future)
+                .await
+                .map_err(|_| PublishError::Timeout(self.timeout))?
+        }
+
+        async fn connect(&self) -> Result<BufReader<TcpStream>, PublishError> {
+            let mut connection = BufReader::new(TcpStream::connect(&self.address).await?);
+            // the server greets with its INFO
+            read_line(&mut connection).await?;
+            connection
+                .get_mut()
+                .write_all(b"CONNECT {\"verbose\":false,\"pedantic\":false}\r\n")
+                .await?;
+            Ok(connection)
+        }
+    }
+
+    /// Subjects are dot separated tokens, which must not contain whitespace or wildcards.
+    fn subject(topic: &str, key: &str) -> String {
+        let tokens = key.split('/').map(|token| {
+            token
+                .chars()
+                .map(|c| match c {
+                    '.' | '*' | '>' => '_',
+                    c if c.is_whitespace() => '_',
+                    c => c,
+                })
+                .collect::<String>()
+        });
+        std::iter::once(topic.to_string())
+            .chain(tokens.filter(|token| !token.is_empty()))
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+
+    async fn read_line(connection: &mut BufReader<TcpStream>) -> Result<String, PublishError> {
+        let mut line = String::new();
+        if connection.read_line(&mut line).await? == 0 {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+        Ok(line.trim_end().to_string())
+    }
+
+    async fn publish_on(
+        connection: &mut BufReader<TcpStream>,
+        subject: &str,
+        payload: &[u8],
+    ) -> Result<(), PublishError> {
+        let stream = connection.get_mut();
+        stream
+            .write_all(format!("PUB {subject} {}\r\n", payload.len()).as_bytes())
+            .await?;
+        stream.write_all(payload).await?;
+        stream.write_all(b"\r\nPING\r\n").await?;
+        loop {
+            let line = read_line(connection).await?;
+            match line.as_str() {
+                "PONG" => return Ok(()),
+                "PING" => connection.get_mut().write_all(b"PONG\r\n").await?,
+                line if line.starts_with("-ERR") => {
+                    return Err(PublishError::Rejected(
+                        line.trim_start_matches("-ERR ").into(),
+                    ))
+                }
+                // +OK and INFO updates
+                _ => {}
+            }
+        }
+    }
+
+    impl EventPublisher for NatsPublisher {
+        fn publish(
+            &self,
+            topic: &str,
+            key: &str,
+            payload: Bytes,
+        ) -> BoxFuture<'_, Result<(), PublishError>> {
+            let subject = subject(topic, key);
+            Box::pin(async move {
+                let idle = self.idle.lock().unwrap().take();
+                let mut open = match idle {
+                    Some(open) => open,
+                    None => self.timed(self.connect()).await?,
+                };
+                self.timed(publish_on(&mut open, &subject, &payload))
+                    .await?;
+                // a failed connection is dropped and opened again for the next event
+                self.idle.lock().unwrap().get_or_insert(open);
+                Ok(())
+            })
+        }
+    }
+}
+
+#[cfg(all(test, feature = "nats"))]
+mod test {
+    use super::{EventPublisher, NatsPublisher, PublishError};
+    use std::time::Duration;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+    #[tokio::test]
+    async fn test_nats_publisher_publishes_to_the_repository_subject() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+            stream.get_mut().write_all(b"INFO {}\r\n").await.unwrap();
+            let mut lines = Vec::new();
+            for _ in 0..2 {
+                let mut line = String::new();
+                stream.read_line(&mut line).await.unwrap();
+                lines.push(line);
+            }
+            let length: usize = lines[1]
+                .trim_end()
+                .rsplit(' ')
+                .next()
+                .unwrap()
+                .parse()
+                .unwrap();
+            let mut payload = vec![0; length + 2];
+            stream.read_exact(&mut payload).await.unwrap();
+            let mut ping = String::new();
+            stream.read_line(&mut ping).await.unwrap();
+            stream.get_mut().write_all(b"PONG\r\n").await.unwrap();
+            (lines.pop().unwrap(), payload, ping)
+        });
+
+        NatsPublisher::new(address.to_string())
+            .publish("github.push", "acme/anvil.rs", "{}".into())
+            .await
+            .unwrap();
+
+        let (publish, payload, ping) = server.await.unwrap();
+        assert_eq!(publish, "PUB github.push.acme.anvil_rs 2\r\n");
+        assert_eq!(payload, b"{}\r\n");
+        assert_eq!(ping, "PING\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_nats_publisher_gives_up_on_a_hung_broker() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        // accepts the connection but never greets
+        let _server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            std::future::pending::<()>().await;
+            drop(stream);
+        });
+
+        let publisher =
+            NatsPublisher::new(address.to_string()).with_timeout(Duration::from_millis(100));
+        let published = publisher.publish("github.push", "acme/anvil", "{}".into());
+        let published = tokio::time::timeout(Duration::from_secs(5), published)
+            .await
+            .expect("the publisher waited for the broker");
+        assert!(matches!(published, Err(PublishError::Timeout(_))));
+    }
+}
@@ -0,0 +1,324 @@
+use crate::routes::metrics::MetricsSink;
+use crate::shutdown::TrackedHandler;
+use github_event_handler::handle::Delivery;
+use octocrab::models::webhook_events::{EventInstallation, WebhookEvent};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, oneshot, OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinHandle;
+
+/// An accepted event waiting to be handled by the worker.
+#[derive(Debug)]
+pub struct QueuedEvent {
+    pub event: WebhookEvent,
+    pub delivery: Delivery,
+    /// Id of the event in the write-ahead log, if it was logged.
+    pub logged: Option<u64>,
+    /// Keeps a shutdown draining until the event was handled or dead-lettered.
+    pub tracked: TrackedHandler,
+}
+
+/// Bounded queue between the webhook endpoint and the worker handling the events, events are
+/// dropped instead of queued once it is full.
+#[derive(Debug, Clone)]
+pub struct EventQueue {
+    sender: mpsc::Sender<QueuedEvent>,
+    depth: Arc<AtomicUsize>,
+    metrics: Arc<dyn MetricsSink>,
+}
+
+pub struct EventQueueReceiver {
+    receiver: mpsc::Receiver<QueuedEvent>,
+    depth: Arc<AtomicUsize>,
+    metrics: Arc<dyn MetricsSink>,
+}
+
+impl EventQueue {
+    pub fn bounded(capacity: usize, metrics: Arc<dyn MetricsSink>) -> (Self, EventQueueReceiver) {
+        let (sender, receiver) = mpsc::channel(capacity);
+        let depth = Arc::new(AtomicUsize::new(0));
+        (
+            Self {
+                sender,
+                depth: depth.clone(),
+                metrics: metrics.clone(),
+            },
+            EventQueueReceiver {
+                receiver,
+                depth,
+                metrics,
+            },
+        )
+    }
+
+    /// Queues the event, hands it back if the queue is full or the worker is gone.
+    pub fn try_enqueue(&self, event: QueuedEvent) -> Result<(), QueuedEvent> {
+        // counted before it is sent, the worker might dequeue it before `try_send` returns
+        let depth = self.depth.fetch_add(1, Ordering::SeqCst) + 1;
+        match self.sender.try_send(event) {
+            Ok(()) => {
+                self.metrics.set_gauge(QUEUE_DEPTH, &[], depth as f64);
+                Ok(())
+            }
+            Err(err) => {
+                self.depth.fetch_sub(1, Ordering::SeqCst);
+                self.metrics.incr_counter(QUEUE_DROPPED, &[], 1);
+                Err(err.into_inner())
+            }
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth.load(Ordering::SeqCst)
+    }
+}
+
+impl EventQueueReceiver {
+    pub async fn dequeue(&mut self) -> Option<QueuedEvent> {
+        let event = self.receiver.recv().await?;
+        let depth = self.depth.fetch_sub(1, Ordering::SeqCst) - 1;
+        self.metrics.set_gauge(QUEUE_DEPTH, &[], depth as f64);
+        Some(event)
+    }
+}
+
+/// Which queued events are handled in delivery order, events with different keys are handled
+/// concurrently.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueOrdering {
+    /// All events one after another.
+    #[default]
+    Global,
+    /// Events of the same repository one after another.
+    Repository,
+    /// Events of the same installation one after another.
+    Installation,
+}
+
+impl QueueOrdering {
+    /// Events without a repository or installation share a key and stay in order.
+    pub fn key(&self, event: &WebhookEvent) -> String {
+        let installation = || match event.installation {
+            Some(EventInstallation::Full(ref installation)) => Some(installation.id.0.to_string()),
+            Some(EventInstallation::Minimal(ref installation)) => {
+                Some(installation.id.0.to_string())
+            }
+            None => None,
+        };
+        match self {
+            QueueOrdering::Global => None,
+            QueueOrdering::Repository => event
+                .repository
+                .as_ref()
+                .map(|repository| {
+                    repository
+                        .full_name
+                        .clone()
+                        .unwrap_or_else(|| repository.id.0.to_string())
+                })
+                .or_else(installation),
+            QueueOrdering::Installation => installation(),
+        }
+        .unwrap_or_default()
+    }
+}
+
+/// Runs tasks with the same key one after another in the order they were spawned in, tasks
+/// with different keys run concurrently.
+///
+/// At most `max_tasks` tasks are spawned at once, including the ones waiting for their
+/// predecessor, so waiting for a [slot](Self::slot) before taking the next task off a queue
+/// keeps the queue applying backpressure.
+#[derive(Debug)]
+pub struct KeyedSequencer {
+    previous: HashMap<String, oneshot::Receiver<()>>,
+    slots: Arc<Semaphore>,
+}
+
+impl KeyedSequencer {
+    pub fn new(max_tasks: usize) -> Self {
+        Self {
+            previous: HashMap::new(),
+            slots: Arc::new(Semaphore::new(max_tasks.max(1))),
+        }
+    }
+
+    /// Waits until another task may be spawned.
+    pub async fn slot(&self) -> OwnedSemaphorePermit {
+        // the semaphore is never closed
+        self.slots.clone().acquire_owned().await.unwrap()
+    }
+
+    /// Spawns the task, releasing its slot once it finished.
+    pub fn spawn<F>(&mut self, key: String, slot: OwnedSemaphorePermit, task: F) -> JoinHandle<()>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        // the sender is dropped once a task has finished, only keys with running tasks remain
+        self.previous
+            .retain(|_, done| matches!(done.try_recv(), Err(oneshot::error::TryRecvError::Empty)));
+        let (finished, done) = oneshot::channel::<()>();
+        let previous = self.previous.insert(key, done);
+        tokio::spawn(async move {
+            if let Some(previous) = previous {
+                let _ = previous.await;
+            }
+            task.await;
+            drop(finished);
+            drop(slot);
+        })
+    }
+}
+
+/// Caps how many events of the same installation are handled at once, so a burst from one
+/// installation neither exhausts its rate limit nor delays the events of others.
+#[derive(Debug, Clone)]
+pub struct InstallationConcurrency {
+    limit: usize,
+    permits: Arc<Mutex<HashMap<String, Arc<Semaphore>>>>,
+}
+
+impl InstallationConcurrency {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit: limit.max(1),
+            permits: Default::default(),
+        }
+    }
+
+    /// Waits until the installation of the event has a free slot, events without an
+    /// installation aren't limited.
+    pub async fn acquire(&self, event: &WebhookEvent) -> Option<OwnedSemaphorePermit> {
+        let installation = QueueOrdering::Installation.key(event);
+        if installation.is_empty() {
+            return None;
+        }
+        let semaphore = {
+            let mut permits = self.permits.lock().unwrap();
+            // permits hold on to their semaphore, only idle installations are forgotten
+            permits.retain(|_, semaphore| Arc::strong_count(semaphore) > 1);
+            permits
+                .entry(installation)
+                .or_insert_with(|| Arc::new(Semaphore::new(self.limit)))
+                .clone()
+        };
+        // the semaphore is never closed
+        semaphore.acquire_owned().await.ok()
+    }
+}
+
+const QUEUE_DEPTH: &str = "github_queue_depth";
+const QUEUE_DROPPED: &str = "github_queue_dropped_total";
+
+#[cfg(test)]
+mod test {
+    use super::{EventQueue, KeyedSequencer, QueuedEvent};
+    use crate::routes::metrics::PrometheusSink;
+    use crate::shutdown::InFlightHandlers;
+    use github_event_handler::handle::Delivery;
+    use metrics_exporter_prometheus::PrometheusBuilder;
+    use octocrab::models::webhook_events::WebhookEvent;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    fn ping() -> QueuedEvent {
+        QueuedEvent {
+            event: WebhookEvent::try_from_header_and_body("ping", r#"{"hook_id":1}"#).unwrap(),
+            delivery: Delivery::default(),
+            logged: None,
+            tracked: InFlightHandlers::default().track(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_reports_depth_and_drops() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+        let (queue, mut receiver) = EventQueue::bounded(2, Arc::new(PrometheusSink));
+
+        metrics::with_local_recorder(&recorder, || {
+            assert!(queue.try_enqueue(ping()).is_ok());
+            assert!(queue.try_enqueue(ping()).is_ok());
+            assert!(queue.try_enqueue(ping()).is_err());
+        });
+        let rendered = handle.render();
+        assert!(rendered.contains("github_queue_depth 2"));
+        assert!(rendered.contains("github_queue_dropped_total 1"));
+
+        let dequeued = receiver.dequeue().await.unwrap();
+        assert_eq!(dequeued.logged, None);
+        assert_eq!(queue.depth(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_depth_never_underflows_with_an_eager_worker() {
+        let (queue, mut receiver) = EventQueue::bounded(1, Arc::new(PrometheusSink));
+        let worker = tokio::spawn(async move {
+            for _ in 0..1000 {
+                receiver.dequeue().await.unwrap();
+            }
+        });
+        let mut sent = 0;
+        while sent < 1000 {
+            if queue.try_enqueue(ping()).is_ok() {
+                sent += 1;
+            }
+            assert!(queue.depth() <= 1, "{}", queue.depth());
+            tokio::task::yield_now().await;
+        }
+        worker.await.unwrap();
+        assert_eq!(queue.depth(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_tasks_of_the_same_key_run_in_order() {
+        let executed = Arc::new(Mutex::new(Vec::new()));
+        let record = |name: &'static str, delay: u64| {
+            let executed = executed.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                executed.lock().unwrap().push(name);
+            }
+        };
+        let mut sequencer = KeyedSequencer::new(3);
+
+        let mut tasks = Vec::new();
+        for (key, name, delay) in [
+            ("acme/anvil", "first anvil", 100),
+            ("acme/anvil", "second anvil", 0),
+            ("acme/rocket", "rocket", 0),
+        ] {
+            let slot = sequencer.slot().await;
+            tasks.push(sequencer.spawn(key.into(), slot, record(name, delay)));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert_eq!(
+            *executed.lock().unwrap(),
+            ["rocket", "first anvil", "second anvil"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_slots_are_released_once_tasks_finished() {
+        let mut sequencer = KeyedSequencer::new(1);
+        let slot = sequencer.slot().await;
+        let (release, released) = tokio::sync::oneshot::channel::<()>();
+        let task = sequencer.spawn("acme/anvil".into(), slot, async move {
+            let _ = released.await;
+        });
+
+        let waiting = tokio::time::timeout(Duration::from_millis(50), sequencer.slot()).await;
+        assert!(waiting.is_err(), "a second slot was handed out");
+        release.send(()).unwrap();
+        task.await.unwrap();
+        let _slot = tokio::time::timeout(Duration::from_secs(1), sequencer.slot())
+            .await
+            .unwrap();
+    }
+}
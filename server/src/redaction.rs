@@ -0,0 +1,58 @@
+use crate::forwarder::DeliverySummary;
+use serde_json::Value;
+
+/// Sanitizes what is written about an event before it is logged or audited, e.g. to keep
+/// personal data out of the logs.
+///
+/// Only the logged and audited views are redacted, handlers always see the full event.
+pub trait LogRedactor: std::fmt::Debug + Send + Sync {
+    /// Redacts the payload before it is logged at `debug` level.
+    fn redact_event(&self, _event: &mut Value) {}
+
+    /// Redacts the summary before it is handed to the delivery forwarder.
+    fn redact_summary(&self, _summary: &mut DeliverySummary) {}
+}
+
+/// Logs and audits events as they are.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoRedaction;
+
+impl LogRedactor for NoRedaction {}
+
+/// Removes the email addresses of commit authors, committers and the pusher of `push` events.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EmailRedactor;
+
+impl LogRedactor for EmailRedactor {
+    fn redact_event(&self, event: &mut Value) {
+        let Some(event) = event.as_object_mut() else {
+            return;
+        };
+        for (field, value) in event.iter_mut() {
+            match field.as_str() {
+                "commits" => value
+                    .as_array_mut()
+                    .into_iter()
+                    .flatten()
+                    .for_each(remove_commit_emails),
+                "head_commit" => remove_commit_emails(value),
+                "pusher" => remove_email(value),
+                _ => {}
+            }
+        }
+    }
+}
+
+fn remove_commit_emails(commit: &mut Value) {
+    for field in ["author", "committer"] {
+        if let Some(user) = commit.get_mut(field) {
+            remove_email(user);
+        }
+    }
+}
+
+fn remove_email(user: &mut Value) {
+    if let Some(user) = user.as_object_mut() {
+        user.remove("email");
+    }
+}
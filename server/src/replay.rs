@@ -0,0 +1,143 @@
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, Method, Request, Uri};
+use axum_core::body::Body;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use hyper_rustls::HttpsConnectorBuilder;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use orion::hazardous::mac::hmac::sha256::{HmacSha256, SecretKey};
+use std::collections::HashMap;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use thiserror::Error;
+
+/// A delivery as captured from GitHub, e.g. copied from the App's advanced settings.
+///
+/// ```json
+/// { "headers": { "x-github-event": "ping", "x-github-delivery": "..." }, "body": { ... } }
+/// ```
+///
+/// Files ending in `.json.gz` are gzip-compressed.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct CapturedDelivery {
+    pub headers: HashMap<String, String>,
+    pub body: serde_json::Value,
+}
+
+impl CapturedDelivery {
+    /// Captures a received delivery, headers whose value isn't text are left out.
+    pub fn from_parts(headers: &HeaderMap, body: &[u8]) -> Result<Self, ReplayError> {
+        let headers = headers
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_owned())))
+            .collect();
+        Ok(Self {
+            headers,
+            body: serde_json::from_slice(body)?,
+        })
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, ReplayError> {
+        let path = path.as_ref();
+        let file = BufReader::new(std::fs::File::open(path)?);
+        let reader: Box<dyn Read> = match is_compressed(path) {
+            true => Box::new(GzDecoder::new(file)),
+            false => Box::new(file),
+        };
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    /// Stores the delivery for a later replay, compressed if the path ends in `.json.gz`.
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), ReplayError> {
+        let path = path.as_ref();
+        let file = BufWriter::new(std::fs::File::create(path)?);
+        match is_compressed(path) {
+            true => {
+                let mut encoder = GzEncoder::new(file, Compression::default());
+                serde_json::to_writer(&mut encoder, self)?;
+                encoder.finish()?.flush()?;
+            }
+            false => {
+                let mut file = file;
+                serde_json::to_writer_pretty(&mut file, self)?;
+                file.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Builds a request to `url` whose signature is recomputed with `secret` and sent in
+    /// `signature_header`, any captured signature headers are dropped.
+    pub fn signed_request(
+        &self,
+        url: Uri,
+        secret: &SecretKey,
+        signature_header: &HeaderName,
+    ) -> Result<Request<Body>, ReplayError> {
+        let body = serde_json::to_vec(&self.body)?;
+        let tag = HmacSha256::hmac(secret, &body).map_err(|_| ReplayError::Signature)?;
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri(url)
+            .header(header::CONTENT_TYPE, "application/json");
+        for (name, value) in &self.headers {
+            let name = HeaderName::try_from(name.as_str())?;
+            if name == "x-hub-signature-256"
+                || name == "x-hub-signature"
+                || name == signature_header
+                || name == header::CONTENT_LENGTH
+            {
+                continue;
+            }
+            request = request.header(name, HeaderValue::try_from(value.as_str())?);
+        }
+        Ok(request
+            .header(
+                signature_header,
+                format!("sha256={}", hex::encode(tag.unprotected_as_bytes())),
+            )
+            .body(Body::from(body))?)
+    }
+}
+
+fn is_compressed(path: &Path) -> bool {
+    path.to_string_lossy().ends_with(".json.gz")
+}
+
+/// Posts the captured delivery to a running server, over `http` or `https`, and returns the
+/// response status.
+pub async fn replay(
+    delivery: &CapturedDelivery,
+    url: Uri,
+    secret: &SecretKey,
+    signature_header: &HeaderName,
+) -> Result<hyper::StatusCode, ReplayError> {
+    let client = Client::builder(TokioExecutor::new()).build(
+        HttpsConnectorBuilder::new()
+            .with_webpki_roots()
+            .https_or_http()
+            .enable_http1()
+            .build(),
+    );
+    let response = client
+        .request(delivery.signed_request(url, secret, signature_header)?)
+        .await?;
+    Ok(response.status())
+}
+
+#[derive(Debug, Error)]
+pub enum ReplayError {
+    #[error("Unable to read the captured delivery: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid captured delivery: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Unable to sign the captured delivery")]
+    Signature,
+    #[error("Invalid captured header name: {0}")]
+    HeaderName(#[from] axum::http::header::InvalidHeaderName),
+    #[error("Invalid captured header value: {0}")]
+    HeaderValue(#[from] axum::http::header::InvalidHeaderValue),
+    #[error("Unable to build the request: {0}")]
+    Request(#[from] axum::http::Error),
+    #[error("Unable to send the delivery: {0}")]
+    Send(#[from] hyper_util::client::legacy::Error),
+}
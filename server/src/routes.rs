@@ -1,3 +1,13 @@
+pub mod ack_body;
+pub mod body_logging;
+pub mod catch_panic;
+pub mod client_ip;
+pub mod debug_config;
 pub mod event_handler;
+pub mod health;
+pub mod maintenance;
 pub mod metrics;
+pub mod processing_time;
+pub mod response_headers;
+pub mod subscriptions;
 pub mod ui;
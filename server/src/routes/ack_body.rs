@@ -0,0 +1,168 @@
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use github_event_handler::handle::{DeliveryOutcome, OutcomeStatus};
+
+/// Formats the body acknowledging a handled delivery, for tooling expecting its own shape.
+pub trait AckBody: std::fmt::Debug + Send + Sync {
+    fn respond(&self, outcome: DeliveryOutcome) -> Response;
+}
+
+/// The outcome as JSON.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonAck;
+
+impl AckBody for JsonAck {
+    fn respond(&self, outcome: DeliveryOutcome) -> Response {
+        (StatusCode::OK, Json(outcome)).into_response()
+    }
+}
+
+/// Replaces `{status}`, `{installation}`, `{handlers}` (comma separated), `{response}` and
+/// `{duration_ms}` in the template, missing values are replaced by nothing.
+///
+/// With a JSON content type the values are escaped to be placed within a string, e.g. a ping's
+/// zen might contain quotes, and a missing `{installation}` is rendered as `null`. Otherwise the
+/// values are inserted unescaped.
+#[derive(Debug, Clone)]
+pub struct TemplateAck {
+    pub template: String,
+    pub content_type: HeaderValue,
+}
+
+impl TemplateAck {
+    pub fn new(template: impl Into<String>) -> Self {
+        Self {
+            template: template.into(),
+            content_type: HeaderValue::from_static("text/plain; charset=utf-8"),
+        }
+    }
+
+    pub fn with_content_type(self, content_type: HeaderValue) -> Self {
+        Self {
+            content_type,
+            ..self
+        }
+    }
+
+    fn render(&self, outcome: &DeliveryOutcome) -> String {
+        let json = is_json(&self.content_type);
+        let escape = |value: &str| match json {
+            // the quotes of the JSON string are left to the template
+            true => {
+                let quoted = serde_json::Value::from(value).to_string();
+                quoted[1..quoted.len() - 1].to_owned()
+            }
+            false => value.to_owned(),
+        };
+        let status = match outcome.status {
+            OutcomeStatus::Handled => "handled",
+            OutcomeStatus::Skipped => "skipped",
+        };
+        let installation = match outcome.installation {
+            Some(installation) => installation.to_string(),
+            None if json => "null".to_string(),
+            None => String::new(),
+        };
+        let handlers = outcome
+            .handlers
+            .iter()
+            .map(|handler| handler.name.as_str())
+            .collect::<Vec<_>>()
+            .join(",");
+        // placeholders are replaced in a single pass, values never get replaced again
+        let placeholders = [
+            ("{status}", status.to_string()),
+            ("{installation}", installation),
+            ("{handlers}", escape(&handlers)),
+            (
+                "{response}",
+                escape(outcome.response.as_deref().unwrap_or_default()),
+            ),
+            ("{duration_ms}", outcome.duration.as_millis().to_string()),
+        ];
+        let mut rendered = String::with_capacity(self.template.len());
+        let mut rest = self.template.as_str();
+        while let Some(start) = rest.find('{') {
+            rendered.push_str(&rest[..start]);
+            rest = &rest[start..];
+            match placeholders
+                .iter()
+                .find(|(placeholder, _)| rest.starts_with(placeholder))
+            {
+                Some((placeholder, value)) => {
+                    rendered.push_str(value);
+                    rest = &rest[placeholder.len()..];
+                }
+                None => {
+                    rendered.push('{');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        rendered.push_str(rest);
+        rendered
+    }
+}
+
+/// `application/json` or a structured syntax suffix like `application/problem+json`, parameters
+/// are ignored.
+fn is_json(content_type: &HeaderValue) -> bool {
+    let content_type = content_type.to_str().unwrap_or_default();
+    let media_type = content_type.split(';').next().unwrap_or_default().trim();
+    let media_type = media_type.to_ascii_lowercase();
+    media_type == "application/json" || media_type.ends_with("+json")
+}
+
+impl AckBody for TemplateAck {
+    fn respond(&self, outcome: DeliveryOutcome) -> Response {
+        (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, self.content_type.clone())],
+            self.render(&outcome),
+        )
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TemplateAck;
+    use axum::http::HeaderValue;
+    use github_event_handler::handle::{DeliveryOutcome, OutcomeStatus};
+    use std::time::Duration;
+
+    fn outcome(response: &str) -> DeliveryOutcome {
+        DeliveryOutcome {
+            status: OutcomeStatus::Handled,
+            installation: None,
+            handlers: Vec::new(),
+            response: Some(response.to_string()),
+            duration: Duration::from_millis(3),
+        }
+    }
+
+    #[test]
+    fn test_json_template_escapes_the_values() {
+        let template = TemplateAck::new(r#"{"installation":{installation},"zen":"{response}"}"#)
+            .with_content_type(HeaderValue::from_static("application/json; charset=utf-8"));
+
+        let rendered = template.render(&outcome(r#"Say "when""#));
+
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&rendered).unwrap(),
+            serde_json::json!({ "installation": null, "zen": r#"Say "when""# })
+        );
+    }
+
+    #[test]
+    fn test_plain_template_inserts_the_values_as_they_are() {
+        let template = TemplateAck::new("{installation}: {response}");
+
+        assert_eq!(
+            template.render(&outcome(r#"Say "when""#)),
+            r#": Say "when""#
+        );
+    }
+}
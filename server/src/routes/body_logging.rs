@@ -0,0 +1,90 @@
+use crate::redaction::LogRedactor;
+use axum::{
+    body::{to_bytes, Body},
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderName, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::Arc;
+
+/// Headers whose values never end up in the log.
+const REDACTED_HEADERS: [HeaderName; 3] = [
+    header::AUTHORIZATION,
+    header::COOKIE,
+    header::PROXY_AUTHORIZATION,
+];
+
+/// What [`log_bodies`] needs to know to leave secrets out of the log.
+#[derive(Debug, Clone)]
+pub struct BodyLogging {
+    pub redactor: Arc<dyn LogRedactor>,
+    /// The header the signatures are read from besides the `X-Hub-Signature*` ones.
+    pub signature_header: HeaderName,
+    /// Larger bodies are rejected like the webhook endpoint would reject them.
+    pub max_bytes: usize,
+}
+
+impl BodyLogging {
+    fn is_secret(&self, name: &HeaderName) -> bool {
+        *name == self.signature_header
+            || name.as_str().starts_with("x-hub-signature")
+            || REDACTED_HEADERS.contains(name)
+    }
+
+    fn redacted_headers(&self, headers: &HeaderMap) -> Vec<(String, String)> {
+        headers
+            .iter()
+            .map(|(name, value)| {
+                let value = match self.is_secret(name) {
+                    true => "[redacted]".to_string(),
+                    false => String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                };
+                (name.to_string(), value)
+            })
+            .collect()
+    }
+
+    fn redacted_body(&self, body: &[u8]) -> String {
+        match serde_json::from_slice::<serde_json::Value>(body) {
+            Ok(mut payload) => {
+                self.redactor.redact_event(&mut payload);
+                payload.to_string()
+            }
+            Err(_) => String::from_utf8_lossy(body).into_owned(),
+        }
+    }
+}
+
+/// Logs every delivery and the response to it, for debugging only: payloads contain whatever
+/// users wrote into issues, commits and comments.
+pub async fn log_bodies(State(logging): State<BodyLogging>, req: Request, next: Next) -> Response {
+    let (parts, body) = req.into_parts();
+    let Ok(body) = to_bytes(body, logging.max_bytes).await else {
+        return (StatusCode::PAYLOAD_TOO_LARGE, "payload too large").into_response();
+    };
+    tracing::info!(
+        method = %parts.method,
+        uri = %parts.uri,
+        headers = ?logging.redacted_headers(&parts.headers),
+        body = logging.redacted_body(&body),
+        "received request"
+    );
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+
+    let (parts, body) = response.into_parts();
+    let body = match to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(err) => {
+            tracing::warn!(%err, "unable to buffer the response for logging");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "unreadable response").into_response();
+        }
+    };
+    tracing::info!(
+        status = %parts.status,
+        body = %String::from_utf8_lossy(&body),
+        "sent response"
+    );
+    Response::from_parts(parts, Body::from(body))
+}
@@ -0,0 +1,44 @@
+use axum::{
+    extract::Request,
+    http::HeaderName,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures_util::FutureExt;
+use hyper::StatusCode;
+use std::{any::Any, panic::AssertUnwindSafe};
+
+static DELIVERY_HEADER: HeaderName = HeaderName::from_static("x-github-delivery");
+
+/// Turns a panicking handler into a `500` instead of dropping the connection.
+pub async fn catch_panic(req: Request, next: Next) -> Response {
+    let delivery = req
+        .headers()
+        .get(&DELIVERY_HEADER)
+        .and_then(|delivery| delivery.to_str().ok())
+        .map(ToOwned::to_owned);
+
+    match AssertUnwindSafe(next.run(req)).catch_unwind().await {
+        Ok(response) => response,
+        Err(panic) => {
+            let message = panic_message(&*panic);
+            tracing::error!(delivery, panic = message, "handler panicked");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": "internal server error" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
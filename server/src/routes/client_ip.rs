@@ -0,0 +1,92 @@
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, HeaderName},
+    middleware::Next,
+    response::Response,
+};
+use std::net::{IpAddr, SocketAddr};
+use tracing::Instrument;
+
+static FORWARDED_FOR_HEADER: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+/// Address of the client which sent the request, see [`client_ip`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClientIp(pub IpAddr);
+
+/// Derives the client address and records it on the request span.
+///
+/// `X-Forwarded-For` is only considered when the server is configured to sit behind
+/// `hops` proxies, otherwise the header could be spoofed by anyone.
+pub async fn client_ip(State(hops): State<usize>, mut req: Request, next: Next) -> Response {
+    let Some(ConnectInfo(peer)) = req.extensions().get::<ConnectInfo<SocketAddr>>().copied() else {
+        return next.run(req).await;
+    };
+    let ip = derive_client_ip(peer.ip(), req.headers(), hops);
+    req.extensions_mut().insert(ClientIp(ip));
+    next.run(req)
+        .instrument(tracing::info_span!("client", client_ip = %ip))
+        .await
+}
+
+fn derive_client_ip(peer: IpAddr, headers: &HeaderMap, hops: usize) -> IpAddr {
+    if hops == 0 {
+        return peer;
+    }
+    let forwarded = headers
+        .get_all(&FORWARDED_FOR_HEADER)
+        .iter()
+        .map(|value| value.to_str().ok())
+        .collect::<Option<Vec<_>>>()
+        .and_then(|values| {
+            values
+                .iter()
+                .flat_map(|value| value.split(','))
+                .map(|ip| ip.trim().parse::<IpAddr>().ok())
+                .collect::<Option<Vec<_>>>()
+        });
+    match forwarded {
+        // each trusted proxy appended the address it received the request from
+        Some(forwarded) if !forwarded.is_empty() => forwarded[forwarded.len().saturating_sub(hops)],
+        _ => peer,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::derive_client_ip;
+    use axum::http::{HeaderMap, HeaderValue};
+    use std::net::IpAddr;
+
+    const PROXY: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(10, 0, 0, 1));
+
+    fn forwarded_for(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn test_trusted_proxy_forwards_client_ip() {
+        let headers = forwarded_for("203.0.113.7, 198.51.100.2");
+        assert_eq!(
+            derive_client_ip(PROXY, &headers, 1),
+            "198.51.100.2".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(
+            derive_client_ip(PROXY, &headers, 2),
+            "203.0.113.7".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_untrusted_peer_ignores_forwarded_for() {
+        let headers = forwarded_for("203.0.113.7");
+        assert_eq!(derive_client_ip(PROXY, &headers, 0), PROXY);
+    }
+
+    #[test]
+    fn test_malformed_forwarded_for_is_ignored() {
+        let headers = forwarded_for("203.0.113.7, not-an-ip");
+        assert_eq!(derive_client_ip(PROXY, &headers, 1), PROXY);
+    }
+}
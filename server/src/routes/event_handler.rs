@@ -1,41 +1,403 @@
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 
-use self::extractors::GitHubEvent;
-use crate::config::GitHubAppConfiguration;
-use axum::http::Uri;
-use axum::{extract::State, response::IntoResponse, routing::any, Router};
+use self::extractors::{
+    ExtractEnterpriseVersion, ExtractTargetId, ExtractTargetType, GitHubEvent, PayloadLimits,
+};
+use crate::config::{ConfigurationError, GitHubAppConfiguration, WebhookEndpointConfiguration};
+use crate::deliveries::DeliveryStore;
+use crate::fan_out::FanOut;
+use crate::forwarder::{DeliveryForwarder, DeliverySummary};
+use crate::pre_auth::PreAuth;
+use crate::publisher::{partition_key, EventPublishing};
+use crate::queue::{
+    EventQueue, EventQueueReceiver, InstallationConcurrency, KeyedSequencer, QueueOrdering,
+    QueuedEvent,
+};
+use crate::redaction::LogRedactor;
+use crate::routes::ack_body::AckBody;
+use crate::routes::body_logging::{log_bodies, BodyLogging};
+use crate::routes::catch_panic::catch_panic;
+use crate::routes::client_ip::{client_ip, ClientIp};
+use crate::routes::maintenance::reject_during_maintenance;
+use crate::routes::metrics::{account_type, EventLabels, MetricsSink};
+use crate::routes::processing_time::processing_time;
+use crate::routes::response_headers::response_headers;
+use crate::routes::subscriptions::{acknowledge_unsubscribed, Subscriptions};
+use crate::secrets::SecretResolver;
+use crate::shutdown::InFlightHandlers;
+use crate::signature::{
+    DeliveryAge, SignatureBypass, SignatureFailures, SignatureHeader, SignatureMigration,
+    DEFAULT_SIGNATURE_HEADER, DELIVERY_TIMESTAMP_SIGNATURE_HEADER,
+};
+use crate::wal::{RecoveredEvent, WriteAheadLog};
+use axum::http::{Extensions, HeaderMap, HeaderName, Uri};
+use axum::{
+    extract::{Request, State},
+    middleware::{from_fn, from_fn_with_state, map_response},
+    response::{IntoResponse, Json, Response},
+    routing::any,
+    Router,
+};
 use axum_core::extract::FromRef;
 use github_event_handler::authentication::{
     AuthenticatedClient, GitHubAppAuthenticator, InstallationAuthenticator,
 };
-use github_event_handler::handle::{handle_event, HandleEventError};
+use github_event_handler::context::{event_action, HandlerSettings, TargetType};
+use github_event_handler::handle::{
+    handle_event, Delivery, DeliveryOutcome, HandleEventError, HandleOptions,
+};
+use github_event_handler::handler::{DeadLetter, Handlers};
 use hyper::StatusCode;
 use jsonwebtoken::EncodingKey;
+use octocrab::models::webhook_events::{EventInstallation, WebhookEvent};
 use octocrab::models::AppId;
 use orion::hazardous::mac::hmac::sha256::SecretKey;
+use tokio_util::sync::CancellationToken;
+use tower::ServiceExt;
+use tower_http::timeout::TimeoutLayer;
+use tracing::{field, Instrument};
 
 mod extractors;
 
-pub async fn router<C: GitHubAppAuthenticator>(
+/// How an event is acknowledged when it isn't queued.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Acknowledgement {
+    /// Handled before responding, with `200` or `204`.
+    #[default]
+    Handled,
+    /// Acknowledged with `202` right away and handled in the background.
+    Accepted,
+}
+
+/// Handlers for the installation clients created by the authenticator `C`.
+pub type AppHandlers<C> =
+    Handlers<<<C as GitHubAppAuthenticator>::Next as InstallationAuthenticator>::Api>;
+
+/// Delay before the first retry of a lazy authentication, doubled for every further one.
+const LAZY_AUTH_BASE_DELAY: Duration = Duration::from_secs(1);
+const LAZY_AUTH_MAX_DELAY: Duration = Duration::from_secs(60);
+
+pub async fn router<C: GitHubAppAuthenticator + 'static>(
     config: GitHubAppConfiguration,
-    path: &str,
+    endpoint: &WebhookEndpointConfiguration,
+    handlers: AppHandlers<C>,
 ) -> Result<Router, Box<dyn std::error::Error>>
 where
     C::Error: 'static,
     C::Next: 'static,
 {
-    let client = authenticate_app::<C>(config.uri, config.app_identifier, config.app_key).await?;
-    let signature_config = ConfigState {
+    if endpoint.disable_signature_verification {
+        if !cfg!(feature = "dangerous") {
+            return Err(ConfigurationError::SignatureVerificationDisabled.into());
+        }
+        tracing::warn!(
+            "SIGNATURE VERIFICATION IS DISABLED, anyone can send events to {}",
+            endpoint.path
+        );
+    }
+    if endpoint.delivery_age.is_enabled() && endpoint.delivery_age.secret.is_none() {
+        return Err(ConfigurationError::UnsignedDeliveryAge.into());
+    }
+    if endpoint.log_bodies {
+        if !cfg!(feature = "dangerous") {
+            return Err(ConfigurationError::BodyLoggingUnavailable.into());
+        }
+        tracing::warn!("BODIES ARE LOGGED, the log contains every payload and response");
+    }
+    let signature_header = SignatureHeader(
+        HeaderName::try_from(endpoint.signature_header_name.as_str())
+            .map_err(ConfigurationError::from)?,
+    );
+    if endpoint.lazy_auth {
+        return Ok(lazy_router::<C>(
+            config,
+            endpoint.clone(),
+            handlers,
+            signature_header,
+        ));
+    }
+    let client = authenticate_app::<C>(
+        config.uri.clone(),
+        config.app_identifier,
+        config.app_key.clone(),
+        &config.user_agent,
+        config.api_timeout,
+        endpoint,
+    )
+    .await?;
+    Ok(authenticated_router::<C>(client, config, endpoint, handlers, signature_header).await)
+}
+
+/// Rejects deliveries with `503` until the App authenticated, the authentication is retried in
+/// the background with a growing delay.
+fn lazy_router<C: GitHubAppAuthenticator + 'static>(
+    config: GitHubAppConfiguration,
+    endpoint: WebhookEndpointConfiguration,
+    handlers: AppHandlers<C>,
+    signature_header: SignatureHeader,
+) -> Router
+where
+    C::Error: 'static,
+    C::Next: 'static,
+{
+    let authenticated: Arc<OnceLock<Router>> = Default::default();
+    let path = endpoint.path.clone();
+    endpoint.auth_health.set(false);
+    let serving = authenticated.clone();
+    tokio::spawn(async move {
+        let mut delay = LAZY_AUTH_BASE_DELAY;
+        let client = loop {
+            match authenticate_app::<C>(
+                config.uri.clone(),
+                config.app_identifier,
+                config.app_key.clone(),
+                &config.user_agent,
+                config.api_timeout,
+                &endpoint,
+            )
+            .await
+            {
+                Ok(client) => break client,
+                Err(err) => {
+                    tracing::warn!(%err, retry_in_secs = delay.as_secs(), "unable to authenticate the App");
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(LAZY_AUTH_MAX_DELAY);
+                }
+            }
+        };
+        let router =
+            authenticated_router::<C>(client, config, &endpoint, handlers, signature_header).await;
+        let _ = serving.set(router);
+        endpoint.auth_health.set(true);
+        tracing::info!("authenticated the App, accepting deliveries");
+    });
+    Router::new().route(
+        &path,
+        any(move |request: Request| async move {
+            match authenticated.get() {
+                Some(router) => router.clone().oneshot(request).await.into_response(),
+                None => (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "The App is not authenticated yet",
+                )
+                    .into_response(),
+            }
+        }),
+    )
+}
+
+async fn authenticated_router<C: GitHubAppAuthenticator>(
+    client: AuthenticatedClient<C::Next>,
+    config: GitHubAppConfiguration,
+    endpoint: &WebhookEndpointConfiguration,
+    handlers: AppHandlers<C>,
+    signature_header: SignatureHeader,
+) -> Router
+where
+    C::Next: 'static,
+{
+    // an empty set lets the middleware pass every event on to be verified first
+    let (subscriptions, verified_subscriptions) = match endpoint.verify_unsubscribed_events {
+        true => (
+            Subscriptions::default(),
+            Some(Subscriptions::new(&config.subscriptions)),
+        ),
+        false => (Subscriptions::new(&config.subscriptions), None),
+    };
+    let base_url = config.uri.to_string();
+    let app = match client.app_metadata().await {
+        Ok(app) => Some(app),
+        Err(err) => {
+            tracing::warn!(%err, "unable to look up the app, handlers won't know its slug");
+            None
+        }
+    };
+    let options = HandleOptions {
+        settings: Arc::new(HandlerSettings {
+            base_url: Some(base_url),
+            app,
+            ..(*endpoint.handling.settings).clone()
+        }),
+        ..endpoint.handling.clone()
+    };
+    let mut signature_config = ConfigState {
         webhook_secret: config.webhook_secret.into(),
         client,
+        handlers: handlers.into(),
+        options: options.into(),
+        payload_limits: PayloadLimits {
+            max_depth: endpoint.max_json_depth,
+            max_bytes: endpoint.max_body_bytes,
+        },
+        forwarder: endpoint.forwarder.clone(),
+        fan_out: (!endpoint.fan_out_urls.is_empty()).then(|| {
+            FanOut::new(
+                endpoint.fan_out_urls.clone(),
+                endpoint.fan_out_secret.clone(),
+            )
+        }),
+        publishing: endpoint.publishing.clone(),
+        delivery_store: endpoint.delivery_store.clone(),
+        delivery_log: endpoint.delivery_log.clone(),
+        secret_resolver: endpoint.secret_resolver.clone(),
+        pre_auth: endpoint.pre_auth.clone(),
+        event_labels: endpoint.event_labels.clone(),
+        metrics: endpoint.metrics.clone(),
+        write_ahead_log: endpoint.write_ahead_log.clone(),
+        in_flight: endpoint.in_flight.clone(),
+        signature_migration: endpoint.signature_migration,
+        delivery_age: endpoint.delivery_age.clone(),
+        signature_failures: endpoint
+            .signature_failures
+            .clone()
+            .with_metrics(endpoint.metrics.clone()),
+        signature_bypass: SignatureBypass(endpoint.disable_signature_verification),
+        signature_header: signature_header.clone(),
+        soft_deadline: endpoint.soft_deadline,
+        queue: None,
+        installation_concurrency: endpoint
+            .installation_concurrency
+            .map(InstallationConcurrency::new),
+        audit_headers: endpoint.audit_headers.clone().into(),
+        log_redactor: endpoint.log_redactor.clone(),
+        ack_body: endpoint.ack_body.clone(),
+        acknowledgements: endpoint.acknowledgements.clone().into(),
+        debug_error_responses: endpoint.debug_error_responses,
+        verified_subscriptions,
     };
-    Ok(Router::new().route(path, any(handle_github_event).with_state(signature_config)))
+    if let Some(log) = &endpoint.write_ahead_log {
+        // a large backlog mustn't delay serving new deliveries
+        tokio::spawn(handle_recovered_events(
+            signature_config.clone(),
+            log.take_recovered(),
+        ));
+    }
+    if let Some(capacity) = endpoint.queue_capacity {
+        let (queue, receiver) = EventQueue::bounded(capacity, endpoint.metrics.clone());
+        tokio::spawn(work_queue(
+            signature_config.clone(),
+            receiver,
+            endpoint.queue_ordering,
+            endpoint.queue_concurrency,
+        ));
+        signature_config.queue = Some(queue);
+    }
+    let mut router = Router::new()
+        .route(
+            &endpoint.path,
+            any(handle_github_event)
+                .with_state(signature_config)
+                .layer(from_fn_with_state(subscriptions, acknowledge_unsubscribed))
+                .layer(from_fn_with_state(
+                    endpoint.maintenance.clone(),
+                    reject_during_maintenance,
+                )),
+        )
+        .layer(from_fn(catch_panic));
+    if endpoint.processing_time_header {
+        router = router.layer(from_fn(processing_time));
+    }
+    if cfg!(feature = "dangerous") && endpoint.log_bodies {
+        let logging = BodyLogging {
+            redactor: endpoint.log_redactor.clone(),
+            signature_header: signature_header.0.clone(),
+            max_bytes: endpoint.max_body_bytes,
+        };
+        router = router.layer(from_fn_with_state(logging, log_bodies));
+    }
+    if let Some(timeout) = endpoint.request_timeout {
+        // bounds reading the body as well, it is read while the request is handled
+        router = router.layer(TimeoutLayer::new(timeout));
+        let status = endpoint.request_timeout_status;
+        if status != StatusCode::REQUEST_TIMEOUT {
+            // nothing inside responds with `408` but the timeout
+            router = router.layer(map_response(move |mut response: Response| async move {
+                if response.status() == StatusCode::REQUEST_TIMEOUT {
+                    *response.status_mut() = status;
+                }
+                response
+            }));
+        }
+    }
+    router
+        .layer(from_fn_with_state(endpoint.trusted_proxy_hops, client_ip))
+        .layer(from_fn_with_state(
+            Arc::new(endpoint.response_headers.clone()),
+            response_headers,
+        ))
 }
 
-#[derive(Clone)]
 struct ConfigState<C: InstallationAuthenticator + Clone> {
     webhook_secret: Arc<SecretKey>,
     client: AuthenticatedClient<C>,
+    handlers: Arc<Handlers<C::Api>>,
+    options: Arc<HandleOptions>,
+    payload_limits: PayloadLimits,
+    forwarder: Option<Arc<dyn DeliveryForwarder>>,
+    fan_out: Option<FanOut>,
+    publishing: Option<EventPublishing>,
+    delivery_store: Option<Arc<dyn DeliveryStore>>,
+    delivery_log: Option<Arc<dyn DeliveryForwarder>>,
+    secret_resolver: Option<Arc<dyn SecretResolver>>,
+    pre_auth: Option<Arc<dyn PreAuth>>,
+    event_labels: EventLabels,
+    metrics: Arc<dyn MetricsSink>,
+    write_ahead_log: Option<Arc<WriteAheadLog>>,
+    in_flight: InFlightHandlers,
+    signature_migration: SignatureMigration,
+    delivery_age: DeliveryAge,
+    signature_failures: SignatureFailures,
+    signature_bypass: SignatureBypass,
+    signature_header: SignatureHeader,
+    soft_deadline: Option<Duration>,
+    queue: Option<EventQueue>,
+    installation_concurrency: Option<InstallationConcurrency>,
+    audit_headers: Arc<[String]>,
+    log_redactor: Arc<dyn LogRedactor>,
+    ack_body: Arc<dyn AckBody>,
+    acknowledgements: Arc<HashMap<String, Acknowledgement>>,
+    debug_error_responses: bool,
+    verified_subscriptions: Option<Subscriptions>,
+}
+
+impl<C: InstallationAuthenticator + Clone> Clone for ConfigState<C> {
+    fn clone(&self) -> Self {
+        Self {
+            webhook_secret: self.webhook_secret.clone(),
+            client: self.client.clone(),
+            handlers: self.handlers.clone(),
+            options: self.options.clone(),
+            payload_limits: self.payload_limits,
+            forwarder: self.forwarder.clone(),
+            fan_out: self.fan_out.clone(),
+            publishing: self.publishing.clone(),
+            delivery_store: self.delivery_store.clone(),
+            delivery_log: self.delivery_log.clone(),
+            secret_resolver: self.secret_resolver.clone(),
+            pre_auth: self.pre_auth.clone(),
+            event_labels: self.event_labels.clone(),
+            metrics: self.metrics.clone(),
+            write_ahead_log: self.write_ahead_log.clone(),
+            in_flight: self.in_flight.clone(),
+            signature_migration: self.signature_migration,
+            delivery_age: self.delivery_age.clone(),
+            signature_failures: self.signature_failures.clone(),
+            signature_bypass: self.signature_bypass,
+            signature_header: self.signature_header.clone(),
+            soft_deadline: self.soft_deadline,
+            queue: self.queue.clone(),
+            installation_concurrency: self.installation_concurrency.clone(),
+            audit_headers: self.audit_headers.clone(),
+            log_redactor: self.log_redactor.clone(),
+            ack_body: self.ack_body.clone(),
+            acknowledgements: self.acknowledgements.clone(),
+            debug_error_responses: self.debug_error_responses,
+            verified_subscriptions: self.verified_subscriptions.clone(),
+        }
+    }
 }
 
 impl<C: InstallationAuthenticator + Clone> FromRef<ConfigState<C>> for Arc<SecretKey> {
@@ -50,65 +412,664 @@ impl<C: InstallationAuthenticator + Clone> FromRef<ConfigState<C>> for Authentic
     }
 }
 
+impl<C: InstallationAuthenticator + Clone> FromRef<ConfigState<C>> for PayloadLimits {
+    fn from_ref(input: &ConfigState<C>) -> Self {
+        input.payload_limits
+    }
+}
+
+impl<C: InstallationAuthenticator + Clone> FromRef<ConfigState<C>>
+    for Option<Arc<dyn SecretResolver>>
+{
+    fn from_ref(input: &ConfigState<C>) -> Self {
+        input.secret_resolver.clone()
+    }
+}
+
+impl<C: InstallationAuthenticator + Clone> FromRef<ConfigState<C>> for Option<Arc<dyn PreAuth>> {
+    fn from_ref(input: &ConfigState<C>) -> Self {
+        input.pre_auth.clone()
+    }
+}
+
+impl<C: InstallationAuthenticator + Clone> FromRef<ConfigState<C>> for SignatureMigration {
+    fn from_ref(input: &ConfigState<C>) -> Self {
+        input.signature_migration
+    }
+}
+
+impl<C: InstallationAuthenticator + Clone> FromRef<ConfigState<C>> for DeliveryAge {
+    fn from_ref(input: &ConfigState<C>) -> Self {
+        input.delivery_age.clone()
+    }
+}
+
+impl<C: InstallationAuthenticator + Clone> FromRef<ConfigState<C>> for SignatureBypass {
+    fn from_ref(input: &ConfigState<C>) -> Self {
+        input.signature_bypass
+    }
+}
+
+impl<C: InstallationAuthenticator + Clone> FromRef<ConfigState<C>> for SignatureHeader {
+    fn from_ref(input: &ConfigState<C>) -> Self {
+        input.signature_header.clone()
+    }
+}
+
+impl<C: InstallationAuthenticator + Clone> FromRef<ConfigState<C>> for SignatureFailures {
+    fn from_ref(input: &ConfigState<C>) -> Self {
+        input.signature_failures.clone()
+    }
+}
+
 async fn authenticate_app<C: GitHubAppAuthenticator>(
     github_uri: Uri,
     app_id: AppId,
     app_key: EncodingKey,
+    user_agent: &str,
+    api_timeout: Option<Duration>,
+    endpoint: &WebhookEndpointConfiguration,
 ) -> Result<AuthenticatedClient<C::Next>, C::Error> {
-    let client = C::authenticate_app(github_uri, app_id, app_key)?;
-    Ok(AuthenticatedClient { client })
+    let client = C::authenticate_app(github_uri, app_id, app_key, user_agent, api_timeout)?;
+    let metrics = endpoint.metrics.clone();
+    let client =
+        AuthenticatedClient::with_cache_capacity(client, endpoint.installation_cache_capacity)
+            .with_token_cache_observer(move |lookup| metrics.incr_counter(lookup.metric(), &[], 1));
+    Ok(match endpoint.max_token_age {
+        Some(max_token_age) => client.with_max_token_age(max_token_age),
+        None => client,
+    })
+}
+
+/// Handles the event once its installation has a free slot and marks it as done in the
+/// write-ahead log if it was logged and handled successfully.
+async fn handle_logged_event<C: InstallationAuthenticator + Clone>(
+    state: &ConfigState<C>,
+    event: WebhookEvent,
+    delivery: Delivery,
+    logged: Option<u64>,
+    cancellation: CancellationToken,
+) -> Result<DeliveryOutcome, HandleEventError> {
+    let span = tracing::info_span!(
+        "delivery",
+        id = delivery.id.as_deref(),
+        account_type = account_type(&event),
+        enterprise_version = delivery.enterprise_version.as_ref().map(field::display),
+    );
+    let _permit = match state.installation_concurrency {
+        Some(ref concurrency) => concurrency.acquire(&event).instrument(span.clone()).await,
+        None => None,
+    };
+    let handled = handle_event(
+        state.client.clone(),
+        &state.handlers,
+        &state.options,
+        event,
+        delivery,
+        cancellation,
+    )
+    .instrument(span)
+    .await;
+    if let (Some(log), Some(id), Ok(_)) = (&state.write_ahead_log, logged, &handled) {
+        if let Err(err) = log.done(id) {
+            tracing::warn!(%err, id, "unable to mark the logged event as done");
+        }
+    }
+    handled
+}
+
+/// Handles the events that were logged but not finished before the last shutdown, failed ones
+/// stay in the log and are tried again on the next startup, until the log gives up on them.
+async fn handle_recovered_events<C: InstallationAuthenticator + Clone>(
+    state: ConfigState<C>,
+    recovered: Vec<RecoveredEvent>,
+) {
+    for RecoveredEvent {
+        id,
+        event,
+        body,
+        recoveries,
+    } in recovered
+    {
+        tracing::info!(id, recoveries, kind = ?event.kind, "handling recovered event");
+        let tracked = state.in_flight.track();
+        let delivery = Delivery {
+            body: Some(body),
+            ..Default::default()
+        };
+        let handled =
+            handle_logged_event(&state, event, delivery, Some(id), tracked.token.clone()).await;
+        if let Err(err) = handled {
+            tracing::error!(%err, id, "failed to handle recovered event");
+        }
+    }
+}
+
+/// Handles the queued events, in delivery order for events which share the same
+/// ordering key.
+///
+/// A shutdown keeps draining the queue until the drain timeout elapses, the events still
+/// waiting by then are dead-lettered instead of handled. Logged events stay in the
+/// write-ahead log and are recovered on the next startup either way.
+async fn work_queue<C: InstallationAuthenticator + Clone + Sync + 'static>(
+    state: ConfigState<C>,
+    mut receiver: EventQueueReceiver,
+    ordering: QueueOrdering,
+    concurrency: usize,
+) {
+    let mut sequencer = KeyedSequencer::new(concurrency);
+    loop {
+        // events stay queued whilst all slots are taken, so a full queue still drops them
+        let slot = sequencer.slot().await;
+        let Some(queued) = receiver.dequeue().await else {
+            break;
+        };
+        let state = state.clone();
+        let key = ordering.key(&queued.event);
+        sequencer.spawn(key, slot, async move {
+            let tracked = queued.tracked;
+            if tracked.token.is_cancelled() {
+                dead_letter_unhandled(&state, &queued.event, &queued.delivery);
+                return;
+            }
+            let handled = handle_logged_event(
+                &state,
+                queued.event,
+                queued.delivery,
+                queued.logged,
+                tracked.token.clone(),
+            )
+            .await;
+            if let Err(err) = handled {
+                tracing::error!(%err, "failed to handle queued event");
+            }
+        });
+    }
+}
+
+/// Hands a queued event the shutdown didn't leave time for to the dead letters.
+fn dead_letter_unhandled<C: InstallationAuthenticator + Clone>(
+    state: &ConfigState<C>,
+    event: &WebhookEvent,
+    delivery: &Delivery,
+) {
+    let Some(ref dead_letters) = state.options.retries.dead_letters else {
+        tracing::warn!(kind = ?event.kind, "dropping queued event, the shutdown cancelled it");
+        return;
+    };
+    dead_letters.dead_letter(DeadLetter {
+        kind: event.kind.clone(),
+        delivery_id: delivery.id.clone(),
+        attempts: 0,
+        error: "the shutdown cancelled the queued event before it was handled".to_string(),
+        body: delivery.body.clone(),
+        headers: delivery.headers.clone(),
+    });
+}
+
+/// Deliveries for another installation than their body claims usually mean a proxy mixed up
+/// the hooks of several Apps. The target of App hooks is the App itself, it is never compared.
+fn warn_on_foreign_target(
+    event: &WebhookEvent,
+    target_type: Option<TargetType>,
+    target_id: Option<u64>,
+) {
+    let installation = match event.installation {
+        Some(EventInstallation::Full(ref installation)) => installation.id,
+        Some(EventInstallation::Minimal(ref installation)) => installation.id,
+        None => return,
+    };
+    match target_id {
+        Some(target_id)
+            if target_type != Some(TargetType::Integration) && target_id != installation.0 =>
+        {
+            tracing::warn!(
+                target_id,
+                installation = installation.0,
+                "the hook installation target id doesn't match the event's installation"
+            );
+        }
+        _ => {}
+    }
+}
+
+/// Error chains longer than this are cut off, as are their messages.
+const MAX_ERROR_CAUSES: usize = 8;
+const MAX_ERROR_CAUSE_CHARS: usize = 256;
+
+/// Context of a failed delivery added to its error response if `debug_error_responses` is
+/// enabled. Neither the payload nor any header values besides the delivery id end up in it.
+#[derive(Debug, serde::Serialize)]
+struct ErrorDebug<'a> {
+    error: String,
+    event: Option<&'a str>,
+    action: Option<String>,
+    delivery: Option<&'a str>,
+    causes: Vec<String>,
+}
+
+impl<'a> ErrorDebug<'a> {
+    fn new(
+        err: &HandleEventError,
+        event: Option<&'a str>,
+        action: Option<String>,
+        delivery: Option<&'a str>,
+    ) -> Self {
+        let causes =
+            std::iter::successors(Some(err as &(dyn std::error::Error + 'static)), |err| {
+                err.source()
+            })
+            .take(MAX_ERROR_CAUSES)
+            .map(|cause| {
+                cause
+                    .to_string()
+                    .chars()
+                    .take(MAX_ERROR_CAUSE_CHARS)
+                    .collect()
+            })
+            .collect();
+        Self {
+            error: String::new(),
+            event,
+            action,
+            delivery,
+            causes,
+        }
+    }
+
+    fn with_error(self, error: String) -> Self {
+        Self { error, ..self }
+    }
+}
+
+/// Forgets a recorded delivery when dropped unless it was acknowledged, so GitHub's redelivery
+/// of a failed, dropped, panicked or timed out delivery is processed again.
+struct RecordedDelivery {
+    store: Arc<dyn DeliveryStore>,
+    delivery: String,
+    acknowledged: bool,
+}
+
+impl RecordedDelivery {
+    fn new(store: Arc<dyn DeliveryStore>, delivery: &str) -> Self {
+        Self {
+            store,
+            delivery: delivery.to_owned(),
+            acknowledged: false,
+        }
+    }
+}
+
+impl Drop for RecordedDelivery {
+    fn drop(&mut self) {
+        if self.acknowledged {
+            return;
+        }
+        if let Err(err) = self.store.forget(&self.delivery) {
+            tracing::warn!(%err, delivery = self.delivery, "unable to forget the delivery");
+        }
+    }
 }
 
-async fn handle_github_event<C: InstallationAuthenticator + Clone>(
-    State(AuthenticatedClient { client }): State<AuthenticatedClient<C>>,
-    GitHubEvent(event): GitHubEvent,
-) -> impl IntoResponse {
+async fn handle_github_event<C: InstallationAuthenticator + Clone + Sync + 'static>(
+    State(state): State<ConfigState<C>>,
+    extensions: Extensions,
+    headers: HeaderMap,
+    ExtractTargetType(target_type): ExtractTargetType,
+    ExtractTargetId(target_id): ExtractTargetId,
+    ExtractEnterpriseVersion(enterprise_version): ExtractEnterpriseVersion,
+    GitHubEvent(event, body): GitHubEvent,
+) -> Response {
+    let kind = headers
+        .get("x-github-event")
+        .and_then(|value| value.to_str().ok());
+    if let (Some(subscriptions), Some(kind)) = (&state.verified_subscriptions, kind) {
+        if !subscriptions.accepts(kind) {
+            tracing::debug!(kind, "acknowledging verified unsubscribed event");
+            return (StatusCode::OK, "not subscribed").into_response();
+        }
+    }
+    let account_type = account_type(&event);
+    if let Some(kind) = kind {
+        state
+            .event_labels
+            .record(state.metrics.as_ref(), kind, account_type);
+    }
+    let delivery = headers
+        .get("x-github-delivery")
+        .and_then(|value| value.to_str().ok());
+    let mut recorded = None;
+    if let (Some(store), Some(delivery)) = (&state.delivery_store, delivery) {
+        match store.record(delivery) {
+            Ok(true) => recorded = Some(RecordedDelivery::new(store.clone(), delivery)),
+            Ok(false) => {
+                tracing::info!(delivery, "skipping already processed delivery");
+                return (StatusCode::OK, "duplicate delivery").into_response();
+            }
+            Err(err) => tracing::warn!(%err, delivery, "unable to record delivery"),
+        }
+    }
+    if let Some(ref fan_out) = state.fan_out {
+        fan_out.mirror(&headers, body.clone());
+    }
+    let action = state
+        .debug_error_responses
+        .then(|| event_action(&body))
+        .flatten();
     let handle_err = |err: HandleEventError| {
-        tracing::error!(%err, "failed to handle event");
-        match err {
+        if !matches!(err, HandleEventError::Rejected { .. }) {
+            tracing::error!(%err, "failed to handle event");
+        }
+        let debug = (state.debug_error_responses
+            && !matches!(err, HandleEventError::Rejected { .. }))
+        .then(|| ErrorDebug::new(&err, kind, action.clone(), delivery));
+        let (status, message) = match err {
+            HandleEventError::Rejected { status, reason } => {
+                tracing::info!(%status, reason, "gate rejected the delivery");
+                (status, reason)
+            }
             HandleEventError::MissingInstallation => (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                "missing installation in the event",
-            )
-                .into_response(),
+                "missing installation in the event".to_owned(),
+            ),
             HandleEventError::InstallationAuthentication { .. } => (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                "unable to access installation",
-            )
-                .into_response(),
+                "unable to access installation".to_owned(),
+            ),
             HandleEventError::MissingRepository => (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                "missing repository parent in the event",
-            )
-                .into_response(),
+                "missing repository parent in the event".to_owned(),
+            ),
+            HandleEventError::UncoveredRepository { .. } => (
+                StatusCode::BAD_REQUEST,
+                "repository is not accessible to the installation".to_owned(),
+            ),
             HandleEventError::EventHandling { event, .. } => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 format!("failed to handle event: {:?}", event),
-            )
-                .into_response(),
+            ),
+            HandleEventError::HandlersFailed {
+                event,
+                failed,
+                invoked,
+                ..
+            } => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!(
+                    "partially handled event: {event:?}, {failed} of {invoked} handlers failed"
+                ),
+            ),
+        };
+        match debug {
+            Some(debug) => (status, Json(debug.with_error(message))).into_response(),
+            None => (status, message).into_response(),
+        }
+    };
+    if tracing::enabled!(tracing::Level::DEBUG) {
+        if let Ok(mut payload) = serde_json::from_slice::<serde_json::Value>(&body) {
+            state.log_redactor.redact_event(&mut payload);
+            tracing::debug!(%payload, "received event");
+        }
+    }
+    let summary = (state.forwarder.is_some() || state.delivery_log.is_some())
+        .then(|| {
+            DeliverySummary::new(&headers, &event).with_headers(&headers, &state.audit_headers)
+        })
+        .map(|summary| {
+            let mut summary = DeliverySummary {
+                client_ip: extensions.get::<ClientIp>().map(|ClientIp(ip)| *ip),
+                ..summary
+            };
+            state.log_redactor.redact_summary(&mut summary);
+            summary
+        });
+    let topic = state
+        .publishing
+        .as_ref()
+        .zip(kind)
+        .and_then(|(publishing, kind)| {
+            publishing.topics.get(kind).map(|topic| (publishing, topic))
+        });
+    // exclusively published events are acknowledged instead of handled
+    let published = match topic {
+        Some((publishing, topic)) => {
+            let published = publishing
+                .publisher
+                .publish(topic, &partition_key(&event), body.clone())
+                .await;
+            match (published, publishing.exclusive) {
+                (Ok(()), true) => Some((StatusCode::ACCEPTED, "published").into_response()),
+                (Ok(()), false) => None,
+                (Err(err), true) => {
+                    tracing::error!(%err, topic, "unable to publish the event");
+                    Some((StatusCode::BAD_GATEWAY, "unable to publish the event").into_response())
+                }
+                (Err(err), false) => {
+                    tracing::warn!(%err, topic, "unable to publish the event, handling it anyway");
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+    let logged = match (&state.write_ahead_log, kind) {
+        _ if published.is_some() => None,
+        (Some(log), Some(kind)) => match log.append(kind, &body) {
+            Ok(id) => Some(id),
+            Err(err) => {
+                tracing::warn!(%err, "unable to log the event before handling it");
+                None
+            }
+        },
+        _ => None,
+    };
+    warn_on_foreign_target(&event, target_type, target_id);
+    let delivery = Delivery {
+        id: delivery.map(str::to_owned),
+        target_type,
+        target_id,
+        body: Some(body),
+        headers: handler_headers(&state, &headers),
+        enterprise_version,
+    };
+    let response = match (published, &state.queue) {
+        (Some(published), _) => published,
+        (None, Some(queue)) => {
+            let queued = QueuedEvent {
+                event,
+                delivery,
+                logged,
+                tracked: state.in_flight.track(),
+            };
+            match queue.try_enqueue(queued) {
+                Ok(()) => (StatusCode::ACCEPTED, "queued").into_response(),
+                Err(dropped) => {
+                    tracing::warn!(kind = ?dropped.event.kind, "dropping event, the queue is full");
+                    if let (Some(log), Some(id)) = (&state.write_ahead_log, dropped.logged) {
+                        if let Err(err) = log.done(id) {
+                            tracing::warn!(%err, id, "unable to mark the dropped event as done");
+                        }
+                    }
+                    (StatusCode::SERVICE_UNAVAILABLE, "event queue is full").into_response()
+                }
+            }
+        }
+        (None, None) => {
+            // the handler future is dropped when the client disconnects, which cancels the
+            // token, as does a shutdown whose drain timeout elapsed
+            let tracked = state.in_flight.track();
+            let cancellation = tracked.token.clone();
+            let guard = cancellation.clone().drop_guard();
+            let respond = |handled: Result<DeliveryOutcome, HandleEventError>| match handled {
+                Ok(outcome) => state.ack_body.respond(outcome),
+                Err(err) => handle_err(err).into_response(),
+            };
+            let acknowledgement = kind
+                .and_then(|kind| state.acknowledgements.get(kind))
+                .copied()
+                .unwrap_or_default();
+            let handled = match (acknowledgement, state.soft_deadline) {
+                (Acknowledgement::Accepted, _) => {
+                    let background = state.clone();
+                    tokio::spawn(async move {
+                        let _tracked = tracked;
+                        let handled =
+                            handle_logged_event(&background, event, delivery, logged, cancellation)
+                                .await;
+                        if let Err(err) = handled {
+                            tracing::error!(%err, "failed to handle accepted event");
+                        }
+                    });
+                    None
+                }
+                (Acknowledgement::Handled, Some(deadline)) => {
+                    let detached = state.clone();
+                    let mut handling = tokio::spawn(async move {
+                        let _tracked = tracked;
+                        handle_logged_event(&detached, event, delivery, logged, cancellation).await
+                    });
+                    match tokio::time::timeout(deadline, &mut handling).await {
+                        Ok(Ok(handled)) => Some(respond(handled)),
+                        Ok(Err(err)) if err.is_panic() => {
+                            std::panic::resume_unwind(err.into_panic())
+                        }
+                        Ok(Err(err)) => {
+                            tracing::error!(%err, "event handling was cancelled");
+                            Some(
+                                (
+                                    StatusCode::SERVICE_UNAVAILABLE,
+                                    "event handling was cancelled",
+                                )
+                                    .into_response(),
+                            )
+                        }
+                        Err(_) => {
+                            tracing::warn!(
+                                deadline_ms = deadline.as_millis() as u64,
+                                "handling exceeds the soft deadline, detaching it"
+                            );
+                            tokio::spawn(async move {
+                                match handling.await {
+                                    Ok(Err(err)) => {
+                                        tracing::error!(%err, "failed to handle detached event")
+                                    }
+                                    Err(err) => {
+                                        tracing::error!(%err, "detached event handling panicked")
+                                    }
+                                    Ok(Ok(_)) => tracing::info!("handled detached event"),
+                                }
+                            });
+                            None
+                        }
+                    }
+                }
+                (Acknowledgement::Handled, None) => {
+                    let handled =
+                        handle_logged_event(&state, event, delivery, logged, cancellation).await;
+                    Some(respond(handled))
+                }
+            };
+            guard.disarm();
+            handled.unwrap_or_else(|| {
+                (StatusCode::ACCEPTED, "handling in the background").into_response()
+            })
         }
     };
-    match handle_event(client, event).await {
-        Ok(Some(res)) => (StatusCode::OK, res).into_response(),
-        Ok(None) => (StatusCode::NO_CONTENT).into_response(),
-        Err(err) => handle_err(err).into_response(),
+    if let Some(recorded) = recorded.as_mut() {
+        recorded.acknowledged = response.status().is_success();
     }
+    if let Some(summary) = summary.map(|summary| summary.with_status(response.status())) {
+        if let Some(log) = state.delivery_log {
+            tokio::spawn(log.forward(summary.clone()));
+        }
+        if let Some(forwarder) = state.forwarder {
+            tokio::spawn(forwarder.forward(summary));
+        }
+    }
+    response
+}
+
+/// The headers of the delivery without the credentials that authenticated it, handlers have
+/// no use for them and might log or forward them.
+fn handler_headers<C: InstallationAuthenticator + Clone>(
+    state: &ConfigState<C>,
+    headers: &HeaderMap,
+) -> HeaderMap {
+    let mut headers = headers.clone();
+    for credential in state
+        .pre_auth
+        .iter()
+        .flat_map(|pre_auth| pre_auth.credential_headers())
+    {
+        headers.remove(credential);
+    }
+    headers.remove(&state.signature_header.0);
+    for signature in [
+        "x-hub-signature",
+        DEFAULT_SIGNATURE_HEADER,
+        DELIVERY_TIMESTAMP_SIGNATURE_HEADER,
+    ] {
+        headers.remove(signature);
+    }
+    headers
 }
 
 #[cfg(test)]
 mod test {
-    use super::{GitHubAppAuthenticator, InstallationAuthenticator};
-    use crate::config::GitHubAppConfiguration;
-    use axum::{body::Body, http::Request};
+    use super::{Acknowledgement, GitHubAppAuthenticator, InstallationAuthenticator, MetricsSink};
+    use crate::clock::ManualClock;
+    use crate::config::{GitHubAppConfiguration, WebhookEndpointConfiguration};
+    use crate::dead_letters::FileDeadLetters;
+    use crate::deliveries::InMemoryDeliveryStore;
+    use crate::delivery_log::{NdjsonDeliveryLog, Rotation};
+    use crate::forwarder::{DeliveryForwarder, DeliverySummary};
+    use crate::pre_auth::ProxySecretHeader;
+    use crate::publisher::{EventPublisher, EventPublishing, PublishError};
+    use crate::redaction::EmailRedactor;
+    use crate::replay::{replay, CapturedDelivery};
+    use crate::routes::ack_body::TemplateAck;
+    use crate::secrets::{
+        CachedSecretProvider, RepositorySecrets, SecretProvider, SecretProviderError,
+        SecretResolver,
+    };
+    use crate::shutdown::{InFlightHandlers, Shutdown};
+    use crate::signature::{
+        DeliveryAge, SignatureFailures, SignatureMigration, DEFAULT_SIGNATURE_HEADER,
+    };
+    use crate::wal::WriteAheadLog;
+    use axum::{
+        body::Body,
+        http::{HeaderName, HeaderValue, Request},
+    };
+    use bytes::Bytes;
+    use futures_util::future::BoxFuture;
     use futures_util::never::Never;
-    use github_event_handler::api::GitHubApi;
+    use github_event_handler::authentication::{AppMetadata, TokenScope};
+    use github_event_handler::context::{EventContext, TargetType};
+    use github_event_handler::handle::{HandleOptions, Visibility};
+    use github_event_handler::handler::{
+        DeadLetter, DeadLetters, EventHandler, Gate, GateDecision, HandlerError, HandlerResult,
+        Handlers, Retries, RetryPolicy,
+    };
+    use github_event_handler::mock::{NoOpApi, FAILED_CHECK_RUNS};
+    use github_event_handler::payload::REPOSITORY_RULESET;
     use http_body_util::BodyExt;
     use hyper::{StatusCode, Uri};
+    use metrics_exporter_prometheus::PrometheusBuilder;
+    use octocrab::models::pulls::ReviewState;
+    use octocrab::models::webhook_events::payload::{RefType, StarWebhookEventAction};
+    use octocrab::models::webhook_events::WebhookEventType;
     use octocrab::models::Repository;
     use orion::hazardous::mac::hmac::sha256::{HmacSha256, SecretKey};
     use rsa::RsaPublicKey;
     use serde_json::json;
+    use std::collections::{HashMap, HashSet};
+    use std::num::NonZeroUsize;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, SystemTime};
     use thiserror::Error;
+    use tokio_util::sync::CancellationToken;
     use tower::ServiceExt;
 
     #[derive(Clone)]
@@ -117,15 +1078,6 @@ mod test {
     #[derive(Debug, Error)]
     enum TestError {}
 
-    struct NoOpApi;
-
-    impl GitHubApi for NoOpApi {
-        #[allow(refining_impl_trait)]
-        async fn create_commit_status(&self, _: &Repository, _: &str) -> Result<(), TestError> {
-            Ok(())
-        }
-    }
-
     impl GitHubAppAuthenticator for TestClient {
         type Next = TestClient;
         type Error = TestError;
@@ -134,26 +1086,74 @@ mod test {
             _uri: Uri,
             _app_id: octocrab::models::AppId,
             _app_key: jsonwebtoken::EncodingKey,
+            _user_agent: &str,
+            _api_timeout: Option<Duration>,
         ) -> Result<Self::Next, Self::Error> {
             Ok(TestClient)
         }
     }
 
     impl InstallationAuthenticator for TestClient {
+        type Api = NoOpApi;
         type Error = Never;
         async fn for_installation(
             &self,
             _id: octocrab::models::InstallationId,
-        ) -> Result<impl GitHubApi, Self::Error> {
+        ) -> Result<Self::Api, Self::Error> {
+            Ok(NoOpApi)
+        }
+
+        async fn for_app(&self) -> Result<Self::Api, Self::Error> {
+            Ok(NoOpApi)
+        }
+
+        async fn installation_account(
+            &self,
+            _id: octocrab::models::InstallationId,
+        ) -> Result<String, Self::Error> {
+            Ok("acme".to_string())
+        }
+
+        async fn repository_installation(
+            &self,
+            _owner: &str,
+            _repo: &str,
+        ) -> Result<octocrab::models::InstallationId, Self::Error> {
+            Ok(octocrab::models::InstallationId(1))
+        }
+
+        async fn scoped_installation(
+            &self,
+            _id: octocrab::models::InstallationId,
+            _scope: &TokenScope,
+        ) -> Result<Self::Api, Self::Error> {
             Ok(NoOpApi)
         }
+
+        async fn app_metadata(&self) -> Result<AppMetadata, Self::Error> {
+            Ok(AppMetadata {
+                slug: "wild-git-yonder".into(),
+                name: "Wild Git Yonder".into(),
+            })
+        }
+
+        async fn installation_count(&self) -> Result<u64, Self::Error> {
+            Ok(1)
+        }
+
+        async fn installation_repositories(
+            &self,
+            _installation: &Self::Api,
+        ) -> Result<Vec<Repository>, Self::Error> {
+            Ok(vec![serde_json::from_value(test_repository()).unwrap()])
+        }
     }
 
     #[tracing_test::traced_test]
     #[tokio::test]
     async fn test_happy_path() {
         let (config, _, secret) = create_test_config();
-        let app = super::router::<TestClient>(config, "/event_handler")
+        let app = super::router::<TestClient>(config, &Default::default(), Default::default())
             .await
             .unwrap();
 
@@ -187,7 +1187,7 @@ mod test {
     #[tokio::test]
     async fn test_missing_signature() {
         let (config, _, _) = create_test_config();
-        let app = super::router::<TestClient>(config, "/event_handler")
+        let app = super::router::<TestClient>(config, &Default::default(), Default::default())
             .await
             .unwrap();
 
@@ -206,7 +1206,7 @@ mod test {
     #[tokio::test]
     async fn test_wrong_signature() {
         let (config, _, _) = create_test_config();
-        let app = super::router::<TestClient>(config, "/event_handler")
+        let app = super::router::<TestClient>(config, &Default::default(), Default::default())
             .await
             .unwrap();
 
@@ -225,6 +1225,3667 @@ mod test {
         assert_eq!(response.status(), StatusCode::BAD_REQUEST);
     }
 
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_body_with_an_added_bom_is_rejected() {
+        let (config, _, secret) = create_test_config();
+        let app = super::router::<TestClient>(config, &Default::default(), Default::default())
+            .await
+            .unwrap();
+
+        // signed as GitHub sent it, the BOM was added on the way
+        let body = ping_body();
+        let signature = format!("sha256={}", calc_hmac_for_body(&secret, &body));
+        let response = app
+            .oneshot(signed_ping_request(
+                signature,
+                [b"\xEF\xBB\xBF".as_slice(), &body].concat(),
+            ))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(logs_contain("gained a UTF-8 BOM"));
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_signature_with_exact_length() {
+        let (config, _, secret) = create_test_config();
+        let app = super::router::<TestClient>(config, &Default::default(), Default::default())
+            .await
+            .unwrap();
+
+        let body = ping_body();
+        let body_hmac = calc_hmac_for_body(&secret, &body);
+        assert_eq!(body_hmac.len(), 64);
+        let response = app
+            .oneshot(signed_ping_request(format!("sha256={body_hmac}"), body))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_signature_with_trailing_bytes() {
+        let (config, _, secret) = create_test_config();
+        let app = super::router::<TestClient>(config, &Default::default(), Default::default())
+            .await
+            .unwrap();
+
+        let body = ping_body();
+        let body_hmac = calc_hmac_for_body(&secret, &body);
+        let response = app
+            .oneshot(signed_ping_request(format!("sha256={body_hmac}00"), body))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(String::from_utf8_lossy(&body).contains("exactly 32 bytes long, got 33"));
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_signature_too_short() {
+        let (config, _, secret) = create_test_config();
+        let app = super::router::<TestClient>(config, &Default::default(), Default::default())
+            .await
+            .unwrap();
+
+        let body = ping_body();
+        let body_hmac = calc_hmac_for_body(&secret, &body);
+        let response = app
+            .oneshot(signed_ping_request(
+                format!("sha256={}", &body_hmac[..62]),
+                body,
+            ))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(String::from_utf8_lossy(&body).contains("exactly 32 bytes long, got 31"));
+    }
+
+    async fn send_ping_with_signature_prefix(prefix: &str) -> StatusCode {
+        let (config, _, secret) = create_test_config();
+        let app = super::router::<TestClient>(config, &Default::default(), Default::default())
+            .await
+            .unwrap();
+
+        let body = ping_body();
+        let body_hmac = calc_hmac_for_body(&secret, &body);
+        app.oneshot(signed_ping_request(format!("{prefix}{body_hmac}"), body))
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_signature_prefix_is_case_insensitive() {
+        assert_eq!(
+            send_ping_with_signature_prefix("sha256=").await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            send_ping_with_signature_prefix("SHA256=").await,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn test_signature_without_prefix_is_rejected() {
+        assert_eq!(
+            send_ping_with_signature_prefix("").await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            send_ping_with_signature_prefix("md5=").await,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_duplicated_signature_header_with_one_match() {
+        let (config, _, secret) = create_test_config();
+        let app = super::router::<TestClient>(config, &Default::default(), Default::default())
+            .await
+            .unwrap();
+
+        let body = ping_body();
+        let body_hmac = calc_hmac_for_body(&secret, &body);
+        let request = Request::builder()
+            .uri("/event_handler")
+            .header("X-GitHub-Event", "ping")
+            .header("x-hub-signature-256", format!("sha256={}", "0".repeat(64)))
+            .header("x-hub-signature-256", format!("sha256={body_hmac}"))
+            .body(Body::from(body))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_duplicated_signature_header_without_match() {
+        let (config, _, _) = create_test_config();
+        let app = super::router::<TestClient>(config, &Default::default(), Default::default())
+            .await
+            .unwrap();
+
+        let request = Request::builder()
+            .uri("/event_handler")
+            .header("X-GitHub-Event", "ping")
+            .header("x-hub-signature-256", format!("sha256={}", "0".repeat(64)))
+            .header("x-hub-signature-256", format!("sha256={}", "1".repeat(64)))
+            .body(Body::from(ping_body()))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(String::from_utf8_lossy(&body).contains("does not match"));
+    }
+
+    async fn send_sha1_signed_ping(migration: SignatureMigration) -> StatusCode {
+        use hmac::{Hmac, Mac};
+
+        let (config, _, secret) = create_test_config();
+        let endpoint = WebhookEndpointConfiguration {
+            signature_migration: migration,
+            ..Default::default()
+        };
+        let app = super::router::<TestClient>(config, &endpoint, Default::default())
+            .await
+            .unwrap();
+
+        let body = ping_body();
+        let mut mac = Hmac::<sha1::Sha1>::new_from_slice(secret.unprotected_as_bytes()).unwrap();
+        mac.update(&body);
+        let request = Request::builder()
+            .uri("/event_handler")
+            .header("X-GitHub-Event", "ping")
+            .header(
+                "x-hub-signature",
+                format!("sha1={}", hex::encode(mac.finalize().into_bytes())),
+            )
+            .body(Body::from(body))
+            .unwrap();
+        app.oneshot(request).await.unwrap().status()
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_sha1_signature_is_accepted_during_migration() {
+        let migration = SignatureMigration {
+            sha1_accepted_until: Some(SystemTime::now() + Duration::from_secs(60)),
+        };
+
+        assert_eq!(send_sha1_signed_ping(migration).await, StatusCode::OK);
+        assert!(logs_contain("legacy SHA-1 signature only"));
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_sha1_signature_is_rejected_after_migration() {
+        let migration = SignatureMigration {
+            sha1_accepted_until: Some(SystemTime::now() - Duration::from_secs(60)),
+        };
+
+        assert_eq!(
+            send_sha1_signed_ping(migration).await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            send_sha1_signed_ping(SignatureMigration::default()).await,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delivery_timestamp_may_be_slightly_in_the_future() {
+        let (config, _, secret) = create_test_config();
+        let timestamp_secret = Arc::new(SecretKey::from_slice(&[7; 32]).unwrap());
+        let endpoint = WebhookEndpointConfiguration {
+            delivery_age: DeliveryAge {
+                max_age: Some(Duration::from_secs(300)),
+                max_future_skew: Duration::from_secs(30),
+                secret: Some(timestamp_secret.clone()),
+            },
+            ..Default::default()
+        };
+        let app = super::router::<TestClient>(config, &endpoint, Default::default())
+            .await
+            .unwrap();
+        let send = |ahead: Duration| {
+            let body = ping_body();
+            let body_hmac = calc_hmac_for_body(&secret, &body);
+            let mut request = signed_ping_request(format!("sha256={body_hmac}"), body);
+            let timestamp = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                + ahead;
+            let timestamp = timestamp.as_secs().to_string();
+            let signed = [timestamp.as_bytes(), b".", &ping_body()].concat();
+            let signature = calc_hmac_for_body(&timestamp_secret, &signed);
+            let headers = request.headers_mut();
+            headers.insert("x-delivery-timestamp", timestamp.parse().unwrap());
+            headers.insert(
+                "x-delivery-timestamp-signature",
+                format!("sha256={signature}").parse().unwrap(),
+            );
+            app.clone().oneshot(request)
+        };
+
+        let within = send(Duration::from_secs(10)).await.unwrap();
+        assert_eq!(within.status(), StatusCode::OK);
+
+        let beyond = send(Duration::from_secs(120)).await.unwrap();
+        assert_eq!(beyond.status(), StatusCode::BAD_REQUEST);
+        let body = beyond.into_body().collect().await.unwrap().to_bytes();
+        assert!(String::from_utf8_lossy(&body).contains("in the future"));
+    }
+
+    /// Fails the first authentication, like GitHub being unreachable at startup.
+    struct FlakyClient;
+
+    static FLAKY_ATTEMPTS: AtomicUsize = AtomicUsize::new(0);
+
+    impl GitHubAppAuthenticator for FlakyClient {
+        type Next = TestClient;
+        type Error = std::io::Error;
+
+        fn authenticate_app(
+            _uri: Uri,
+            _app_id: octocrab::models::AppId,
+            _app_key: jsonwebtoken::EncodingKey,
+            _user_agent: &str,
+            _api_timeout: Option<Duration>,
+        ) -> Result<Self::Next, Self::Error> {
+            match FLAKY_ATTEMPTS.fetch_add(1, Ordering::SeqCst) {
+                0 => Err(std::io::Error::other("GitHub is unreachable")),
+                _ => Ok(TestClient),
+            }
+        }
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_lazy_auth_boots_unauthenticated_and_becomes_ready() {
+        let (config, _, secret) = create_test_config();
+        let endpoint = WebhookEndpointConfiguration {
+            lazy_auth: true,
+            ..Default::default()
+        };
+        let readiness =
+            crate::routes::health::router(Default::default(), None, endpoint.auth_health.clone());
+        let readyz = || async {
+            let request = Request::builder()
+                .uri("/readyz")
+                .body(Body::empty())
+                .unwrap();
+            readiness.clone().oneshot(request).await.unwrap().status()
+        };
+        let app = super::router::<FlakyClient>(config, &endpoint, Default::default())
+            .await
+            .unwrap();
+        let ping = || {
+            let body = ping_body();
+            signed_ping_request(
+                format!("sha256={}", calc_hmac_for_body(&secret, &body)),
+                body,
+            )
+        };
+
+        assert_eq!(readyz().await, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            app.clone().oneshot(ping()).await.unwrap().status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !endpoint.auth_health.is_authenticated() {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(readyz().await, StatusCode::OK);
+        assert_eq!(app.oneshot(ping()).await.unwrap().status(), StatusCode::OK);
+        assert!(logs_contain("unable to authenticate the App"));
+    }
+
+    async fn send_ping_signed_in(
+        header: &str,
+        endpoint: &WebhookEndpointConfiguration,
+    ) -> StatusCode {
+        let (config, _, secret) = create_test_config();
+        let app = super::router::<TestClient>(config, endpoint, Default::default())
+            .await
+            .unwrap();
+
+        let body = ping_body();
+        let request = Request::builder()
+            .uri("/event_handler")
+            .header("X-GitHub-Event", "ping")
+            .header(
+                header,
+                format!("sha256={}", calc_hmac_for_body(&secret, &body)),
+            )
+            .body(Body::from(body))
+            .unwrap();
+        app.oneshot(request).await.unwrap().status()
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_signature_is_read_from_the_configured_header() {
+        let endpoint = WebhookEndpointConfiguration {
+            signature_header_name: "X-Original-Hub-Signature-256".into(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            send_ping_signed_in("x-original-hub-signature-256", &endpoint).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            send_ping_signed_in("x-hub-signature-256", &endpoint).await,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_signature_is_read_from_the_default_header() {
+        let endpoint = WebhookEndpointConfiguration::default();
+
+        assert_eq!(
+            send_ping_signed_in("X-Hub-Signature-256", &endpoint).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            send_ping_signed_in("x-original-hub-signature-256", &endpoint).await,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_json_content_type_is_accepted_with_any_parameters() {
+        let (config, _, secret) = create_test_config();
+        let app = super::router::<TestClient>(config, &Default::default(), Default::default())
+            .await
+            .unwrap();
+
+        for (content_type, expected) in [
+            ("application/json", StatusCode::OK),
+            ("application/json; charset=utf-8", StatusCode::OK),
+            ("Application/JSON;charset=UTF-8", StatusCode::OK),
+            ("text/plain", StatusCode::UNSUPPORTED_MEDIA_TYPE),
+            (
+                "application/x-www-form-urlencoded",
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ),
+        ] {
+            let body = ping_body();
+            let body_hmac = calc_hmac_for_body(&secret, &body);
+            let mut request = signed_ping_request(format!("sha256={body_hmac}"), body);
+            request
+                .headers_mut()
+                .insert("content-type", HeaderValue::from_static(content_type));
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), expected, "{content_type}");
+        }
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_repeated_mismatches_are_counted_and_cross_the_threshold() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+        let _guard = metrics::set_default_local_recorder(&recorder);
+        let (config, _, _) = create_test_config();
+        let endpoint = WebhookEndpointConfiguration {
+            signature_failures: SignatureFailures::new(3, Duration::from_secs(60)),
+            ..Default::default()
+        };
+        let app = super::router::<TestClient>(config, &endpoint, Default::default())
+            .await
+            .unwrap();
+        let other_secret = SecretKey::from_slice(b"rotated on one side only").unwrap();
+
+        for _ in 0..2 {
+            let response = app
+                .clone()
+                .oneshot(signed_request(&other_secret, "ping", json!({"hook_id": 1})))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+        assert!(!logs_contain("signature failure threshold crossed"));
+        let response = app
+            .oneshot(signed_request(&other_secret, "ping", json!({"hook_id": 1})))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(handle
+            .render()
+            .contains(r#"github_signature_failures_total{reason="mismatch"} 3"#));
+        assert!(logs_contain("signature failure threshold crossed"));
+    }
+
+    /// Metric name, labels and value of every call, `"counter"`, `"histogram"` or `"gauge"`.
+    type RecordedMetric = (&'static str, &'static str, Vec<(&'static str, String)>, f64);
+
+    #[derive(Debug, Default)]
+    struct MockSink(Mutex<Vec<RecordedMetric>>);
+
+    impl MetricsSink for MockSink {
+        fn incr_counter(&self, name: &'static str, labels: &[(&'static str, String)], value: u64) {
+            let call = ("counter", name, labels.to_vec(), value as f64);
+            self.0.lock().unwrap().push(call);
+        }
+
+        fn observe_histogram(
+            &self,
+            name: &'static str,
+            labels: &[(&'static str, String)],
+            value: f64,
+        ) {
+            let call = ("histogram", name, labels.to_vec(), value);
+            self.0.lock().unwrap().push(call);
+        }
+
+        fn set_gauge(&self, name: &'static str, labels: &[(&'static str, String)], value: f64) {
+            let call = ("gauge", name, labels.to_vec(), value);
+            self.0.lock().unwrap().push(call);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_processed_delivery_is_recorded_with_the_configured_sink() {
+        let (config, _, secret) = create_test_config();
+        let sink = Arc::new(MockSink::default());
+        let endpoint = WebhookEndpointConfiguration {
+            metrics: sink.clone(),
+            ..Default::default()
+        };
+        let app = super::router::<TestClient>(config, &endpoint, Default::default())
+            .await
+            .unwrap()
+            .route_layer(axum::middleware::from_fn_with_state(
+                endpoint.metrics.clone(),
+                crate::track_metrics,
+            ));
+
+        let body = ping_body();
+        let body_hmac = calc_hmac_for_body(&secret, &body);
+        let response = app
+            .oneshot(signed_ping_request(format!("sha256={body_hmac}"), body))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let calls = sink.0.lock().unwrap();
+        let request_labels = vec![
+            ("method", "GET".to_string()),
+            ("path", "/event_handler".to_string()),
+            ("status", "200".to_string()),
+        ];
+        assert_eq!(
+            calls
+                .iter()
+                .map(|(kind, name, labels, _)| (*kind, *name, labels.clone()))
+                .collect::<Vec<_>>(),
+            [
+                (
+                    "counter",
+                    "github_events_total",
+                    vec![
+                        ("event", "ping".to_string()),
+                        ("account_type", "unknown".to_string())
+                    ]
+                ),
+                // the ping carries an installation, a token is minted for it
+                ("counter", "github_token_cache_misses_total", vec![]),
+                ("counter", "http_requests_total", request_labels.clone()),
+                (
+                    "histogram",
+                    "http_requests_duration_seconds",
+                    request_labels
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_token_cache_lookups_are_recorded_with_the_configured_sink() {
+        let (config, _, secret) = create_test_config();
+        let sink = Arc::new(MockSink::default());
+        let endpoint = WebhookEndpointConfiguration {
+            metrics: sink.clone(),
+            ..Default::default()
+        };
+        let app = super::router::<TestClient>(config, &endpoint, Default::default())
+            .await
+            .unwrap();
+
+        for _ in 0..2 {
+            let response = app
+                .clone()
+                .oneshot(signed_request(&secret, "push", push_body()))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let calls = sink.0.lock().unwrap();
+        let lookups = calls
+            .iter()
+            .filter(|(_, name, _, _)| name.starts_with("github_token_cache"))
+            .map(|(kind, name, _, value)| (*kind, *name, *value))
+            .collect::<Vec<_>>();
+        // the installation's token is only minted for the first lookup
+        let (minted, reused) = lookups.split_first().unwrap();
+        assert_eq!(*minted, ("counter", "github_token_cache_misses_total", 1.0));
+        assert!(!reused.is_empty());
+        assert!(reused
+            .iter()
+            .all(|lookup| *lookup == ("counter", "github_token_cache_hits_total", 1.0)));
+    }
+
+    #[cfg(feature = "dangerous")]
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_disabled_signature_verification_accepts_unsigned_deliveries() {
+        let (config, _, _) = create_test_config();
+        let endpoint = WebhookEndpointConfiguration {
+            disable_signature_verification: true,
+            ..Default::default()
+        };
+        let app = super::router::<TestClient>(config, &endpoint, Default::default())
+            .await
+            .unwrap();
+        assert!(logs_contain("anyone can send events"));
+
+        let request = Request::builder()
+            .uri("/event_handler")
+            .header("X-GitHub-Event", "ping")
+            .body(Body::from(ping_body()))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(logs_contain("accepted the delivery unverified"));
+    }
+
+    #[cfg(feature = "dangerous")]
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_logged_bodies_leave_out_the_signature() {
+        let (config, _, secret) = create_test_config();
+        let endpoint = WebhookEndpointConfiguration {
+            log_bodies: true,
+            ..Default::default()
+        };
+        let app = super::router::<TestClient>(config, &endpoint, Default::default())
+            .await
+            .unwrap();
+        assert!(logs_contain("BODIES ARE LOGGED"));
+
+        let body = ping_body();
+        let body_hmac = calc_hmac_for_body(&secret, &body);
+        let response = app
+            .oneshot(signed_ping_request(format!("sha256={body_hmac}"), body))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(logs_contain("received request"));
+        assert!(logs_contain("Half measures are as bad as nothing at all."));
+        assert!(logs_contain("sent response"));
+        assert!(logs_contain("[redacted]"));
+        assert!(!logs_contain(&body_hmac));
+    }
+
+    #[cfg(not(feature = "dangerous"))]
+    #[tokio::test]
+    async fn test_body_logging_requires_the_dangerous_feature() {
+        let (config, _, _) = create_test_config();
+        let endpoint = WebhookEndpointConfiguration {
+            log_bodies: true,
+            ..Default::default()
+        };
+
+        let result = super::router::<TestClient>(config, &endpoint, Default::default()).await;
+
+        assert!(result.is_err());
+    }
+
+    #[cfg(not(feature = "dangerous"))]
+    #[tokio::test]
+    async fn test_disabled_signature_verification_requires_the_dangerous_feature() {
+        let (config, _, _) = create_test_config();
+        let endpoint = WebhookEndpointConfiguration {
+            disable_signature_verification: true,
+            ..Default::default()
+        };
+
+        let result = super::router::<TestClient>(config, &endpoint, Default::default()).await;
+
+        assert!(result.is_err());
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_response_headers_on_success_and_error() {
+        let (config, _, secret) = create_test_config();
+        let mut endpoint = WebhookEndpointConfiguration::default();
+        endpoint.response_headers.insert(
+            "x-content-type-options",
+            HeaderValue::from_static("nosniff"),
+        );
+        let app = super::router::<TestClient>(config, &endpoint, Default::default())
+            .await
+            .unwrap();
+
+        let body = ping_body();
+        let body_hmac = calc_hmac_for_body(&secret, &body);
+        let mut success = signed_ping_request(format!("sha256={body_hmac}"), body);
+        success.headers_mut().insert(
+            "x-github-delivery",
+            HeaderValue::from_static("72d3162e-cc78-11e3-81ab-4c9367dc0958"),
+        );
+        let error = Request::builder()
+            .uri("/event_handler")
+            .header("X-GitHub-Event", "ping")
+            .header("X-GitHub-Delivery", "72d3162e-cc78-11e3-81ab-4c9367dc0959")
+            .body(Body::from(ping_body()))
+            .unwrap();
+
+        let success = app.clone().oneshot(success).await.unwrap();
+        assert_eq!(success.status(), StatusCode::OK);
+        assert_eq!(success.headers()["x-content-type-options"], "nosniff");
+        assert_eq!(
+            success.headers()["x-delivery-id"],
+            "72d3162e-cc78-11e3-81ab-4c9367dc0958"
+        );
+
+        let error = app.oneshot(error).await.unwrap();
+        assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(error.headers()["x-content-type-options"], "nosniff");
+        assert_eq!(
+            error.headers()["x-delivery-id"],
+            "72d3162e-cc78-11e3-81ab-4c9367dc0959"
+        );
+    }
+
+    #[derive(Clone, Default)]
+    struct RecordCheckSuite(Arc<Mutex<Vec<String>>>);
+
+    impl EventHandler<NoOpApi> for RecordCheckSuite {
+        fn handle<'a>(&'a self, ctx: &'a EventContext<NoOpApi>) -> BoxFuture<'a, HandlerResult> {
+            Box::pin(async move {
+                let suite = ctx.check_suite().ok_or("missing check suite")?;
+                ctx.create_check_run("wild-git-yonder", &suite.head_sha)
+                    .await?;
+                self.0.lock().unwrap().push(suite.head_sha);
+                Ok(())
+            })
+        }
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_processing_time_header_is_added_only_when_enabled() {
+        for enabled in [true, false] {
+            let (config, _, secret) = create_test_config();
+            let endpoint = WebhookEndpointConfiguration {
+                processing_time_header: enabled,
+                ..Default::default()
+            };
+            let app = super::router::<TestClient>(config, &endpoint, Default::default())
+                .await
+                .unwrap();
+
+            let body = ping_body();
+            let body_hmac = calc_hmac_for_body(&secret, &body);
+            let response = app
+                .oneshot(signed_ping_request(format!("sha256={body_hmac}"), body))
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::OK);
+            let processing_time = response
+                .headers()
+                .get("x-processing-time-ms")
+                .map(|value| value.to_str().unwrap().parse::<u64>().unwrap());
+            assert_eq!(processing_time.is_some(), enabled);
+        }
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_check_suite_requested_invokes_handler() {
+        let (config, _, secret) = create_test_config();
+        let recorded = RecordCheckSuite::default();
+        let handlers = Handlers::default().on(WebhookEventType::CheckSuite, recorded.clone());
+        let app = super::router::<TestClient>(config, &Default::default(), handlers)
+            .await
+            .unwrap();
+
+        let body = json!({
+            "action": "requested",
+            "check_suite": {
+                "id": 5,
+                "head_sha": "d6fde92930d4715a2b49857d24b940956b26d2d3",
+                "head_branch": "main"
+            },
+            "repository": test_repository(),
+            "installation": { "id": 1, "node_id": "dGVzdA==" }
+        });
+        let response = app
+            .oneshot(signed_request(&secret, "check_suite", body))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            *recorded.0.lock().unwrap(),
+            ["d6fde92930d4715a2b49857d24b940956b26d2d3"]
+        );
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_check_suite_completed_is_not_dispatched() {
+        let (config, _, secret) = create_test_config();
+        let recorded = RecordCheckSuite::default();
+        let handlers = Handlers::default().on(WebhookEventType::CheckSuite, recorded.clone());
+        let app = super::router::<TestClient>(config, &Default::default(), handlers)
+            .await
+            .unwrap();
+
+        let body = json!({
+            "action": "completed",
+            "check_suite": { "id": 5, "head_sha": "d6fde92930d4715a2b49857d24b940956b26d2d3" },
+            "repository": test_repository(),
+            "installation": { "id": 1, "node_id": "dGVzdA==" }
+        });
+        let response = app
+            .oneshot(signed_request(&secret, "check_suite", body))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(recorded.0.lock().unwrap().is_empty());
+    }
+
+    /// Records whatever `extract` pulls out of the context of each handled event.
+    struct Recorder<T> {
+        recorded: Arc<Mutex<Vec<T>>>,
+        extract: fn(&EventContext<NoOpApi>) -> T,
+    }
+
+    impl<T> Recorder<T> {
+        fn new(extract: fn(&EventContext<NoOpApi>) -> T) -> (Self, Arc<Mutex<Vec<T>>>) {
+            let recorded = Arc::new(Mutex::new(Vec::new()));
+            let recorder = Self {
+                recorded: recorded.clone(),
+                extract,
+            };
+            (recorder, recorded)
+        }
+    }
+
+    impl<T: Send> EventHandler<NoOpApi> for Recorder<T> {
+        fn handle<'a>(&'a self, ctx: &'a EventContext<NoOpApi>) -> BoxFuture<'a, HandlerResult> {
+            Box::pin(async move {
+                self.recorded.lock().unwrap().push((self.extract)(ctx));
+                Ok(())
+            })
+        }
+    }
+
+    struct Deployer;
+
+    impl EventHandler<NoOpApi> for Deployer {
+        fn handle<'a>(&'a self, _: &'a EventContext<NoOpApi>) -> BoxFuture<'a, HandlerResult> {
+            Box::pin(async { Ok(()) })
+        }
+
+        fn name(&self) -> &str {
+            "deploy"
+        }
+    }
+
+    #[tokio::test]
+    async fn test_outcome_lists_every_handler_and_its_result() {
+        let (config, _, secret) = create_test_config();
+        let (recorder, _) = Recorder::new(|ctx| ctx.delivery_id().map(str::to_owned));
+        let handlers = Handlers::default()
+            .on(WebhookEventType::Ping, recorder)
+            .on(WebhookEventType::Ping, Deployer);
+        let app = super::router::<TestClient>(config, &Default::default(), handlers)
+            .await
+            .unwrap();
+
+        let body = ping_body();
+        let body_hmac = calc_hmac_for_body(&secret, &body);
+        let response = app
+            .clone()
+            .oneshot(signed_ping_request(format!("sha256={body_hmac}"), body))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let mut outcome: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(outcome["duration_ms"].is_u64());
+        outcome.as_object_mut().unwrap().remove("duration_ms");
+
+        assert_eq!(
+            outcome,
+            json!({
+                "status": "handled",
+                "installation": 1,
+                "handlers": [
+                    { "name": "Recorder", "result": "succeeded" },
+                    { "name": "deploy", "result": "succeeded" }
+                ],
+                "response": "Half measures are as bad as nothing at all."
+            })
+        );
+    }
+
+    /// Tracks the running and the most ever running handlers per installation.
+    #[derive(Clone, Default)]
+    struct Occupancy {
+        running: Arc<Mutex<HashMap<u64, (usize, usize)>>>,
+    }
+
+    impl EventHandler<NoOpApi> for Occupancy {
+        fn handle<'a>(&'a self, ctx: &'a EventContext<NoOpApi>) -> BoxFuture<'a, HandlerResult> {
+            Box::pin(async move {
+                let installation = ctx.installation_id().unwrap().0;
+                {
+                    let mut running = self.running.lock().unwrap();
+                    let (current, max) = running.entry(installation).or_default();
+                    *current += 1;
+                    *max = (*max).max(*current);
+                }
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                self.running
+                    .lock()
+                    .unwrap()
+                    .get_mut(&installation)
+                    .unwrap()
+                    .0 -= 1;
+                Ok(())
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_is_capped_per_installation() {
+        let (config, _, secret) = create_test_config();
+        let occupancy = Occupancy::default();
+        let handlers = Handlers::default().on(WebhookEventType::Ping, occupancy.clone());
+        let endpoint = WebhookEndpointConfiguration {
+            installation_concurrency: Some(2),
+            ..Default::default()
+        };
+        let app = super::router::<TestClient>(config, &endpoint, handlers)
+            .await
+            .unwrap();
+        let ping = |installation: u64| {
+            let body = json!({
+                "zen": "Design for failure.",
+                "installation": { "id": installation, "node_id": "dGVzdA==" }
+            });
+            let request = signed_request(&secret, "ping", body);
+            let app = app.clone();
+            tokio::spawn(async move {
+                let started = std::time::Instant::now();
+                let response = app.oneshot(request).await.unwrap();
+                (response.status(), started.elapsed())
+            })
+        };
+
+        let bursting: Vec<_> = (0..6).map(|_| ping(1)).collect();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let (status, elapsed) = ping(2).await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert!(elapsed < Duration::from_millis(400), "{elapsed:?}");
+        for burst in bursting {
+            assert_eq!(burst.await.unwrap().0, StatusCode::OK);
+        }
+
+        let running = occupancy.running.lock().unwrap();
+        assert_eq!(running[&1], (0, 2));
+        assert_eq!(running[&2], (0, 1));
+    }
+
+    #[tokio::test]
+    async fn test_outcome_of_unhandled_event_is_skipped() {
+        let (config, _, secret) = create_test_config();
+        let app = super::router::<TestClient>(config, &Default::default(), Default::default())
+            .await
+            .unwrap();
+
+        let body = json!({
+            "ref": "feature/anvil",
+            "ref_type": "branch",
+            "master_branch": "main",
+            "description": null,
+            "pusher_type": "user",
+            "repository": test_repository(),
+            "installation": { "id": 1, "node_id": "dGVzdA==" }
+        });
+        let request = signed_request(&secret, "create", body);
+        let response = app.oneshot(request).await.unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let outcome: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(outcome["status"], "skipped");
+        assert_eq!(outcome["handlers"], json!([]));
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_pull_request_review_submitted() {
+        let (config, _, secret) = create_test_config();
+        let (recorder, recorded) =
+            Recorder::new(|ctx| (ctx.review_state(), ctx.pull_request_number()));
+        let handlers = Handlers::default().on(WebhookEventType::PullRequestReview, recorder);
+        let app = super::router::<TestClient>(config, &Default::default(), handlers)
+            .await
+            .unwrap();
+
+        let body = json!({
+            "action": "submitted",
+            "review": {
+                "id": 80,
+                "node_id": "MDE3OlB1bGxSZXF1ZXN0UmV2aWV3ODA=",
+                "html_url": "https://github.local/acme/wild-git-yonder/pull/8#pullrequestreview-80",
+                "user": null,
+                "body": "looks good",
+                "state": "approved"
+            },
+            "pull_request": test_pull_request(8),
+            "repository": test_repository(),
+            "installation": { "id": 1, "node_id": "dGVzdA==" }
+        });
+        let response = app
+            .oneshot(signed_request(&secret, "pull_request_review", body))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            *recorded.lock().unwrap(),
+            [(Some(ReviewState::Approved), Some(8))]
+        );
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_workflow_run_completed() {
+        let (config, _, secret) = create_test_config();
+        let (recorder, recorded) = Recorder::new(|ctx| {
+            ctx.workflow_run()
+                .map(|run| (run.id, run.conclusion, run.logs_url))
+        });
+        let handlers = Handlers::default().on(WebhookEventType::WorkflowRun, recorder);
+        let app = super::router::<TestClient>(config, &Default::default(), handlers)
+            .await
+            .unwrap();
+
+        let body = json!({
+            "action": "completed",
+            "workflow_run": {
+                "id": 30433642,
+                "name": "Build",
+                "run_number": 562,
+                "head_sha": "acb5820ced9479c074f688cc328bf03f341a511d",
+                "head_branch": "main",
+                "status": "completed",
+                "conclusion": "failure",
+                "html_url": "https://github.local/acme/wild-git-yonder/actions/runs/30433642",
+                "logs_url": "https://api.github.local/repos/acme/wild-git-yonder/actions/runs/30433642/logs",
+                "artifacts_url": "https://api.github.local/repos/acme/wild-git-yonder/actions/runs/30433642/artifacts"
+            },
+            "repository": test_repository(),
+            "installation": { "id": 1, "node_id": "dGVzdA==" }
+        });
+        let response = app
+            .oneshot(signed_request(&secret, "workflow_run", body))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            *recorded.lock().unwrap(),
+            [Some((
+                30433642,
+                Some("failure".to_string()),
+                "https://api.github.local/repos/acme/wild-git-yonder/actions/runs/30433642/logs"
+                    .to_string()
+            ))]
+        );
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_release_published() {
+        let (config, _, secret) = create_test_config();
+        let (recorder, recorded) = Recorder::new(|ctx| {
+            ctx.release()
+                .map(|release| (release.tag_name, release.prerelease))
+        });
+        let handlers = Handlers::default().on(WebhookEventType::Release, recorder);
+        let app = super::router::<TestClient>(config, &Default::default(), handlers)
+            .await
+            .unwrap();
+
+        let body = json!({
+            "action": "published",
+            "release": {
+                "id": 1,
+                "tag_name": "v1.0.0-rc.1",
+                "name": "First release candidate",
+                "draft": false,
+                "prerelease": true,
+                "html_url": "https://github.local/acme/wild-git-yonder/releases/v1.0.0-rc.1",
+                "upload_url": "https://uploads.github.local/repos/acme/wild-git-yonder/releases/1/assets{?name,label}",
+                "assets": []
+            },
+            "repository": test_repository(),
+            "installation": { "id": 1, "node_id": "dGVzdA==" }
+        });
+        let response = app
+            .oneshot(signed_request(&secret, "release", body))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            *recorded.lock().unwrap(),
+            [Some(("v1.0.0-rc.1".to_string(), true))]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_package_published_outside_the_repository_selection() {
+        let (config, _, secret) = create_test_config();
+        let (recorder, recorded) = Recorder::new(|ctx| {
+            ctx.package().map(|package| {
+                let version = package.package_version.map(|version| version.version);
+                (package.name, package.ecosystem, version)
+            })
+        });
+        let handlers = Handlers::default().on(WebhookEventType::Package, recorder);
+        let mut endpoint = WebhookEndpointConfiguration::default();
+        endpoint.handling.reject_uncovered_repositories = true;
+        let app = super::router::<TestClient>(config, &endpoint, handlers)
+            .await
+            .unwrap();
+
+        let body = json!({
+            "action": "published",
+            "package": {
+                "id": 12,
+                "name": "anvil",
+                "namespace": "acme",
+                "ecosystem": "CONTAINER",
+                "package_type": "CONTAINER",
+                "package_version": { "id": 34, "version": "sha256:3c9f0a" }
+            },
+            "repository": {
+                "id": 2,
+                "name": "roadrunner",
+                "full_name": "coyote/roadrunner",
+                "url": "https://api.github.local/repos/coyote/roadrunner"
+            },
+            "installation": { "id": 1, "node_id": "dGVzdA==" }
+        });
+        let response = app
+            .oneshot(signed_request(&secret, "package", body))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            *recorded.lock().unwrap(),
+            [Some((
+                "anvil".to_string(),
+                "CONTAINER".to_string(),
+                Some("sha256:3c9f0a".to_string())
+            ))]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_star_created_exposes_sender_and_repository() {
+        let (config, _, secret) = create_test_config();
+        let (recorder, recorded) = Recorder::new(|ctx| {
+            let action = ctx.star().map(|star| star.action.clone());
+            let sender = ctx.sender().map(|sender| sender.login.clone());
+            let repository = ctx
+                .event()
+                .repository
+                .as_ref()
+                .map(|repo| repo.name.clone());
+            (action, sender, repository)
+        });
+        let handlers = Handlers::default().on(WebhookEventType::Star, recorder);
+        let app = super::router::<TestClient>(config, &Default::default(), handlers)
+            .await
+            .unwrap();
+
+        let url = "https://api.github.local/users/wile";
+        let body = json!({
+            "action": "created",
+            "starred_at": "2026-10-14T08:00:00Z",
+            "repository": test_repository(),
+            "sender": {
+                "login": "wile", "id": 7, "node_id": "dGVzdA==", "gravatar_id": "",
+                "avatar_url": url, "url": url, "html_url": url, "followers_url": url,
+                "following_url": url, "gists_url": url, "starred_url": url,
+                "subscriptions_url": url, "organizations_url": url, "repos_url": url,
+                "events_url": url, "received_events_url": url,
+                "type": "User", "site_admin": false
+            },
+            "installation": { "id": 1, "node_id": "dGVzdA==" }
+        });
+        let response = app
+            .oneshot(signed_request(&secret, "star", body))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            *recorded.lock().unwrap(),
+            [(
+                Some(StarWebhookEventAction::Created),
+                Some("wile".to_string()),
+                Some("wild-git-yonder".to_string())
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_app_authorization_reaches_the_handlers_without_an_installation() {
+        let (config, _, secret) = create_test_config();
+        let (recorder, recorded) = Recorder::new(|ctx| {
+            let sender = ctx
+                .event()
+                .sender
+                .as_ref()
+                .map(|sender| sender.login.clone());
+            (sender, ctx.account_login().map(str::to_owned))
+        });
+        let handlers = Handlers::default().on(WebhookEventType::GithubAppAuthorization, recorder);
+        let app = super::router::<TestClient>(config, &Default::default(), handlers)
+            .await
+            .unwrap();
+
+        let url = "https://api.github.local/users/wile";
+        let body = json!({
+            "action": "revoked",
+            "sender": {
+                "login": "wile", "id": 7, "node_id": "dGVzdA==", "gravatar_id": "",
+                "avatar_url": url, "url": url, "html_url": url, "followers_url": url,
+                "following_url": url, "gists_url": url, "starred_url": url,
+                "subscriptions_url": url, "organizations_url": url, "repos_url": url,
+                "events_url": url, "received_events_url": url,
+                "type": "User", "site_admin": false
+            }
+        });
+        let response = app
+            .oneshot(signed_request(&secret, "github_app_authorization", body))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            *recorded.lock().unwrap(),
+            [(Some("wile".to_string()), None)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_branch_protection_rule_edited_exposes_the_rule() {
+        let (config, _, secret) = create_test_config();
+        let (recorder, recorded) = Recorder::new(|ctx| {
+            ctx.branch_protection_rule().map(|event| {
+                let changed = event
+                    .changes
+                    .unwrap_or_default()
+                    .into_keys()
+                    .collect::<Vec<_>>();
+                (
+                    event.action,
+                    event.rule.name,
+                    event.rule.admin_enforced,
+                    changed,
+                )
+            })
+        });
+        let handlers = Handlers::default().on(WebhookEventType::BranchProtectionRule, recorder);
+        let app = super::router::<TestClient>(config, &Default::default(), handlers)
+            .await
+            .unwrap();
+
+        // as sent by GitHub, which octocrab's payload doesn't accept
+        let body = json!({
+            "action": "edited",
+            "rule": {
+                "id": 21, "repository_id": 1, "name": "main",
+                "created_at": "2026-10-01T08:00:00Z", "updated_at": "2026-10-14T08:00:00Z",
+                "admin_enforced": true,
+                "pull_request_reviews_enforcement_level": "everyone",
+                "required_status_checks_enforcement_level": "non_admins",
+                "required_status_checks": ["ci"]
+            },
+            "changes": { "admin_enforced": { "from": false } },
+            "repository": test_repository(),
+            "installation": { "id": 1, "node_id": "dGVzdA==" }
+        });
+        let response = app
+            .oneshot(signed_request(&secret, "branch_protection_rule", body))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            *recorded.lock().unwrap(),
+            [Some((
+                "edited".to_string(),
+                "main".to_string(),
+                Some(true),
+                vec!["admin_enforced".to_string()]
+            ))]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_code_scanning_alert_created_exposes_the_alert() {
+        let (config, _, secret) = create_test_config();
+        let (recorder, recorded) = Recorder::new(|ctx| {
+            ctx.code_scanning_alert()
+                .map(|event| (event.action, event.alert.number, event.alert.rule.id))
+        });
+        let handlers = Handlers::default().on(WebhookEventType::CodeScanningAlert, recorder);
+        let app = super::router::<TestClient>(config, &Default::default(), handlers)
+            .await
+            .unwrap();
+
+        let url = "https://api.github.local/repos/acme/wild-git-yonder/code-scanning/alerts/3";
+        let body = json!({
+            "action": "created",
+            "alert": {
+                "number": 3, "created_at": "2026-10-14T08:00:00Z", "url": url, "html_url": url,
+                "instances_url": url, "state": "open",
+                "rule": {
+                    "id": "js/sql-injection", "name": "js/sql-injection", "severity": "error",
+                    "description": "Database query built from user-controlled sources"
+                },
+                "tool": { "name": "CodeQL", "version": "2.19.0" }
+            },
+            "ref": "refs/heads/main",
+            "commit_oid": "d6fde92930d4715a2b49857d24b940956b26d2d3",
+            "repository": test_repository(),
+            "installation": { "id": 1, "node_id": "dGVzdA==" }
+        });
+        let response = app
+            .oneshot(signed_request(&secret, "code_scanning_alert", body))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            *recorded.lock().unwrap(),
+            [Some((
+                "created".to_string(),
+                3,
+                Some("js/sql-injection".to_string())
+            ))]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_repository_ruleset_exposes_the_enforcement() {
+        let (config, _, secret) = create_test_config();
+        let (recorder, recorded) = Recorder::new(|ctx| {
+            ctx.repository_ruleset().map(|event| {
+                (
+                    event.repository_ruleset.name,
+                    event.repository_ruleset.enforcement,
+                )
+            })
+        });
+        let handlers = Handlers::default().on(
+            WebhookEventType::Unknown(REPOSITORY_RULESET.into()),
+            recorder,
+        );
+        let app = super::router::<TestClient>(config, &Default::default(), handlers)
+            .await
+            .unwrap();
+
+        let body = json!({
+            "action": "created",
+            "repository_ruleset": {
+                "id": 5, "name": "protect-tags", "target": "tag", "source_type": "Repository",
+                "source": "Eastwooder/wild-git-yonder", "enforcement": "active"
+            },
+            "repository": test_repository(),
+            "installation": { "id": 1, "node_id": "dGVzdA==" }
+        });
+        let response = app
+            .oneshot(signed_request(&secret, REPOSITORY_RULESET, body))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            *recorded.lock().unwrap(),
+            [Some(("protect-tags".to_string(), "active".to_string()))]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handler_reads_a_custom_delivery_header() {
+        let (config, _, secret) = create_test_config();
+        let (recorder, recorded) = Recorder::new(|ctx| {
+            ctx.headers()
+                .get("x-route-to")
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_owned)
+        });
+        let handlers = Handlers::default().on(WebhookEventType::Push, recorder);
+        let app = super::router::<TestClient>(config, &Default::default(), handlers)
+            .await
+            .unwrap();
+        let mut request = signed_request(&secret, "push", push_body());
+        request
+            .headers_mut()
+            .insert("x-route-to", HeaderValue::from_static("team-roadrunner"));
+
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            *recorded.lock().unwrap(),
+            [Some("team-roadrunner".to_string())]
+        );
+    }
+
+    async fn handled_pushes(process_visibility: Visibility, private: bool) -> usize {
+        let (config, _, secret) = create_test_config();
+        let (recorder, recorded) = Recorder::new(|_| ());
+        let handlers = Handlers::default().on(WebhookEventType::Push, recorder);
+        let endpoint = WebhookEndpointConfiguration {
+            handling: HandleOptions {
+                process_visibility,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let app = super::router::<TestClient>(config, &endpoint, handlers)
+            .await
+            .unwrap();
+        let mut body = push_body();
+        body["repository"]["private"] = json!(private);
+
+        let response = app
+            .oneshot(signed_request(&secret, "push", body))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let handled = recorded.lock().unwrap().len();
+        handled
+    }
+
+    #[tokio::test]
+    async fn test_public_repository_is_skipped_when_only_private_ones_are_processed() {
+        assert_eq!(handled_pushes(Visibility::Private, false).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_repository_of_the_processed_visibility_is_handled() {
+        assert_eq!(handled_pushes(Visibility::Private, true).await, 1);
+        assert_eq!(handled_pushes(Visibility::Public, false).await, 1);
+        assert_eq!(handled_pushes(Visibility::Both, false).await, 1);
+    }
+
+    async fn handled_pushes_of_installations(allowed: &[u64]) -> usize {
+        let (config, _, secret) = create_test_config();
+        let (recorder, recorded) = Recorder::new(|_| ());
+        let handlers = Handlers::default().on(WebhookEventType::Push, recorder);
+        let endpoint = WebhookEndpointConfiguration {
+            handling: HandleOptions {
+                allowed_installations: Some(
+                    allowed
+                        .iter()
+                        .copied()
+                        .map(octocrab::models::InstallationId)
+                        .collect(),
+                ),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let app = super::router::<TestClient>(config, &endpoint, handlers)
+            .await
+            .unwrap();
+
+        let response = app
+            .oneshot(signed_request(&secret, "push", push_body()))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let handled = recorded.lock().unwrap().len();
+        handled
+    }
+
+    #[tokio::test]
+    async fn test_event_of_an_allowed_installation_is_handled() {
+        assert_eq!(handled_pushes_of_installations(&[1, 2]).await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_event_of_a_disallowed_installation_is_skipped() {
+        assert_eq!(handled_pushes_of_installations(&[2]).await, 0);
+    }
+
+    async fn handle_ref_event(event: &str, body: serde_json::Value) -> Vec<(String, RefType)> {
+        let (config, _, secret) = create_test_config();
+        let (recorder, recorded) = Recorder::new(|ctx| {
+            (
+                ctx.ref_name().unwrap().to_string(),
+                ctx.ref_type().unwrap().clone(),
+            )
+        });
+        let kind = serde_json::from_value(json!(event)).unwrap();
+        let handlers = Handlers::default().on(kind, recorder);
+        let app = super::router::<TestClient>(config, &Default::default(), handlers)
+            .await
+            .unwrap();
+
+        let response = app
+            .oneshot(signed_request(&secret, event, body))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let recorded = recorded.lock().unwrap().clone();
+        recorded
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_create_branch() {
+        let recorded = handle_ref_event(
+            "create",
+            json!({
+                "ref": "feature/anvil",
+                "ref_type": "branch",
+                "master_branch": "main",
+                "description": null,
+                "pusher_type": "user",
+                "repository": test_repository(),
+                "installation": { "id": 1, "node_id": "dGVzdA==" }
+            }),
+        )
+        .await;
+        assert_eq!(recorded, [("feature/anvil".to_string(), RefType::Branch)]);
+    }
+
+    async fn create_branch_in(repository: serde_json::Value) -> (StatusCode, Vec<bool>) {
+        let (config, _, secret) = create_test_config();
+        let (recorder, recorded) = Recorder::new(|ctx| ctx.installation_covers_repo());
+        let handlers = Handlers::default().on(WebhookEventType::Create, recorder);
+        let mut endpoint = WebhookEndpointConfiguration::default();
+        endpoint.handling.reject_uncovered_repositories = true;
+        let app = super::router::<TestClient>(config, &endpoint, handlers)
+            .await
+            .unwrap();
+
+        let body = json!({
+            "ref": "feature/anvil",
+            "ref_type": "branch",
+            "master_branch": "main",
+            "description": null,
+            "pusher_type": "user",
+            "repository": repository,
+            "installation": { "id": 1, "node_id": "dGVzdA==" }
+        });
+        let response = app
+            .oneshot(signed_request(&secret, "create", body))
+            .await
+            .unwrap();
+        let recorded = recorded.lock().unwrap().clone();
+        (response.status(), recorded)
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_event_of_a_covered_repository_is_handled() {
+        let (status, recorded) = create_branch_in(test_repository()).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(recorded, [true]);
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_event_of_an_uncovered_repository_is_rejected() {
+        let (status, recorded) = create_branch_in(json!({
+            "id": 2,
+            "name": "roadrunner",
+            "full_name": "coyote/roadrunner",
+            "url": "https://api.github.local/repos/coyote/roadrunner"
+        }))
+        .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(recorded.is_empty());
+        assert!(logs_contain("coyote/roadrunner"));
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_organization_event_without_repository_is_handled() {
+        let (config, _, secret) = create_test_config();
+        let (recorder, recorded) = Recorder::new(|ctx| ctx.organization_login().map(str::to_owned));
+        let handlers = Handlers::default().on(WebhookEventType::Membership, recorder);
+        let mut endpoint = WebhookEndpointConfiguration::default();
+        endpoint.handling.reject_uncovered_repositories = true;
+        let app = super::router::<TestClient>(config, &endpoint, handlers)
+            .await
+            .unwrap();
+
+        let org = "https://api.github.local/orgs/acme";
+        let body = json!({
+            "action": "added",
+            "scope": "team",
+            "member": { "login": "wile", "id": 2 },
+            "team": { "name": "Anvils", "slug": "anvils", "id": 3 },
+            "organization": {
+                "login": "acme", "id": 4, "node_id": "dGVzdA==", "url": org,
+                "repos_url": org, "events_url": org, "hooks_url": org, "issues_url": org,
+                "members_url": org, "public_members_url": org, "avatar_url": org
+            },
+            "installation": { "id": 1, "node_id": "dGVzdA==" }
+        });
+        let response = app
+            .oneshot(signed_request(&secret, "membership", body))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(*recorded.lock().unwrap(), [Some("acme".to_string())]);
+    }
+
+    struct RepositoryGate;
+
+    impl Gate<NoOpApi> for RepositoryGate {
+        fn allow<'a>(&'a self, ctx: &'a EventContext<NoOpApi>) -> BoxFuture<'a, GateDecision> {
+            Box::pin(async move {
+                match ctx.event().repository {
+                    Some(ref repository) if repository.name == "roadrunner" => {
+                        GateDecision::Reject {
+                            status: StatusCode::FORBIDDEN,
+                            reason: "roadrunner is frozen".to_string(),
+                        }
+                    }
+                    _ => GateDecision::Allow,
+                }
+            })
+        }
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_gate_rejects_a_repository_and_allows_others() {
+        let (config, _, secret) = create_test_config();
+        let (recorder, recorded) = Recorder::new(|ctx| ctx.ref_name().unwrap().to_string());
+        let handlers = Handlers::default()
+            .on(WebhookEventType::Create, recorder)
+            .gate(RepositoryGate);
+        let app = super::router::<TestClient>(config, &Default::default(), handlers)
+            .await
+            .unwrap();
+        let create_in = |repository: serde_json::Value| {
+            json!({
+                "ref": "feature/anvil",
+                "ref_type": "branch",
+                "master_branch": "main",
+                "description": null,
+                "pusher_type": "user",
+                "repository": repository,
+                "installation": { "id": 1, "node_id": "dGVzdA==" }
+            })
+        };
+
+        let rejected = app
+            .clone()
+            .oneshot(signed_request(
+                &secret,
+                "create",
+                create_in(json!({
+                    "id": 2,
+                    "name": "roadrunner",
+                    "url": "https://api.github.local/repos/acme/roadrunner"
+                })),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(rejected.status(), StatusCode::FORBIDDEN);
+        let reason = rejected.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(reason, "roadrunner is frozen");
+        assert!(recorded.lock().unwrap().is_empty());
+
+        let allowed = app
+            .oneshot(signed_request(
+                &secret,
+                "create",
+                create_in(test_repository()),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(allowed.status(), StatusCode::OK);
+        assert_eq!(*recorded.lock().unwrap(), ["feature/anvil"]);
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_delete_tag() {
+        let recorded = handle_ref_event(
+            "delete",
+            json!({
+                "ref": "v1.0.0",
+                "ref_type": "tag",
+                "pusher_type": "user",
+                "repository": test_repository(),
+                "installation": { "id": 1, "node_id": "dGVzdA==" }
+            }),
+        )
+        .await;
+        assert_eq!(recorded, [("v1.0.0".to_string(), RefType::Tag)]);
+    }
+
+    async fn ping_installation(installation: serde_json::Value) -> (StatusCode, Vec<Option<u64>>) {
+        let (config, _, secret) = create_test_config();
+        let (recorder, recorded) = Recorder::new(|ctx| ctx.installation_id().map(|id| id.0));
+        let handlers = Handlers::default().on(WebhookEventType::Ping, recorder);
+        let app = super::router::<TestClient>(config, &Default::default(), handlers)
+            .await
+            .unwrap();
+
+        let body = json!({
+            "zen": "Keep it logically awesome.",
+            "installation": installation
+        });
+        let response = app
+            .oneshot(signed_request(&secret, "ping", body))
+            .await
+            .unwrap();
+        let recorded = recorded.lock().unwrap().clone();
+        (response.status(), recorded)
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_full_installation_id() {
+        let account = json!({
+            "login": "acme",
+            "id": 2,
+            "node_id": "MDEyOk9yZ2FuaXphdGlvbjI=",
+            "avatar_url": "https://github.local/images/acme.gif",
+            "gravatar_id": "",
+            "url": "https://api.github.local/users/acme",
+            "html_url": "https://github.local/acme",
+            "followers_url": "https://api.github.local/users/acme/followers",
+            "following_url": "https://api.github.local/users/acme/following",
+            "gists_url": "https://api.github.local/users/acme/gists",
+            "starred_url": "https://api.github.local/users/acme/starred",
+            "subscriptions_url": "https://api.github.local/users/acme/subscriptions",
+            "organizations_url": "https://api.github.local/users/acme/orgs",
+            "repos_url": "https://api.github.local/users/acme/repos",
+            "events_url": "https://api.github.local/users/acme/events",
+            "received_events_url": "https://api.github.local/users/acme/received_events",
+            "type": "Organization",
+            "site_admin": false
+        });
+        let (status, recorded) = ping_installation(json!({
+            "id": 5,
+            "account": account,
+            "permissions": {},
+            "events": ["ping"]
+        }))
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(recorded, [Some(5)]);
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_minimal_installation_id() {
+        let (status, recorded) = ping_installation(json!({ "id": 5, "node_id": "dGVzdA==" })).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(recorded, [Some(5)]);
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_zero_installation_id_is_skipped() {
+        let (status, recorded) = ping_installation(json!({ "id": 0, "node_id": "dGVzdA==" })).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(recorded.is_empty());
+    }
+
+    async fn handle_pull_requests(skip_drafts: bool, drafts: &[bool]) -> Vec<Option<bool>> {
+        let (config, _, secret) = create_test_config();
+        let (recorder, recorded) = Recorder::new(|ctx| ctx.is_draft_pull_request());
+        let handlers = Handlers::default().on(WebhookEventType::PullRequest, recorder);
+        let mut endpoint = WebhookEndpointConfiguration::default();
+        endpoint.handling.skip_draft_pull_requests = skip_drafts;
+        let app = super::router::<TestClient>(config, &endpoint, handlers)
+            .await
+            .unwrap();
+
+        for (number, draft) in drafts.iter().enumerate() {
+            let mut pull_request = test_pull_request(number as u64);
+            pull_request["draft"] = json!(draft);
+            let body = json!({
+                "action": "opened",
+                "number": number,
+                "pull_request": pull_request,
+                "repository": test_repository(),
+                "installation": { "id": 1, "node_id": "dGVzdA==" }
+            });
+            let response = app
+                .clone()
+                .oneshot(signed_request(&secret, "pull_request", body))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let recorded = recorded.lock().unwrap().clone();
+        recorded
+    }
+
+    async fn handle_pull_request_action(action: &str) -> (serde_json::Value, Vec<Option<u64>>) {
+        let (config, _, secret) = create_test_config();
+        let (recorder, recorded) = Recorder::new(|ctx| ctx.pull_request_number());
+        let handlers = Handlers::default().on(WebhookEventType::PullRequest, recorder);
+        let mut endpoint = WebhookEndpointConfiguration::default();
+        endpoint.handling.allowed_actions = Some(HashMap::from([(
+            "pull_request".to_string(),
+            HashSet::from(["opened".to_string(), "reopened".to_string()]),
+        )]));
+        let app = super::router::<TestClient>(config, &endpoint, handlers)
+            .await
+            .unwrap();
+
+        let body = json!({
+            "action": action,
+            "number": 7,
+            "pull_request": test_pull_request(7),
+            "repository": test_repository(),
+            "installation": { "id": 1, "node_id": "dGVzdA==" }
+        });
+        let response = app
+            .oneshot(signed_request(&secret, "pull_request", body))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let recorded = recorded.lock().unwrap().clone();
+        (serde_json::from_slice(&body).unwrap(), recorded)
+    }
+
+    #[tokio::test]
+    async fn test_allowed_action_is_dispatched() {
+        let (outcome, recorded) = handle_pull_request_action("reopened").await;
+
+        assert_eq!(outcome["status"], "handled");
+        assert_eq!(recorded, [Some(7)]);
+    }
+
+    #[tokio::test]
+    async fn test_disallowed_action_is_acknowledged_and_skipped() {
+        let (outcome, recorded) = handle_pull_request_action("synchronize").await;
+
+        assert_eq!(outcome["status"], "skipped");
+        assert!(recorded.is_empty());
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_draft_pull_request_is_skipped() {
+        assert!(handle_pull_requests(true, &[true]).await.is_empty());
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_ready_pull_request_is_processed() {
+        assert_eq!(handle_pull_requests(true, &[false]).await, [Some(false)]);
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_draft_pull_request_is_processed_when_not_skipping() {
+        assert_eq!(
+            handle_pull_requests(false, &[true, false]).await,
+            [Some(true), Some(false)]
+        );
+    }
+
+    struct Panicking;
+
+    impl EventHandler<NoOpApi> for Panicking {
+        fn handle<'a>(&'a self, _: &'a EventContext<NoOpApi>) -> BoxFuture<'a, HandlerResult> {
+            Box::pin(async move { panic!("handler blew up") })
+        }
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_panicking_handler_is_reported_as_failed() {
+        let (config, _, secret) = create_test_config();
+        let handlers = Handlers::default().on(WebhookEventType::Ping, Panicking);
+        let app = super::router::<TestClient>(config, &Default::default(), handlers)
+            .await
+            .unwrap();
+
+        let body = ping_body();
+        let body_hmac = calc_hmac_for_body(&secret, &body);
+        let mut request = signed_ping_request(format!("sha256={body_hmac}"), body);
+        request.headers_mut().insert(
+            "x-github-delivery",
+            HeaderValue::from_static("72d3162e-cc78-11e3-81ab-4c9367dc0958"),
+        );
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(
+            body,
+            "partially handled event: Ping, 1 of 1 handlers failed"
+        );
+        assert!(logs_contain("Handler panicked"));
+        assert!(logs_contain("handler blew up"));
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_debug_error_response_names_the_event_and_delivery() {
+        let (config, _, secret) = create_test_config();
+        let endpoint = WebhookEndpointConfiguration {
+            debug_error_responses: true,
+            ..Default::default()
+        };
+        let handlers = Handlers::default().on(WebhookEventType::PullRequest, Failing);
+        let app = super::router::<TestClient>(config, &endpoint, handlers)
+            .await
+            .unwrap();
+
+        let mut request = signed_request(
+            &secret,
+            "pull_request",
+            json!({
+                "action": "opened",
+                "number": 7,
+                "pull_request": test_pull_request(7),
+                "repository": test_repository(),
+                "installation": { "id": 1, "node_id": "dGVzdA==" }
+            }),
+        );
+        request.headers_mut().insert(
+            "x-github-delivery",
+            HeaderValue::from_static("72d3162e-cc78-11e3-81ab-4c9367dc0958"),
+        );
+        let signature = request.headers()["x-hub-signature-256"].clone();
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["event"], "pull_request");
+        assert_eq!(body["action"], "opened");
+        assert_eq!(body["delivery"], "72d3162e-cc78-11e3-81ab-4c9367dc0958");
+        assert_eq!(
+            body["error"],
+            "partially handled event: PullRequest, 1 of 1 handlers failed"
+        );
+        assert!(!body["causes"].as_array().unwrap().is_empty());
+        assert!(!body.to_string().contains(signature.to_str().unwrap()));
+    }
+
+    struct Failing;
+
+    impl EventHandler<NoOpApi> for Failing {
+        fn handle<'a>(&'a self, _: &'a EventContext<NoOpApi>) -> BoxFuture<'a, HandlerResult> {
+            Box::pin(async move { Err("the anvil \u{1b}[31mmissed\u{1b}[0m ```".into()) })
+        }
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_handler_failure_is_reported_as_failed_check_run() {
+        let (config, _, secret) = create_test_config();
+        let endpoint = WebhookEndpointConfiguration {
+            handling: HandleOptions {
+                failure_check_run: Some("wild-git-yonder/failures".into()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let handlers = Handlers::default().on(WebhookEventType::PullRequest, Failing);
+        let app = super::router::<TestClient>(config, &endpoint, handlers)
+            .await
+            .unwrap();
+
+        let body = json!({
+            "action": "opened",
+            "number": 7,
+            "pull_request": test_pull_request(7),
+            "repository": test_repository(),
+            "installation": { "id": 1, "node_id": "dGVzdA==" }
+        });
+        let created = Arc::new(Mutex::new(Vec::new()));
+        let response = FAILED_CHECK_RUNS
+            .scope(
+                created.clone(),
+                app.oneshot(signed_request(&secret, "pull_request", body)),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(
+            *created.lock().unwrap(),
+            [(
+                "wild-git-yonder/failures".to_string(),
+                "d6fde92930d4715a2b49857d24b940956b26d2d3".to_string(),
+                "```\nHandler returned an error: the anvil [31mmissed[0m '''\n```".to_string(),
+            )]
+        );
+    }
+
+    #[derive(Debug)]
+    struct RecordingForwarder(tokio::sync::mpsc::UnboundedSender<DeliverySummary>);
+
+    impl DeliveryForwarder for RecordingForwarder {
+        fn forward(&self, summary: DeliverySummary) -> BoxFuture<'static, ()> {
+            let _ = self.0.send(summary);
+            Box::pin(async {})
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fan_out_mirrors_the_delivery_re_signed() {
+        let (mirrored, mut received) = tokio::sync::mpsc::unbounded_channel();
+        let receiver = axum::Router::new().route(
+            "/mirror",
+            axum::routing::post(
+                move |headers: axum::http::HeaderMap, body: bytes::Bytes| async move {
+                    let _ = mirrored.send((headers, body));
+                    StatusCode::NO_CONTENT
+                },
+            ),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, receiver).await });
+        let unreachable = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap()
+        };
+        let fan_out_secret = SecretKey::from_slice(b"shared with the mirror").unwrap();
+        let expected_signature = format!(
+            "sha256={}",
+            calc_hmac_for_body(&fan_out_secret, &ping_body())
+        );
+        let (config, _, secret) = create_test_config();
+        let endpoint = WebhookEndpointConfiguration {
+            fan_out_urls: vec![
+                format!("http://{unreachable}/mirror").parse().unwrap(),
+                format!("http://{addr}/mirror").parse().unwrap(),
+            ],
+            fan_out_secret: Some(Arc::new(fan_out_secret)),
+            ..Default::default()
+        };
+        let app = super::router::<TestClient>(config, &endpoint, Default::default())
+            .await
+            .unwrap();
+
+        let body = ping_body();
+        let body_hmac = calc_hmac_for_body(&secret, &body);
+        let mut request = signed_ping_request(format!("sha256={body_hmac}"), body.clone());
+        request.headers_mut().insert(
+            "x-github-delivery",
+            HeaderValue::from_static("72d3162e-cc78-11e3-81ab-4c9367dc0958"),
+        );
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let (headers, mirrored_body) = received.recv().await.unwrap();
+        assert_eq!(mirrored_body, body);
+        assert_eq!(headers["x-hub-signature-256"], expected_signature);
+        assert_eq!(headers["x-github-event"], "ping");
+        assert_eq!(
+            headers["x-github-delivery"],
+            "72d3162e-cc78-11e3-81ab-4c9367dc0958"
+        );
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_forwarder_receives_summary() {
+        let (config, _, secret) = create_test_config();
+        let (sender, mut summaries) = tokio::sync::mpsc::unbounded_channel();
+        let endpoint = WebhookEndpointConfiguration {
+            forwarder: Some(Arc::new(RecordingForwarder(sender))),
+            ..Default::default()
+        };
+        let app = super::router::<TestClient>(config, &endpoint, Default::default())
+            .await
+            .unwrap();
+
+        let mut request = signed_request(
+            &secret,
+            "pull_request",
+            json!({
+                "action": "opened",
+                "number": 7,
+                "pull_request": test_pull_request(7),
+                "repository": test_repository(),
+                "installation": { "id": 1, "node_id": "dGVzdA==" }
+            }),
+        );
+        request.headers_mut().insert(
+            "x-github-delivery",
+            HeaderValue::from_static("72d3162e-cc78-11e3-81ab-4c9367dc0958"),
+        );
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let summary = summaries.recv().await.unwrap();
+        assert_eq!(
+            summary,
+            DeliverySummary {
+                delivery: Some("72d3162e-cc78-11e3-81ab-4c9367dc0958".to_string()),
+                event: "pull_request".to_string(),
+                installation: Some(1),
+                repository: Some("wild-git-yonder".to_string()),
+                client_ip: None,
+                status: 200,
+                headers: Default::default(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delivery_log_records_a_line_per_delivery() {
+        let (config, _, secret) = create_test_config();
+        let path = std::env::temp_dir().join(format!("deliveries-{}.ndjson", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let endpoint = WebhookEndpointConfiguration {
+            delivery_log: Some(Arc::new(
+                NdjsonDeliveryLog::open(&path, Default::default()).unwrap(),
+            )),
+            ..Default::default()
+        };
+        let app = super::router::<TestClient>(config, &endpoint, Default::default())
+            .await
+            .unwrap();
+
+        let mut log = String::new();
+        for (n, delivery) in ["delivery-1", "delivery-2"].into_iter().enumerate() {
+            let mut request = signed_request(&secret, "push", push_body());
+            request
+                .headers_mut()
+                .insert("x-github-delivery", HeaderValue::from_static(delivery));
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            // the lines are written after responding, in the background
+            for _ in 0..100 {
+                log = std::fs::read_to_string(&path).unwrap();
+                if log.lines().count() > n {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+        let lines = log
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["delivery"], "delivery-1");
+        assert_eq!(lines[1]["delivery"], "delivery-2");
+        for line in &lines {
+            assert_eq!(line["event"], "push");
+            assert_eq!(line["status"], 200);
+            assert!(line["recorded_at"].is_u64());
+            assert!(line.get("commits").is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delivery_log_rotates_at_the_size_threshold() {
+        let (config, _, secret) = create_test_config();
+        let dir = std::env::temp_dir().join(format!("delivery-log-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("deliveries.ndjson");
+        // any line fills the file, the second one rotates it
+        let rotation = Rotation {
+            max_bytes: Some(1),
+            max_age: None,
+        };
+        let recorded_at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let clock = Arc::new(ManualClock::at(recorded_at));
+        let endpoint = WebhookEndpointConfiguration {
+            delivery_log: Some(Arc::new(
+                NdjsonDeliveryLog::open_with_clock(&path, rotation, clock).unwrap(),
+            )),
+            ..Default::default()
+        };
+        let app = super::router::<TestClient>(config, &endpoint, Default::default())
+            .await
+            .unwrap();
+        let rotated = dir.join("deliveries.ndjson.1700000000");
+        let read = |path: &std::path::Path| std::fs::read_to_string(path).unwrap_or_default();
+
+        for (n, delivery) in ["delivery-1", "delivery-2"].into_iter().enumerate() {
+            let mut request = signed_request(&secret, "push", push_body());
+            request
+                .headers_mut()
+                .insert("x-github-delivery", HeaderValue::from_static(delivery));
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            // the lines are written after responding, in the background
+            for _ in 0..100 {
+                if read(&path).lines().count() + read(&rotated).lines().count() > n {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+
+        let (rotated, current) = (read(&rotated), read(&path));
+        std::fs::remove_dir_all(&dir).unwrap();
+        let delivery = |line: &str| {
+            let line: serde_json::Value = serde_json::from_str(line).unwrap();
+            assert_eq!(line["recorded_at"], 1_700_000_000);
+            line["delivery"].as_str().unwrap().to_owned()
+        };
+        assert_eq!(
+            rotated.lines().map(delivery).collect::<Vec<_>>(),
+            ["delivery-1"]
+        );
+        assert_eq!(
+            current.lines().map(delivery).collect::<Vec<_>>(),
+            ["delivery-2"]
+        );
+    }
+
+    /// Records the topic, key and payload of every published event.
+    #[derive(Debug, Default)]
+    struct RecordingPublisher(Mutex<Vec<(String, String, Bytes)>>);
+
+    impl EventPublisher for RecordingPublisher {
+        fn publish(
+            &self,
+            topic: &str,
+            key: &str,
+            payload: Bytes,
+        ) -> BoxFuture<'_, Result<(), PublishError>> {
+            self.0
+                .lock()
+                .unwrap()
+                .push((topic.to_string(), key.to_string(), payload));
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_exclusively_published_event_skips_the_handlers() {
+        let (config, _, secret) = create_test_config();
+        let publisher = Arc::new(RecordingPublisher::default());
+        let endpoint = WebhookEndpointConfiguration {
+            publishing: Some(EventPublishing {
+                publisher: publisher.clone(),
+                topics: HashMap::from([("push".to_string(), "github.push".to_string())]),
+                exclusive: true,
+            }),
+            ..Default::default()
+        };
+        let (recorder, recorded) = Recorder::new(|_| ());
+        let handlers = Handlers::default().on(WebhookEventType::Push, recorder);
+        let app = super::router::<TestClient>(config, &endpoint, handlers)
+            .await
+            .unwrap();
+
+        let mut body = push_body();
+        body["repository"]["full_name"] = json!("acme/wild-git-yonder");
+        let request = signed_request(&secret, "push", body.clone());
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert!(recorded.lock().unwrap().is_empty());
+        let published = publisher.0.lock().unwrap();
+        let [(topic, key, payload)] = published.as_slice() else {
+            panic!("expected a single published event, got {published:?}");
+        };
+        assert_eq!(topic, "github.push");
+        assert_eq!(key, "acme/wild-git-yonder");
+        assert_eq!(*payload, serde_json::to_vec(&body).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_stalled_body_times_out_with_the_configured_status() {
+        let (config, _, secret) = create_test_config();
+        let endpoint = WebhookEndpointConfiguration {
+            request_timeout: Some(Duration::from_millis(100)),
+            request_timeout_status: StatusCode::SERVICE_UNAVAILABLE,
+            ..Default::default()
+        };
+        let app = super::router::<TestClient>(config, &endpoint, Default::default())
+            .await
+            .unwrap();
+        let body = ping_body();
+        let hmac = calc_hmac_for_body(&secret, &body);
+        // sends half of the body, then stalls
+        let (head, _) = body.split_at(body.len() / 2);
+        let streamed = futures_util::StreamExt::chain(
+            futures_util::stream::once(std::future::ready(Ok::<_, std::convert::Infallible>(
+                Bytes::copy_from_slice(head),
+            ))),
+            futures_util::stream::pending(),
+        );
+        let request = Request::builder()
+            .uri("/event_handler")
+            .header("X-GitHub-Event", "ping")
+            .header("x-hub-signature-256", format!("sha256={hmac}"))
+            .body(Body::from_stream(streamed))
+            .unwrap();
+
+        let response = tokio::time::timeout(Duration::from_secs(5), app.oneshot(request))
+            .await
+            .expect("the stalled request was not timed out")
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_pre_auth_rejects_before_reading_the_body() {
+        let (config, _, secret) = create_test_config();
+        let endpoint = WebhookEndpointConfiguration {
+            pre_auth: Some(Arc::new(ProxySecretHeader::new(
+                HeaderName::from_static("x-proxy-secret"),
+                "shared with the proxy",
+            ))),
+            ..Default::default()
+        };
+        let app = super::router::<TestClient>(config, &endpoint, Default::default())
+            .await
+            .unwrap();
+        let body = ping_body();
+        let hmac = calc_hmac_for_body(&secret, &body);
+        let polled = Arc::new(AtomicBool::new(false));
+        let request = |proxy_secret: Option<&'static str>| {
+            let polled = polled.clone();
+            let body = body.clone();
+            let streamed = futures_util::stream::once(async move {
+                polled.store(true, Ordering::SeqCst);
+                Ok::<_, std::convert::Infallible>(Bytes::from(body))
+            });
+            let mut request = Request::builder()
+                .uri("/event_handler")
+                .header("X-GitHub-Event", "ping")
+                .header("x-hub-signature-256", format!("sha256={hmac}"));
+            if let Some(proxy_secret) = proxy_secret {
+                request = request.header("x-proxy-secret", proxy_secret);
+            }
+            request.body(Body::from_stream(streamed)).unwrap()
+        };
+
+        let response = app.clone().oneshot(request(None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = app.clone().oneshot(request(Some("guessed"))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(!polled.load(Ordering::SeqCst));
+
+        let response = app
+            .oneshot(request(Some("shared with the proxy")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(polled.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_handlers_do_not_see_the_credentials_of_the_delivery() {
+        let (config, _, secret) = create_test_config();
+        let (recorder, recorded) = Recorder::new(|ctx| {
+            let headers = ctx.headers();
+            [
+                "x-proxy-secret",
+                "x-hub-signature",
+                "x-hub-signature-256",
+                "x-route-to",
+            ]
+            .map(|header| headers.contains_key(header))
+        });
+        let handlers = Handlers::default().on(WebhookEventType::Push, recorder);
+        let endpoint = WebhookEndpointConfiguration {
+            pre_auth: Some(Arc::new(ProxySecretHeader::new(
+                HeaderName::from_static("x-proxy-secret"),
+                "shared with the proxy",
+            ))),
+            ..Default::default()
+        };
+        let app = super::router::<TestClient>(config, &endpoint, handlers)
+            .await
+            .unwrap();
+        let mut request = signed_request(&secret, "push", push_body());
+        let headers = request.headers_mut();
+        headers.insert(
+            "x-proxy-secret",
+            HeaderValue::from_static("shared with the proxy"),
+        );
+        headers.insert("x-hub-signature", HeaderValue::from_static("sha1=00"));
+        headers.insert("x-route-to", HeaderValue::from_static("team-roadrunner"));
+
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(*recorded.lock().unwrap(), [[false, false, false, true]]);
+    }
+
+    #[tokio::test]
+    async fn test_ack_body_template_formats_the_outcome() {
+        let (config, _, secret) = create_test_config();
+        let endpoint = WebhookEndpointConfiguration {
+            ack_body: Arc::new(
+                TemplateAck::new(
+                    r#"{"ok":"{status}","installation":{installation},"zen":"{response}"}"#,
+                )
+                .with_content_type(HeaderValue::from_static("application/json")),
+            ),
+            ..Default::default()
+        };
+        let app = super::router::<TestClient>(config, &endpoint, Default::default())
+            .await
+            .unwrap();
+
+        let body = ping_body();
+        let hmac = calc_hmac_for_body(&secret, &body);
+        let response = app
+            .oneshot(signed_ping_request(format!("sha256={hmac}"), body))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "application/json");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            json!({
+                "ok": "handled",
+                "installation": 1,
+                "zen": "Half measures are as bad as nothing at all."
+            })
+        );
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_redactor_removes_emails_from_the_logged_event() {
+        let (config, _, secret) = create_test_config();
+        let endpoint = WebhookEndpointConfiguration {
+            log_redactor: Arc::new(EmailRedactor),
+            ..Default::default()
+        };
+        let app = super::router::<TestClient>(config, &endpoint, Default::default())
+            .await
+            .unwrap();
+
+        let response = app
+            .oneshot(signed_request(&secret, "push", push_body()))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(logs_contain("received event"));
+        assert!(logs_contain("Sharpen the anvil"));
+        assert!(!logs_contain("wile@acme.local"));
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_unsubscribed_event_is_acknowledged_without_reading_the_body() {
+        let (mut config, _, _) = create_test_config();
+        config.subscriptions = vec![WebhookEventType::PullRequest];
+        let app = super::router::<TestClient>(config, &Default::default(), Default::default())
+            .await
+            .unwrap();
+        let polled = Arc::new(AtomicBool::new(false));
+        let sentinel = polled.clone();
+        let body = futures_util::stream::poll_fn(move |_| {
+            sentinel.store(true, Ordering::SeqCst);
+            std::task::Poll::Ready(None::<Result<bytes::Bytes, std::io::Error>>)
+        });
+
+        let request = Request::builder()
+            .uri("/event_handler")
+            .header("X-GitHub-Event", "push")
+            .body(Body::from_stream(body))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!polled.load(Ordering::SeqCst));
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_unsubscribed_event_is_verified_first_when_configured() {
+        let (mut config, _, secret) = create_test_config();
+        config.subscriptions = vec![WebhookEventType::PullRequest];
+        let endpoint = WebhookEndpointConfiguration {
+            verify_unsubscribed_events: true,
+            ..Default::default()
+        };
+        let app = super::router::<TestClient>(config, &endpoint, Default::default())
+            .await
+            .unwrap();
+
+        let forged = Request::builder()
+            .uri("/event_handler")
+            .header("X-GitHub-Event", "push")
+            .header(
+                "x-hub-signature-256",
+                "sha256=46288437613044114D21E7FAD79837C12336202F4C85008548FB226693426F56",
+            )
+            .body(Body::from(serde_json::to_vec(&push_body()).unwrap()))
+            .unwrap();
+        let forged = app.clone().oneshot(forged).await.unwrap();
+        let signed = app
+            .oneshot(signed_request(&secret, "push", push_body()))
+            .await
+            .unwrap();
+
+        // rejected like any delivery with a mismatching signature
+        assert_eq!(forged.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(signed.status(), StatusCode::OK);
+        let body = signed.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "not subscribed");
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_configured_headers_are_captured_in_the_summary() {
+        let (config, _, secret) = create_test_config();
+        let (sender, mut summaries) = tokio::sync::mpsc::unbounded_channel();
+        let endpoint = WebhookEndpointConfiguration {
+            forwarder: Some(Arc::new(RecordingForwarder(sender))),
+            audit_headers: vec![
+                "X-GitHub-Hook-ID".to_string(),
+                "user-agent".to_string(),
+                "X-Hub-Signature-256".to_string(),
+            ],
+            ..Default::default()
+        };
+        let app = super::router::<TestClient>(config, &endpoint, Default::default())
+            .await
+            .unwrap();
+
+        let mut request = signed_request(
+            &secret,
+            "ping",
+            json!({ "zen": "Keep it logically awesome." }),
+        );
+        request
+            .headers_mut()
+            .insert("x-github-hook-id", HeaderValue::from_static("292430182"));
+        request.headers_mut().insert(
+            "user-agent",
+            HeaderValue::from_static("GitHub-Hookshot/044aadd"),
+        );
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let summary = summaries.recv().await.unwrap();
+        assert_eq!(
+            summary.headers,
+            [
+                (
+                    "user-agent".to_string(),
+                    "GitHub-Hookshot/044aadd".to_string()
+                ),
+                ("x-github-hook-id".to_string(), "292430182".to_string()),
+            ]
+            .into()
+        );
+        assert!(!serde_json::to_string(&summary).unwrap().contains("sha256="));
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_installation_target_type_is_available_to_handlers() {
+        let (config, _, secret) = create_test_config();
+        let (recorder, recorded) = Recorder::new(|ctx| ctx.target_type());
+        let handlers = Handlers::default().on(WebhookEventType::Ping, recorder);
+        let app = super::router::<TestClient>(config, &Default::default(), handlers)
+            .await
+            .unwrap();
+
+        for target_type in [Some("integration"), None] {
+            let body = ping_body();
+            let body_hmac = calc_hmac_for_body(&secret, &body);
+            let mut request = signed_ping_request(format!("sha256={body_hmac}"), body);
+            if let Some(target_type) = target_type {
+                request.headers_mut().insert(
+                    "x-github-hook-installation-target-type",
+                    HeaderValue::from_static(target_type),
+                );
+            }
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        assert_eq!(
+            *recorded.lock().unwrap(),
+            vec![Some(TargetType::Integration), None]
+        );
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_installation_target_id_is_cross_checked() {
+        let (config, _, secret) = create_test_config();
+        let (recorder, recorded) = Recorder::new(|ctx| ctx.target_id());
+        let handlers = Handlers::default().on(WebhookEventType::Ping, recorder);
+        let app = super::router::<TestClient>(config, &Default::default(), handlers)
+            .await
+            .unwrap();
+        let send = |target_id: &'static str| {
+            let body = ping_body();
+            let body_hmac = calc_hmac_for_body(&secret, &body);
+            let mut request = signed_ping_request(format!("sha256={body_hmac}"), body);
+            request.headers_mut().insert(
+                "x-github-hook-installation-target-id",
+                HeaderValue::from_static(target_id),
+            );
+            app.clone().oneshot(request)
+        };
+
+        assert_eq!(send("1").await.unwrap().status(), StatusCode::OK);
+        assert!(!logs_contain("doesn't match the event's installation"));
+        assert_eq!(send("42").await.unwrap().status(), StatusCode::OK);
+        assert!(logs_contain("doesn't match the event's installation"));
+
+        assert_eq!(*recorded.lock().unwrap(), vec![Some(1), Some(42)]);
+    }
+
+    #[tokio::test]
+    async fn test_handlers_can_read_the_app_slug_and_delivery_id() {
+        let (config, _, secret) = create_test_config();
+        let (recorder, recorded) = Recorder::new(|ctx| {
+            (
+                ctx.app_slug().map(str::to_owned),
+                ctx.delivery_id().map(str::to_owned),
+                ctx.raw_body().is_some(),
+            )
+        });
+        let handlers = Handlers::default().on(WebhookEventType::Ping, recorder);
+        let app = super::router::<TestClient>(config, &Default::default(), handlers)
+            .await
+            .unwrap();
+
+        let body = ping_body();
+        let body_hmac = calc_hmac_for_body(&secret, &body);
+        let mut request = signed_ping_request(format!("sha256={body_hmac}"), body);
+        request.headers_mut().insert(
+            "x-github-delivery",
+            HeaderValue::from_static("72d3162e-cc78-11e3-81ab-4c9367dc0958"),
+        );
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            *recorded.lock().unwrap(),
+            vec![(
+                Some("wild-git-yonder".to_string()),
+                Some("72d3162e-cc78-11e3-81ab-4c9367dc0958".to_string()),
+                true
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_enterprise_version_is_available_to_handlers() {
+        let (config, _, secret) = create_test_config();
+        let (recorder, recorded) = Recorder::new(|ctx| ctx.enterprise_version().cloned());
+        let handlers = Handlers::default().on(WebhookEventType::Ping, recorder);
+        let app = super::router::<TestClient>(config, &Default::default(), handlers)
+            .await
+            .unwrap();
+
+        for version in [Some("3.11.2"), Some("3.12"), Some("not a version"), None] {
+            let body = ping_body();
+            let body_hmac = calc_hmac_for_body(&secret, &body);
+            let mut request = signed_ping_request(format!("sha256={body_hmac}"), body);
+            if let Some(version) = version {
+                request.headers_mut().insert(
+                    "x-github-enterprise-version",
+                    HeaderValue::from_static(version),
+                );
+            }
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        assert_eq!(
+            *recorded.lock().unwrap(),
+            vec![
+                Some(semver::Version::new(3, 11, 2)),
+                Some(semver::Version::new(3, 12, 0)),
+                None,
+                None
+            ]
+        );
+    }
+
+    struct Slow(Duration, Arc<AtomicBool>);
+
+    impl EventHandler<NoOpApi> for Slow {
+        fn handle<'a>(&'a self, _ctx: &'a EventContext<NoOpApi>) -> BoxFuture<'a, HandlerResult> {
+            Box::pin(async move {
+                tokio::time::sleep(self.0).await;
+                self.1.store(true, Ordering::SeqCst);
+                Ok(())
+            })
+        }
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_slow_handler_is_detached_after_the_soft_deadline() {
+        let (config, _, secret) = create_test_config();
+        let endpoint = WebhookEndpointConfiguration {
+            soft_deadline: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+        let completed = Arc::new(AtomicBool::new(false));
+        let handlers = Handlers::default().on(
+            WebhookEventType::Ping,
+            Slow(Duration::from_millis(500), completed.clone()),
+        );
+        let app = super::router::<TestClient>(config, &endpoint, handlers)
+            .await
+            .unwrap();
+
+        let body = ping_body();
+        let body_hmac = calc_hmac_for_body(&secret, &body);
+        let started = std::time::Instant::now();
+        let response = app
+            .oneshot(signed_ping_request(format!("sha256={body_hmac}"), body))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert!(started.elapsed() < Duration::from_millis(500));
+        assert!(!completed.load(Ordering::SeqCst));
+        assert!(logs_contain("detaching it"));
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !completed.load(Ordering::SeqCst) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the detached handler did not complete");
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_acknowledgement_is_configured_per_event() {
+        let (config, _, secret) = create_test_config();
+        let endpoint = WebhookEndpointConfiguration {
+            acknowledgements: [
+                ("push".to_string(), Acknowledgement::Accepted),
+                ("pull_request".to_string(), Acknowledgement::Handled),
+            ]
+            .into(),
+            ..Default::default()
+        };
+        let (recorder, recorded) = Recorder::new(|ctx| ctx.event().kind.clone());
+        let pushes = Recorder {
+            recorded: recorded.clone(),
+            extract: recorder.extract,
+        };
+        let handlers = Handlers::default()
+            .on(WebhookEventType::Push, pushes)
+            .on(WebhookEventType::PullRequest, recorder);
+        let app = super::router::<TestClient>(config, &endpoint, handlers)
+            .await
+            .unwrap();
+
+        let pull_request = json!({
+            "action": "opened",
+            "number": 7,
+            "pull_request": test_pull_request(7),
+            "repository": test_repository(),
+            "installation": { "id": 1, "node_id": "dGVzdA==" }
+        });
+        let response = app
+            .clone()
+            .oneshot(signed_request(&secret, "pull_request", pull_request))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(*recorded.lock().unwrap(), [WebhookEventType::PullRequest]);
+
+        let response = app
+            .oneshot(signed_request(&secret, "push", push_body()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while recorded.lock().unwrap().len() < 2 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the accepted event was not handled");
+        assert_eq!(recorded.lock().unwrap()[1], WebhookEventType::Push);
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_queued_event_is_acknowledged_and_handled_in_background() {
+        let (config, _, secret) = create_test_config();
+        let endpoint = WebhookEndpointConfiguration {
+            queue_capacity: Some(4),
+            ..Default::default()
+        };
+        let (recorder, recorded) = Recorder::new(|ctx| ctx.installation_id().map(|id| id.0));
+        let handlers = Handlers::default().on(WebhookEventType::Ping, recorder);
+        let app = super::router::<TestClient>(config, &endpoint, handlers)
+            .await
+            .unwrap();
+
+        let body = ping_body();
+        let body_hmac = calc_hmac_for_body(&secret, &body);
+        let response = app
+            .oneshot(signed_ping_request(format!("sha256={body_hmac}"), body))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while recorded.lock().unwrap().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the queued event was not handled");
+        assert_eq!(*recorded.lock().unwrap(), vec![Some(1)]);
+    }
+
+    /// Counts the events it handled, each takes a while.
+    #[derive(Clone, Default)]
+    struct Counting(Arc<AtomicUsize>);
+
+    impl EventHandler<NoOpApi> for Counting {
+        fn handle<'a>(&'a self, _: &'a EventContext<NoOpApi>) -> BoxFuture<'a, HandlerResult> {
+            Box::pin(async move {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                self.0.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+        }
+    }
+
+    #[derive(Debug, Default)]
+    struct DeadLetterList(Mutex<Vec<DeadLetter>>);
+
+    impl DeadLetters for DeadLetterList {
+        fn dead_letter(&self, letter: DeadLetter) {
+            self.0.lock().unwrap().push(letter);
+        }
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_shutdown_drains_the_queue_and_dead_letters_the_rest() {
+        let (config, _, secret) = create_test_config();
+        let in_flight = InFlightHandlers::default();
+        let dead_letters = Arc::new(DeadLetterList::default());
+        let endpoint = WebhookEndpointConfiguration {
+            queue_capacity: Some(8),
+            in_flight: in_flight.clone(),
+            handling: HandleOptions {
+                retries: Retries {
+                    dead_letters: Some(dead_letters.clone()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let counting = Counting::default();
+        let handlers = Handlers::default().on(WebhookEventType::Ping, counting.clone());
+        let app = super::router::<TestClient>(config, &endpoint, handlers)
+            .await
+            .unwrap();
+
+        for _ in 0..5 {
+            let body = ping_body();
+            let body_hmac = calc_hmac_for_body(&secret, &body);
+            let response = app
+                .clone()
+                .oneshot(signed_ping_request(format!("sha256={body_hmac}"), body))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::ACCEPTED);
+        }
+        let shutdown = Shutdown::default();
+        shutdown.trigger();
+        crate::drain(
+            std::future::ready(Ok(())),
+            shutdown,
+            in_flight.clone(),
+            Duration::from_millis(250),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            in_flight.count(),
+            0,
+            "the queued events were neither handled nor dead-lettered"
+        );
+
+        let processed = counting.0.load(Ordering::SeqCst);
+        let dead = dead_letters.0.lock().unwrap().len();
+        assert_eq!(processed + dead, 5);
+        assert!(processed >= 1, "{processed}");
+        assert!(dead >= 1, "{dead}");
+        assert!(logs_contain("drain timeout elapsed"));
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_duplicate_delivery_is_not_processed_again() {
+        let (config, _, secret) = create_test_config();
+        let endpoint = WebhookEndpointConfiguration {
+            delivery_store: Some(Arc::new(InMemoryDeliveryStore::new(Duration::from_secs(
+                60,
+            )))),
+            ..Default::default()
+        };
+        let (recorder, recorded) = Recorder::new(|ctx| ctx.pull_request_number());
+        let handlers = Handlers::default().on(WebhookEventType::Ping, recorder);
+        let app = super::router::<TestClient>(config, &endpoint, handlers)
+            .await
+            .unwrap();
+
+        for _ in 0..2 {
+            let body = ping_body();
+            let body_hmac = calc_hmac_for_body(&secret, &body);
+            let mut request = signed_ping_request(format!("sha256={body_hmac}"), body);
+            request.headers_mut().insert(
+                "x-github-delivery",
+                HeaderValue::from_static("72d3162e-cc78-11e3-81ab-4c9367dc0958"),
+            );
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        assert_eq!(recorded.lock().unwrap().len(), 1);
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_redelivery_of_a_failed_delivery_is_processed_again() {
+        let (config, _, secret) = create_test_config();
+        let endpoint = WebhookEndpointConfiguration {
+            delivery_store: Some(Arc::new(InMemoryDeliveryStore::new(Duration::from_secs(
+                60,
+            )))),
+            ..Default::default()
+        };
+        let handlers = Handlers::default().on(WebhookEventType::Ping, Failing);
+        let app = super::router::<TestClient>(config, &endpoint, handlers)
+            .await
+            .unwrap();
+
+        for _ in 0..2 {
+            let body = ping_body();
+            let body_hmac = calc_hmac_for_body(&secret, &body);
+            let mut request = signed_ping_request(format!("sha256={body_hmac}"), body);
+            request.headers_mut().insert(
+                "x-github-delivery",
+                HeaderValue::from_static("72d3162e-cc78-11e3-81ab-4c9367dc0958"),
+            );
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_unfinished_logged_event_is_handled_on_restart() {
+        let path = std::env::temp_dir().join(format!("wal-restart-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let log = WriteAheadLog::open(&path).unwrap();
+        log.append("ping", &ping_body()).unwrap();
+        drop(log);
+
+        let (config, _, _) = create_test_config();
+        let endpoint = WebhookEndpointConfiguration {
+            write_ahead_log: Some(Arc::new(WriteAheadLog::open(&path).unwrap())),
+            ..Default::default()
+        };
+        let (recorder, recorded) = Recorder::new(|ctx| ctx.installation_id().map(|id| id.0));
+        let handlers = Handlers::default().on(WebhookEventType::Ping, recorder);
+        let _app = super::router::<TestClient>(config, &endpoint, handlers)
+            .await
+            .unwrap();
+        // recovery runs in the background
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !std::fs::read_to_string(&path)
+                .unwrap()
+                .contains(r#"{"op":"done""#)
+            {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the recovered event was not handled");
+
+        assert_eq!(*recorded.lock().unwrap(), vec![Some(1)]);
+        drop(endpoint);
+        let log = WriteAheadLog::open(&path).unwrap();
+        assert!(log.take_recovered().is_empty());
+        std::fs::remove_file(log.path()).unwrap();
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_maintenance_mode_rejects_deliveries() {
+        let (config, _, secret) = create_test_config();
+        let endpoint = WebhookEndpointConfiguration::default();
+        let maintenance = endpoint.maintenance.clone();
+        let app = super::router::<TestClient>(config, &endpoint, Default::default())
+            .await
+            .unwrap();
+        let request = || {
+            let body = ping_body();
+            let body_hmac = calc_hmac_for_body(&secret, &body);
+            signed_ping_request(format!("sha256={body_hmac}"), body)
+        };
+
+        maintenance.set(true);
+        let response = app.clone().oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()["retry-after"], "120");
+
+        maintenance.set(false);
+        let response = app.oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    async fn verify_with_repository_secrets(repository: &str, secret: &SecretKey) -> StatusCode {
+        let (config, _, _) = create_test_config();
+        let secrets = RepositorySecrets::default()
+            .with("acme/anvil", SecretKey::from_slice(&[1; 32]).unwrap());
+        let endpoint = WebhookEndpointConfiguration {
+            secret_resolver: Some(Arc::new(secrets)),
+            ..Default::default()
+        };
+        let app = super::router::<TestClient>(config, &endpoint, Default::default())
+            .await
+            .unwrap();
+        let body = json!({
+            "zen": "Keep it logically awesome.",
+            "repository": { "id": 1, "name": "anvil", "full_name": repository, "url": "https://api.github.local/repos/acme/anvil" },
+            "installation": { "id": 1, "node_id": "dGVzdA==" }
+        });
+        app.oneshot(signed_request(secret, "ping", body))
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_repository_secret_is_used_for_verification() {
+        let repository_secret = SecretKey::from_slice(&[1; 32]).unwrap();
+        let app_secret = SecretKey::from_slice(&[0; 32]).unwrap();
+        assert_eq!(
+            verify_with_repository_secrets("acme/anvil", &repository_secret).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            verify_with_repository_secrets("acme/anvil", &app_secret).await,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    /// Serves the secret of `acme/anvil` until it is told to fail.
+    #[derive(Debug, Default)]
+    struct Vault {
+        failing: Arc<AtomicBool>,
+        fetches: Arc<AtomicUsize>,
+    }
+
+    impl SecretProvider for Vault {
+        fn fetch(&self, _: &str) -> Result<Option<SecretKey>, SecretProviderError> {
+            self.fetches.fetch_add(1, Ordering::SeqCst);
+            if self.failing.load(Ordering::SeqCst) {
+                return Err(SecretProviderError("vault is sealed".into()));
+            }
+            Ok(Some(SecretKey::from_slice(&[1; 32]).unwrap()))
+        }
+    }
+
+    #[test]
+    fn test_failed_secret_lookup_is_retried_after_a_backoff() {
+        let vault = Vault::default();
+        let fetches = vault.fetches.clone();
+        vault.failing.store(true, Ordering::SeqCst);
+        let secrets = CachedSecretProvider::new(vault, Duration::from_secs(300))
+            .with_attempts(3)
+            .with_retry_backoff(Duration::from_millis(20));
+
+        let started = std::time::Instant::now();
+        assert!(secrets.resolve("acme/anvil").is_none());
+
+        assert_eq!(fetches.load(Ordering::SeqCst), 3);
+        // the second retry waits twice as long as the first one
+        assert!(started.elapsed() >= Duration::from_millis(60));
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_last_known_secret_bridges_provider_outages() {
+        let (config, _, _) = create_test_config();
+        let vault = Vault::default();
+        let (failing, fetches) = (vault.failing.clone(), vault.fetches.clone());
+        let clock = Arc::new(ManualClock::at(SystemTime::UNIX_EPOCH));
+        let secrets =
+            CachedSecretProvider::with_clock(vault, Duration::from_secs(300), clock.clone());
+        let endpoint = WebhookEndpointConfiguration {
+            secret_resolver: Some(Arc::new(secrets)),
+            ..Default::default()
+        };
+        let app = super::router::<TestClient>(config, &endpoint, Default::default())
+            .await
+            .unwrap();
+        let send = || {
+            let body = json!({
+                "zen": "Keep it logically awesome.",
+                "repository": { "id": 1, "name": "anvil", "full_name": "acme/anvil", "url": "https://api.github.local/repos/acme/anvil" },
+                "installation": { "id": 1, "node_id": "dGVzdA==" }
+            });
+            let secret = SecretKey::from_slice(&[1; 32]).unwrap();
+            app.clone().oneshot(signed_request(&secret, "ping", body))
+        };
+
+        assert_eq!(send().await.unwrap().status(), StatusCode::OK);
+        failing.store(true, Ordering::SeqCst);
+        clock.advance(Duration::from_secs(300));
+        assert_eq!(send().await.unwrap().status(), StatusCode::OK);
+        assert!(logs_contain("using the last known secret"));
+        assert_eq!(fetches.load(Ordering::SeqCst), 3);
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(send().await.unwrap().status(), StatusCode::BAD_REQUEST);
+        assert!(logs_contain("no recent secret is known"));
+    }
+
+    #[test]
+    fn test_only_the_most_recent_secrets_are_remembered() {
+        let vault = Vault::default();
+        let failing = vault.failing.clone();
+        let secrets = CachedSecretProvider::new(vault, Duration::from_secs(300))
+            .with_attempts(1)
+            .with_capacity(NonZeroUsize::new(1).unwrap());
+
+        assert!(secrets.resolve("acme/anvil").is_some());
+        assert!(secrets.resolve("acme/rocket").is_some());
+        failing.store(true, Ordering::SeqCst);
+
+        assert!(secrets.resolve("acme/anvil").is_none());
+        assert!(secrets.resolve("acme/rocket").is_some());
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_repository_without_secret_falls_back_to_app_secret() {
+        let app_secret = SecretKey::from_slice(&[0; 32]).unwrap();
+        assert_eq!(
+            verify_with_repository_secrets("acme/rocket", &app_secret).await,
+            StatusCode::OK
+        );
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_replayed_delivery_is_accepted() {
+        let (config, _, secret) = create_test_config();
+        let app = super::router::<TestClient>(config, &Default::default(), Default::default())
+            .await
+            .unwrap();
+        let path = std::env::temp_dir().join(format!("delivery-{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            json!({
+                "headers": {
+                    "x-github-event": "ping",
+                    "x-github-delivery": "72d3162e-cc78-11e3-81ab-4c9367dc0958",
+                    "x-hub-signature-256": "sha256=outdated"
+                },
+                "body": serde_json::from_slice::<serde_json::Value>(&ping_body()).unwrap()
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let delivery = CapturedDelivery::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let signature_header = HeaderName::from_static(DEFAULT_SIGNATURE_HEADER);
+        let request = delivery
+            .signed_request(
+                Uri::from_static("/event_handler"),
+                &secret,
+                &signature_header,
+            )
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    /// Fails with a retryable error the first time it is invoked only.
+    #[derive(Clone, Default)]
+    struct FailsOnce(Arc<AtomicUsize>);
+
+    impl EventHandler<NoOpApi> for FailsOnce {
+        fn handle<'a>(&'a self, _: &'a EventContext<NoOpApi>) -> BoxFuture<'a, HandlerResult> {
+            let invocation = self.0.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move {
+                match invocation {
+                    0 => Err(HandlerError::Retryable {
+                        source: "vault is sealed".into(),
+                    }
+                    .into()),
+                    _ => Ok(()),
+                }
+            })
+        }
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_dead_letter_is_stored_and_replayed() {
+        let (config, _, secret) = create_test_config();
+        let dir = std::env::temp_dir().join(format!("dead-letters-{}", std::process::id()));
+        let dead_letters = FileDeadLetters::open(&dir).unwrap().compressed();
+        let endpoint = WebhookEndpointConfiguration {
+            handling: HandleOptions {
+                retries: Retries {
+                    policy: RetryPolicy {
+                        max_attempts: 1,
+                        ..Default::default()
+                    },
+                    dead_letters: Some(Arc::new(dead_letters)),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let fails_once = FailsOnce::default();
+        let handlers = Handlers::default().on(WebhookEventType::Ping, fails_once.clone());
+        let app = super::router::<TestClient>(config, &endpoint, handlers)
+            .await
+            .unwrap();
+        let body = ping_body();
+        let body_hmac = calc_hmac_for_body(&secret, &body);
+        let mut request = signed_ping_request(format!("sha256={body_hmac}"), body);
+        request.headers_mut().insert(
+            "x-github-delivery",
+            HeaderValue::from_static("72d3162e-cc78-11e3-81ab-4c9367dc0958"),
+        );
+        app.clone().oneshot(request).await.unwrap();
+
+        let path = dir.join("72d3162e-cc78-11e3-81ab-4c9367dc0958.json.gz");
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !logs_contain("stored the dead letter") {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the dead letter was not stored");
+        let delivery = CapturedDelivery::read(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/event_handler", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let signature_header = HeaderName::from_static(DEFAULT_SIGNATURE_HEADER);
+        let status = replay(&delivery, url.parse().unwrap(), &secret, &signature_header)
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(fails_once.0.load(Ordering::SeqCst), 2);
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_replayed_delivery_is_signed_in_the_configured_header() {
+        let (config, _, secret) = create_test_config();
+        let endpoint = WebhookEndpointConfiguration {
+            signature_header_name: "X-Original-Hub-Signature-256".into(),
+            ..Default::default()
+        };
+        let app = super::router::<TestClient>(config, &endpoint, Default::default())
+            .await
+            .unwrap();
+        let delivery = CapturedDelivery {
+            headers: [
+                ("x-github-event".to_string(), "ping".to_string()),
+                (
+                    "x-original-hub-signature-256".to_string(),
+                    "sha256=outdated".to_string(),
+                ),
+            ]
+            .into(),
+            body: serde_json::from_slice(&ping_body()).unwrap(),
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/event_handler", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let signature_header = HeaderName::from_static("x-original-hub-signature-256");
+        let status = replay(&delivery, url.parse().unwrap(), &secret, &signature_header)
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_compressed_delivery_is_stored_and_replayed() {
+        let (config, _, secret) = create_test_config();
+        let app = super::router::<TestClient>(config, &Default::default(), Default::default())
+            .await
+            .unwrap();
+        let path = std::env::temp_dir().join(format!("delivery-{}.json.gz", std::process::id()));
+        let captured = CapturedDelivery {
+            headers: [("x-github-event".to_string(), "ping".to_string())].into(),
+            body: serde_json::from_slice(&ping_body()).unwrap(),
+        };
+
+        captured.write(&path).unwrap();
+        let stored = std::fs::read(&path).unwrap();
+        let delivery = CapturedDelivery::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let signature_header = HeaderName::from_static(DEFAULT_SIGNATURE_HEADER);
+        let request = delivery
+            .signed_request(
+                Uri::from_static("/event_handler"),
+                &secret,
+                &signature_header,
+            )
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(stored[..2], [0x1f, 0x8b], "not gzip-compressed");
+        assert_eq!(delivery.body, captured.body);
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Shared(&'static str);
+
+    struct InsertShared;
+
+    impl EventHandler<NoOpApi> for InsertShared {
+        fn handle<'a>(&'a self, ctx: &'a EventContext<NoOpApi>) -> BoxFuture<'a, HandlerResult> {
+            ctx.insert(Shared("computed once"));
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_handlers_share_values() {
+        let (config, _, secret) = create_test_config();
+        let (before, seen_before) = Recorder::new(|ctx| ctx.get::<Shared>());
+        let (after, seen_after) = Recorder::new(|ctx| ctx.get::<Shared>());
+        let handlers = Handlers::default()
+            .on(WebhookEventType::Ping, before)
+            .on(WebhookEventType::Ping, InsertShared)
+            .on(WebhookEventType::Ping, after);
+        let app = super::router::<TestClient>(config, &Default::default(), handlers)
+            .await
+            .unwrap();
+
+        let body = ping_body();
+        let body_hmac = calc_hmac_for_body(&secret, &body);
+        let response = app
+            .oneshot(signed_ping_request(format!("sha256={body_hmac}"), body))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(*seen_before.lock().unwrap(), [None]);
+        assert_eq!(*seen_after.lock().unwrap(), [Some(Shared("computed once"))]);
+    }
+
+    struct AwaitDisconnect(tokio::sync::mpsc::UnboundedSender<CancellationToken>);
+
+    impl EventHandler<NoOpApi> for AwaitDisconnect {
+        fn handle<'a>(&'a self, ctx: &'a EventContext<NoOpApi>) -> BoxFuture<'a, HandlerResult> {
+            let _ = self.0.send(ctx.cancellation().clone());
+            Box::pin(std::future::pending())
+        }
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_disconnect_cancels_handler_token() {
+        let (config, _, secret) = create_test_config();
+        let (sender, mut tokens) = tokio::sync::mpsc::unbounded_channel();
+        let handlers = Handlers::default().on(WebhookEventType::Ping, AwaitDisconnect(sender));
+        let app = super::router::<TestClient>(config, &Default::default(), handlers)
+            .await
+            .unwrap();
+
+        let body = ping_body();
+        let body_hmac = calc_hmac_for_body(&secret, &body);
+        let request = signed_ping_request(format!("sha256={body_hmac}"), body);
+        let connection = tokio::spawn(app.oneshot(request));
+        let token = tokens.recv().await.unwrap();
+        assert!(!token.is_cancelled());
+
+        connection.abort();
+        tokio::time::timeout(std::time::Duration::from_secs(1), token.cancelled())
+            .await
+            .unwrap();
+    }
+
+    async fn post_chunked_ping(max_body_bytes: usize) -> StatusCode {
+        let (config, _, secret) = create_test_config();
+        let endpoint = WebhookEndpointConfiguration {
+            max_body_bytes,
+            ..Default::default()
+        };
+        let app = super::router::<TestClient>(config, &endpoint, Default::default())
+            .await
+            .unwrap();
+
+        let body = ping_body();
+        let body_hmac = calc_hmac_for_body(&secret, &body);
+        let chunks = body
+            .chunks(16)
+            .map(|chunk| Ok::<_, std::io::Error>(bytes::Bytes::copy_from_slice(chunk)))
+            .collect::<Vec<_>>();
+        let request = Request::builder()
+            .uri("/event_handler")
+            .header("X-GitHub-Event", "ping")
+            .header("x-hub-signature-256", format!("sha256={body_hmac}"))
+            .body(Body::from_stream(futures_util::stream::iter(chunks)))
+            .unwrap();
+        assert!(request.headers().get("content-length").is_none());
+        app.oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_oversized_form_encoded_body_is_rejected() {
+        let (config, _, secret) = create_test_config();
+        let endpoint = WebhookEndpointConfiguration {
+            max_body_bytes: 32,
+            ..Default::default()
+        };
+        let app = super::router::<TestClient>(config, &endpoint, Default::default())
+            .await
+            .unwrap();
+        let body = format!("payload={}", "a".repeat(64)).into_bytes();
+        let body_hmac = calc_hmac_for_body(&secret, &body);
+        let form_request = || {
+            Request::builder()
+                .uri("/event_handler")
+                .header("X-GitHub-Event", "ping")
+                .header("content-type", "application/x-www-form-urlencoded")
+                .header("x-hub-signature-256", format!("sha256={body_hmac}"))
+        };
+
+        let declared = form_request()
+            .header("content-length", body.len())
+            .body(Body::from(body.clone()))
+            .unwrap();
+        let chunked = form_request()
+            .body(Body::from_stream(futures_util::stream::iter(
+                body.chunks(16)
+                    .map(|chunk| Ok::<_, std::io::Error>(bytes::Bytes::copy_from_slice(chunk)))
+                    .collect::<Vec<_>>(),
+            )))
+            .unwrap();
+
+        for request in [declared, chunked] {
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        }
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_chunked_body_under_limit_is_accepted() {
+        assert_eq!(post_chunked_ping(1024).await, StatusCode::OK);
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_chunked_body_over_limit_is_rejected() {
+        assert_eq!(post_chunked_ping(32).await, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tracing_test::traced_test]
+    #[tokio::test]
+    async fn test_deeply_nested_payload_is_rejected() {
+        let (config, _, secret) = create_test_config();
+        let endpoint = WebhookEndpointConfiguration {
+            max_json_depth: 8,
+            ..Default::default()
+        };
+        let app = super::router::<TestClient>(config, &endpoint, Default::default())
+            .await
+            .unwrap();
+
+        let body = format!(r#"{{"zen": {}1{}}}"#, "[".repeat(8), "]".repeat(8)).into_bytes();
+        let body_hmac = calc_hmac_for_body(&secret, &body);
+        let response = app
+            .oneshot(signed_ping_request(format!("sha256={body_hmac}"), body))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    async fn post_event(event: &str, body: serde_json::Value) -> (StatusCode, String) {
+        let (config, _, secret) = create_test_config();
+        let app = super::router::<TestClient>(config, &Default::default(), Default::default())
+            .await
+            .unwrap();
+
+        let response = app
+            .oneshot(signed_request(&secret, event, body))
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, String::from_utf8_lossy(&body).into_owned())
+    }
+
+    #[tokio::test]
+    async fn test_payload_matching_its_event_header_is_accepted() {
+        let (status, _) = post_event("push", push_body()).await;
+
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_payload_of_another_event_is_unprocessable() {
+        let pull_request = json!({
+            "action": "opened",
+            "number": 7,
+            "pull_request": test_pull_request(7),
+            "repository": test_repository(),
+            "installation": { "id": 1, "node_id": "dGVzdA==" }
+        });
+
+        let (status, error) = post_event("push", pull_request).await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(error.contains("not a `push` event"), "{error}");
+    }
+
+    async fn post_with_event_headers(events: &[&'static str]) -> (StatusCode, String) {
+        let (config, _, secret) = create_test_config();
+        let app = super::router::<TestClient>(config, &Default::default(), Default::default())
+            .await
+            .unwrap();
+
+        let mut request = signed_request(&secret, events[0], push_body());
+        for event in &events[1..] {
+            request
+                .headers_mut()
+                .append("x-github-event", HeaderValue::from_static(event));
+        }
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, String::from_utf8_lossy(&body).into_owned())
+    }
+
+    #[tokio::test]
+    async fn test_differing_event_headers_are_rejected() {
+        let (status, error) = post_with_event_headers(&["push", "ping"]).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(error.contains("differing values"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn test_single_event_header_is_accepted() {
+        let (status, _) = post_with_event_headers(&["push"]).await;
+
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    fn test_pull_request(number: u64) -> serde_json::Value {
+        json!({
+            "url": format!("https://api.github.local/repos/acme/wild-git-yonder/pulls/{number}"),
+            "id": number,
+            "number": number,
+            "locked": false,
+            "maintainer_can_modify": false,
+            "head": { "ref": "feature", "sha": "d6fde92930d4715a2b49857d24b940956b26d2d3" },
+            "base": { "ref": "main", "sha": "9049f1265b7d61be4a8904a9a27120d2064dab3b" }
+        })
+    }
+
+    fn push_body() -> serde_json::Value {
+        let author = json!({ "name": "Wile E. Coyote", "email": "wile@acme.local" });
+        let commit = json!({
+            "id": "d6fde92930d4715a2b49857d24b940956b26d2d3",
+            "tree_id": "8c7d2b9b5a4f0c1e3d6a9b8c7d2b9b5a4f0c1e3d",
+            "distinct": true,
+            "message": "Sharpen the anvil",
+            "timestamp": "2024-01-01T00:00:00Z",
+            "url": "https://github.local/acme/wild-git-yonder/commit/d6fde92",
+            "author": author,
+            "committer": author
+        });
+        json!({
+            "ref": "refs/heads/main",
+            "before": "0000000000000000000000000000000000000000",
+            "after": "d6fde92930d4715a2b49857d24b940956b26d2d3",
+            "base_ref": null,
+            "commits": [commit],
+            "head_commit": commit,
+            "compare": "https://github.local/acme/wild-git-yonder/compare/main",
+            "created": false,
+            "deleted": false,
+            "forced": false,
+            "pusher": author,
+            "repository": test_repository(),
+            "installation": { "id": 1, "node_id": "dGVzdA==" }
+        })
+    }
+
+    fn test_repository() -> serde_json::Value {
+        json!({
+            "id": 1,
+            "name": "wild-git-yonder",
+            "url": "https://api.github.local/repos/acme/wild-git-yonder"
+        })
+    }
+
+    fn signed_request(secret: &SecretKey, event: &str, body: serde_json::Value) -> Request<Body> {
+        let body = serde_json::to_vec(&body).unwrap();
+        let body_hmac = calc_hmac_for_body(secret, &body);
+        Request::builder()
+            .uri("/event_handler")
+            .header("X-GitHub-Event", event)
+            .header("x-hub-signature-256", format!("sha256={body_hmac}"))
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    fn ping_body() -> Vec<u8> {
+        serde_json::to_vec(&json!(
+            {
+                "installation": {
+                    "id": 1,
+                    "node_id": "dGVzdA=="
+                },
+                "zen": "Half measures are as bad as nothing at all."
+            }
+        ))
+        .unwrap()
+    }
+
+    fn signed_ping_request(signature: String, body: Vec<u8>) -> Request<Body> {
+        Request::builder()
+            .uri("/event_handler")
+            .header("X-GitHub-Event", "ping")
+            .header("x-hub-signature-256", signature)
+            .body(Body::from(body))
+            .unwrap()
+    }
+
     fn create_test_config() -> (GitHubAppConfiguration, RsaPublicKey, SecretKey) {
         use jsonwebtoken::EncodingKey;
         use octocrab::models::AppId;
@@ -247,6 +4908,9 @@ mod test {
                 app_identifier: AppId(1),
                 app_key: { EncodingKey::from_rsa_pem(cert_pem_str.as_bytes()).unwrap() },
                 uri: Uri::from_static("https://github.local"),
+                user_agent: "wild-git-yonder-test".to_string(),
+                api_timeout: None,
+                subscriptions: Vec::new(),
             },
             pub_key,
             SecretKey::from_slice(&[0; 32]).unwrap(),
@@ -1,5 +1,17 @@
+use std::convert::Infallible;
 use std::sync::Arc;
+use std::time::SystemTime;
 
+use crate::pre_auth::PreAuth;
+use crate::secrets::SecretResolver;
+pub use crate::signature::SignatureHeaderError;
+use crate::signature::{
+    DeliveryAge, Sha256VerificationSignature, SignatureBypass, SignatureFailureReason,
+    SignatureFailures, SignatureHeader, SignatureMigration, StreamingSignature,
+    DELIVERY_TIMESTAMP_HEADER, DELIVERY_TIMESTAMP_SIGNATURE_HEADER,
+};
+use crate::verification::{event_type, parse_verified, signatures, verify_body};
+pub use crate::verification::{GitHubEventExtractionError, GitHubEventHeaderError};
 use axum::{
     extract::{FromRequest, FromRequestParts},
     http::{request::Parts, HeaderName},
@@ -8,78 +20,135 @@ use axum_core::{
     extract::{FromRef, Request},
     response::{IntoResponse, Response},
 };
-use hex::FromHexError;
-use http_body_util::BodyExt;
-use hyper::{header::ToStrError, StatusCode};
+use bytes::{Bytes, BytesMut};
+use github_event_handler::context::TargetType;
+use http_body_util::{BodyExt, LengthLimitError, Limited};
+use hyper::{
+    header::{CONTENT_LENGTH, CONTENT_TYPE},
+    StatusCode,
+};
 use octocrab::models::webhook_events::WebhookEvent;
-use orion::hazardous::mac::hmac::sha256::{SecretKey, Tag};
-use thiserror::Error;
-
-pub struct ExtractSignatureHeader(pub(crate) Sha256VerificationSignature);
-
-#[derive(Clone)]
-pub(crate) struct Sha256VerificationSignature(Vec<u8>);
-
-impl<'a> TryFrom<(&'a str, &'a str)> for Sha256VerificationSignature {
-    type Error = SignatureHeaderError;
+use orion::hazardous::mac::hmac::sha256::SecretKey;
 
-    fn try_from((kind, hmac): (&'a str, &'a str)) -> Result<Self, Self::Error> {
-        match kind {
-            "sha256" => Ok(Sha256VerificationSignature(hex::decode(hmac)?)),
-            _ => Err(SignatureHeaderError::MissingHeader),
-        }
-    }
-}
-
-impl PartialEq<Tag> for &Sha256VerificationSignature {
-    fn eq(&self, other: &Tag) -> bool {
-        *other == &*self.0
-    }
-}
+/// All signatures sent along the request, proxies might duplicate or fold the header.
+pub struct ExtractSignatureHeader(pub(crate) Vec<Sha256VerificationSignature>);
 
 impl<S> FromRequestParts<S> for ExtractSignatureHeader
 where
     S: Send + Sync,
+    SignatureHeader: FromRef<S>,
 {
     type Rejection = SignatureHeaderError;
 
-    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
-        static HEADER: HeaderName = HeaderName::from_static("x-hub-signature-256");
-        let Some(signature) = parts.headers.get(&HEADER) else {
-            return Err(SignatureHeaderError::MissingHeader);
-        };
-        let (kind, hmac) = signature
-            .to_str()?
-            .split_once('=')
-            .ok_or(SignatureHeaderError::NotAPair)?;
-        Ok(Self((kind, hmac).try_into()?))
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let SignatureHeader(header) = SignatureHeader::from_ref(state);
+        signatures(&parts.headers, &header).map(Self)
     }
 }
 
-#[derive(Debug, Error)]
-pub enum SignatureHeaderError {
-    #[error("The header value does not consist of a valid string")]
-    InvalidValue(#[from] ToStrError),
-    #[error("The header value is missing the correct delimiter")]
-    NotAPair,
-    #[error("The header value is not a valid hex value")]
-    NotHex(#[from] FromHexError),
-    #[error("Missing header pair (either left or right side)")]
-    MissingHeader,
-}
-
 impl IntoResponse for SignatureHeaderError {
     fn into_response(self) -> Response {
         match self {
             e @ SignatureHeaderError::InvalidValue(_) => (StatusCode::BAD_REQUEST, e.to_string()),
             e @ SignatureHeaderError::NotAPair => (StatusCode::BAD_REQUEST, e.to_string()),
             e @ SignatureHeaderError::NotHex(_) => (StatusCode::BAD_REQUEST, e.to_string()),
+            e @ SignatureHeaderError::InvalidLength(_) => (StatusCode::BAD_REQUEST, e.to_string()),
+            e @ SignatureHeaderError::UnknownAlgorithm(_) => {
+                (StatusCode::BAD_REQUEST, e.to_string())
+            }
             e @ SignatureHeaderError::MissingHeader => (StatusCode::BAD_REQUEST, e.to_string()),
+            e @ SignatureHeaderError::TooManySignatures => (StatusCode::BAD_REQUEST, e.to_string()),
         }
         .into_response()
     }
 }
 
+/// The `X-GitHub-Hook-Installation-Target-Type` of the delivery, `None` if missing or unknown.
+pub struct ExtractTargetType(pub Option<TargetType>);
+
+impl<S> FromRequestParts<S> for ExtractTargetType
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        static HEADER: HeaderName =
+            HeaderName::from_static("x-github-hook-installation-target-type");
+        let target_type = parts
+            .headers
+            .get(&HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| {
+                value
+                    .parse()
+                    .inspect_err(|err| tracing::debug!(%err, "ignoring target type"))
+                    .ok()
+            });
+        Ok(Self(target_type))
+    }
+}
+
+/// The `X-GitHub-Hook-Installation-Target-ID` of the delivery, `None` if missing or not a number.
+pub struct ExtractTargetId(pub Option<u64>);
+
+impl<S> FromRequestParts<S> for ExtractTargetId
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        static HEADER: HeaderName = HeaderName::from_static("x-github-hook-installation-target-id");
+        let target_id = parts
+            .headers
+            .get(&HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| {
+                value
+                    .trim()
+                    .parse()
+                    .inspect_err(|err| tracing::debug!(%err, value, "ignoring target id"))
+                    .ok()
+            });
+        Ok(Self(target_id))
+    }
+}
+
+/// The `X-GitHub-Enterprise-Version` of deliveries from GitHub Enterprise Server, `None` for
+/// github.com or if the version can't be parsed.
+pub struct ExtractEnterpriseVersion(pub Option<semver::Version>);
+
+impl<S> FromRequestParts<S> for ExtractEnterpriseVersion
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        static HEADER: HeaderName = HeaderName::from_static("x-github-enterprise-version");
+        let version = parts
+            .headers
+            .get(&HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| {
+                parse_enterprise_version(value)
+                    .inspect_err(|err| tracing::warn!(%err, value, "ignoring enterprise version"))
+                    .ok()
+            });
+        Ok(Self(version))
+    }
+}
+
+/// GHES releases are named by their feature release, e.g. `3.11`, which lacks the patch level.
+fn parse_enterprise_version(value: &str) -> Result<semver::Version, semver::Error> {
+    let value = value.trim();
+    match value.matches('.').count() {
+        1 => semver::Version::parse(&format!("{value}.0")),
+        _ => semver::Version::parse(value),
+    }
+}
+
 pub(crate) struct ExtractGitHubEventHeader(String);
 
 impl<S> FromRequestParts<S> for ExtractGitHubEventHeader
@@ -89,91 +158,191 @@ where
     type Rejection = GitHubEventHeaderError;
 
     async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
-        static HEADER: HeaderName = HeaderName::from_static("x-github-event");
-        let Some(signature) = parts.headers.get(&HEADER) else {
-            return Err(GitHubEventHeaderError::MissingHeader);
-        };
-        Ok(Self(signature.to_str()?.to_owned()))
+        event_type(&parts.headers).map(Self)
     }
 }
 
-#[derive(Debug, Error)]
-pub enum GitHubEventHeaderError {
-    #[error("Missing header")]
-    MissingHeader,
-    #[error("The header value does not consist of a valid string")]
-    InvalidValue(#[from] ToStrError),
-}
-
 impl IntoResponse for GitHubEventHeaderError {
     fn into_response(self) -> Response {
         match self {
             e @ GitHubEventHeaderError::MissingHeader => (StatusCode::BAD_REQUEST, e.to_string()),
             e @ GitHubEventHeaderError::InvalidValue(_) => (StatusCode::BAD_REQUEST, e.to_string()),
+            e @ GitHubEventHeaderError::Ambiguous(..) => (StatusCode::BAD_REQUEST, e.to_string()),
         }
         .into_response()
     }
 }
 
-pub(crate) struct GitHubEvent(pub(crate) WebhookEvent);
+/// The verified event and its body as it was signed.
+pub(crate) struct GitHubEvent(pub(crate) WebhookEvent, pub(crate) Bytes);
+
+/// Bounds for payloads, checked before they are parsed.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PayloadLimits {
+    /// Maximum nesting of arrays and objects.
+    pub(crate) max_depth: usize,
+    pub(crate) max_bytes: usize,
+}
 
 impl<S> FromRequest<S> for GitHubEvent
 where
     S: Send + Sync,
     Arc<SecretKey>: FromRef<S>,
+    PayloadLimits: FromRef<S>,
+    Option<Arc<dyn SecretResolver>>: FromRef<S>,
+    Option<Arc<dyn PreAuth>>: FromRef<S>,
+    SignatureMigration: FromRef<S>,
+    DeliveryAge: FromRef<S>,
+    SignatureFailures: FromRef<S>,
+    SignatureBypass: FromRef<S>,
+    SignatureHeader: FromRef<S>,
 {
     type Rejection = GitHubEventExtractionError;
 
-    async fn from_request(request: Request, webhook_secret: &S) -> Result<Self, Self::Rejection> {
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
         let (mut parts, body) = request.into_parts();
-        let webhook_secret = Arc::<SecretKey>::from_ref(webhook_secret);
+        if let Some(pre_auth) = Option::<Arc<dyn PreAuth>>::from_ref(state) {
+            pre_auth.authenticate(&parts.headers)?;
+        }
+        let webhook_secret = Arc::<SecretKey>::from_ref(state);
+        let limits = PayloadLimits::from_ref(state);
+        let secret_resolver = Option::<Arc<dyn SecretResolver>>::from_ref(state);
 
         let ExtractGitHubEventHeader(event) =
             ExtractGitHubEventHeader::from_request_parts(&mut parts, &()).await?;
-        let ExtractSignatureHeader(signature) =
-            ExtractSignatureHeader::from_request_parts(&mut parts, &()).await?;
+        // the read cap holds for every content type, bodies declared too large aren't read at
+        // all and the content type is only looked at once the body was read within the cap
+        let declared_length = parts
+            .headers
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok()?.parse::<usize>().ok());
+        if declared_length.is_some_and(|length| length > limits.max_bytes) {
+            return Err(GitHubEventExtractionError::PayloadTooLarge(
+                limits.max_bytes,
+            ));
+        }
+        let accepts_sha1 = SignatureMigration::from_ref(state).accepts_sha1(SystemTime::now());
+        let failures = SignatureFailures::from_ref(state);
+        let bypass = SignatureBypass::from_ref(state).enabled();
+        let signatures = match ExtractSignatureHeader::from_request_parts(&mut parts, state).await {
+            Ok(ExtractSignatureHeader(signatures)) => signatures,
+            Err(_) if bypass => Vec::new(),
+            Err(SignatureHeaderError::MissingHeader) if accepts_sha1 => Vec::new(),
+            Err(err) => {
+                failures.record((&err).into());
+                return Err(err.into());
+            }
+        };
+        let sha1_signature = parts
+            .headers
+            .get("x-hub-signature")
+            .filter(|_| accepts_sha1 && !bypass)
+            .map(|value| value.to_str().map(str::to_owned))
+            .transpose()
+            .inspect_err(|_| failures.record(SignatureFailureReason::Malformed))?;
 
-        let body = body.collect().await?.to_bytes();
+        // the secret of a repository is only known once the body was read, the default
+        // secret allows hashing the body whilst it arrives
+        let mut streaming = secret_resolver
+            .is_none()
+            .then(|| StreamingSignature::new(&webhook_secret));
+        let capacity = declared_length.unwrap_or_default().min(limits.max_bytes);
+        let mut collected = BytesMut::with_capacity(capacity);
+        // counts the bytes as they arrive, `Content-Length` might be missing or wrong
+        let mut body = Limited::new(body, limits.max_bytes);
+        while let Some(frame) = body.frame().await {
+            let frame = frame.map_err(|err| match err.downcast::<LengthLimitError>() {
+                Ok(_) => GitHubEventExtractionError::PayloadTooLarge(limits.max_bytes),
+                Err(err) => GitHubEventExtractionError::BodyUnreadable(err),
+            })?;
+            if let Ok(chunk) = frame.into_data() {
+                if let Some(streaming) = streaming.as_mut() {
+                    streaming.update(&chunk)?;
+                }
+                collected.extend_from_slice(&chunk);
+            }
+        }
+        let body = collected.freeze();
+        let header = |name: &str| {
+            parts
+                .headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+        };
+        DeliveryAge::from_ref(state).check(
+            header(DELIVERY_TIMESTAMP_HEADER),
+            header(DELIVERY_TIMESTAMP_SIGNATURE_HEADER),
+            &body,
+            SystemTime::now(),
+        )?;
+        // GitHub always sends a content type, only the form encoded one can't be parsed
+        if let Some(content_type) = parts.headers.get(CONTENT_TYPE) {
+            let content_type = content_type.to_str()?;
+            if !is_json(content_type) {
+                return Err(GitHubEventExtractionError::UnsupportedMediaType(
+                    content_type.to_owned(),
+                ));
+            }
+        }
 
-        verify_signature(&signature, &webhook_secret, &body)?;
-        Ok(Self(
-            WebhookEvent::try_from_header_and_body(&event, &body)
-                .map_err(GitHubEventExtractionError::EventUnparsable)?,
-        ))
+        let streamed = streaming.map(|streaming| streaming.verify(&signatures));
+        if bypass {
+            tracing::warn!("SIGNATURE VERIFICATION IS DISABLED, accepted the delivery unverified");
+        } else if !matches!(streamed, Some(Ok(()))) {
+            // a body the streamed signature doesn't match goes through the verification shared
+            // with `verify_and_parse`, which tries the fallbacks and records the rejection
+            let webhook_secret = match streamed {
+                Some(_) => webhook_secret,
+                None => resolve_secret(secret_resolver, &body)
+                    .await
+                    .unwrap_or(webhook_secret),
+            };
+            verify_body(
+                &signatures,
+                sha1_signature.as_deref(),
+                &[webhook_secret],
+                &body,
+                Some(&failures),
+            )?;
+        }
+        Ok(Self(parse_verified(&event, &body, limits.max_depth)?, body))
     }
 }
 
-fn verify_signature(
-    signature: &Sha256VerificationSignature,
-    webhook_secret: &SecretKey,
+/// Compares the media type only, parameters like `charset=utf-8` are ignored.
+fn is_json(content_type: &str) -> bool {
+    let media_type = content_type.split(';').next().unwrap_or_default();
+    media_type.trim().eq_ignore_ascii_case("application/json")
+}
+
+/// Resolvers may block, e.g. whilst backing off from a failing vault.
+async fn resolve_secret(
+    resolver: Option<Arc<dyn SecretResolver>>,
     body: &[u8],
-) -> Result<(), GitHubEventExtractionError> {
-    use orion::hazardous::mac::hmac::sha256::HmacSha256;
-    let tag = HmacSha256::hmac(webhook_secret, body)
-        .map_err(|_| GitHubEventExtractionError::InvalidSignature)?;
-    if signature == tag {
-        Ok(())
-    } else {
-        Err(GitHubEventExtractionError::SignatureMismatch)
-    }
+) -> Option<Arc<SecretKey>> {
+    let (resolver, repository) = (resolver?, claimed_repository(body)?);
+    let span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || span.in_scope(|| resolver.resolve(&repository)))
+        .await
+        .inspect_err(|err| tracing::error!(%err, "resolving the repository secret failed"))
+        .ok()
+        .flatten()
 }
 
-#[derive(Debug, Error)]
-pub enum GitHubEventExtractionError {
-    #[error("The header value does not consist of a valid string")]
-    InvalidValue(#[from] ToStrError),
-    #[error("Unable to calculate the signature of the body")]
-    InvalidSignature,
-    #[error("Signature of body does not match the header")]
-    SignatureMismatch,
-    #[error("Unable to verify the signature: {0}")]
-    SignatureHeader(#[from] SignatureHeaderError),
-    #[error("Unable to fetch the event name: {0}")]
-    GitHubHeader(#[from] GitHubEventHeaderError),
-    #[error("Unable to parse and process the request")]
-    EventUnparsable(serde_json::Error),
-    #[error("Something went wrong whilst processing the body")]
-    AxumError(#[from] axum::Error),
+/// Peeks at the repository of the unverified body, it only selects the secret to verify with.
+fn claimed_repository(body: &[u8]) -> Option<String> {
+    #[derive(serde::Deserialize)]
+    struct Payload {
+        repository: Option<Repository>,
+    }
+    #[derive(serde::Deserialize)]
+    struct Repository {
+        full_name: String,
+    }
+    serde_json::from_slice::<Payload>(body)
+        .ok()?
+        .repository
+        .map(|repository| repository.full_name)
 }
 
 impl IntoResponse for GitHubEventExtractionError {
@@ -188,18 +357,37 @@ impl IntoResponse for GitHubEventExtractionError {
             e @ GitHubEventExtractionError::SignatureMismatch => {
                 (StatusCode::BAD_REQUEST, e.to_string())
             }
+            e @ GitHubEventExtractionError::DeliveryAge(_) => {
+                (StatusCode::BAD_REQUEST, e.to_string())
+            }
             e @ GitHubEventExtractionError::SignatureHeader(_) => {
                 (StatusCode::BAD_REQUEST, e.to_string())
             }
             e @ GitHubEventExtractionError::EventUnparsable(_) => {
                 (StatusCode::BAD_REQUEST, e.to_string())
             }
+            e @ GitHubEventExtractionError::PayloadMismatch { .. } => {
+                (StatusCode::UNPROCESSABLE_ENTITY, e.to_string())
+            }
+            e @ GitHubEventExtractionError::PayloadTooDeep(_) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, e.to_string())
+            }
+            e @ GitHubEventExtractionError::PayloadTooLarge(_) => {
+                (StatusCode::PAYLOAD_TOO_LARGE, e.to_string())
+            }
+            e @ GitHubEventExtractionError::BodyUnreadable(_) => {
+                (StatusCode::BAD_REQUEST, e.to_string())
+            }
             e @ GitHubEventExtractionError::AxumError(_) => {
                 (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
             }
             e @ GitHubEventExtractionError::GitHubHeader(_) => {
                 (StatusCode::BAD_REQUEST, e.to_string())
             }
+            e @ GitHubEventExtractionError::PreAuth(_) => (StatusCode::UNAUTHORIZED, e.to_string()),
+            e @ GitHubEventExtractionError::UnsupportedMediaType(_) => {
+                (StatusCode::UNSUPPORTED_MEDIA_TYPE, e.to_string())
+            }
         }
         .into_response()
     }
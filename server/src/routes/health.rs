@@ -0,0 +1,140 @@
+use crate::probe::GitHubProbe;
+use crate::shutdown::Shutdown;
+use axum::{extract::State, routing::get, Router};
+use hyper::StatusCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Whether the App is authenticated towards GitHub, only ever unauthenticated while a lazy
+/// authentication is still being retried.
+#[derive(Debug, Clone)]
+pub struct AuthHealth(Arc<AtomicBool>);
+
+impl Default for AuthHealth {
+    fn default() -> Self {
+        Self(Arc::new(AtomicBool::new(true)))
+    }
+}
+
+impl AuthHealth {
+    pub fn is_authenticated(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn set(&self, authenticated: bool) {
+        self.0.store(authenticated, Ordering::SeqCst);
+    }
+}
+
+#[derive(Clone)]
+struct Readiness {
+    shutdown: Shutdown,
+    probe: Option<GitHubProbe>,
+    auth: AuthHealth,
+}
+
+/// `/readyz` additionally reports not ready while the `probe` doesn't reach GitHub or the App
+/// isn't authenticated yet.
+pub fn router(shutdown: Shutdown, probe: Option<GitHubProbe>, auth: AuthHealth) -> Router {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .with_state(Readiness {
+            shutdown,
+            probe,
+            auth,
+        })
+}
+
+async fn healthz() -> StatusCode {
+    StatusCode::OK
+}
+
+async fn readyz(State(readiness): State<Readiness>) -> StatusCode {
+    let reachable = readiness.probe.is_none_or(|probe| probe.is_reachable());
+    if readiness.shutdown.is_ready() && reachable && readiness.auth.is_authenticated() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::probe::{reachability_check, GitHubProbe};
+    use crate::shutdown::Shutdown;
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use hyper::StatusCode;
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    async fn status(app: &Router, path: &str) -> StatusCode {
+        let request = Request::builder().uri(path).body(Body::empty()).unwrap();
+        app.clone().oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_readiness_flips_on_shutdown() {
+        let shutdown = Shutdown::default();
+        let app = super::router(shutdown.clone(), None, Default::default());
+        assert_eq!(status(&app, "/readyz").await, StatusCode::OK);
+        assert_eq!(status(&app, "/healthz").await, StatusCode::OK);
+
+        shutdown.trigger();
+
+        assert_eq!(
+            status(&app, "/readyz").await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(status(&app, "/healthz").await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_readiness_follows_the_last_probe() {
+        let probe = GitHubProbe::new(Duration::from_secs(10), Duration::from_secs(300));
+        let app = super::router(Shutdown::default(), Some(probe.clone()), Default::default());
+        assert_eq!(status(&app, "/readyz").await, StatusCode::OK);
+
+        let mut delays = Vec::new();
+        for _ in 0..3 {
+            probe.record(false);
+            delays.push(probe.next_delay());
+            assert_eq!(
+                status(&app, "/readyz").await,
+                StatusCode::SERVICE_UNAVAILABLE
+            );
+        }
+        assert!(delays.windows(2).all(|delays| delays[0] < delays[1]));
+
+        probe.record(true);
+        assert_eq!(status(&app, "/readyz").await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_probe_timing_out_reports_not_ready() {
+        let github = Router::new().route(
+            "/meta",
+            get(|| async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                "{}"
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let uri = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, github).await });
+        let probe = GitHubProbe::new(Duration::from_secs(10), Duration::from_secs(300));
+        let shutdown = Shutdown::default();
+        let check = reachability_check(uri.parse().unwrap(), Duration::from_millis(50), 1024);
+        let app = super::router(shutdown.clone(), Some(probe.clone()), Default::default());
+
+        let running = tokio::spawn(probe.clone().run(check.unwrap(), shutdown.clone()));
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        assert_eq!(
+            status(&app, "/readyz").await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        shutdown.trigger();
+        running.await.unwrap();
+    }
+}
@@ -0,0 +1,152 @@
+use crate::pre_auth::PreAuth;
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::put,
+    Router,
+};
+use hyper::StatusCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Header `MAINTENANCE_TOKEN` is expected in.
+pub const MAINTENANCE_TOKEN_HEADER: HeaderName = HeaderName::from_static("x-maintenance-token");
+
+/// Seconds GitHub is asked to wait before redelivering during maintenance.
+static RETRY_AFTER: HeaderValue = HeaderValue::from_static("120");
+
+/// Runtime toggle to stop processing deliveries while the endpoint stays reachable.
+#[derive(Debug, Clone, Default)]
+pub struct Maintenance(Arc<AtomicBool>);
+
+impl Maintenance {
+    pub fn is_enabled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    pub fn set(&self, enabled: bool) {
+        self.0.store(enabled, Ordering::SeqCst);
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Admin {
+    maintenance: Maintenance,
+    auth: Option<Arc<dyn PreAuth>>,
+}
+
+impl Admin {
+    /// Without credentials configured nobody may toggle the maintenance mode.
+    fn authenticate(&self, headers: &HeaderMap) -> Result<(), StatusCode> {
+        let Some(auth) = self.auth.as_ref() else {
+            tracing::warn!("rejected a maintenance request, no credentials are configured");
+            return Err(StatusCode::UNAUTHORIZED);
+        };
+        auth.authenticate(headers).map_err(|err| {
+            tracing::warn!(%err, "rejected a maintenance request");
+            StatusCode::UNAUTHORIZED
+        })
+    }
+}
+
+/// Admin routes to toggle the maintenance mode, only to be served on the internal endpoint.
+///
+/// Requests `auth` rejects are answered with `401`.
+pub fn router(maintenance: Maintenance, auth: Option<Arc<dyn PreAuth>>) -> Router {
+    Router::new()
+        .route("/maintenance", put(enable).delete(disable))
+        .with_state(Admin { maintenance, auth })
+}
+
+async fn enable(State(admin): State<Admin>, headers: HeaderMap) -> StatusCode {
+    if let Err(status) = admin.authenticate(&headers) {
+        return status;
+    }
+    tracing::warn!("maintenance mode enabled");
+    admin.maintenance.set(true);
+    StatusCode::NO_CONTENT
+}
+
+async fn disable(State(admin): State<Admin>, headers: HeaderMap) -> StatusCode {
+    if let Err(status) = admin.authenticate(&headers) {
+        return status;
+    }
+    tracing::info!("maintenance mode disabled");
+    admin.maintenance.set(false);
+    StatusCode::NO_CONTENT
+}
+
+/// Rejects all requests before any processing whilst the maintenance mode is enabled.
+pub async fn reject_during_maintenance(
+    State(maintenance): State<Maintenance>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if maintenance.is_enabled() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, RETRY_AFTER.clone())],
+            "in maintenance",
+        )
+            .into_response();
+    }
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Maintenance, MAINTENANCE_TOKEN_HEADER};
+    use crate::pre_auth::ProxySecretHeader;
+    use axum::{body::Body, http::Request};
+    use hyper::StatusCode;
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    fn request(method: &str, token: Option<&str>) -> Request<Body> {
+        let mut request = Request::builder().method(method).uri("/maintenance");
+        if let Some(token) = token {
+            request = request.header(MAINTENANCE_TOKEN_HEADER, token);
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_admin_endpoint_toggles_maintenance() {
+        let maintenance = Maintenance::default();
+        let auth = ProxySecretHeader::new(MAINTENANCE_TOKEN_HEADER, "admin-token");
+        let app = super::router(maintenance.clone(), Some(Arc::new(auth)));
+
+        let response = app
+            .clone()
+            .oneshot(request("PUT", Some("admin-token")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(maintenance.is_enabled());
+
+        app.oneshot(request("DELETE", Some("admin-token")))
+            .await
+            .unwrap();
+        assert!(!maintenance.is_enabled());
+    }
+
+    #[tokio::test]
+    async fn test_admin_endpoint_requires_the_token() {
+        let maintenance = Maintenance::default();
+        let auth = ProxySecretHeader::new(MAINTENANCE_TOKEN_HEADER, "admin-token");
+        let app = super::router(maintenance.clone(), Some(Arc::new(auth)));
+        let unconfigured = super::router(maintenance.clone(), None);
+
+        for (app, token) in [
+            (app.clone(), None),
+            (app, Some("guessed")),
+            (unconfigured, Some("admin-token")),
+        ] {
+            let response = app.oneshot(request("PUT", token)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{token:?}");
+        }
+        assert!(!maintenance.is_enabled());
+    }
+}
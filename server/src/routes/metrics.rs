@@ -1,8 +1,17 @@
+use std::collections::HashSet;
+use std::fmt::Debug;
 use std::future::ready;
+use std::sync::{Arc, OnceLock};
 
-use axum::{extract::MatchedPath, middleware::Next, routing::get, Router};
+use axum::{
+    extract::{MatchedPath, State},
+    middleware::Next,
+    routing::get,
+    Router,
+};
 use axum_core::{extract::Request, response::IntoResponse};
 use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use octocrab::models::webhook_events::{EventInstallation, WebhookEvent};
 use tokio::time::Instant;
 
 pub fn router() -> Router {
@@ -10,22 +19,57 @@ pub fn router() -> Router {
     Router::new().route("/metrics", get(move || ready(recorder_handle.render())))
 }
 
+/// The recorder is global, it's installed by the first call and shared by the following ones.
 pub fn setup_metrics_recorder() -> PrometheusHandle {
     const EXPONENTIAL_SECONDS: &[f64] = &[
         0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
     ];
+    static HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
 
-    PrometheusBuilder::new()
-        .set_buckets_for_metric(
-            Matcher::Full(REQUEST_DURATION.to_string()),
-            EXPONENTIAL_SECONDS,
-        )
-        .unwrap()
-        .install_recorder()
-        .unwrap()
+    HANDLE
+        .get_or_init(|| {
+            PrometheusBuilder::new()
+                .set_buckets_for_metric(
+                    Matcher::Full(REQUEST_DURATION.to_string()),
+                    EXPONENTIAL_SECONDS,
+                )
+                .unwrap()
+                .install_recorder()
+                .unwrap()
+        })
+        .clone()
+}
+
+/// Backend the server's metrics are recorded with.
+pub trait MetricsSink: Debug + Send + Sync {
+    fn incr_counter(&self, name: &'static str, labels: &[(&'static str, String)], value: u64);
+    fn observe_histogram(&self, name: &'static str, labels: &[(&'static str, String)], value: f64);
+    fn set_gauge(&self, name: &'static str, labels: &[(&'static str, String)], value: f64);
 }
 
-pub async fn track_metrics(req: Request, next: Next) -> impl IntoResponse {
+/// Records through the `metrics` facade, rendered by the Prometheus recorder of `/metrics`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PrometheusSink;
+
+impl MetricsSink for PrometheusSink {
+    fn incr_counter(&self, name: &'static str, labels: &[(&'static str, String)], value: u64) {
+        metrics::counter!(name, labels).increment(value);
+    }
+
+    fn observe_histogram(&self, name: &'static str, labels: &[(&'static str, String)], value: f64) {
+        metrics::histogram!(name, labels).record(value);
+    }
+
+    fn set_gauge(&self, name: &'static str, labels: &[(&'static str, String)], value: f64) {
+        metrics::gauge!(name, labels).set(value);
+    }
+}
+
+pub async fn track_metrics(
+    State(metrics): State<Arc<dyn MetricsSink>>,
+    req: Request,
+    next: Next,
+) -> impl IntoResponse {
     const UNKNOWN_PATH: &str = "/<unknown>";
 
     let path = req
@@ -48,11 +92,147 @@ pub async fn track_metrics(req: Request, next: Next) -> impl IntoResponse {
         ("status", status),
     ];
 
-    metrics::counter!(SUM_REQUESTS, &labels).increment(1);
-    metrics::histogram!(REQUEST_DURATION, &labels).record(latency);
+    metrics.incr_counter(SUM_REQUESTS, &labels, 1);
+    metrics.observe_histogram(REQUEST_DURATION, &labels, latency);
 
     response
 }
 
+/// Event types recorded under their own label, every other event is counted as `other` so
+/// unexpected `X-GitHub-Event` values cannot grow the number of series without bounds.
+#[derive(Debug, Clone)]
+pub struct EventLabels(Arc<HashSet<String>>);
+
+impl EventLabels {
+    const OTHER: &'static str = "other";
+
+    pub fn new<I: IntoIterator<Item = S>, S: Into<String>>(events: I) -> Self {
+        Self(Arc::new(events.into_iter().map(Into::into).collect()))
+    }
+
+    pub fn label<'a>(&self, event: &'a str) -> &'a str {
+        if self.0.contains(event) {
+            event
+        } else {
+            Self::OTHER
+        }
+    }
+
+    pub fn record(&self, metrics: &dyn MetricsSink, event: &str, account_type: &'static str) {
+        let labels = [
+            ("event", self.label(event).to_owned()),
+            ("account_type", account_type.to_owned()),
+        ];
+        metrics.incr_counter(SUM_EVENTS, &labels, 1);
+    }
+}
+
+/// Whether the event's installation belongs to an `Organization` or a `User`, `unknown` if the
+/// event doesn't tell.
+///
+/// Most events only carry the installation id, the account of the repository or the
+/// organization the event is about is the one the App is installed on then.
+pub fn account_type(event: &WebhookEvent) -> &'static str {
+    let account_type = match event.installation {
+        Some(EventInstallation::Full(ref installation)) => {
+            Some(installation.account.r#type.as_str())
+        }
+        _ => None,
+    }
+    .or_else(|| {
+        event
+            .repository
+            .as_ref()
+            .and_then(|repository| repository.owner.as_ref())
+            .map(|owner| owner.r#type.as_str())
+    })
+    .or(event.organization.as_ref().map(|_| "Organization"));
+    match account_type {
+        Some("Organization") => "Organization",
+        Some("User") => "User",
+        _ => "unknown",
+    }
+}
+
+impl Default for EventLabels {
+    fn default() -> Self {
+        Self::new([
+            "ping",
+            "push",
+            "pull_request",
+            "check_suite",
+            "check_run",
+            "workflow_run",
+            "workflow_job",
+            "installation",
+            "installation_repositories",
+            "package",
+            "registry_package",
+        ])
+    }
+}
+
 const REQUEST_DURATION: &str = "http_requests_duration_seconds";
 const SUM_REQUESTS: &str = "http_requests_total";
+const SUM_EVENTS: &str = "github_events_total";
+
+#[cfg(test)]
+mod test {
+    use super::{account_type, EventLabels, PrometheusSink};
+    use metrics_exporter_prometheus::PrometheusBuilder;
+    use octocrab::models::webhook_events::WebhookEvent;
+    use serde_json::json;
+
+    #[test]
+    fn test_unlisted_event_is_labelled_other() {
+        let labels = EventLabels::new(["push"]);
+
+        assert_eq!(labels.label("push"), "push");
+        assert_eq!(labels.label("sponsorship"), "other");
+    }
+
+    #[test]
+    fn test_organization_installation_is_labelled_with_its_account_type() {
+        let account = json!({
+            "login": "acme",
+            "id": 2,
+            "node_id": "MDEyOk9yZ2FuaXphdGlvbjI=",
+            "avatar_url": "https://github.local/images/acme.gif",
+            "gravatar_id": "",
+            "url": "https://api.github.local/users/acme",
+            "html_url": "https://github.local/acme",
+            "followers_url": "https://api.github.local/users/acme/followers",
+            "following_url": "https://api.github.local/users/acme/following",
+            "gists_url": "https://api.github.local/users/acme/gists",
+            "starred_url": "https://api.github.local/users/acme/starred",
+            "subscriptions_url": "https://api.github.local/users/acme/subscriptions",
+            "organizations_url": "https://api.github.local/users/acme/orgs",
+            "repos_url": "https://api.github.local/users/acme/repos",
+            "events_url": "https://api.github.local/users/acme/events",
+            "received_events_url": "https://api.github.local/users/acme/received_events",
+            "type": "Organization",
+            "site_admin": false
+        });
+        let body = json!({
+            "zen": "Mind your words, they are important.",
+            "installation": { "id": 5, "account": account, "permissions": {}, "events": ["ping"] }
+        });
+        let event = WebhookEvent::try_from_header_and_body("ping", &body.to_string()).unwrap();
+        let minimal = WebhookEvent::try_from_header_and_body(
+            "ping",
+            r#"{"installation":{"id":5,"node_id":"dGVzdA=="}}"#,
+        )
+        .unwrap();
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+
+        metrics::with_local_recorder(&recorder, || {
+            EventLabels::default().record(&PrometheusSink, "ping", account_type(&event));
+        });
+
+        assert!(handle
+            .render()
+            .contains(r#"github_events_total{event="ping",account_type="Organization"} 1"#));
+        assert_eq!(account_type(&minimal), "unknown");
+    }
+}
@@ -0,0 +1,23 @@
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use std::time::Instant;
+
+static PROCESSING_TIME_HEADER: HeaderName = HeaderName::from_static("x-processing-time-ms");
+
+/// Reports how long the delivery took server-side, to compare with the duration GitHub shows
+/// for the delivery.
+pub async fn processing_time(req: Request, next: Next) -> Response {
+    let started = Instant::now();
+
+    let mut response = next.run(req).await;
+
+    let elapsed = HeaderValue::from(started.elapsed().as_millis() as u64);
+    response
+        .headers_mut()
+        .insert(&PROCESSING_TIME_HEADER, elapsed);
+    response
+}
@@ -0,0 +1,30 @@
+use axum::{
+    extract::{Request, State},
+    http::{HeaderMap, HeaderName},
+    middleware::Next,
+    response::Response,
+};
+use std::sync::Arc;
+
+static DELIVERY_HEADER: HeaderName = HeaderName::from_static("x-github-delivery");
+static ECHO_DELIVERY_HEADER: HeaderName = HeaderName::from_static("x-delivery-id");
+
+/// Adds the configured static headers to every response and echoes the delivery id.
+pub async fn response_headers(
+    State(headers): State<Arc<HeaderMap>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let delivery = req.headers().get(&DELIVERY_HEADER).cloned();
+
+    let mut response = next.run(req).await;
+
+    let response_headers = response.headers_mut();
+    for (name, value) in headers.iter() {
+        response_headers.insert(name, value.clone());
+    }
+    if let Some(delivery) = delivery {
+        response_headers.insert(&ECHO_DELIVERY_HEADER, delivery);
+    }
+    response
+}
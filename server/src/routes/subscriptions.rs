@@ -0,0 +1,83 @@
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use octocrab::models::webhook_events::WebhookEventType;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// Sent by GitHub whatever the App is subscribed to.
+const ALWAYS_SENT: [&str; 4] = [
+    "ping",
+    "github_app_authorization",
+    "installation",
+    "installation_repositories",
+];
+
+/// Event types the App is subscribed to, an empty set accepts every event.
+#[derive(Debug, Clone, Default)]
+pub struct Subscriptions(Arc<HashSet<String>>);
+
+impl Subscriptions {
+    pub fn new<'a>(kinds: impl IntoIterator<Item = &'a WebhookEventType>) -> Self {
+        Self(Arc::new(
+            kinds
+                .into_iter()
+                .filter_map(|kind| serde_json::to_value(kind).ok())
+                .filter_map(|kind| kind.as_str().map(str::to_owned))
+                .collect(),
+        ))
+    }
+
+    /// Events GitHub sends regardless of the subscriptions are always accepted, the
+    /// installation ones keep the cached tokens and repository mappings current.
+    pub fn accepts(&self, kind: &str) -> bool {
+        self.0.is_empty() || ALWAYS_SENT.contains(&kind) || self.0.contains(kind)
+    }
+}
+
+/// Acknowledges events the App isn't subscribed to by their `X-GitHub-Event` header alone,
+/// before the body is buffered or its signature verified.
+///
+/// Unverified requests can therefore get a `200` for unsubscribed events, they never reach a
+/// handler though. As verified events take longer to answer, their timing reveals which events
+/// are subscribed, `verify_unsubscribed_events` verifies every delivery first instead.
+pub async fn acknowledge_unsubscribed(
+    State(subscriptions): State<Subscriptions>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let kind = req
+        .headers()
+        .get("x-github-event")
+        .and_then(|value| value.to_str().ok());
+    if let Some(kind) = kind.filter(|kind| !subscriptions.accepts(kind)) {
+        tracing::debug!(kind, "acknowledging unsubscribed event");
+        return (StatusCode::OK, "not subscribed").into_response();
+    }
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::Subscriptions;
+    use octocrab::models::webhook_events::WebhookEventType;
+
+    #[test]
+    fn test_events_sent_regardless_of_the_subscriptions_are_accepted() {
+        let subscriptions = Subscriptions::new(&[WebhookEventType::PullRequest]);
+
+        for kind in [
+            "pull_request",
+            "ping",
+            "github_app_authorization",
+            "installation",
+            "installation_repositories",
+        ] {
+            assert!(subscriptions.accepts(kind), "{kind}");
+        }
+        assert!(!subscriptions.accepts("push"));
+    }
+}
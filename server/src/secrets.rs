@@ -0,0 +1,171 @@
+use crate::clock::{Clock, SystemClock};
+use lru::LruCache;
+use orion::hazardous::mac::hmac::sha256::SecretKey;
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+
+/// Maps repositories to the webhook secret of their repository-level webhook.
+///
+/// Repositories without a dedicated secret are verified with the App's webhook secret.
+pub trait SecretResolver: std::fmt::Debug + Send + Sync {
+    /// `repository` is the `owner/name` taken from the not yet verified payload.
+    fn resolve(&self, repository: &str) -> Option<Arc<SecretKey>>;
+}
+
+/// Fixed set of secrets keyed by `owner/name`.
+#[derive(Debug, Default)]
+pub struct RepositorySecrets(HashMap<String, Arc<SecretKey>>);
+
+impl RepositorySecrets {
+    pub fn with(mut self, repository: impl Into<String>, secret: SecretKey) -> Self {
+        self.0.insert(repository.into(), Arc::new(secret));
+        self
+    }
+}
+
+impl SecretResolver for RepositorySecrets {
+    fn resolve(&self, repository: &str) -> Option<Arc<SecretKey>> {
+        self.0.get(repository).cloned()
+    }
+}
+
+/// First delay between failed lookups of [`CachedSecretProvider`].
+const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_millis(50);
+
+/// Repositories whose last fetched secret [`CachedSecretProvider`] keeps by default.
+const DEFAULT_KNOWN_SECRETS: NonZeroUsize = NonZeroUsize::new(1024).unwrap();
+
+/// A source of repository secrets which might be briefly unavailable, e.g. a vault client.
+pub trait SecretProvider: std::fmt::Debug + Send + Sync {
+    /// `Ok(None)` if the repository has no dedicated secret.
+    fn fetch(&self, repository: &str) -> Result<Option<SecretKey>, SecretProviderError>;
+}
+
+#[derive(Debug, Error)]
+#[error("The secret provider failed: {0}")]
+pub struct SecretProviderError(pub Box<dyn std::error::Error + Send + Sync>);
+
+/// Resolves secrets through a [`SecretProvider`], failed lookups are tried again after a backoff
+/// and then answered with the last secret fetched for the repository, as long as it isn't older
+/// than the maximum staleness.
+///
+/// Like the lookups themselves the backoff blocks the calling thread, it doubles with every
+/// retry. The webhook route resolves on a blocking thread for this reason.
+///
+/// Only repositories which have a secret are remembered, the least recently resolved ones are
+/// forgotten once the capacity is reached. The repository is taken from the unverified payload,
+/// anyone can make up new ones.
+///
+/// Without a fresh enough secret the delivery is verified with the App's webhook secret, which
+/// rejects deliveries signed with the repository's secret.
+#[derive(Debug)]
+pub struct CachedSecretProvider<P> {
+    provider: P,
+    attempts: u32,
+    retry_backoff: Duration,
+    max_staleness: Duration,
+    clock: Arc<dyn Clock>,
+    known: Mutex<LruCache<String, KnownSecret>>,
+}
+
+#[derive(Debug)]
+struct KnownSecret {
+    secret: Arc<SecretKey>,
+    fetched: SystemTime,
+}
+
+impl<P: SecretProvider> CachedSecretProvider<P> {
+    /// Fetches twice, 50 milliseconds apart, before falling back to a cached secret.
+    pub fn new(provider: P, max_staleness: Duration) -> Self {
+        Self::with_clock(provider, max_staleness, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(provider: P, max_staleness: Duration, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            provider,
+            attempts: 2,
+            retry_backoff: DEFAULT_RETRY_BACKOFF,
+            max_staleness,
+            clock,
+            known: Mutex::new(LruCache::new(DEFAULT_KNOWN_SECRETS)),
+        }
+    }
+
+    /// Fetches including the first one, at least one.
+    pub fn with_attempts(self, attempts: u32) -> Self {
+        Self {
+            attempts: attempts.max(1),
+            ..self
+        }
+    }
+
+    /// Remembers the secrets of this many repositories, 1024 by default.
+    pub fn with_capacity(self, capacity: NonZeroUsize) -> Self {
+        self.known.lock().unwrap().resize(capacity);
+        self
+    }
+
+    /// Waits this long before the first retry, twice as long before the next one and so on.
+    pub fn with_retry_backoff(self, retry_backoff: Duration) -> Self {
+        Self {
+            retry_backoff,
+            ..self
+        }
+    }
+
+    fn fetch(&self, repository: &str) -> Result<Option<Arc<SecretKey>>, SecretProviderError> {
+        let mut attempt = 1;
+        let mut backoff = self.retry_backoff;
+        loop {
+            match self.provider.fetch(repository) {
+                Ok(secret) => return Ok(secret.map(Arc::new)),
+                Err(err) if attempt >= self.attempts => return Err(err),
+                Err(err) => tracing::debug!(%err, attempt, repository, "retrying secret lookup"),
+            }
+            // a provider which just failed rarely recovers within microseconds
+            std::thread::sleep(backoff);
+            backoff *= 2;
+            attempt += 1;
+        }
+    }
+}
+
+impl<P: SecretProvider> SecretResolver for CachedSecretProvider<P> {
+    fn resolve(&self, repository: &str) -> Option<Arc<SecretKey>> {
+        let now = self.clock.now();
+        let fetched = self.fetch(repository);
+        let mut known = self.known.lock().unwrap();
+        match fetched {
+            Ok(Some(secret)) => {
+                let fetched = KnownSecret {
+                    secret: secret.clone(),
+                    fetched: now,
+                };
+                known.put(repository.to_owned(), fetched);
+                Some(secret)
+            }
+            Ok(None) => {
+                known.pop(repository);
+                None
+            }
+            Err(err) => {
+                let fresh = known.get(repository).filter(|known| {
+                    now.duration_since(known.fetched).unwrap_or_default() <= self.max_staleness
+                });
+                match fresh {
+                    Some(known) => {
+                        tracing::warn!(%err, repository, "using the last known secret");
+                        Some(known.secret.clone())
+                    }
+                    None => {
+                        tracing::error!(%err, repository, "no recent secret is known");
+                        None
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+
+/// Coordinates the graceful shutdown of the public and internal endpoints.
+///
+/// Once triggered the service reports itself as not ready and the public endpoint starts
+/// draining, the internal endpoint keeps serving until the draining has finished.
+#[derive(Clone)]
+pub struct Shutdown {
+    ready: Arc<AtomicBool>,
+    triggered: CancellationToken,
+    drained: CancellationToken,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self {
+            ready: Arc::new(AtomicBool::new(true)),
+            triggered: CancellationToken::new(),
+            drained: CancellationToken::new(),
+        }
+    }
+}
+
+impl Shutdown {
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::SeqCst)
+    }
+
+    pub fn trigger(&self) {
+        // flip readiness first so load balancers stop routing before we stop accepting
+        self.ready.store(false, Ordering::SeqCst);
+        self.triggered.cancel();
+    }
+
+    pub async fn triggered(self) {
+        self.triggered.cancelled().await
+    }
+
+    pub(crate) fn mark_drained(&self) {
+        self.drained.cancel();
+    }
+
+    pub async fn drained(self) {
+        self.drained.cancelled().await
+    }
+}
+
+/// Handlers that are currently running, so they can be cancelled once draining takes too long.
+#[derive(Debug, Clone, Default)]
+pub struct InFlightHandlers {
+    cancel: CancellationToken,
+    count: Arc<AtomicUsize>,
+}
+
+impl InFlightHandlers {
+    /// Registers a running handler until the returned guard is dropped.
+    pub fn track(&self) -> TrackedHandler {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        TrackedHandler {
+            token: self.cancel.child_token(),
+            count: self.count.clone(),
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    /// Cancels the tokens of all running handlers, returns how many were still running.
+    pub fn cancel_all(&self) -> usize {
+        self.cancel.cancel();
+        self.count()
+    }
+}
+
+#[derive(Debug)]
+pub struct TrackedHandler {
+    pub token: CancellationToken,
+    count: Arc<AtomicUsize>,
+}
+
+impl Drop for TrackedHandler {
+    fn drop(&mut self) {
+        self.count.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Triggers the shutdown once the process receives either `SIGINT` or `SIGTERM`.
+pub async fn listen_for_signals(shutdown: Shutdown) {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            tracing::error!(%err, "unable to listen for ctrl-c");
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                tracing::error!(%err, "unable to listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+    tracing::info!("shutdown requested, draining in-flight requests");
+    shutdown.trigger();
+}
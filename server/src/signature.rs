@@ -0,0 +1,657 @@
+use crate::clock::{Clock, SystemClock};
+use crate::routes::metrics::{MetricsSink, PrometheusSink};
+use hex::FromHexError;
+use hyper::header::{HeaderName, ToStrError};
+use orion::hazardous::mac::hmac::sha256::{HmacSha256, SecretKey, Tag};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+
+/// A single `sha256=<hex>` entry of the `X-Hub-Signature-256` header, the algorithm prefix is
+/// matched case-insensitively.
+#[derive(Clone)]
+pub struct Sha256VerificationSignature(Vec<u8>);
+
+impl Sha256VerificationSignature {
+    /// Length of a HMAC-SHA256 tag in bytes, anything else can never match.
+    const LENGTH: usize = 32;
+}
+
+impl<'a> TryFrom<(&'a str, &'a str)> for Sha256VerificationSignature {
+    type Error = SignatureHeaderError;
+
+    fn try_from((kind, hmac): (&'a str, &'a str)) -> Result<Self, Self::Error> {
+        match kind.trim() {
+            kind if kind.eq_ignore_ascii_case("sha256") => {
+                let hmac = hmac.trim();
+                // rejects oversized values before hex decoding allocates for them
+                if hmac.len() > 2 * Self::LENGTH {
+                    return Err(SignatureHeaderError::InvalidLength(hmac.len() / 2));
+                }
+                let signature = hex::decode(hmac)?;
+                if signature.len() != Self::LENGTH {
+                    return Err(SignatureHeaderError::InvalidLength(signature.len()));
+                }
+                Ok(Sha256VerificationSignature(signature))
+            }
+            kind => Err(SignatureHeaderError::UnknownAlgorithm(kind.to_owned())),
+        }
+    }
+}
+
+impl PartialEq<Tag> for &Sha256VerificationSignature {
+    fn eq(&self, other: &Tag) -> bool {
+        *other == &*self.0
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SignatureHeaderError {
+    #[error("The header value does not consist of a valid string")]
+    InvalidValue(#[from] ToStrError),
+    #[error("The header value is missing the correct delimiter")]
+    NotAPair,
+    #[error("The header value is not a valid hex value")]
+    NotHex(#[from] FromHexError),
+    #[error("The signature must be exactly 32 bytes long, got {0}")]
+    InvalidLength(usize),
+    #[error("The signature algorithm {0:?} is not supported")]
+    UnknownAlgorithm(String),
+    #[error("Missing header pair (either left or right side)")]
+    MissingHeader,
+    #[error("More than {MAX_SIGNATURES} signatures were sent")]
+    TooManySignatures,
+}
+
+/// GitHub sends a single signature, allow a few more for proxies duplicating the header.
+pub const MAX_SIGNATURES: usize = 16;
+
+#[derive(Debug, Error)]
+pub enum SignatureError {
+    #[error(transparent)]
+    Header(#[from] SignatureHeaderError),
+    #[error("Signature could not be computed")]
+    InvalidSignature,
+    #[error("Signature mismatch")]
+    Mismatch,
+}
+
+/// Byte order mark some proxies prepend to bodies they re-encode.
+pub(crate) const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Parses a (possibly folded) header value into its signatures, appending them to `signatures`.
+pub(crate) fn parse_signatures(
+    value: &str,
+    signatures: &mut Vec<Sha256VerificationSignature>,
+) -> Result<(), SignatureHeaderError> {
+    for signature in value.split(',').map(str::trim) {
+        if signature.is_empty() {
+            continue;
+        }
+        if signatures.len() == MAX_SIGNATURES {
+            return Err(SignatureHeaderError::TooManySignatures);
+        }
+        let (kind, hmac) = signature
+            .split_once('=')
+            .ok_or(SignatureHeaderError::NotAPair)?;
+        signatures.push((kind, hmac).try_into()?);
+    }
+    Ok(())
+}
+
+/// Verifies `body` against the value of an `X-Hub-Signature-256` header, e.g. when replaying
+/// or checking deliveries outside of the webhook route.
+pub fn verify_signature(
+    secret: &SecretKey,
+    body: &[u8],
+    signature_header: &str,
+) -> Result<(), SignatureError> {
+    let mut signatures = Vec::new();
+    parse_signatures(signature_header, &mut signatures)?;
+    if signatures.is_empty() {
+        return Err(SignatureHeaderError::MissingHeader.into());
+    }
+    verify_signatures(&signatures, secret, body)
+}
+
+/// Accepts the body if any of the given signatures matches.
+pub(crate) fn verify_signatures(
+    signatures: &[Sha256VerificationSignature],
+    secret: &SecretKey,
+    body: &[u8],
+) -> Result<(), SignatureError> {
+    let mut streaming = StreamingSignature::new(secret);
+    streaming.update(body)?;
+    streaming.verify(signatures)
+}
+
+/// Computes the signature of a body chunk by chunk as it arrives, instead of hashing it once
+/// it was buffered completely.
+pub(crate) struct StreamingSignature(HmacSha256);
+
+impl StreamingSignature {
+    pub(crate) fn new(secret: &SecretKey) -> Self {
+        Self(HmacSha256::new(secret))
+    }
+
+    pub(crate) fn update(&mut self, chunk: &[u8]) -> Result<(), SignatureError> {
+        self.0
+            .update(chunk)
+            .map_err(|_| SignatureError::InvalidSignature)
+    }
+
+    /// Accepts the body if any of the given signatures matches.
+    pub(crate) fn verify(
+        mut self,
+        signatures: &[Sha256VerificationSignature],
+    ) -> Result<(), SignatureError> {
+        let tag = self
+            .0
+            .finalize()
+            .map_err(|_| SignatureError::InvalidSignature)?;
+        if signatures.iter().any(|signature| signature == tag) {
+            Ok(())
+        } else {
+            Err(SignatureError::Mismatch)
+        }
+    }
+}
+
+/// Window in which deliveries are still accepted by their legacy `X-Hub-Signature` SHA-1
+/// signature, e.g. whilst a hook is migrated to SHA-256.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SignatureMigration {
+    pub sha1_accepted_until: Option<SystemTime>,
+}
+
+impl SignatureMigration {
+    pub fn accepts_sha1(&self, now: SystemTime) -> bool {
+        self.sha1_accepted_until.is_some_and(|until| now < until)
+    }
+}
+
+/// Header a signing proxy puts the unix timestamp of the delivery in, GitHub sends none itself.
+pub const DELIVERY_TIMESTAMP_HEADER: &str = "x-delivery-timestamp";
+
+/// Header carrying the proxy's `sha256=<hex>` HMAC over `<timestamp>.<body>`, the signature of
+/// the body doesn't cover the timestamp.
+pub const DELIVERY_TIMESTAMP_SIGNATURE_HEADER: &str = "x-delivery-timestamp-signature";
+
+/// Rejects deliveries by the age of their [timestamp](DELIVERY_TIMESTAMP_HEADER), so captured
+/// deliveries can't be replayed long after they were signed.
+///
+/// Anyone replaying a delivery could set a fresh timestamp, it only counts if the proxy signed
+/// it together with the body by [`DELIVERY_TIMESTAMP_SIGNATURE_HEADER`].
+#[derive(Debug, Clone)]
+pub struct DeliveryAge {
+    /// `None` disables the check, deliveries without a signed timestamp are rejected otherwise.
+    pub max_age: Option<Duration>,
+    /// How far timestamps may lie in the future, the clocks of the proxy and the server are
+    /// never quite in sync.
+    pub max_future_skew: Duration,
+    /// The secret the proxy signs timestamps with, required by `max_age`.
+    pub secret: Option<Arc<SecretKey>>,
+}
+
+impl Default for DeliveryAge {
+    fn default() -> Self {
+        Self {
+            max_age: None,
+            max_future_skew: Duration::from_secs(30),
+            secret: None,
+        }
+    }
+}
+
+impl DeliveryAge {
+    pub fn is_enabled(&self) -> bool {
+        self.max_age.is_some()
+    }
+
+    /// Checks the age of the `timestamp` whose `signature` covers `<timestamp>.<body>`.
+    pub fn check(
+        &self,
+        timestamp: Option<&str>,
+        signature: Option<&str>,
+        body: &[u8],
+        now: SystemTime,
+    ) -> Result<(), DeliveryAgeError> {
+        let Some(max_age) = self.max_age else {
+            return Ok(());
+        };
+        let timestamp = timestamp.ok_or(DeliveryAgeError::InvalidTimestamp)?;
+        let secret = self.secret.as_ref().ok_or(DeliveryAgeError::Unsigned)?;
+        let mut signatures = Vec::new();
+        parse_signatures(signature.unwrap_or_default(), &mut signatures)
+            .map_err(|_| DeliveryAgeError::Unsigned)?;
+        let verified = || {
+            let mut streaming = StreamingSignature::new(secret);
+            streaming.update(timestamp.as_bytes())?;
+            streaming.update(b".")?;
+            streaming.update(body)?;
+            streaming.verify(&signatures)
+        };
+        verified().map_err(|_| DeliveryAgeError::Unsigned)?;
+        let signed = timestamp
+            .trim()
+            .parse()
+            .ok()
+            .map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+            .ok_or(DeliveryAgeError::InvalidTimestamp)?;
+        match now.duration_since(signed) {
+            Ok(age) if age > max_age => Err(DeliveryAgeError::TooOld(age, max_age)),
+            Ok(_) => Ok(()),
+            Err(ahead) if ahead.duration() > self.max_future_skew => Err(
+                DeliveryAgeError::InFuture(ahead.duration(), self.max_future_skew),
+            ),
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum DeliveryAgeError {
+    #[error("The delivery timestamp is missing or not a unix timestamp")]
+    InvalidTimestamp,
+    #[error("The delivery timestamp is not signed by the proxy")]
+    Unsigned,
+    #[error("The delivery is {0:?} old, at most {1:?} are accepted")]
+    TooOld(Duration, Duration),
+    #[error("The delivery timestamp is {0:?} in the future, at most {1:?} are accepted")]
+    InFuture(Duration, Duration),
+}
+
+/// Header carrying the SHA-256 signatures, gateways rewriting headers might forward them under
+/// another name.
+#[derive(Debug, Clone)]
+pub struct SignatureHeader(pub(crate) HeaderName);
+
+impl Default for SignatureHeader {
+    fn default() -> Self {
+        Self(HeaderName::from_static(DEFAULT_SIGNATURE_HEADER))
+    }
+}
+
+pub const DEFAULT_SIGNATURE_HEADER: &str = "x-hub-signature-256";
+
+/// Whether signatures are checked at all, see
+/// [`WebhookEndpointConfiguration::disable_signature_verification`](crate::config::WebhookEndpointConfiguration::disable_signature_verification).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SignatureBypass(pub(crate) bool);
+
+impl SignatureBypass {
+    /// Only builds with the `dangerous` feature can bypass the verification.
+    pub(crate) fn enabled(&self) -> bool {
+        cfg!(feature = "dangerous") && self.0
+    }
+}
+
+/// Verifies `body` against the value of a legacy `X-Hub-Signature` header.
+pub(crate) fn verify_sha1_signature(
+    secret: &SecretKey,
+    body: &[u8],
+    signature_header: &str,
+) -> Result<(), SignatureError> {
+    use hmac::{Hmac, Mac};
+
+    let (kind, hmac) = signature_header
+        .trim()
+        .split_once('=')
+        .ok_or(SignatureHeaderError::NotAPair)?;
+    let kind = kind.trim();
+    if !kind.eq_ignore_ascii_case("sha1") {
+        return Err(SignatureHeaderError::UnknownAlgorithm(kind.to_owned()).into());
+    }
+    let signature = hex::decode(hmac.trim()).map_err(SignatureHeaderError::from)?;
+    let mut mac = Hmac::<sha1::Sha1>::new_from_slice(secret.unprotected_as_bytes())
+        .map_err(|_| SignatureError::InvalidSignature)?;
+    mac.update(body);
+    mac.verify_slice(&signature)
+        .map_err(|_| SignatureError::Mismatch)
+}
+
+/// Why a delivery's signature was rejected, the `reason` label of `github_signature_failures_total`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureFailureReason {
+    Missing,
+    Malformed,
+    Mismatch,
+}
+
+impl SignatureFailureReason {
+    pub fn label(&self) -> &'static str {
+        match self {
+            SignatureFailureReason::Missing => "missing",
+            SignatureFailureReason::Malformed => "malformed",
+            SignatureFailureReason::Mismatch => "mismatch",
+        }
+    }
+}
+
+impl From<&SignatureHeaderError> for SignatureFailureReason {
+    fn from(error: &SignatureHeaderError) -> Self {
+        match error {
+            SignatureHeaderError::MissingHeader => SignatureFailureReason::Missing,
+            _ => SignatureFailureReason::Malformed,
+        }
+    }
+}
+
+/// Counts rejected signatures and warns once as many failed within the window as the threshold
+/// allows, a spike usually means the webhook secret was rotated on one side only.
+#[derive(Debug, Clone)]
+pub struct SignatureFailures {
+    threshold: usize,
+    window: Duration,
+    clock: Arc<dyn Clock>,
+    metrics: Arc<dyn MetricsSink>,
+    state: Arc<Mutex<FailureWindow>>,
+}
+
+#[derive(Debug, Default)]
+struct FailureWindow {
+    /// The most recent failures, at most `threshold` of them.
+    failures: VecDeque<SystemTime>,
+    alerting: bool,
+}
+
+impl SignatureFailures {
+    pub fn new(threshold: usize, window: Duration) -> Self {
+        Self::with_clock(threshold, window, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(threshold: usize, window: Duration, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            threshold: threshold.max(1),
+            window,
+            clock,
+            metrics: Arc::new(PrometheusSink),
+            state: Default::default(),
+        }
+    }
+
+    /// Counts the failures with `metrics` instead of the Prometheus recorder.
+    pub fn with_metrics(self, metrics: Arc<dyn MetricsSink>) -> Self {
+        Self { metrics, ..self }
+    }
+
+    pub fn record(&self, reason: SignatureFailureReason) {
+        let labels = [("reason", reason.label().to_owned())];
+        self.metrics.incr_counter(SIGNATURE_FAILURES, &labels, 1);
+        let now = self.clock.now();
+        let mut state = self.state.lock().unwrap();
+        while state
+            .failures
+            .front()
+            .is_some_and(|failed| now.duration_since(*failed).unwrap_or_default() >= self.window)
+        {
+            state.failures.pop_front();
+        }
+        // the oldest failures beyond the threshold can't change whether it is crossed
+        if state.failures.len() == self.threshold {
+            state.failures.pop_front();
+        }
+        state.failures.push_back(now);
+        let failures = state.failures.len();
+        if failures < self.threshold {
+            state.alerting = false;
+        } else if !state.alerting {
+            state.alerting = true;
+            tracing::warn!(
+                failures,
+                window_secs = self.window.as_secs(),
+                reason = reason.label(),
+                "signature failure threshold crossed, check the webhook secret"
+            );
+        }
+    }
+}
+
+impl Default for SignatureFailures {
+    fn default() -> Self {
+        Self::new(10, Duration::from_secs(60))
+    }
+}
+
+const SIGNATURE_FAILURES: &str = "github_signature_failures_total";
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    const BODY: &[u8] = br#"{"zen":"Keep it logically awesome."}"#;
+
+    fn secret() -> SecretKey {
+        SecretKey::from_slice(b"It's a Secret to Everybody").unwrap()
+    }
+
+    fn header_for(body: &[u8]) -> String {
+        let tag = HmacSha256::hmac(&secret(), body).unwrap();
+        format!("sha256={}", hex::encode(tag.unprotected_as_bytes()))
+    }
+
+    #[test]
+    fn test_valid_signature_is_accepted() {
+        let header = format!("sha256={}, {}", "00".repeat(32), header_for(BODY));
+
+        assert!(verify_signature(&secret(), BODY, &header).is_ok());
+    }
+
+    #[test]
+    fn test_streaming_signature_matches_the_one_shot_signature() {
+        let body = br#"{"action":"opened","number":1,"pull_request":{"title":"Chunked"}}"#;
+        let signature: Sha256VerificationSignature =
+            ("sha256", header_for(body).trim_start_matches("sha256="))
+                .try_into()
+                .unwrap();
+
+        for chunk_size in [1, 7, body.len()] {
+            let mut streaming = StreamingSignature::new(&secret());
+            for chunk in body.chunks(chunk_size) {
+                streaming.update(chunk).unwrap();
+            }
+            assert!(streaming.verify(&[signature.clone()]).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_signature_of_other_body_is_rejected() {
+        let header = header_for(b"{}");
+
+        assert!(matches!(
+            verify_signature(&secret(), BODY, &header),
+            Err(SignatureError::Mismatch)
+        ));
+    }
+
+    #[test]
+    fn test_malformed_signatures_are_rejected() {
+        let not_a_pair = verify_signature(&secret(), BODY, "sha256");
+        let not_hex = verify_signature(&secret(), BODY, "sha256=zz");
+        let too_short = verify_signature(&secret(), BODY, "sha256=abcd");
+        let wrong_kind = verify_signature(&secret(), BODY, &format!("sha1={}", "00".repeat(32)));
+
+        assert!(matches!(
+            not_a_pair,
+            Err(SignatureError::Header(SignatureHeaderError::NotAPair))
+        ));
+        assert!(matches!(
+            not_hex,
+            Err(SignatureError::Header(SignatureHeaderError::NotHex(_)))
+        ));
+        assert!(matches!(
+            too_short,
+            Err(SignatureError::Header(SignatureHeaderError::InvalidLength(
+                2
+            )))
+        ));
+        assert!(matches!(
+            wrong_kind,
+            Err(SignatureError::Header(SignatureHeaderError::UnknownAlgorithm(ref kind))) if kind == "sha1"
+        ));
+    }
+
+    #[test]
+    fn test_adversarial_headers_are_rejected() {
+        let valid = header_for(BODY);
+        let hmac = valid.trim_start_matches("sha256=");
+        let cases = [
+            (hmac.to_string(), "NotAPair"),
+            (format!("sha256={hmac}=="), "InvalidLength"),
+            (format!("sha256=={hmac}"), "InvalidLength"),
+            (format!("sha256={}", "ä".repeat(32)), "NotHex"),
+            (
+                format!("sha256={}", "a".repeat(1024 * 1024)),
+                "InvalidLength",
+            ),
+            (format!("sha256={}", "a".repeat(63)), "NotHex"),
+            (" , ,".to_string(), "MissingHeader"),
+            (vec![valid.as_str(); 17].join(","), "TooManySignatures"),
+        ];
+
+        for (header, expected) in cases {
+            let Err(SignatureError::Header(err)) = verify_signature(&secret(), BODY, &header)
+            else {
+                panic!("{expected} was not rejected");
+            };
+            assert!(
+                format!("{err:?}").starts_with(expected),
+                "{err:?} is not {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_future_timestamps_within_the_skew_are_accepted() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let secret = Arc::new(SecretKey::from_slice(&[7; 32]).unwrap());
+        let age = DeliveryAge {
+            max_age: Some(Duration::from_secs(300)),
+            max_future_skew: Duration::from_secs(30),
+            secret: Some(secret.clone()),
+        };
+        let check = |timestamp: &str| {
+            let signature = timestamp_signature(&secret, timestamp, BODY);
+            age.check(Some(timestamp), Some(&signature), BODY, now)
+        };
+
+        assert!(check("1700000000").is_ok());
+        assert!(check("1699999800").is_ok());
+        assert!(check("1700000025").is_ok());
+        assert!(matches!(
+            check("1700000031"),
+            Err(DeliveryAgeError::InFuture(ahead, _)) if ahead == Duration::from_secs(31)
+        ));
+        assert!(matches!(
+            check("1699999600"),
+            Err(DeliveryAgeError::TooOld(..))
+        ));
+        assert!(matches!(
+            age.check(None, None, BODY, now),
+            Err(DeliveryAgeError::InvalidTimestamp)
+        ));
+        assert!(DeliveryAge::default().check(None, None, BODY, now).is_ok());
+    }
+
+    fn timestamp_signature(secret: &SecretKey, timestamp: &str, body: &[u8]) -> String {
+        let signed = [timestamp.as_bytes(), b".", body].concat();
+        let tag = HmacSha256::hmac(secret, &signed).unwrap();
+        format!("sha256={}", hex::encode(tag.unprotected_as_bytes()))
+    }
+
+    #[test]
+    fn test_refreshed_timestamp_of_a_replayed_delivery_is_rejected() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let secret = Arc::new(SecretKey::from_slice(&[7; 32]).unwrap());
+        let age = DeliveryAge {
+            max_age: Some(Duration::from_secs(300)),
+            secret: Some(secret.clone()),
+            ..Default::default()
+        };
+        // captured an hour ago with its then valid signature
+        let captured = timestamp_signature(&secret, "1699996400", BODY);
+
+        assert!(matches!(
+            age.check(Some("1700000000"), Some(&captured), BODY, now),
+            Err(DeliveryAgeError::Unsigned)
+        ));
+        assert!(matches!(
+            age.check(Some("1700000000"), None, BODY, now),
+            Err(DeliveryAgeError::Unsigned)
+        ));
+        let unconfigured = DeliveryAge {
+            secret: None,
+            ..age
+        };
+        assert!(matches!(
+            unconfigured.check(Some("1700000000"), Some(&captured), BODY, now),
+            Err(DeliveryAgeError::Unsigned)
+        ));
+    }
+
+    #[tracing_test::traced_test]
+    #[test]
+    fn test_failures_outside_the_window_are_forgotten() {
+        let clock = Arc::new(ManualClock::at(SystemTime::UNIX_EPOCH));
+        let failures = SignatureFailures::with_clock(2, Duration::from_secs(60), clock.clone());
+
+        failures.record(SignatureFailureReason::Mismatch);
+        clock.advance(Duration::from_secs(60));
+        failures.record(SignatureFailureReason::Mismatch);
+        assert!(!logs_contain("threshold crossed"));
+        failures.record(SignatureFailureReason::Missing);
+
+        assert!(logs_contain("threshold crossed"));
+    }
+
+    #[test]
+    fn test_only_the_threshold_of_failures_is_kept() {
+        let clock = Arc::new(ManualClock::at(SystemTime::UNIX_EPOCH));
+        let failures = SignatureFailures::with_clock(3, Duration::from_secs(60), clock);
+
+        for _ in 0..100 {
+            failures.record(SignatureFailureReason::Mismatch);
+        }
+
+        assert_eq!(failures.state.lock().unwrap().failures.len(), 3);
+    }
+
+    #[test]
+    fn test_algorithm_prefix_is_case_insensitive() {
+        let hmac = header_for(BODY).trim_start_matches("sha256=").to_string();
+
+        for prefix in ["sha256", "SHA256", "Sha256"] {
+            assert!(verify_signature(&secret(), BODY, &format!("{prefix}={hmac}")).is_ok());
+        }
+        assert!(matches!(
+            verify_signature(&secret(), BODY, &format!("SHA256={}", "zz".repeat(32))),
+            Err(SignatureError::Header(SignatureHeaderError::NotHex(_)))
+        ));
+    }
+
+    #[test]
+    fn test_sha1_prefix_is_case_insensitive() {
+        use hmac::{Hmac, Mac};
+
+        let mut mac = Hmac::<sha1::Sha1>::new_from_slice(secret().unprotected_as_bytes()).unwrap();
+        mac.update(BODY);
+        let hmac = hex::encode(mac.finalize().into_bytes());
+
+        for prefix in ["sha1", "SHA1", "Sha1"] {
+            assert!(verify_sha1_signature(&secret(), BODY, &format!("{prefix}={hmac}")).is_ok());
+        }
+        assert!(matches!(
+            verify_sha1_signature(&secret(), BODY, &format!("md5={hmac}")),
+            Err(SignatureError::Header(SignatureHeaderError::UnknownAlgorithm(ref kind))) if kind == "md5"
+        ));
+    }
+
+    #[test]
+    fn test_whitespace_around_signatures_is_ignored() {
+        let hmac = header_for(BODY).trim_start_matches("sha256=").to_string();
+
+        assert!(verify_signature(&secret(), BODY, &format!(" sha256 = {hmac} ,")).is_ok());
+    }
+}
@@ -0,0 +1,167 @@
+use axum::Router;
+use axum_server::tls_rustls::RustlsConfig;
+use rustls::pki_types::pem::{self, PemObject};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::ServerConfig;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::net::TcpListener;
+
+/// Certificate chain and private key for deployments without a TLS-terminating proxy.
+#[derive(Debug, Clone)]
+pub struct TlsConfiguration {
+    /// PEM file with the certificate chain, leaf certificate first.
+    pub cert_path: PathBuf,
+    /// PEM file with the private key of the leaf certificate.
+    pub key_path: PathBuf,
+}
+
+#[derive(Debug, Error)]
+pub enum TlsError {
+    #[error("Unable to read the certificate chain from {path:?}: {source}")]
+    Certificate { path: PathBuf, source: pem::Error },
+    #[error("No certificate found in {0:?}")]
+    NoCertificate(PathBuf),
+    #[error("Unable to read the private key from {path:?}: {source}")]
+    PrivateKey { path: PathBuf, source: pem::Error },
+    #[error("The certificate chain and private key cannot be used: {0}")]
+    Invalid(#[from] rustls::Error),
+}
+
+impl TlsConfiguration {
+    /// Reads the certificate and key, failing if they are unreadable or don't belong together.
+    pub fn load(&self) -> Result<RustlsConfig, TlsError> {
+        let certificates = CertificateDer::pem_file_iter(&self.cert_path)
+            .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+            .map_err(|source| TlsError::Certificate {
+                path: self.cert_path.clone(),
+                source,
+            })?;
+        if certificates.is_empty() {
+            return Err(TlsError::NoCertificate(self.cert_path.clone()));
+        }
+        let key = PrivateKeyDer::from_pem_file(&self.key_path).map_err(|source| {
+            TlsError::PrivateKey {
+                path: self.key_path.clone(),
+                source,
+            }
+        })?;
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(certificates, key)?;
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Ok(RustlsConfig::from_config(Arc::new(config)))
+    }
+}
+
+/// Serves `routes` over HTTPS until the future `shutdown` completes, connections which don't
+/// complete a TLS handshake are closed.
+pub async fn serve_tls(
+    listener: TcpListener,
+    routes: Router,
+    tls_config: RustlsConfig,
+    shutdown: impl std::future::Future<Output = ()> + Send + 'static,
+) -> std::io::Result<()> {
+    let handle = axum_server::Handle::new();
+    let graceful = handle.clone();
+    tokio::spawn(async move {
+        shutdown.await;
+        graceful.graceful_shutdown(None);
+    });
+    axum_server::from_tcp_rustls(listener.into_std()?, tls_config)
+        .handle(handle)
+        .serve(routes.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::{serve_tls, TlsConfiguration, TlsError};
+    use axum::{routing::get, Router};
+    use rustls::pki_types::{CertificateDer, ServerName};
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    fn write_certificate(name: &str) -> (TlsConfiguration, CertificateDer<'static>) {
+        let certified = rcgen::generate_simple_self_signed(["localhost".to_string()]).unwrap();
+        let dir: PathBuf = std::env::temp_dir().join(format!("tls-{}-{name}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = TlsConfiguration {
+            cert_path: dir.join("cert.pem"),
+            key_path: dir.join("key.pem"),
+        };
+        std::fs::write(&config.cert_path, certified.cert.pem()).unwrap();
+        std::fs::write(&config.key_path, certified.key_pair.serialize_pem()).unwrap();
+        (config, certified.cert.der().clone())
+    }
+
+    #[tokio::test]
+    async fn test_https_is_served_and_plain_http_rejected() {
+        let (config, certificate) = write_certificate("serve");
+        let tls_config = config.load().unwrap();
+        std::fs::remove_dir_all(config.cert_path.parent().unwrap()).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let routes = Router::new().route("/", get(|| async { "hello" }));
+        tokio::spawn(serve_tls(
+            listener,
+            routes,
+            tls_config,
+            std::future::pending(),
+        ));
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(certificate).unwrap();
+        let client_config = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(client_config));
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut tls = connector
+            .connect(ServerName::try_from("localhost").unwrap(), stream)
+            .await
+            .unwrap();
+        tls.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        tls.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.ends_with("hello"), "{response}");
+
+        let mut plain = TcpStream::connect(addr).await.unwrap();
+        plain
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = Vec::new();
+        let _ = plain.read_to_end(&mut response).await;
+        assert!(!response.starts_with(b"HTTP/"));
+    }
+
+    #[test]
+    fn test_key_of_another_certificate_is_rejected() {
+        let (config, _) = write_certificate("mismatch");
+        let (other, _) = write_certificate("other");
+        let mismatched = TlsConfiguration {
+            cert_path: config.cert_path.clone(),
+            key_path: other.key_path.clone(),
+        };
+
+        let result = mismatched.load();
+        std::fs::remove_dir_all(config.cert_path.parent().unwrap()).unwrap();
+        std::fs::remove_dir_all(other.cert_path.parent().unwrap()).unwrap();
+
+        assert!(matches!(result, Err(TlsError::Invalid(_))));
+    }
+}
@@ -0,0 +1,328 @@
+use crate::pre_auth::PreAuthError;
+use crate::signature::{
+    parse_signatures, verify_sha1_signature, verify_signatures, DeliveryAgeError,
+    Sha256VerificationSignature, SignatureError, SignatureFailureReason, SignatureFailures,
+    SignatureHeaderError, DEFAULT_SIGNATURE_HEADER, UTF8_BOM,
+};
+use github_event_handler::payload::parse_event;
+use hyper::header::{HeaderMap, HeaderName, ToStrError};
+use octocrab::models::webhook_events::WebhookEvent;
+use orion::hazardous::mac::hmac::sha256::SecretKey;
+use std::sync::Arc;
+use thiserror::Error;
+
+/// Nesting of arrays and objects accepted unless configured otherwise, GitHub's payloads stay
+/// well below it.
+pub const DEFAULT_MAX_JSON_DEPTH: usize = 64;
+
+/// A delivery whose signature matched, as parsed from its headers and body.
+#[derive(Debug)]
+pub struct ParsedDelivery {
+    pub event: WebhookEvent,
+    /// The `X-GitHub-Delivery` id, `None` if missing.
+    pub delivery: Option<String>,
+    /// The `X-GitHub-Event` the payload was parsed as.
+    pub event_type: String,
+}
+
+/// Verifies and parses a delivery without a webhook route, e.g. in a serverless function or
+/// a queue consumer. The body is accepted if its `X-Hub-Signature-256` matches any of the
+/// secrets, which allows rotating them.
+///
+/// The signatures are checked by [`verify_body`] like those of the webhook route, unlike the
+/// route it neither bounds the size of the body nor accepts legacy SHA-1 signatures.
+pub fn verify_and_parse(
+    headers: &HeaderMap,
+    body: &[u8],
+    secrets: &[Arc<SecretKey>],
+) -> Result<ParsedDelivery, GitHubEventExtractionError> {
+    let event_type = event_type(headers)?;
+    let signatures = signatures(headers, &HeaderName::from_static(DEFAULT_SIGNATURE_HEADER))?;
+    verify_body(&signatures, None, secrets, body, None)?;
+    let delivery = headers
+        .get("x-github-delivery")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned);
+    Ok(ParsedDelivery {
+        event: parse_verified(&event_type, body, DEFAULT_MAX_JSON_DEPTH)?,
+        delivery,
+        event_type,
+    })
+}
+
+/// The `X-GitHub-Event` of the delivery.
+pub fn event_type(headers: &HeaderMap) -> Result<String, GitHubEventHeaderError> {
+    let mut values = headers.get_all("x-github-event").iter();
+    let Some(event) = values.next() else {
+        return Err(GitHubEventHeaderError::MissingHeader);
+    };
+    // the event decides the routing, a proxy duplicating the header must not pick one
+    if let Some(other) = values.find(|other| *other != event) {
+        return Err(GitHubEventHeaderError::Ambiguous(
+            event.to_str()?.to_owned(),
+            other.to_str()?.to_owned(),
+        ));
+    }
+    Ok(event.to_str()?.to_owned())
+}
+
+/// All signatures sent in `header`, proxies might duplicate or fold it.
+pub fn signatures(
+    headers: &HeaderMap,
+    header: &HeaderName,
+) -> Result<Vec<Sha256VerificationSignature>, SignatureHeaderError> {
+    let mut signatures = Vec::new();
+    for value in headers.get_all(header) {
+        parse_signatures(value.to_str()?, &mut signatures)?;
+    }
+    if signatures.is_empty() {
+        return Err(SignatureHeaderError::MissingHeader);
+    }
+    Ok(signatures)
+}
+
+/// Verifies that `body` was signed by any of `secrets`, for the webhook route as well as for
+/// [`verify_and_parse`]. The legacy SHA-1 `sha1_signature` is only tried if no SHA-256
+/// signature matches, rejections are recorded to `failures` if given.
+pub(crate) fn verify_body(
+    signatures: &[Sha256VerificationSignature],
+    sha1_signature: Option<&str>,
+    secrets: &[Arc<SecretKey>],
+    body: &[u8],
+    failures: Option<&SignatureFailures>,
+) -> Result<(), GitHubEventExtractionError> {
+    let record = |reason| {
+        if let Some(failures) = failures {
+            failures.record(reason);
+        }
+    };
+    let mut error = SignatureError::Mismatch;
+    for secret in secrets {
+        match verify_signatures(signatures, secret, body) {
+            Ok(()) => return Ok(()),
+            Err(err) => error = err,
+        }
+    }
+    match sha1_signature {
+        Some(sha1)
+            if secrets
+                .iter()
+                .any(|secret| verify_sha1_signature(secret, body, sha1).is_ok()) =>
+        {
+            tracing::warn!("accepted delivery by its legacy SHA-1 signature only");
+            return Ok(());
+        }
+        None if signatures.is_empty() => {
+            record(SignatureFailureReason::Missing);
+            return Err(SignatureHeaderError::MissingHeader.into());
+        }
+        _ => {}
+    }
+    // GitHub never sends a BOM, the signature is still checked over the bytes as received but
+    // the operator learns what to fix
+    if let Some(unprefixed) = body.strip_prefix(UTF8_BOM) {
+        if secrets
+            .iter()
+            .any(|secret| verify_signatures(signatures, secret, unprefixed).is_ok())
+        {
+            tracing::warn!(
+                "rejected delivery whose body gained a UTF-8 BOM, it only matches the signature \
+                 without one, check the proxies in front"
+            );
+        }
+    }
+    record(SignatureFailureReason::Mismatch);
+    Err(error.into())
+}
+
+/// Parses the body of a verified delivery as the event its header declares.
+pub(crate) fn parse_verified(
+    event: &str,
+    body: &[u8],
+    max_depth: usize,
+) -> Result<WebhookEvent, GitHubEventExtractionError> {
+    if exceeds_json_depth(body, max_depth) {
+        return Err(GitHubEventExtractionError::PayloadTooDeep(max_depth));
+    }
+    parse_event(event, body).map_err(|err| {
+        // the body is valid JSON by now, it just isn't the event the header declares
+        match err.is_data() {
+            true => GitHubEventExtractionError::PayloadMismatch {
+                event: event.to_owned(),
+                source: err,
+            },
+            false => GitHubEventExtractionError::EventUnparsable(err),
+        }
+    })
+}
+
+/// Scans the nesting of the body without parsing it, so pathological payloads are rejected
+/// before `serde_json` spends any time on them.
+fn exceeds_json_depth(body: &[u8], max_depth: usize) -> bool {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    for &byte in body {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match byte {
+            b'"' => in_string = true,
+            b'{' | b'[' => {
+                depth += 1;
+                if depth > max_depth {
+                    return true;
+                }
+            }
+            b'}' | b']' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    false
+}
+
+#[derive(Debug, Error)]
+pub enum GitHubEventHeaderError {
+    #[error("Missing header")]
+    MissingHeader,
+    #[error("The header value does not consist of a valid string")]
+    InvalidValue(#[from] ToStrError),
+    #[error("The header was sent more than once with differing values: {0:?} and {1:?}")]
+    Ambiguous(String, String),
+}
+
+#[derive(Debug, Error)]
+pub enum GitHubEventExtractionError {
+    #[error("The header value does not consist of a valid string")]
+    InvalidValue(#[from] ToStrError),
+    #[error("Unable to calculate the signature of the body")]
+    InvalidSignature,
+    #[error("Signature of body does not match the header")]
+    SignatureMismatch,
+    #[error(transparent)]
+    DeliveryAge(#[from] DeliveryAgeError),
+    #[error("Unable to verify the signature: {0}")]
+    SignatureHeader(#[from] SignatureHeaderError),
+    #[error("Unable to fetch the event name: {0}")]
+    GitHubHeader(#[from] GitHubEventHeaderError),
+    #[error("The delivery is not authenticated: {0}")]
+    PreAuth(#[from] PreAuthError),
+    #[error("Unable to parse and process the request")]
+    EventUnparsable(serde_json::Error),
+    #[error("The payload is not a `{event}` event: {source}")]
+    PayloadMismatch {
+        event: String,
+        source: serde_json::Error,
+    },
+    #[error("The payload is nested deeper than {0} levels")]
+    PayloadTooDeep(usize),
+    #[error("Something went wrong whilst processing the body")]
+    AxumError(#[from] axum::Error),
+    #[error("The payload exceeds {0} bytes")]
+    PayloadTooLarge(usize),
+    #[error("Unable to read the body: {0}")]
+    BodyUnreadable(Box<dyn std::error::Error + Send + Sync>),
+    #[error("The content type {0:?} is not supported, the webhook must send application/json")]
+    UnsupportedMediaType(String),
+}
+
+impl From<SignatureError> for GitHubEventExtractionError {
+    fn from(error: SignatureError) -> Self {
+        match error {
+            SignatureError::Header(e) => GitHubEventExtractionError::SignatureHeader(e),
+            SignatureError::InvalidSignature => GitHubEventExtractionError::InvalidSignature,
+            SignatureError::Mismatch => GitHubEventExtractionError::SignatureMismatch,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{signatures, verify_and_parse, verify_body, GitHubEventExtractionError};
+    use crate::signature::SignatureHeaderError;
+    use hmac::{Hmac, Mac};
+    use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+    use octocrab::models::webhook_events::WebhookEventType;
+    use orion::hazardous::mac::hmac::sha256::{HmacSha256, SecretKey};
+    use std::sync::Arc;
+
+    const BODY: &[u8] = br#"{"zen":"Keep it logically awesome.","hook_id":1}"#;
+
+    fn secret(byte: u8) -> Arc<SecretKey> {
+        Arc::new(SecretKey::from_slice(&[byte; 32]).unwrap())
+    }
+
+    fn headers(secret: &SecretKey) -> HeaderMap {
+        let tag = HmacSha256::hmac(secret, BODY).unwrap();
+        let signature = format!("sha256={}", hex::encode(tag.unprotected_as_bytes()));
+        let mut headers = HeaderMap::new();
+        headers.insert("x-github-event", HeaderValue::from_static("ping"));
+        headers.insert("x-github-delivery", HeaderValue::from_static("72d3162e"));
+        headers.insert("x-hub-signature-256", signature.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_delivery_signed_by_any_secret_is_parsed() {
+        let (old, new) = (secret(1), secret(2));
+        let parsed = verify_and_parse(&headers(&old), BODY, &[new, old]).unwrap();
+        assert_eq!(parsed.event.kind, WebhookEventType::Ping);
+        assert_eq!(parsed.event_type, "ping");
+        assert_eq!(parsed.delivery.as_deref(), Some("72d3162e"));
+    }
+
+    #[test]
+    fn test_delivery_with_an_invalid_signature_is_rejected() {
+        let forged = headers(&secret(3));
+        assert!(matches!(
+            verify_and_parse(&forged, BODY, &[secret(1)]),
+            Err(GitHubEventExtractionError::SignatureMismatch)
+        ));
+        assert!(matches!(
+            verify_and_parse(&forged, BODY, &[]),
+            Err(GitHubEventExtractionError::SignatureMismatch)
+        ));
+
+        let mut unsigned = forged;
+        unsigned.remove("x-hub-signature-256");
+        assert!(matches!(
+            verify_and_parse(&unsigned, BODY, &[secret(1)]),
+            Err(GitHubEventExtractionError::SignatureHeader(
+                SignatureHeaderError::MissingHeader
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_legacy_sha1_signature_is_only_tried_by_the_webhook_route() {
+        let (signed, other) = (secret(1), secret(2));
+        let mut mac = Hmac::<sha1::Sha1>::new_from_slice(signed.unprotected_as_bytes()).unwrap();
+        mac.update(BODY);
+        let sha1 = format!("sha1={}", hex::encode(mac.finalize().into_bytes()));
+        // the SHA-256 signature is made with another secret, only the SHA-1 one matches
+        let headers = headers(&other);
+        let signatures = signatures(&headers, &HeaderName::from_static("x-hub-signature-256"));
+
+        // the route passes the legacy header on whilst the migration lasts
+        let route = verify_body(
+            &signatures.unwrap(),
+            Some(&sha1),
+            &[signed.clone()],
+            BODY,
+            None,
+        );
+        assert!(route.is_ok());
+        let mut with_sha1 = headers;
+        with_sha1.insert("x-hub-signature", sha1.parse().unwrap());
+        assert!(matches!(
+            verify_and_parse(&with_sha1, BODY, &[signed]),
+            Err(GitHubEventExtractionError::SignatureMismatch)
+        ));
+    }
+}
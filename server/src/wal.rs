@@ -0,0 +1,306 @@
+use bytes::Bytes;
+use github_event_handler::payload::parse_event;
+use octocrab::models::webhook_events::WebhookEvent;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use thiserror::Error;
+
+/// Accepted events are appended before they are handled and marked done afterwards, so events
+/// that were accepted but never finished can be handled again after a restart.
+///
+/// Entries hold the signed body as it was received, finished entries are dropped and the file
+/// is compacted whenever the log is opened. Entries which can't be parsed anymore, and those
+/// recovered [`DEFAULT_MAX_RECOVERIES`] times without being finished, are moved to
+/// `<path>.dead` instead of being retried on every start.
+#[derive(Debug)]
+pub struct WriteAheadLog {
+    path: PathBuf,
+    state: Mutex<LogState>,
+}
+
+/// Startups an unfinished event is handled again on before it is given up on.
+pub const DEFAULT_MAX_RECOVERIES: u32 = 3;
+
+#[derive(Debug)]
+struct LogState {
+    file: File,
+    next: u64,
+    recovered: Vec<RecoveredEvent>,
+}
+
+/// An event that was logged but not marked done before the log was opened.
+#[derive(Debug)]
+pub struct RecoveredEvent {
+    pub id: u64,
+    pub event: WebhookEvent,
+    /// The body as it was signed.
+    pub body: Bytes,
+    /// How often the event was recovered, including this time.
+    pub recoveries: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Entry {
+    Append {
+        id: u64,
+        /// The `X-GitHub-Event` header the body is parsed by.
+        kind: String,
+        body: String,
+        /// Startups the entry was recovered on, missing in logs of earlier versions.
+        #[serde(default)]
+        recoveries: u32,
+    },
+    Done {
+        id: u64,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum WriteAheadLogError {
+    #[error("Unable to access the write-ahead log: {0}")]
+    Io(#[from] io::Error),
+    #[error("Unable to encode the event: {0}")]
+    Encoding(#[from] serde_json::Error),
+    #[error("The body is not valid UTF-8: {0}")]
+    NotUtf8(#[from] std::str::Utf8Error),
+}
+
+impl WriteAheadLog {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, WriteAheadLogError> {
+        Self::open_with_max_recoveries(path, DEFAULT_MAX_RECOVERIES)
+    }
+
+    /// Moves unfinished events aside once they were recovered `max_recoveries` times, e.g.
+    /// events whose handlers keep failing.
+    pub fn open_with_max_recoveries(
+        path: impl AsRef<Path>,
+        max_recoveries: u32,
+    ) -> Result<Self, WriteAheadLogError> {
+        let path = path.as_ref().to_path_buf();
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+        let mut dead = Vec::new();
+        match File::open(&path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    let line = line?;
+                    // a torn last line is expected if the process died whilst appending
+                    match serde_json::from_str::<Entry>(&line) {
+                        Ok(Entry::Append {
+                            id,
+                            kind,
+                            body,
+                            recoveries,
+                        }) => {
+                            next = next.max(id + 1);
+                            pending.insert(id, (kind, body, recoveries));
+                        }
+                        Ok(Entry::Done { id }) => {
+                            next = next.max(id + 1);
+                            pending.remove(&id);
+                        }
+                        Err(_) if line.trim().is_empty() => continue,
+                        Err(err) => {
+                            tracing::warn!(%err, "moving an unreadable logged entry aside");
+                            dead.push(line);
+                        }
+                    }
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+        // the pending entries are written to a new file and swapped in, a crash whilst
+        // compacting leaves the previous log in place
+        let mut compacted = path.clone().into_os_string();
+        compacted.push(".compacting");
+        let mut file = File::create(&compacted)?;
+        let mut recovered = Vec::new();
+        for (id, (kind, body, recoveries)) in pending {
+            if recoveries >= max_recoveries {
+                tracing::error!(
+                    id,
+                    kind,
+                    recoveries,
+                    "giving up on logged event, moving it aside"
+                );
+                dead.push(serde_json::to_string(&Entry::Append {
+                    id,
+                    kind,
+                    body,
+                    recoveries,
+                })?);
+                continue;
+            }
+            match parse_event(&kind, body.as_bytes()) {
+                Ok(event) => {
+                    let recoveries = recoveries + 1;
+                    writeln!(
+                        file,
+                        "{}",
+                        serde_json::to_string(&Entry::Append {
+                            id,
+                            kind: kind.clone(),
+                            body: body.clone(),
+                            recoveries,
+                        })?
+                    )?;
+                    recovered.push(RecoveredEvent {
+                        id,
+                        event,
+                        body: body.into(),
+                        recoveries,
+                    });
+                }
+                Err(err) => {
+                    tracing::warn!(%err, id, kind, "unable to recover logged event, moving it aside");
+                    dead.push(serde_json::to_string(&Entry::Append {
+                        id,
+                        kind,
+                        body,
+                        recoveries,
+                    })?);
+                }
+            }
+        }
+        file.sync_all()?;
+        if !dead.is_empty() {
+            let mut dead_path = path.clone().into_os_string();
+            dead_path.push(".dead");
+            let mut dead_file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(dead_path)?;
+            for line in dead {
+                writeln!(dead_file, "{line}")?;
+            }
+            dead_file.sync_all()?;
+        }
+        std::fs::rename(&compacted, &path)?;
+        let file = OpenOptions::new().append(true).open(&path)?;
+        Ok(Self {
+            path,
+            state: Mutex::new(LogState {
+                file,
+                next,
+                recovered,
+            }),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Events that were logged but not marked done before the log was opened.
+    pub fn take_recovered(&self) -> Vec<RecoveredEvent> {
+        std::mem::take(&mut self.state.lock().unwrap().recovered)
+    }
+
+    /// Logs the signed body under the `kind` of its `X-GitHub-Event` header, returns its id.
+    pub fn append(&self, kind: &str, body: &[u8]) -> Result<u64, WriteAheadLogError> {
+        let body = std::str::from_utf8(body)?.to_owned();
+        let mut state = self.state.lock().unwrap();
+        let id = state.next;
+        let entry = Entry::Append {
+            id,
+            kind: kind.to_owned(),
+            body,
+            recoveries: 0,
+        };
+        writeln!(state.file, "{}", serde_json::to_string(&entry)?)?;
+        state.file.sync_data()?;
+        state.next += 1;
+        Ok(id)
+    }
+
+    pub fn done(&self, id: u64) -> Result<(), WriteAheadLogError> {
+        let mut state = self.state.lock().unwrap();
+        writeln!(
+            state.file,
+            "{}",
+            serde_json::to_string(&Entry::Done { id })?
+        )?;
+        state.file.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::WriteAheadLog;
+    use octocrab::models::webhook_events::WebhookEventType;
+
+    const BODY: &[u8] = br#"{"zen":"Keep it logically awesome.","hook_id":1}"#;
+
+    #[test]
+    fn test_unfinished_events_are_recovered() {
+        let path = std::env::temp_dir().join(format!("wal-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let log = WriteAheadLog::open(&path).unwrap();
+        let finished = log.append("ping", BODY).unwrap();
+        let unfinished = log.append("ping", BODY).unwrap();
+        log.done(finished).unwrap();
+        drop(log);
+
+        let log = WriteAheadLog::open(&path).unwrap();
+        let recovered = log.take_recovered();
+        assert_eq!(recovered.len(), 1);
+        assert_eq!(recovered[0].id, unfinished);
+        assert_eq!(recovered[0].event.kind, WebhookEventType::Ping);
+        assert_eq!(recovered[0].body, BODY);
+        assert_ne!(log.append("ping", BODY).unwrap(), unfinished);
+        std::fs::remove_file(log.path()).unwrap();
+    }
+
+    #[test]
+    fn test_unparsable_events_are_moved_aside() {
+        let path = std::env::temp_dir().join(format!("wal-dead-{}", std::process::id()));
+        let dead = path.with_extension("dead");
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&dead);
+
+        let log = WriteAheadLog::open(&path).unwrap();
+        log.append("push", BODY).unwrap();
+        drop(log);
+
+        let log = WriteAheadLog::open(&path).unwrap();
+        assert!(log.take_recovered().is_empty());
+        drop(log);
+        let log = WriteAheadLog::open(&path).unwrap();
+        assert!(log.take_recovered().is_empty());
+        assert_eq!(std::fs::read_to_string(&dead).unwrap().lines().count(), 1);
+        std::fs::remove_file(log.path()).unwrap();
+        std::fs::remove_file(dead).unwrap();
+    }
+
+    #[test]
+    fn test_events_recovered_too_often_are_moved_aside() {
+        let path = std::env::temp_dir().join(format!("wal-poison-{}", std::process::id()));
+        let dead = path.with_extension("dead");
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&dead);
+
+        let log = WriteAheadLog::open_with_max_recoveries(&path, 2).unwrap();
+        log.append("ping", BODY).unwrap();
+        drop(log);
+
+        for recoveries in 1..=2 {
+            let log = WriteAheadLog::open_with_max_recoveries(&path, 2).unwrap();
+            let recovered = log.take_recovered();
+            assert_eq!(recovered.len(), 1);
+            assert_eq!(recovered[0].recoveries, recoveries);
+        }
+        let log = WriteAheadLog::open_with_max_recoveries(&path, 2).unwrap();
+        assert!(log.take_recovered().is_empty());
+        assert_eq!(std::fs::read_to_string(&dead).unwrap().lines().count(), 1);
+        std::fs::remove_file(log.path()).unwrap();
+        std::fs::remove_file(dead).unwrap();
+    }
+}